@@ -0,0 +1,18 @@
+#version 120
+
+uniform sampler2D texture;
+uniform float density; // 0 to 1
+uniform float brightness;
+
+varying vec2 frag_texcoord;
+varying float frag_edge; // 0 at the center of the sheet, 1 at the edge of the circle
+
+void main() {
+    float noise = texture2D(texture, frag_texcoord).r;
+    /* Denser skies lower the threshold noise has to pass to become cloud */
+    float threshold = 1.0 - density;
+    float alpha = smoothstep(threshold - 0.1, threshold + 0.1, noise);
+    /* Fade into the sky before the edge of the sheet */
+    alpha *= 1.0 - smoothstep(0.5, 1.0, frag_edge);
+    gl_FragColor = vec4(vec3(brightness), alpha * 0.9);
+}
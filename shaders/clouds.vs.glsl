@@ -0,0 +1,21 @@
+#version 120
+
+uniform mat4 view;
+uniform mat4 projection;
+uniform vec3 center; // below the camera at cloud height, relative to the camera's chunk
+uniform float radius;
+uniform vec2 texture_offset; // texture coordinate at center
+uniform float tile_size; // blocks per texture repeat
+
+attribute vec2 position; // -1 to 1 across the sheet
+
+varying vec2 frag_texcoord;
+varying float frag_edge;
+
+void main() {
+    vec3 world_position = center + vec3(position.x, 0.0, position.y) * radius;
+    gl_Position = projection * view * vec4(world_position, 1.0);
+
+    frag_texcoord = texture_offset + position * radius / tile_size;
+    frag_edge = length(position);
+}
@@ -0,0 +1,13 @@
+#version 120
+
+uniform vec4 color;
+uniform vec3 light_direction;
+
+varying vec3 frag_position;
+
+void main() {
+    /* Boxes are flat-faced, so the normal falls out of the screen-space derivatives */
+    vec3 normal = normalize(cross(dFdx(frag_position), dFdy(frag_position)));
+    float light = 0.6 + 0.4 * max(0.0, dot(normal, -light_direction));
+    gl_FragColor = vec4(color.rgb * light, color.a);
+}
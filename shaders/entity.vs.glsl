@@ -0,0 +1,15 @@
+#version 120
+
+uniform mat4 view;
+uniform mat4 projection;
+uniform mat4 model; // relative to the camera's chunk
+
+attribute vec3 position;
+
+varying vec3 frag_position;
+
+void main() {
+    vec4 p = model * vec4(position, 1.0);
+    gl_Position = projection * view * p;
+    frag_position = p.xyz;
+}
@@ -0,0 +1,15 @@
+#version 120
+
+uniform vec3 fog_color; // also the sky color
+uniform float inner_radius; // loaded chunks are drawn within this distance
+
+varying vec3 frag_color;
+varying float frag_fog_factor;
+varying float frag_distance;
+
+void main() {
+    if (frag_distance < inner_radius) {
+        discard;
+    }
+    gl_FragColor = mix(vec4(fog_color, 1.0), vec4(frag_color, 1.0), frag_fog_factor);
+}
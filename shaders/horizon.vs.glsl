@@ -0,0 +1,34 @@
+#version 120
+
+uniform mat4 view;
+uniform mat4 projection;
+uniform vec3 center; // grid center, relative to the camera's chunk
+uniform vec3 camera_position;
+uniform float fog_density;
+uniform float brightness;
+
+attribute vec3 position; // relative to center
+attribute vec3 color;
+
+varying vec3 frag_color;
+varying float frag_fog_factor;
+varying float frag_distance;
+
+const float PLANET_RADIUS = 6371000.0 / 5000.0; // as in curvature_drop() in renderer.rs
+
+float curvature_drop(float horiz_dist) {
+    float d = max(horiz_dist - 100.0, 0.0);
+    return PLANET_RADIUS - sqrt(max(PLANET_RADIUS * PLANET_RADIUS - d * d, 0.0));
+}
+
+void main() {
+    vec3 world_position = center + position;
+    frag_distance = length(world_position - camera_position);
+    world_position.y -= curvature_drop(length(world_position.xz - camera_position.xz));
+
+    vec4 eye_position = view * vec4(world_position, 1.0);
+    gl_Position = projection * eye_position;
+
+    frag_color = color * brightness;
+    frag_fog_factor = clamp(exp2(-pow(length(eye_position), 2.0) * pow(fog_density, 2.0) * 1.44), 0.0, 1.0);
+}
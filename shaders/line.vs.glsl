@@ -0,0 +1,14 @@
+#version 120
+
+uniform mat4 view;
+uniform mat4 projection;
+
+attribute vec3 position; // relative to the camera's chunk
+attribute vec4 color;
+
+varying vec4 frag_color;
+
+void main() {
+    gl_Position = projection * view * vec4(position, 1.0);
+    frag_color = color;
+}
@@ -1,19 +1,77 @@
 #version 120
 
-const vec4 fog_color = vec4(0.0, 0.75, 1.0, 1.0);
-
 uniform sampler2D texture;
+uniform sampler2D block_table; // see texture::make_block_table
+uniform float block_table_size;
+uniform float block_table_rows;
+uniform vec3 fog_color; // also the sky color
+uniform vec3 normal;
+uniform sampler3D voxels; // opaque and glowing blocks of the chunk and its border
+uniform int use_voxels;
+uniform float voxel_texture_size;
+uniform float time; // seconds, for flowing blocks
 
-varying vec4 frag_diffuse_factor;
-varying vec2 frag_texcoord1;
-varying vec2 frag_texcoord2;
-varying float frag_tex_factor;
+varying float frag_blocktype;
+varying float frag_brightness;
+varying vec2 frag_texcoord;
 varying float frag_fog_factor;
+varying vec3 frag_biome_tint;
+varying vec3 frag_position;
+
+const float AO_STRENGTH = 0.6;
+/* Texture widths a flowing block's pattern drifts per second */
+const float FLOW_SPEED = 0.01;
+/* How far a flowing block's brightness swings either way */
+const float FLOW_PULSE = 0.15;
+
+/* How much of the block space around p is opaque (x) and how brightly it
+   glows (y), filtered across blocks */
+vec2 occupancy(vec3 p) {
+    /* The texture's axes are z, y, x, and it starts at the border block */
+    return texture3D(voxels, (p.zyx + 1.0) / voxel_texture_size).rg;
+}
+
+/* Samples the blocks beside and beyond the air cell in front of the
+   fragment. x darkens corners and crevices, and y is the light from
+   glowing blocks nearby */
+vec2 ambient_light() {
+    vec3 air = frag_position + normal * 0.5;
+    vec3 u = normal.zxy * 0.5;
+    vec3 v = normal.yzx * 0.5;
+    vec2 a = occupancy(air + u);
+    vec2 b = occupancy(air - u);
+    vec2 c = occupancy(air + v);
+    vec2 d = occupancy(air - v);
+    float occluded = a.x + b.x + c.x + d.x;
+    float glow = max(max(max(a.y, b.y), max(c.y, d.y)), occupancy(air + normal * 0.5).y);
+    return vec2(1.0 - AO_STRENGTH * occluded / 4.0, glow);
+}
 
 void main() {
-    vec4 noise = mix(texture2D(texture, frag_texcoord1),
-                     texture2D(texture, frag_texcoord2),
-                     frag_tex_factor);
-    gl_FragColor = noise * frag_diffuse_factor;
-    gl_FragColor = mix(fog_color, gl_FragColor, frag_fog_factor);
+    float u = (floor(frag_blocktype + 0.5) + 0.5) / block_table_size;
+    vec4 tint = texture2D(block_table, vec2(u, 0.5 / block_table_rows));
+    vec4 look = texture2D(block_table, vec2(u, 1.5 / block_table_rows));
+    vec4 flags = texture2D(block_table, vec2(u, 2.5 / block_table_rows));
+    tint.rgb *= mix(vec3(1.0), frag_biome_tint, flags.r);
+    vec2 scales = exp2(floor(look.rg * 255.0 + 0.5) / 16.0 - 8.0);
+    float light = look.a;
+
+    /* Flowing blocks drift and pulse */
+    float flow = flags.g;
+    vec2 texcoord = frag_texcoord + flow * time * FLOW_SPEED * vec2(1.0, 0.6);
+    tint.rgb *= 1.0 + flow * FLOW_PULSE * sin(time * 1.5 + (texcoord.x + texcoord.y) * 40.0);
+
+    vec4 noise = mix(texture2D(texture, texcoord * scales.x),
+                     texture2D(texture, texcoord * scales.y),
+                     look.b);
+    float brightness = mix(frag_brightness, 1.0, light);
+    float occlusion = 1.0;
+    if (use_voxels != 0) {
+        vec2 ambient = ambient_light();
+        /* Glowing blocks aren't shadowed, and light what's next to them */
+        occlusion = mix(ambient.x, 1.0, light);
+        brightness = mix(brightness, 1.0, ambient.y);
+    }
+    gl_FragColor = noise * vec4(brightness * occlusion * tint.rgb, tint.a);
+    gl_FragColor = mix(vec4(fog_color, 1.0), gl_FragColor, frag_fog_factor);
 }
@@ -3,73 +3,55 @@
 uniform mat4 view;
 uniform mat4 projection;
 uniform vec3 camera_position;
-uniform vec3 light_direction;
-uniform vec3 chunk_position;
+uniform vec3 chunk_position; // relative to the camera's chunk
+uniform vec3 texture_origin; // the chunk's origin, wrapped, see renderer::texture_origin
+uniform float fog_density;
+uniform float fade; // 0 when the chunk is first shown, 1 once it has faded in
 uniform vec3 normal;
+uniform int face;
+uniform float face_brightness[6]; // indexed by face, from the sun direction
+uniform vec3 biome_tints[8]; // at the chunk's corners, see climate::chunk_tints
+uniform float chunk_size;
 
 attribute vec3 position;
 attribute float blocktype;
 
-varying vec4 frag_diffuse_factor;
-varying vec2 frag_texcoord1;
-varying vec2 frag_texcoord2;
-varying float frag_tex_factor;
+varying float frag_blocktype;
+varying float frag_brightness;
+varying vec2 frag_texcoord;
 varying float frag_fog_factor;
+varying vec3 frag_biome_tint;
+varying vec3 frag_position; // relative to the chunk
 
-const vec4 light_diffuse = vec4(0.8, 0.8, 0.8, 0.0);
-const vec4 light_ambient = vec4(0.2, 0.2, 0.2, 1.0);
-
-const float fog_density = 0.003;
 const float tex_size = 128.0;
 
-const float BLOCK_GRASS = 1.0;
-const float BLOCK_STONE = 2.0;
-const float BLOCK_DIRT = 3.0;
-const float BLOCK_WATER = 4.0;
+/* Blends the corner tints, so they match the neighbors' at the borders */
+vec3 biome_tint() {
+    vec3 t = clamp(position / chunk_size, 0.0, 1.0);
+    vec3 bottom = mix(mix(biome_tints[0], biome_tints[1], t.x),
+                      mix(biome_tints[4], biome_tints[5], t.x), t.z);
+    vec3 top = mix(mix(biome_tints[2], biome_tints[3], t.x),
+                   mix(biome_tints[6], biome_tints[7], t.x), t.z);
+    return mix(bottom, top, t.y);
+}
 
 void main() {
     vec3 world_position = chunk_position + position;
     vec4 eye_position = view * vec4(world_position, 1.0);
 
     gl_Position = projection * eye_position;
+    frag_position = position;
 
-    frag_texcoord1 = vec2(dot(normal.zxy, position),
-                          dot(normal.yzx, position));
-
-    frag_texcoord1 /= tex_size;
-    frag_texcoord2 = frag_texcoord1;
-
-    vec4 base_color;
-    if (blocktype == BLOCK_GRASS) {
-        base_color = vec4(0.0, 0.8, 0.2, 1.0);
-        frag_texcoord1 *= 0.5;
-        frag_texcoord2 *= 16.0;
-        frag_tex_factor = 0.8;
-    } else if (blocktype == BLOCK_STONE) {
-        base_color = vec4(0.8, 0.8, 0.8, 1.0);
-        frag_texcoord1 *= 1.0;
-        frag_texcoord2 *= 8.0;
-        frag_tex_factor = 0.3;
-    } else if (blocktype == BLOCK_DIRT) {
-        base_color = vec4(0.63, 0.35, 0.03, 1.0);
-        frag_texcoord1 *= 0.5;
-        frag_texcoord2 *= 16.0;
-        frag_tex_factor = 0.8;
-    } else if (blocktype == BLOCK_WATER) {
-        base_color = vec4(0.1, 0.1, 0.9, 1.0);
-        frag_texcoord1 *= 2.0;
-        frag_texcoord2 *= 0.1;
-        frag_tex_factor = 0.8;
-    } else {
-        base_color = vec4(1.0, 0.0, 0.0, 1.0);
-        frag_texcoord1 *= 16.0;
-        frag_texcoord2 *= 16.0;
-        frag_tex_factor = 0.5;
-    }
+    vec3 texture_position = texture_origin + position;
+    frag_texcoord = vec2(dot(normal.zxy, texture_position),
+                         dot(normal.yzx, texture_position)) / tex_size;
 
-    vec4 diffuse_factor
-        = max(-dot(normal, light_direction), 0.0) * light_diffuse;
-    frag_diffuse_factor = (diffuse_factor + light_ambient) * base_color;
+    /* The fragment shader looks up how the block type is drawn */
+    frag_blocktype = blocktype;
+    frag_biome_tint = biome_tint();
+    frag_brightness = face_brightness[face];
 
     frag_fog_factor = clamp(exp2(-pow(length(eye_position), 2.0) * pow(fog_density, 2.0) * 1.44), 0.0, 1.0);
+    /* New chunks emerge from the fog instead of popping in */
+    frag_fog_factor *= fade;
 }
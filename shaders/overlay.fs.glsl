@@ -0,0 +1,9 @@
+#version 120
+
+uniform sampler2D texture;
+
+varying vec2 frag_texcoord;
+
+void main() {
+    gl_FragColor = vec4(0.0, 0.0, 0.0, 0.7 * texture2D(texture, frag_texcoord).r);
+}
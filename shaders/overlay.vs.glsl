@@ -0,0 +1,18 @@
+#version 120
+
+const float crack_stages = 8.0; // mining::CRACK_STAGES
+
+uniform mat4 view;
+uniform mat4 projection;
+uniform vec3 block_position; // relative to the camera's chunk
+uniform float stage;
+
+attribute vec3 position;
+attribute vec2 texcoord;
+
+varying vec2 frag_texcoord;
+
+void main() {
+    gl_Position = projection * view * vec4(position + block_position, 1.0);
+    frag_texcoord = vec2((texcoord.x + stage) / crack_stages, texcoord.y);
+}
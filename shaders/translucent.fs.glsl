@@ -0,0 +1,40 @@
+#version 120
+
+uniform sampler2D texture;
+uniform sampler2D block_table; // see texture::make_block_table
+uniform float block_table_size;
+uniform float block_table_rows;
+uniform vec3 fog_color; // also the sky color
+uniform float time; // seconds, for flowing blocks
+
+varying float frag_blocktype;
+varying float frag_brightness;
+varying vec2 frag_texcoord;
+varying float frag_fog_factor;
+varying vec3 frag_biome_tint;
+
+/* As in main.fs.glsl */
+const float FLOW_SPEED = 0.01;
+const float FLOW_PULSE = 0.15;
+
+void main() {
+    float u = (floor(frag_blocktype + 0.5) + 0.5) / block_table_size;
+    vec4 tint = texture2D(block_table, vec2(u, 0.5 / block_table_rows));
+    vec4 look = texture2D(block_table, vec2(u, 1.5 / block_table_rows));
+    vec4 flags = texture2D(block_table, vec2(u, 2.5 / block_table_rows));
+    tint.rgb *= mix(vec3(1.0), frag_biome_tint, flags.r);
+    vec2 scales = exp2(floor(look.rg * 255.0 + 0.5) / 16.0 - 8.0);
+    float light = look.a;
+
+    float flow = flags.g;
+    vec2 texcoord = frag_texcoord + flow * time * FLOW_SPEED * vec2(1.0, 0.6);
+    tint.rgb *= 1.0 + flow * FLOW_PULSE * sin(time * 1.5 + (texcoord.x + texcoord.y) * 40.0);
+
+    float noise = mix(texture2D(texture, texcoord * scales.x).r,
+                      texture2D(texture, texcoord * scales.y).r,
+                      look.b);
+    vec3 diffuse = mix(frag_brightness, 1.0, light) * tint.rgb;
+    vec3 color = mix(fog_color, noise * diffuse, frag_fog_factor);
+    /* Fogged water fades to the opaque fog color */
+    gl_FragColor = vec4(color, mix(1.0, tint.a, frag_fog_factor));
+}
@@ -0,0 +1,63 @@
+#version 120
+
+// Like main.vs.glsl, but the face index comes from each vertex because
+// translucent quads are drawn sorted by distance rather than by face.
+
+uniform mat4 view;
+uniform mat4 projection;
+uniform vec3 chunk_position; // relative to the camera's chunk
+uniform vec3 texture_origin; // the chunk's origin, wrapped, see renderer::texture_origin
+uniform float fog_density;
+uniform float fade; // 0 when the chunk is first shown, 1 once it has faded in
+uniform float face_brightness[6]; // indexed by face, from the sun direction
+uniform vec3 biome_tints[8]; // at the chunk's corners, see climate::chunk_tints
+uniform float chunk_size;
+
+attribute vec3 position;
+attribute float blocktype;
+attribute float face;
+
+varying float frag_blocktype;
+varying float frag_brightness;
+varying vec2 frag_texcoord;
+varying float frag_fog_factor;
+varying vec3 frag_biome_tint;
+
+// Same order as mesh::faces
+const vec3 normals[6] = vec3[6](vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0),
+                                vec3(1.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0),
+                                vec3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0));
+
+const float tex_size = 128.0;
+
+/* Blends the corner tints, so they match the neighbors' at the borders */
+vec3 biome_tint() {
+    vec3 t = clamp(position / chunk_size, 0.0, 1.0);
+    vec3 bottom = mix(mix(biome_tints[0], biome_tints[1], t.x),
+                      mix(biome_tints[4], biome_tints[5], t.x), t.z);
+    vec3 top = mix(mix(biome_tints[2], biome_tints[3], t.x),
+                   mix(biome_tints[6], biome_tints[7], t.x), t.z);
+    return mix(bottom, top, t.y);
+}
+
+void main() {
+    vec3 world_position = chunk_position + position;
+    vec4 eye_position = view * vec4(world_position, 1.0);
+
+    gl_Position = projection * eye_position;
+
+    int face_index = int(face + 0.5);
+    vec3 normal = normals[face_index];
+
+    vec3 texture_position = texture_origin + position;
+    frag_texcoord = vec2(dot(normal.zxy, texture_position),
+                         dot(normal.yzx, texture_position)) / tex_size;
+
+    frag_blocktype = blocktype;
+    frag_biome_tint = biome_tint();
+    frag_brightness = face_brightness[face_index];
+
+    frag_fog_factor = clamp(exp2(-pow(length(eye_position), 2.0) * pow(fog_density, 2.0) * 1.44), 0.0, 1.0);
+    /* New chunks emerge from the fog instead of popping in */
+    frag_fog_factor *= fade;
+}
@@ -0,0 +1,10 @@
+#version 120
+
+uniform sampler2D texture;
+
+varying vec2 frag_texcoord;
+varying vec4 frag_color;
+
+void main() {
+    gl_FragColor = vec4(frag_color.rgb, frag_color.a * texture2D(texture, frag_texcoord).r);
+}
@@ -0,0 +1,17 @@
+#version 120
+
+uniform vec2 window_size;
+
+attribute vec2 position; // pixels from the top left
+attribute vec2 texcoord;
+attribute vec4 color;
+
+varying vec2 frag_texcoord;
+varying vec4 frag_color;
+
+void main() {
+    vec2 p = position / window_size * 2.0 - 1.0;
+    gl_Position = vec4(p.x, -p.y, 0.0, 1.0);
+    frag_texcoord = texcoord;
+    frag_color = color;
+}
@@ -0,0 +1,249 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Block types defined in TOML files, added to the registry at startup. R
+//! reads them again for blocks::reload_looks().
+//!
+//! Each file's name is the namespace of the types in it, and each table is
+//! one type. Every key is optional. For example, glow.toml might have:
+//!
+//!     [lamp]
+//!     color = [1.0, 0.9, 0.5]   # red, green, blue and maybe alpha, 0 to 1
+//!     texture = "stone"         # noise pattern of a built-in type
+//!     hardness = 0.3            # seconds to mine
+//!     light = 1.0               # 0 is lit by the sun, 1 always fully bright
+//!
+//!     [moss]
+//!     color = [0.3, 0.6, 0.2]
+//!     texture = "grass"
+//!     biome_tint = true         # shaded by the climate, like grass
+//!
+//!     [magma]
+//!     color = [0.9, 0.2, 0.1]
+//!     texture = "lava"
+//!     light = 1.0
+//!     flow = true               # drifts and flickers, like lava
+//!
+//!     [glass]
+//!     color = [0.8, 0.9, 1.0, 0.3]
+//!     transparent = true
+//!     mineable = false
+//!
+//! which defines glow:lamp, glow:moss, glow:magma and glow:glass. material can also be
+//! "soil", "rock" (the default) or "liquid", which players move through.
+
+extern crate cgmath;
+extern crate toml;
+
+use std::io::File;
+use std::io::fs;
+
+use cgmath::vector::Vector4;
+
+use blocks::{BlockInfo, Look, Material, MaterialSoil, MaterialRock, MaterialLiquid};
+
+static KEYS : [&'static str, ..9] =
+    ["color", "texture", "hardness", "mineable", "material", "transparent", "light", "biome_tint", "flow"];
+
+/// Reads the *.toml files in each of dirs, in order of file name. Files that
+/// can't be read or parsed are skipped with an error message.
+pub fn load_dirs(dirs: &[Path]) -> Vec<BlockInfo> {
+    let mut infos = Vec::new();
+    for dir in dirs.iter() {
+        let mut paths: Vec<Path> = fs::readdir(dir).unwrap_or(Vec::new()).move_iter().
+            filter(|path| path.extension_str() == Some("toml")).collect();
+        paths.sort();
+        for path in paths.iter() {
+            let namespace = path.filestem_str().unwrap_or("");
+            let result = File::open(path).read_to_string().map_err(|e| e.to_string()).
+                and_then(|src| parse(namespace, src.as_slice()));
+            match result {
+                Ok(parsed) => infos.push_all_move(parsed),
+                Err(msg) => println!("Error loading block types from {}: {}", path.display(), msg),
+            }
+        }
+    }
+    infos
+}
+
+/// Parses the block types of a file with the given namespace.
+fn parse(namespace: &str, src: &str) -> Result<Vec<BlockInfo>, String> {
+    if namespace.is_empty() || namespace == "cubeland" ||
+            !namespace.chars().all(|c| c.is_lowercase() || c.is_digit() || c == '_' || c == '-') {
+        return Err(format!("bad namespace \"{}\"", namespace));
+    }
+
+    let mut parser = toml::Parser::new(src);
+    let table = match parser.parse() {
+        Some(table) => table,
+        None => {
+            let e = &parser.errors[0];
+            let (line, col) = parser.to_linecol(e.lo);
+            return Err(format!("line {}, column {}: {}", line + 1, col + 1, e.desc));
+        },
+    };
+
+    let mut infos = Vec::new();
+    for (name, value) in table.iter() {
+        let block = match value.as_table() {
+            Some(block) => block,
+            None => return Err(format!("{} isn't a table", name)),
+        };
+        let id = format!("{}:{}", namespace, name);
+        match parse_block(id.clone(), block) {
+            Ok(info) => infos.push(info),
+            Err(msg) => return Err(format!("{}: {}", id, msg)),
+        }
+    }
+    Ok(infos)
+}
+
+fn parse_block(id: String, block: &toml::TomlTable) -> Result<BlockInfo, String> {
+    for key in block.keys() {
+        if !KEYS.contains(&key.as_slice()) {
+            return Err(format!("unknown key {}", key));
+        }
+    }
+
+    let transparent = match get(block, "transparent") {
+        Some(value) => try!(value.as_bool().ok_or("transparent isn't true or false".to_string())),
+        None => false,
+    };
+
+    let mut color = Vector4::new(1.0f32, 0.0, 1.0, 1.0);
+    match get(block, "color") {
+        Some(value) => {
+            let parts: Vec<Option<f64>> = match value.as_slice() {
+                Some(values) => values.iter().map(number).collect(),
+                None => Vec::new(),
+            };
+            match parts.as_slice() {
+                [Some(r), Some(g), Some(b)] => color = Vector4::new(r as f32, g as f32, b as f32, 1.0),
+                [Some(r), Some(g), Some(b), Some(a)] => color = Vector4::new(r as f32, g as f32, b as f32, a as f32),
+                _ => return Err("color isn't 3 or 4 numbers".to_string()),
+            }
+        },
+        None => {},
+    }
+    if [color.x, color.y, color.z, color.w].iter().any(|&v| v < 0.0 || v > 1.0) {
+        return Err("color components must be from 0 to 1".to_string());
+    }
+    /* Alpha only matters for see-through blocks */
+    if !transparent {
+        color.w = 1.0;
+    }
+
+    let texture = match get(block, "texture") {
+        Some(value) => try!(value.as_str().ok_or("texture isn't a string".to_string())),
+        None => "stone",
+    };
+    let mut look = match Look::new(color, texture) {
+        Some(look) => look,
+        None => return Err(format!("unknown texture {}", texture)),
+    };
+    match get(block, "light") {
+        Some(value) => match number(value) {
+            Some(light) if light >= 0.0 && light <= 1.0 => look.light = light as f32,
+            _ => return Err("light isn't a number from 0 to 1".to_string()),
+        },
+        None => {},
+    }
+    match get(block, "biome_tint") {
+        Some(value) => look.biome_tint = try!(value.as_bool().ok_or("biome_tint isn't true or false".to_string())),
+        None => {},
+    }
+    match get(block, "flow") {
+        Some(value) => look.flow = try!(value.as_bool().ok_or("flow isn't true or false".to_string())),
+        None => {},
+    }
+
+    let mut hardness = match get(block, "hardness") {
+        Some(value) => match number(value) {
+            Some(hardness) if hardness >= 0.0 => Some(hardness),
+            _ => return Err("hardness isn't a number of seconds".to_string()),
+        },
+        None => Some(1.0),
+    };
+    match get(block, "mineable").map(|value| value.as_bool()) {
+        Some(Some(false)) => hardness = None,
+        Some(Some(true)) | None => {},
+        Some(None) => return Err("mineable isn't true or false".to_string()),
+    }
+
+    let material = match get(block, "material") {
+        Some(value) => try!(value.as_str().and_then(parse_material).
+                            ok_or("material isn't soil, rock or liquid".to_string())),
+        None => MaterialRock,
+    };
+
+    Ok(BlockInfo {
+        name: id.clone(),
+        id: id,
+        material: material,
+        opaque: !transparent,
+        height: 1.0,
+        hardness: hardness,
+        color: color,
+        look: look,
+    })
+}
+
+fn get<'a>(block: &'a toml::TomlTable, key: &str) -> Option<&'a toml::Value> {
+    block.find(&key.to_string())
+}
+
+/// Integers are accepted too, so "hardness = 2" works.
+fn number(value: &toml::Value) -> Option<f64> {
+    value.as_float().or(value.as_integer().map(|v| v as f64))
+}
+
+fn parse_material(name: &str) -> Option<Material> {
+    match name {
+        "soil" => Some(MaterialSoil),
+        "rock" => Some(MaterialRock),
+        "liquid" => Some(MaterialLiquid),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_parse() {
+    let src = "[lamp]\ncolor = [1.0, 0.9, 0.5]\nhardness = 2\nlight = 1.0\n\n\
+               [glass]\ncolor = [0.8, 0.9, 1.0, 0.3]\ntransparent = true\nmineable = false\n";
+    let infos = parse("glow", src).unwrap();
+    assert_eq!(infos.len(), 2);
+    /* Tables come out in order of name */
+    let (glass, lamp) = (&infos[0], &infos[1]);
+    assert_eq!(lamp.id.as_slice(), "glow:lamp");
+    assert_eq!(lamp.name.as_slice(), "glow:lamp");
+    assert!(lamp.opaque && lamp.material == MaterialRock);
+    assert_eq!(lamp.hardness, Some(2.0));
+    assert_eq!(lamp.look.light, 1.0);
+    assert_eq!(lamp.color.w, 1.0);
+    assert!(!glass.opaque);
+    assert_eq!(glass.hardness, None);
+    assert_eq!(glass.look.tint.w, 0.3);
+    assert!(!glass.look.biome_tint);
+    assert!(parse("glow", "[moss]\nbiome_tint = true\n").unwrap()[0].look.biome_tint);
+    assert!(!lamp.look.flow);
+    assert!(parse("glow", "[magma]\ntexture = \"lava\"\nflow = true\n").unwrap()[0].look.flow);
+
+    assert!(parse("cubeland", src).is_err());
+    assert!(parse("glow", "[lamp]\nglow = 1.0\n").is_err());
+    assert!(parse("glow", "[lamp]\ntexture = \"marble\"\n").is_err());
+    assert!(parse("glow", "[lamp]\ncolor = [1.0, 0.9]\n").is_err());
+    assert!(parse("glow", "[lamp]\nbiome_tint = 1\n").is_err());
+    assert!(parse("glow", "[lamp]\nflow = \"yes\"\n").is_err());
+    assert!(parse("glow", "[lamp\n").is_err());
+}
@@ -0,0 +1,526 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Registry of block types.
+//!
+//! Each type has a stable string ID like "cubeland:grass" and a numeric ID
+//! that terrain, journals and messages store one byte of per block. Numeric
+//! IDs are handed out as types are registered, so they're only meaningful
+//! within one run. Anything written for another run (saved chunks, messages
+//! to a peer) carries a Palette of the string IDs behind its numbers, and
+//! the reader maps them to its own. The original built-in types always come
+//! first, in the order older saves numbered them, so data without a palette
+//! still reads correctly.
+//!
+//! The registry is built once, by init() or the first time it's used, and
+//! never changes after that, so any task can read it without locking. Only
+//! how types look can change later, with reload_looks().
+
+#![allow(non_uppercase_statics)]
+
+extern crate cgmath;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::IoResult;
+use std::mem;
+use std::sync::atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+use std::sync::one::{Once, ONCE_INIT};
+
+use cgmath::vector::Vector4;
+
+/// Namespace of the built-in types, assumed for IDs given without one.
+static NAMESPACE : &'static str = "cubeland";
+
+/// Numeric IDs are stored in a byte.
+static MAX_BLOCK_TYPES : uint = 256;
+/// The first types are the ones there were before palettes. Their numeric
+/// IDs never change, and palettes leave them out.
+static FIXED_TYPES : uint = 5;
+
+#[deriving(Clone, PartialEq, Eq, Hash)]
+pub struct BlockType {
+    id: u8,
+}
+
+pub static BlockAir : BlockType = BlockType { id: 0 };
+pub static BlockGrass : BlockType = BlockType { id: 1 };
+pub static BlockStone : BlockType = BlockType { id: 2 };
+pub static BlockDirt : BlockType = BlockType { id: 3 };
+pub static BlockWater : BlockType = BlockType { id: 4 };
+/// Built in, but newer than palettes, so saves write them in theirs.
+pub static BlockSnow : BlockType = BlockType { id: 5 };
+pub static BlockSand : BlockType = BlockType { id: 6 };
+pub static BlockLava : BlockType = BlockType { id: 7 };
+
+/// Height of a layer of snow, in blocks.
+static SNOW_HEIGHT : f32 = 0.125;
+
+/// What a block is made of, which decides how things move through it.
+#[deriving(PartialEq, Eq, Show)]
+pub enum Material {
+    MaterialAir,
+    MaterialSoil,
+    MaterialRock,
+    MaterialLiquid,
+    /// A thin layer lying on the ground, which things pass through.
+    MaterialCover,
+}
+
+impl Material {
+    /// Whether players and entities collide with blocks of the material.
+    pub fn is_solid(&self) -> bool {
+        *self != MaterialAir && *self != MaterialLiquid && *self != MaterialCover
+    }
+}
+
+/// Texture patterns of the built-in types: two scales of the noise texture
+/// and how much of the second to mix in.
+static NOISE_PRESETS : [(&'static str, [f32, ..2], f32), ..7] = [
+    ("grass", [0.5, 16.0], 0.8),
+    ("stone", [1.0, 8.0], 0.3),
+    ("dirt", [0.5, 16.0], 0.8),
+    ("water", [2.0, 0.1], 0.8),
+    ("snow", [0.5, 4.0], 0.2),
+    ("sand", [1.0, 32.0], 0.5),
+    ("lava", [1.0, 4.0], 0.6),
+];
+
+/// How the chunk shaders draw a block: its tint times a mix of two samples
+/// of the noise texture at different scales, lit by the sun unless it
+/// glows.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Look {
+    /// Alpha only matters for see-through blocks.
+    pub tint: Vector4<f32>,
+    /// Texture coordinate scales of the two samples.
+    pub noise_scales: [f32, ..2],
+    /// How much of the second sample to mix in.
+    pub noise_mix: f32,
+    /// From 0, lit only by the sun, to 1, always fully bright. With voxel
+    /// textures on, it also lights the faces of the blocks next to it.
+    pub light: f32,
+    /// Whether the tint is shaded by the climate (see climate.rs).
+    pub biome_tint: bool,
+    /// Whether the texture drifts and flickers over time, like something
+    /// molten.
+    pub flow: bool,
+}
+
+impl Look {
+    /// Returns an unlit look with the texture of the built-in type named
+    /// texture, if there is one.
+    pub fn new(tint: Vector4<f32>, texture: &str) -> Option<Look> {
+        NOISE_PRESETS.iter().find(|&&(name, _, _)| name == texture).map(|&(_, scales, mix)| {
+            Look { tint: tint, noise_scales: scales, noise_mix: mix, light: 0.0, biome_tint: false, flow: false }
+        })
+    }
+}
+
+pub struct BlockInfo {
+    /// Stable ID, with its namespace.
+    pub id: String,
+    /// Used in commands and player files: the ID without its namespace if
+    /// it's built in, otherwise the whole ID.
+    pub name: String,
+    pub material: Material,
+    /// Whether it hides the faces of blocks behind it. Other full height
+    /// blocks that aren't air are drawn see-through.
+    pub opaque: bool,
+    /// Height of its shape, in blocks, from the bottom of its cell. Blocks
+    /// less than 1 high, like layers of snow, can't be opaque.
+    pub height: f32,
+    /// Seconds it takes to mine, or None if it can't be mined.
+    pub hardness: Option<f64>,
+    /// Flat color used where the block is drawn without terrain texturing,
+    /// such as on the hotbar and for dropped items.
+    pub color: Vector4<f32>,
+    pub look: Look,
+}
+
+impl BlockInfo {
+    fn builtin(name: &str, material: Material, opaque: bool, hardness: Option<f64>,
+               color: Vector4<f32>, tint: Vector4<f32>) -> BlockInfo {
+        BlockInfo {
+            id: format!("{}:{}", NAMESPACE, name),
+            name: name.to_string(),
+            material: material,
+            opaque: opaque,
+            height: 1.0,
+            hardness: hardness,
+            color: color,
+            /* Air is never drawn */
+            look: Look::new(tint, name).unwrap_or(Look::new(tint, "stone").unwrap()),
+        }
+    }
+}
+
+pub struct BlockRegistry {
+    /// Indexed by numeric ID.
+    blocks: Vec<BlockInfo>,
+    by_id: HashMap<String, BlockType>,
+}
+
+impl BlockRegistry {
+    /// A registry of just the built-in types, with the numeric IDs older
+    /// saves used.
+    fn builtin() -> BlockRegistry {
+        let mut registry = BlockRegistry { blocks: Vec::new(), by_id: HashMap::new() };
+        let mut builtins = vec!(
+            BlockInfo::builtin("air", MaterialAir, false, None,
+                               Vector4::new(0.0, 0.0, 0.0, 0.0), Vector4::new(0.0, 0.0, 0.0, 0.0)),
+            BlockInfo::builtin("grass", MaterialSoil, true, Some(0.6),
+                               Vector4::new(0.2, 0.7, 0.2, 1.0), Vector4::new(0.2, 0.8, 0.2, 1.0)),
+            BlockInfo::builtin("stone", MaterialRock, true, Some(1.5),
+                               Vector4::new(0.5, 0.5, 0.5, 1.0), Vector4::new(0.8, 0.8, 0.8, 1.0)),
+            BlockInfo::builtin("dirt", MaterialSoil, true, Some(0.5),
+                               Vector4::new(0.5, 0.35, 0.2, 1.0), Vector4::new(0.63, 0.35, 0.03, 1.0)),
+            BlockInfo::builtin("water", MaterialLiquid, false, None,
+                               Vector4::new(0.2, 0.3, 0.9, 1.0), Vector4::new(0.1, 0.1, 0.9, 0.6)),
+            BlockInfo::builtin("snow", MaterialCover, false, Some(0.1),
+                               Vector4::new(0.95, 0.95, 1.0, 1.0), Vector4::new(0.95, 0.97, 1.0, 1.0)),
+            BlockInfo::builtin("sand", MaterialSoil, true, Some(0.4),
+                               Vector4::new(0.85, 0.8, 0.55, 1.0), Vector4::new(0.95, 0.88, 0.6, 1.0)),
+            BlockInfo::builtin("lava", MaterialLiquid, true, None,
+                               Vector4::new(1.0, 0.4, 0.05, 1.0), Vector4::new(1.0, 0.45, 0.1, 1.0)),
+        );
+        builtins.get_mut(BlockSnow.id as uint).height = SNOW_HEIGHT;
+        /* Its tint has some red, so a hot, dry climate can turn it yellow */
+        builtins.get_mut(BlockGrass.id as uint).look.biome_tint = true;
+        {
+            let lava = &mut builtins.get_mut(BlockLava.id as uint).look;
+            lava.light = 1.0;
+            lava.flow = true;
+        }
+        for info in builtins.move_iter() {
+            registry.register(info).unwrap();
+        }
+        registry
+    }
+
+    /// Adds a type and returns it. Fails if its ID is taken or the numeric
+    /// IDs have run out.
+    pub fn register(&mut self, info: BlockInfo) -> Result<BlockType, String> {
+        if self.by_id.contains_key(&info.id) {
+            return Err(format!("block type {} is already registered", info.id));
+        }
+        if self.blocks.len() >= MAX_BLOCK_TYPES {
+            return Err(format!("too many block types to add {}", info.id));
+        }
+        let blocktype = BlockType { id: self.blocks.len() as u8 };
+        self.by_id.insert(info.id.clone(), blocktype);
+        self.blocks.push(info);
+        Ok(blocktype)
+    }
+
+    pub fn len(&self) -> uint {
+        self.blocks.len()
+    }
+
+    pub fn info<'a>(&'a self, blocktype: BlockType) -> &'a BlockInfo {
+        &self.blocks[blocktype.id as uint]
+    }
+
+    /// Looks up a type by its ID or name.
+    pub fn find(&self, id: &str) -> Option<BlockType> {
+        if id.contains_char(':') {
+            self.by_id.find(&id.to_string()).map(|&b| b)
+        } else {
+            self.by_id.find(&format!("{}:{}", NAMESPACE, id)).map(|&b| b)
+        }
+    }
+
+    /// Every type, in the order of their numeric IDs.
+    pub fn types(&self) -> Vec<BlockType> {
+        range(0, self.blocks.len()).map(|i| BlockType { id: i as u8 }).collect()
+    }
+}
+
+static mut REGISTRY : *const BlockRegistry = 0 as *const BlockRegistry;
+static REGISTRY_INIT : Once = ONCE_INIT;
+
+/// Builds the registry with extra types after the built-in ones. Call it
+/// before anything looks up a block type; once the registry is built it
+/// only complains.
+pub fn init(extra: Vec<BlockInfo>) {
+    let mut extra = extra;
+    let mut built = false;
+    REGISTRY_INIT.doit(|| {
+        let mut registry = box BlockRegistry::builtin();
+        for info in mem::replace(&mut extra, Vec::new()).move_iter() {
+            match registry.register(info) {
+                Ok(_) => {},
+                Err(msg) => println!("Error adding block type: {}", msg),
+            }
+        }
+        unsafe { REGISTRY = mem::transmute(registry); }
+        built = true;
+    });
+    if !built {
+        println!("Block types were used before {} more were defined; ignoring them", extra.len());
+    }
+}
+
+/// Returns the registry, building it if this is the first use.
+pub fn registry() -> &'static BlockRegistry {
+    REGISTRY_INIT.doit(|| {
+        unsafe { REGISTRY = mem::transmute(box BlockRegistry::builtin()); }
+    });
+    unsafe { &*REGISTRY }
+}
+
+/// The parts of a type reload_looks() replaces.
+struct Appearance {
+    color: Vector4<f32>,
+    look: Look,
+}
+
+/// Address of the Vec<Appearance> by numeric ID that reload_looks() last
+/// made, or 0 if it hasn't been called. Replaced ones are never freed, since
+/// other tasks may still be reading them.
+static APPEARANCES : AtomicUint = INIT_ATOMIC_UINT;
+
+fn appearances() -> Option<&'static Vec<Appearance>> {
+    match APPEARANCES.load(SeqCst) {
+        0 => None,
+        p => Some(unsafe { mem::transmute(p) }),
+    }
+}
+
+/// Replaces the colors and looks of registered types with those of infos,
+/// such as the types' definitions read again after their files were edited.
+/// Everything else about them stays as registered, and types that aren't
+/// registered yet are skipped with a message. Returns how many were
+/// replaced.
+pub fn reload_looks(infos: Vec<BlockInfo>) -> uint {
+    let registry = registry();
+    let mut appearances: Vec<Appearance> = registry.types().iter().map(|blocktype| {
+        Appearance { color: blocktype.color(), look: blocktype.look() }
+    }).collect();
+    let mut count = 0;
+    for info in infos.iter() {
+        match registry.find(info.id.as_slice()) {
+            Some(blocktype) => {
+                *appearances.get_mut(blocktype.id as uint) = Appearance { color: info.color, look: info.look };
+                count += 1;
+            },
+            None => println!("Block type {} is new; restart to add it", info.id),
+        }
+    }
+    unsafe { APPEARANCES.store(mem::transmute(box appearances), SeqCst); }
+    count
+}
+
+impl BlockType {
+    /// Returns the type with numeric ID v, if there is one.
+    pub fn from_u8(v: u8) -> Option<BlockType> {
+        if (v as uint) < registry().len() {
+            Some(BlockType { id: v })
+        } else {
+            None
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        self.id
+    }
+
+    /// Parses an ID, or the name of a built-in type.
+    pub fn from_name(name: &str) -> Option<BlockType> {
+        registry().find(name)
+    }
+
+    pub fn info(&self) -> &'static BlockInfo {
+        registry().info(*self)
+    }
+
+    /// Stable ID, to write wherever the numeric ID isn't enough.
+    pub fn id(&self) -> &'static str {
+        self.info().id.as_slice()
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.info().name.as_slice()
+    }
+
+    /// Whether players and entities collide with the block.
+    pub fn is_solid(&self) -> bool {
+        self.info().material.is_solid()
+    }
+
+    /// Whether the block is drawn see-through, in the translucent pass.
+    pub fn is_translucent(&self) -> bool {
+        *self != BlockAir && !self.info().opaque && self.info().height >= 1.0
+    }
+
+    pub fn height(&self) -> f32 {
+        self.info().height
+    }
+
+    pub fn color(&self) -> Vector4<f32> {
+        match appearances() {
+            Some(appearances) => appearances[self.id as uint].color,
+            None => self.info().color,
+        }
+    }
+
+    /// How the chunk shaders draw it. Use this rather than info().look,
+    /// which reload_looks() doesn't change.
+    pub fn look(&self) -> Look {
+        match appearances() {
+            Some(appearances) => appearances[self.id as uint].look,
+            None => self.info().look,
+        }
+    }
+
+    pub fn hardness(&self) -> Option<f64> {
+        self.info().hardness
+    }
+}
+
+impl fmt::Show for BlockType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The block types behind the numeric IDs in data from another run.
+pub struct Palette {
+    /// By numeric ID in the data.
+    types: Vec<BlockType>,
+}
+
+impl Palette {
+    /// Palette of data written before palettes were, which only has the
+    /// fixed types.
+    pub fn builtin() -> Palette {
+        Palette { types: vec!(BlockAir, BlockGrass, BlockStone, BlockDirt, BlockWater) }
+    }
+
+    /// Writes the string IDs of the registry's numeric IDs, for read(). The
+    /// fixed types are left out.
+    pub fn write(w: &mut Writer) -> IoResult<()> {
+        let types = registry().types();
+        let extra = types.slice_from(FIXED_TYPES);
+        try!(w.write_le_u16(extra.len() as u16));
+        for blocktype in extra.iter() {
+            let id = blocktype.id();
+            try!(w.write_u8(id.len() as u8));
+            try!(w.write_str(id));
+        }
+        Ok(())
+    }
+
+    /// Reads what write() wrote, possibly in a run with other types.
+    /// Types this run doesn't have read as air. Returns None if the data is
+    /// invalid.
+    pub fn read(r: &mut Reader) -> IoResult<Option<Palette>> {
+        let count = try!(r.read_le_u16()) as uint;
+        if FIXED_TYPES + count > MAX_BLOCK_TYPES {
+            return Ok(None);
+        }
+        let mut palette = Palette::builtin();
+        for _ in range(0, count) {
+            let len = try!(r.read_u8()) as uint;
+            let id = match String::from_utf8(try!(r.read_exact(len))) {
+                Ok(id) => id,
+                Err(_) => return Ok(None),
+            };
+            palette.types.push(registry().find(id.as_slice()).unwrap_or(BlockAir));
+        }
+        Ok(Some(palette))
+    }
+
+    /// Returns the type numeric ID v in the data stands for.
+    pub fn get(&self, v: u8) -> Option<BlockType> {
+        self.types.as_slice().get(v as uint).map(|&b| b)
+    }
+
+    /// Rewrites numeric IDs in the data into this run's. Returns false if
+    /// one isn't in the palette.
+    pub fn translate(&self, bytes: &mut [u8]) -> bool {
+        for v in bytes.mut_iter() {
+            match self.get(*v) {
+                Some(blocktype) => *v = blocktype.id,
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+#[test]
+fn test_registry() {
+    assert_eq!(BlockType::from_name("grass"), Some(BlockGrass));
+    assert_eq!(BlockType::from_name("cubeland:water"), Some(BlockWater));
+    assert_eq!(BlockType::from_name("other:grass"), None);
+    assert_eq!(BlockStone.id(), "cubeland:stone");
+    assert!(BlockStone.is_solid() && !BlockWater.is_solid());
+    assert!(BlockWater.is_translucent() && !BlockAir.is_translucent());
+    assert_eq!(BlockType::from_name("snow"), Some(BlockSnow));
+    assert_eq!(BlockType::from_name("sand"), Some(BlockSand));
+    assert!(!BlockSnow.is_solid() && !BlockSnow.is_translucent());
+    /* Lava is a liquid, but can't be seen through */
+    assert!(!BlockLava.is_solid() && !BlockLava.is_translucent());
+    assert!(BlockLava.info().look.light == 1.0 && BlockLava.info().look.flow);
+    assert_eq!(BlockType::from_u8(registry().len() as u8), None);
+
+    let mut registry = BlockRegistry::builtin();
+    let color = Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let info = BlockInfo::builtin("dirt", MaterialSoil, true, None, color, color);
+    assert!(registry.register(info).is_err());
+}
+
+#[test]
+fn test_reload_looks() {
+    /* The same look as before, since other tests share the registry */
+    let info = |id: &str| BlockInfo {
+        id: id.to_string(),
+        name: id.to_string(),
+        material: MaterialSoil,
+        opaque: true,
+        height: 1.0,
+        hardness: None,
+        color: BlockSand.color(),
+        look: BlockSand.look(),
+    };
+    let look = BlockSand.look();
+    assert_eq!(reload_looks(vec!(info("cubeland:sand"), info("other:sand"))), 1);
+    assert_eq!(BlockSand.look(), look);
+    assert!(BlockType::from_name("other:sand").is_none());
+}
+
+#[test]
+fn test_palette() {
+    use std::io::{MemWriter, BufReader};
+
+    let mut w = MemWriter::new();
+    Palette::write(&mut w).unwrap();
+    let palette = Palette::read(&mut BufReader::new(w.get_ref())).unwrap().unwrap();
+    assert_eq!(palette.get(BlockDirt.to_u8()), Some(BlockDirt));
+
+    /* Another run's types, one of which this run doesn't have */
+    let mut w = MemWriter::new();
+    w.write_le_u16(2).unwrap();
+    for id in ["cubeland:stone", "mod:glass"].iter() {
+        w.write_u8(id.len() as u8).unwrap();
+        w.write_str(*id).unwrap();
+    }
+    let palette = Palette::read(&mut BufReader::new(w.get_ref())).unwrap().unwrap();
+    let mut bytes = [6u8, 1, 5];
+    assert!(palette.translate(bytes.as_mut_slice()));
+    assert_eq!(bytes.as_slice(), [BlockAir.to_u8(), BlockGrass.to_u8(), BlockStone.to_u8()].as_slice());
+    let mut unknown = [7u8];
+    assert!(!palette.translate(unknown.as_mut_slice()));
+}
@@ -0,0 +1,324 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Block updates: blocks that change by themselves over time.
+//!
+//! Updates run on a fixed tick and come from two places. Scheduled updates
+//! run a given number of ticks after they're queued, and every block change
+//! queues updates of the changed block and its neighbors so rules can react
+//! to edits. Random ticks update a few random blocks in each loaded chunk,
+//! for slow processes that have no trigger. Both are bounded per tick, so a
+//! flood of updates is spread over several frames instead of stalling one.
+//!
+//! Grass spreads to sky-lit dirt next to it, and dies back to dirt when
+//! something is placed on it. Both are slow, so they only happen on random
+//! ticks.
+
+extern crate cgmath;
+
+use std::collections::{HashSet, PriorityQueue, TreeSet};
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vector3;
+
+use CHUNK_SIZE;
+use chunk::ChunkLoader;
+use coord::{ChunkCoord, FnvHasher};
+use lcg;
+use terrain::{BlockAir, BlockDirt, BlockGrass, BlockSnow, BlockWater, BlockLava};
+
+/// Seconds per block update tick.
+pub static TICK_LENGTH : f64 = 0.05;
+/// Ticks run per call to run() at most. A slower frame rate than this
+/// supports slows the simulation down rather than piling up ticks.
+static MAX_TICKS_PER_RUN : uint = 4;
+/// Scheduled updates run per tick at most. The rest wait for later ticks.
+static MAX_SCHEDULED_PER_TICK : uint = 256;
+/// Random blocks updated per chunk per tick.
+static RANDOM_TICKS_PER_CHUNK : uint = 3;
+/// Chunks given random ticks per tick at most, taken in turn.
+static MAX_RANDOM_TICK_CHUNKS : uint = 128;
+/// Ticks between a block changing and its neighbors' updates.
+static NEIGHBOR_DELAY : u64 = 2;
+
+/// A queued update of block p, due at tick. Updates due at the same tick
+/// run in the order they were queued, by seq.
+struct Scheduled {
+    tick: u64,
+    seq: u64,
+    p: Vector3<i64>,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Scheduled) -> bool {
+        (self.tick, self.seq) == (other.tick, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Scheduled) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The greatest is due first.
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Scheduled) -> Ordering {
+        (other.tick, other.seq).cmp(&(self.tick, self.seq))
+    }
+}
+
+pub struct BlockUpdates {
+    tick: u64,
+    /// Seconds not yet used up by ticks.
+    time: f64,
+    scheduled: PriorityQueue<Scheduled>,
+    next_seq: u64,
+    /// Positions in scheduled, so a block is only queued once.
+    pending: HashSet<ChunkCoord, FnvHasher>,
+    rng: u32,
+    /// Loaded chunks, in the stable order they take turns at random ticks
+    /// in. Kept up to date from ChunkLoader::take_cache_changes().
+    chunks: TreeSet<(i64, i64, i64)>,
+    /// The next chunk to get random ticks is the first at or after this.
+    random_cursor: (i64, i64, i64),
+}
+
+impl BlockUpdates {
+    pub fn new(seed: u32) -> BlockUpdates {
+        BlockUpdates {
+            tick: 0,
+            time: 0.0,
+            scheduled: PriorityQueue::new(),
+            next_seq: 0,
+            pending: HashSet::with_hasher(FnvHasher),
+            rng: seed,
+            chunks: TreeSet::new(),
+            random_cursor: (0, 0, 0),
+        }
+    }
+
+    /// Number of scheduled updates waiting to run.
+    pub fn len(&self) -> uint {
+        self.scheduled.len()
+    }
+
+    /// Queues an update of block p in delay ticks, unless it already has one
+    /// queued.
+    pub fn schedule(&mut self, p: Vector3<i64>, delay: u64) {
+        if self.pending.insert(ChunkCoord::from_vec(p)) {
+            self.scheduled.push(Scheduled { tick: self.tick + delay, seq: self.next_seq, p: p });
+            self.next_seq += 1;
+        }
+    }
+
+    /// Queues updates of block p and its six neighbors.
+    pub fn schedule_around(&mut self, p: Vector3<i64>) {
+        self.schedule(p, NEIGHBOR_DELAY);
+        for &(dx, dy, dz) in [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)].iter() {
+            self.schedule(p.add_v(&Vector3::new(dx, dy, dz)), NEIGHBOR_DELAY);
+        }
+    }
+
+    /// Runs the ticks that dt seconds add up to.
+    pub fn run(&mut self, dt: f64, chunk_loader: &mut ChunkLoader) {
+        self.time += dt;
+        let mut ticks = 0;
+        while self.time >= TICK_LENGTH {
+            if ticks == MAX_TICKS_PER_RUN {
+                self.time = 0.0;
+                break;
+            }
+            self.time -= TICK_LENGTH;
+            self.run_tick(chunk_loader);
+            ticks += 1;
+        }
+    }
+
+    fn run_tick(&mut self, chunk_loader: &mut ChunkLoader) {
+        /* Includes the changes made by the last tick's updates */
+        for p in chunk_loader.take_changed().move_iter() {
+            self.schedule_around(p);
+        }
+        for &(c, loaded) in chunk_loader.take_cache_changes().iter() {
+            if loaded {
+                self.chunks.insert((c.x, c.y, c.z));
+            } else {
+                self.chunks.remove(&(c.x, c.y, c.z));
+            }
+        }
+
+        self.tick += 1;
+
+        for p in self.take_due().move_iter() {
+            scheduled_update(chunk_loader, p);
+        }
+
+        for c in self.take_random_chunks().move_iter() {
+            for _ in range(0, RANDOM_TICKS_PER_CHUNK) {
+                let offset = Vector3::new(self.random_offset(), self.random_offset(), self.random_offset());
+                random_tick(chunk_loader, c.mul_s(CHUNK_SIZE as i64).add_v(&offset));
+            }
+        }
+    }
+
+    /// Removes and returns the positions of the updates that are due, oldest
+    /// first, at most MAX_SCHEDULED_PER_TICK of them.
+    fn take_due(&mut self) -> Vec<Vector3<i64>> {
+        let mut positions = Vec::new();
+        while positions.len() < MAX_SCHEDULED_PER_TICK {
+            match self.scheduled.top() {
+                Some(update) if update.tick <= self.tick => {},
+                _ => break,
+            }
+            let p = self.scheduled.pop().unwrap().p;
+            self.pending.remove(&ChunkCoord::from_vec(p));
+            positions.push(p);
+        }
+        positions
+    }
+
+    /// Returns the chunks whose turn it is for random ticks, at most
+    /// MAX_RANDOM_TICK_CHUNKS of them, and moves the cursor past them.
+    fn take_random_chunks(&mut self) -> Vec<Vector3<i64>> {
+        let count = self.chunks.len().min(MAX_RANDOM_TICK_CHUNKS);
+        /* Wraps around to the start; count is at most all of them, so none repeat */
+        let chunks: Vec<(i64, i64, i64)> = self.chunks.lower_bound(&self.random_cursor).
+            chain(self.chunks.iter()).take(count).map(|&c| c).collect();
+        match chunks.last() {
+            /* The next coordinate in the set's order */
+            Some(&(x, y, z)) => self.random_cursor = (x, y, z + 1),
+            None => {},
+        }
+        chunks.iter().map(|&(x, y, z)| Vector3::new(x, y, z)).collect()
+    }
+
+    fn random_offset(&mut self) -> i64 {
+        (lcg::next(&mut self.rng) >> 16) as i64 % CHUNK_SIZE as i64
+    }
+}
+
+/// Runs the rules for block p after something near it changed.
+fn scheduled_update(chunk_loader: &mut ChunkLoader, p: Vector3<i64>) {
+    settle(chunk_loader, p);
+}
+
+/// Runs the rules for block p when it's picked for a random tick.
+fn random_tick(chunk_loader: &mut ChunkLoader, p: Vector3<i64>) {
+    settle(chunk_loader, p);
+    grow_grass(chunk_loader, p);
+}
+
+/// Grass covered by water or a solid block turns to dirt. Grass under grass
+/// is left alone, since the terrain generator makes it two blocks deep, and
+/// so is grass under snow.
+/// Uncovered, sky-lit dirt next to grass, including diagonally and a block
+/// up or down, turns to grass.
+fn grow_grass(chunk_loader: &mut ChunkLoader, p: Vector3<i64>) {
+    let above = chunk_loader.get_block(p.add_v(&Vector3::unit_y()));
+    let covered = match above {
+        Some(blocktype) => blocktype != BlockAir && blocktype != BlockGrass && blocktype != BlockSnow,
+        /* Unknown until the chunk above loads; leave it alone */
+        None => return,
+    };
+
+    let blocktype = chunk_loader.get_block(p);
+    if blocktype == Some(BlockGrass) && covered {
+        chunk_loader.set_block(p, BlockDirt);
+    } else if blocktype == Some(BlockDirt) && above == Some(BlockAir) && chunk_loader.sky_lit(p) &&
+            next_to_grass(chunk_loader, p) {
+        chunk_loader.set_block(p, BlockGrass);
+    }
+}
+
+fn next_to_grass(chunk_loader: &ChunkLoader, p: Vector3<i64>) -> bool {
+    for dx in range(-1i64, 2) {
+        for dy in range(-1i64, 2) {
+            for dz in range(-1i64, 2) {
+                if (dx, dy, dz) == (0, 0, 0) {
+                    continue;
+                }
+                if chunk_loader.get_block(p.add_v(&Vector3::new(dx, dy, dz))) == Some(BlockGrass) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Water and lava with air below fall one block. Snow with air below is
+/// gone.
+fn settle(chunk_loader: &mut ChunkLoader, p: Vector3<i64>) {
+    let blocktype = match chunk_loader.get_block(p) {
+        Some(blocktype) if blocktype == BlockWater || blocktype == BlockLava || blocktype == BlockSnow => blocktype,
+        _ => return,
+    };
+    let below = p.sub_v(&Vector3::unit_y());
+    if chunk_loader.get_block(below) == Some(BlockAir) {
+        if blocktype != BlockSnow {
+            chunk_loader.set_block(below, blocktype);
+        }
+        chunk_loader.set_block(p, BlockAir);
+    }
+}
+
+#[test]
+fn test_block_updates_schedule() {
+    let mut updates = BlockUpdates::new(1);
+    let a = Vector3::new(0i64, 0, 0);
+    let b = Vector3::new(5i64, 0, 0);
+    updates.schedule(b, 2);
+    updates.schedule(a, 1);
+    updates.schedule(a, 1);
+    assert_eq!(updates.len(), 2);
+
+    updates.tick = 1;
+    assert_eq!(updates.take_due(), vec![a]);
+    updates.tick = 2;
+    assert_eq!(updates.take_due(), vec![b]);
+    assert!(updates.take_due().is_empty());
+
+    /* Past the per-tick bound, updates wait for the next tick */
+    for x in range(0, MAX_SCHEDULED_PER_TICK as i64 + 10) {
+        updates.schedule(Vector3::new(x, 1, 0), 0);
+    }
+    assert_eq!(updates.take_due().len(), MAX_SCHEDULED_PER_TICK);
+    assert_eq!(updates.take_due().len(), 10);
+    assert_eq!(updates.len(), 0);
+}
+
+#[test]
+fn test_block_updates_random_chunks() {
+    let mut updates = BlockUpdates::new(1);
+    assert!(updates.take_random_chunks().is_empty());
+
+    let count = MAX_RANDOM_TICK_CHUNKS as i64 + 2;
+    for x in range(0, count).rev() {
+        updates.chunks.insert((x, 0, 0));
+    }
+    /* Chunks take turns in coordinate order, wrapping around */
+    let first = updates.take_random_chunks();
+    assert_eq!(first.len(), MAX_RANDOM_TICK_CHUNKS);
+    assert_eq!(first[0], Vector3::new(0, 0, 0));
+    assert_eq!(updates.take_random_chunks().slice_to(3),
+               [Vector3::new(count - 2, 0, 0), Vector3::new(count - 1, 0, 0), Vector3::new(0, 0, 0)].as_slice());
+
+    /* An unloaded chunk is skipped */
+    updates.chunks.remove(&(count - 1, 0, 0));
+    updates.random_cursor = (count - 2, 0, 0);
+    assert_eq!(updates.take_random_chunks().slice_to(2), [Vector3::new(count - 2, 0, 0), Vector3::new(0, 0, 0)].as_slice());
+}
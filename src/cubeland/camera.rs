@@ -21,14 +21,116 @@ use cgmath::vector::Vector;
 use cgmath::vector::Vector2;
 use cgmath::vector::Vector3;
 
-static CAMERA_SPEED : f64 = 30.0;
-static FAST_MULTIPLIER : f64 = 10.0;
+/// Limits of the scroll wheel adjustment to a preset's speed.
+static MIN_SPEED_SCALE : f64 = 0.1;
+static MAX_SPEED_SCALE : f64 = 10.0;
+/// Speed scale change per scroll wheel step.
+static SCROLL_SPEED_STEP : f64 = 1.25;
+
+/// Fraction of normal speed the camera moves at underwater.
+static WATER_SPEED_FACTOR : f64 = 0.4;
+
+/// Radians of bob cycle per second while walking.
+static BOB_RATE : f64 = 9.0;
+/// Blocks the view rises at the top of each step.
+static BOB_HEIGHT : f64 = 0.06;
+/// Blocks the view sways to either side.
+static BOB_WIDTH : f64 = 0.03;
+/// Seconds for the bob to fade in when walking starts, or out when it stops.
+static BOB_FADE_TIME : f64 = 0.2;
+
+/// Turns of the camera per pixel of cursor movement at sensitivity 1.
+static TURNS_PER_PIXEL : f64 = 0.0005;
+/// Limits of the mouse sensitivity multiplier.
+pub static MIN_SENSITIVITY : f64 = 0.1;
+pub static MAX_SENSITIVITY : f64 = 10.0;
+
+#[deriving(PartialEq, Show)]
+pub enum SpeedPreset {
+    SpeedWalk,
+    SpeedSprint,
+    SpeedFastFly,
+    SpeedUltra,
+}
+
+impl SpeedPreset {
+    /// Blocks per second.
+    pub fn speed(&self) -> f64 {
+        match *self {
+            SpeedWalk => 5.0,
+            SpeedSprint => 30.0,
+            SpeedFastFly => 300.0,
+            SpeedUltra => 3000.0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SpeedWalk => "walk",
+            SpeedSprint => "sprint",
+            SpeedFastFly => "fast-fly",
+            SpeedUltra => "ultra",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SpeedPreset> {
+        match name {
+            "walk" => Some(SpeedWalk),
+            "sprint" => Some(SpeedSprint),
+            "fast-fly" => Some(SpeedFastFly),
+            "ultra" => Some(SpeedUltra),
+            _ => None,
+        }
+    }
+
+    /// The next faster preset, wrapping around to the slowest.
+    pub fn next(&self) -> SpeedPreset {
+        match *self {
+            SpeedWalk => SpeedSprint,
+            SpeedSprint => SpeedFastFly,
+            SpeedFastFly => SpeedUltra,
+            SpeedUltra => SpeedWalk,
+        }
+    }
+
+    fn is_flying(&self) -> bool {
+        match *self {
+            SpeedFastFly | SpeedUltra => true,
+            _ => false,
+        }
+    }
+}
 
 pub struct Camera {
     pub position : Vector3<f64>,
     pub velocity : Vector3<f64>,
     pub angle : Vector2<f64>,
-    fast : bool,
+    angle_offset : Vector2<f64>,
+    cursor : Vector2<f64>,
+    /// Take the next cursor position as where the cursor is now rather
+    /// than as movement, e.g. after it's been free.
+    recenter : bool,
+    /// Multiplier of how far the camera turns for a given cursor movement.
+    sensitivity : f64,
+    /// Moving the mouse up looks down.
+    invert_y : bool,
+    speed_preset : SpeedPreset,
+    speed_scale : f64,
+    /// While held, move at the next preset's speed.
+    boost : bool,
+    /// Whether the camera is underwater, which slows it down.
+    in_water : bool,
+    /// Where the cursor points the camera. angle follows it, immediately or
+    /// smoothed.
+    target_angle : Vector2<f64>,
+    /// Time constant in seconds of the exponential damping of look input,
+    /// or None to follow the cursor exactly.
+    pub smoothing : Option<f64>,
+    /// Whether the view bobs while walking.
+    pub bobbing : bool,
+    bob_phase : f64,
+    /// How much of the bob is showing, from 0 to 1.
+    bob_amount : f64,
 }
 
 impl Camera {
@@ -37,31 +139,246 @@ impl Camera {
             position: position,
             velocity: Vector3::zero(),
             angle: Vector2::zero(),
-            fast: false,
+            angle_offset: Vector2::zero(),
+            cursor: Vector2::zero(),
+            recenter: false,
+            sensitivity: 1.0,
+            invert_y: false,
+            speed_preset: SpeedSprint,
+            speed_scale: 1.0,
+            boost: false,
+            in_water: false,
+            target_angle: Vector2::zero(),
+            smoothing: None,
+            bobbing: false,
+            bob_phase: 0.0,
+            bob_amount: 0.0,
         }
     }
 
-    pub fn accelerate(&mut self, acceleration: Vector3<f64>) {
-        self.velocity.add_self_v(&acceleration);
+    pub fn boost(&mut self, boost: bool) {
+        self.boost = boost;
+    }
+
+    pub fn set_in_water(&mut self, in_water: bool) {
+        self.in_water = in_water;
+    }
+
+    pub fn speed_preset(&self) -> SpeedPreset {
+        self.speed_preset
     }
 
-    pub fn fast(&mut self, fast: bool) {
-        self.fast = fast;
+    pub fn speed_scale(&self) -> f64 {
+        self.speed_scale
+    }
+
+    /// Switches to preset, keeping the scroll wheel adjustment only if
+    /// scale is given.
+    pub fn set_speed(&mut self, preset: SpeedPreset, scale: Option<f64>) {
+        self.speed_preset = preset;
+        self.speed_scale = scale.unwrap_or(1.0).max(MIN_SPEED_SCALE).min(MAX_SPEED_SCALE);
+    }
+
+    pub fn cycle_speed_preset(&mut self) {
+        let next = self.speed_preset.next();
+        self.set_speed(next, None);
+    }
+
+    /// Adjusts the speed by the scroll wheel offset. Only the flying presets
+    /// can be adjusted.
+    pub fn scroll_speed(&mut self, offset: f64) -> bool {
+        if !self.speed_preset.is_flying() {
+            return false;
+        }
+        let scale = self.speed_scale * SCROLL_SPEED_STEP.powf(offset);
+        self.speed_scale = scale.max(MIN_SPEED_SCALE).min(MAX_SPEED_SCALE);
+        true
+    }
+
+    /// Current speed in blocks per second.
+    pub fn speed(&self) -> f64 {
+        let preset = if self.boost { self.speed_preset.next() } else { self.speed_preset };
+        /* Boosting from ultra wraps around to walk; stay at ultra instead */
+        let speed = preset.speed().max(self.speed_preset.speed());
+        let medium = if self.in_water { WATER_SPEED_FACTOR } else { 1.0 };
+        speed * self.speed_scale * medium
     }
 
     pub fn look(&mut self, cursor: Vector2<f64>) {
-        self.angle.x = ((cursor.y * 0.0005) % 1.0) * std::f64::consts::PI * 2.0;
-        self.angle.y = ((cursor.x * 0.0005) % 1.0) * std::f64::consts::PI * 2.0;
+        if self.recenter {
+            /* Where the cursor went while it was free, or where grabbing
+               it put it, isn't the player turning */
+            self.recenter = false;
+            self.cursor = cursor;
+            self.angle_offset = self.target_angle.sub_v(&self.cursor_angle(cursor));
+            return;
+        }
+        self.cursor = cursor;
+        self.target_angle = self.cursor_angle(cursor).add_v(&self.angle_offset);
+        if self.smoothing.is_none() {
+            self.angle = self.target_angle;
+        }
     }
 
-    pub fn tick(&mut self, tick_length: f64) {
-        let mut speed = CAMERA_SPEED;
-        if self.fast {
-            speed *= FAST_MULTIPLIER;
+    /// Ignores how far the cursor moved before the next call to look(), for
+    /// when it's grabbed again.
+    pub fn recenter(&mut self) {
+        self.recenter = true;
+    }
+
+    /// Points the camera at angle without moving the cursor; later cursor
+    /// movement is applied relative to it.
+    pub fn set_angle(&mut self, angle: Vector2<f64>) {
+        self.angle_offset = angle.sub_v(&self.cursor_angle(self.cursor));
+        self.angle = angle;
+        self.target_angle = angle;
+    }
+
+    pub fn sensitivity(&self) -> f64 {
+        self.sensitivity
+    }
+
+    /// Clamped to MIN_SENSITIVITY and MAX_SENSITIVITY. The camera doesn't
+    /// turn when it changes.
+    pub fn set_sensitivity(&mut self, sensitivity: f64) {
+        self.sensitivity = sensitivity.max(MIN_SENSITIVITY).min(MAX_SENSITIVITY);
+        self.angle_offset = self.target_angle.sub_v(&self.cursor_angle(self.cursor));
+    }
+
+    pub fn invert_y(&self) -> bool {
+        self.invert_y
+    }
+
+    pub fn set_invert_y(&mut self, invert_y: bool) {
+        self.invert_y = invert_y;
+        self.angle_offset = self.target_angle.sub_v(&self.cursor_angle(self.cursor));
+    }
+
+    /// Camera angle for a cursor position, before angle_offset.
+    fn cursor_angle(&self, cursor: Vector2<f64>) -> Vector2<f64> {
+        let turns = TURNS_PER_PIXEL * self.sensitivity;
+        let y = if self.invert_y { -cursor.y } else { cursor.y };
+        Vector2 {
+            x: ((y * turns) % 1.0) * std::f64::consts::PI * 2.0,
+            y: ((cursor.x * turns) % 1.0) * std::f64::consts::PI * 2.0,
         }
+    }
+
+    /// Position to render from: the camera position plus any view bob.
+    pub fn eye(&self) -> Vector3<f64> {
+        if self.bob_amount == 0.0 {
+            return self.position;
+        }
+        let rise = self.bob_phase.sin().abs() * BOB_HEIGHT * self.bob_amount;
+        let sway = self.bob_phase.cos() * BOB_WIDTH * self.bob_amount;
+        /* Sway along the camera's right, level with the ground */
+        let right = Vector3::new(self.angle.y.cos(), 0.0, self.angle.y.sin());
+        self.position.add_v(&right.mul_s(sway)).add_v(&Vector3::new(0.0, rise, 0.0))
+    }
+
+    /// Unit vector in the direction the camera is looking.
+    pub fn direction(&self) -> Vector3<f64> {
+        let inv_camera_rotation = Matrix3::from_euler(rad(-self.angle.x), rad(-self.angle.y), rad(0.0));
+        inv_camera_rotation.mul_v(&Vector3::new(0.0, 0.0, -1.0))
+    }
 
+    /// Velocity in world space, in blocks per second.
+    pub fn absolute_velocity(&self) -> Vector3<f64> {
         let inv_camera_rotation = Matrix3::from_euler(rad(-self.angle.x), rad(-self.angle.y), rad(0.0));
-        let absolute_camera_velocity = inv_camera_rotation.mul_v(&self.velocity).mul_s(speed).mul_s(tick_length);
+        inv_camera_rotation.mul_v(&self.velocity).mul_s(self.speed())
+    }
+
+    pub fn tick(&mut self, tick_length: f64) {
+        let absolute_camera_velocity = self.absolute_velocity().mul_s(tick_length);
         self.position.add_self_v(&absolute_camera_velocity);
+
+        self.angle = match self.smoothing {
+            Some(time) if time > 0.0 => {
+                let t = 1.0 - (-tick_length / time).exp();
+                self.angle.add_v(&self.target_angle.sub_v(&self.angle).mul_s(t))
+            },
+            _ => self.target_angle,
+        };
+
+        let walking = self.bobbing && self.speed_preset == SpeedWalk && self.velocity != Vector3::zero();
+        let step = tick_length / BOB_FADE_TIME;
+        self.bob_amount = if walking {
+            (self.bob_amount + step).min(1.0)
+        } else {
+            (self.bob_amount - step).max(0.0)
+        };
+        if self.bob_amount > 0.0 {
+            self.bob_phase = (self.bob_phase + BOB_RATE * tick_length) % (2.0 * std::f64::consts::PI);
+        }
     }
 }
+
+#[test]
+fn test_camera_smoothing() {
+    let mut camera = Camera::new(Vector3::zero());
+    camera.look(Vector2::new(0.0, 100.0));
+    camera.set_angle(Vector2::zero());
+    camera.smoothing = Some(0.1);
+    camera.look(Vector2::new(0.0, 200.0));
+    let target = camera.target_angle;
+    assert_eq!(camera.angle, Vector2::zero());
+
+    /* One time constant covers about 63% of the way */
+    camera.tick(0.1);
+    let progress = camera.angle.y / target.y;
+    assert!(progress > 0.6 && progress < 0.65);
+}
+
+#[test]
+fn test_view_bobbing() {
+    let mut camera = Camera::new(Vector3::zero());
+    camera.set_speed(SpeedWalk, None);
+    camera.velocity = Vector3::new(0.0, 0.0, -1.0);
+    camera.tick(0.1);
+    assert_eq!(camera.eye(), camera.position);
+
+    camera.bobbing = true;
+    camera.tick(0.1);
+    assert!(camera.eye() != camera.position);
+
+    camera.velocity = Vector3::zero();
+    camera.tick(BOB_FADE_TIME);
+    assert_eq!(camera.eye(), camera.position);
+}
+
+#[test]
+fn test_camera_recenter() {
+    let mut camera = Camera::new(Vector3::zero());
+    camera.look(Vector2::new(100.0, 0.0));
+    let angle = camera.angle;
+
+    /* The cursor jumped while it was free */
+    camera.recenter();
+    camera.look(Vector2::new(900.0, 0.0));
+    assert_eq!(camera.angle, angle);
+
+    /* Movement after that turns the camera as usual */
+    camera.look(Vector2::new(1000.0, 0.0));
+    assert!(camera.angle.y > angle.y);
+}
+
+#[test]
+fn test_camera_sensitivity() {
+    let mut camera = Camera::new(Vector3::zero());
+    camera.look(Vector2::new(0.0, 0.0));
+    camera.look(Vector2::new(10.0, 10.0));
+    let normal = camera.angle;
+
+    /* Changing the settings doesn't turn the camera */
+    camera.set_sensitivity(2.0);
+    camera.set_invert_y(true);
+    assert_eq!(camera.angle, normal);
+
+    camera.look(Vector2::new(20.0, 20.0));
+    let turned = camera.angle.sub_v(&normal);
+    assert!((turned.y - normal.y * 2.0).abs() < 1e-9);
+    assert!((turned.x + normal.x * 2.0).abs() < 1e-9);
+
+    camera.set_sensitivity(100.0);
+    assert_eq!(camera.sensitivity(), MAX_SENSITIVITY);
+}
@@ -0,0 +1,214 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Camera paths for flythroughs.
+//!
+//! A path is a list of keyframes dropped in-game, each a camera position and
+//! angle. Playback spends the same time on each pair of keyframes. It moves
+//! along a Catmull-Rom spline through the positions, so the camera passes
+//! through every keyframe without sharp turns. The view direction is
+//! spherically interpolated between keyframes. The camera never rolls, so
+//! its direction is all there is to its orientation.
+
+extern crate cgmath;
+
+use cgmath::vector::{EuclideanVector, Vector};
+use cgmath::vector::Vector2;
+use cgmath::vector::Vector3;
+
+use wrap::Wrap;
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct Keyframe {
+    pub position: Vector3<f64>,
+    /// Camera angle, as in Camera.
+    pub angle: Vector2<f64>,
+}
+
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    /// Seconds playback takes from the first keyframe to the last.
+    duration: f64,
+    /// Seconds since playback started, or None when not playing.
+    time: Option<f64>,
+}
+
+impl CameraPath {
+    pub fn new() -> CameraPath {
+        CameraPath {
+            keyframes: Vec::new(),
+            duration: 0.0,
+            time: None,
+        }
+    }
+
+    pub fn len(&self) -> uint {
+        self.keyframes.len()
+    }
+
+    /// Appends a keyframe. In a wrapped world the position is moved next to
+    /// the previous keyframe, so the path takes the short way across the
+    /// edge of the world.
+    pub fn add(&mut self, position: Vector3<f64>, angle: Vector2<f64>, wrap: &Wrap) {
+        let position = match self.keyframes.last() {
+            Some(last) => last.position.add_v(&wrap.position_offset(position, last.position)),
+            None => position,
+        };
+        self.keyframes.push(Keyframe { position: position, angle: angle });
+    }
+
+    /// Removes the last keyframe. Returns false if there were none.
+    pub fn remove_last(&mut self) -> bool {
+        self.stop();
+        self.keyframes.pop().is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.stop();
+        self.keyframes.clear();
+    }
+
+    /// Starts playback from the first keyframe, taking duration seconds to
+    /// reach the last. Returns false if there are fewer than two keyframes.
+    pub fn play(&mut self, duration: f64) -> bool {
+        if self.keyframes.len() < 2 {
+            return false;
+        }
+        self.duration = duration;
+        self.time = Some(0.0);
+        true
+    }
+
+    pub fn stop(&mut self) {
+        self.time = None;
+    }
+
+    pub fn playing(&self) -> bool {
+        self.time.is_some()
+    }
+
+    /// Advances playback by dt seconds and returns where the camera should
+    /// be, or None if the path isn't playing. Playback stops once it returns
+    /// the last keyframe.
+    pub fn tick(&mut self, dt: f64) -> Option<Keyframe> {
+        let time = match self.time {
+            Some(time) => time + dt,
+            None => return None,
+        };
+        let t = if self.duration > 0.0 { time / self.duration } else { 1.0 };
+        self.time = if t < 1.0 { Some(time) } else { None };
+        Some(self.sample(t.min(1.0)))
+    }
+
+    /// The camera at fraction t of the way along the path.
+    fn sample(&self, t: f64) -> Keyframe {
+        let n = self.keyframes.len();
+        if n == 1 {
+            return self.keyframes[0].clone();
+        }
+
+        let s = t * (n - 1) as f64;
+        let i = (s.floor() as uint).min(n - 2);
+        let u = s - i as f64;
+
+        /* The ends repeat their keyframes for the missing neighbors */
+        let key = |j: int| self.keyframes[j.max(0).min(n as int - 1) as uint].clone();
+        let i = i as int;
+        let position = catmull_rom(key(i - 1).position, key(i).position,
+                                   key(i + 1).position, key(i + 2).position, u);
+        let direction = slerp(direction(key(i).angle), direction(key(i + 1).angle), u);
+        Keyframe { position: position, angle: angle(direction) }
+    }
+}
+
+/// Point at fraction u of the way from p1 to p2 on the uniform Catmull-Rom
+/// spline through p0, p1, p2 and p3.
+fn catmull_rom(p0: Vector3<f64>, p1: Vector3<f64>, p2: Vector3<f64>, p3: Vector3<f64>,
+               u: f64) -> Vector3<f64> {
+    let u2 = u * u;
+    let u3 = u2 * u;
+    p0.mul_s(-u3 + 2.0 * u2 - u).
+        add_v(&p1.mul_s(3.0 * u3 - 5.0 * u2 + 2.0)).
+        add_v(&p2.mul_s(-3.0 * u3 + 4.0 * u2 + u)).
+        add_v(&p3.mul_s(u3 - u2)).
+        mul_s(0.5)
+}
+
+/// Rotates unit vector a a fraction t of the way toward unit vector b.
+fn slerp(a: Vector3<f64>, b: Vector3<f64>, t: f64) -> Vector3<f64> {
+    let theta = a.dot(&b).max(-1.0).min(1.0).acos();
+    let sin = theta.sin();
+    if sin < 1e-6 {
+        /* Same or opposite directions, where any rotation axis would do */
+        return if t < 0.5 { a } else { b };
+    }
+    a.mul_s(((1.0 - t) * theta).sin() / sin).add_v(&b.mul_s((t * theta).sin() / sin))
+}
+
+/// Unit vector a camera with the given angle looks along, matching
+/// Camera::direction.
+fn direction(angle: Vector2<f64>) -> Vector3<f64> {
+    Vector3::new(angle.x.cos() * angle.y.sin(), -angle.x.sin(), -angle.x.cos() * angle.y.cos())
+}
+
+/// Camera angle looking along unit vector d.
+fn angle(d: Vector3<f64>) -> Vector2<f64> {
+    Vector2::new(-d.y.max(-1.0).min(1.0).asin(), d.x.atan2(-d.z))
+}
+
+#[test]
+fn test_camera_path() {
+    use camera::Camera;
+
+    let close = |a: Vector3<f64>, b: Vector3<f64>| a.sub_v(&b).length() < 1e-9;
+
+    let angle_a = Vector2::new(0.3, 1.0);
+    let mut camera = Camera::new(Vector3::zero());
+    camera.set_angle(angle_a);
+    assert!(close(direction(angle_a), camera.direction()));
+    assert!(close(direction(angle(direction(angle_a))), direction(angle_a)));
+
+    let mut path = CameraPath::new();
+    path.add(Vector3::new(0.0, 0.0, 0.0), angle_a, &Wrap::none());
+    assert!(!path.play(1.0));
+    path.add(Vector3::new(10.0, 0.0, 0.0), Vector2::new(0.0, 2.0), &Wrap::none());
+    path.add(Vector3::new(10.0, 10.0, 0.0), Vector2::new(-0.5, 2.0), &Wrap::none());
+
+    /* Passes through every keyframe */
+    for (i, &t) in [0.0, 0.5, 1.0].iter().enumerate() {
+        let key = path.sample(t);
+        assert!(close(key.position, path.keyframes[i].position));
+        assert!(close(direction(key.angle), direction(path.keyframes[i].angle)));
+    }
+
+    /* Turns at a steady rate between keyframes */
+    let a = direction(path.keyframes[0].angle);
+    let b = direction(path.keyframes[1].angle);
+    let middle = direction(path.sample(0.25).angle);
+    assert!((middle.dot(&a) - middle.dot(&b)).abs() < 1e-9);
+
+    assert!(path.play(2.0));
+    assert!(close(path.tick(1.0).unwrap().position, path.keyframes[1].position));
+    assert!(close(path.tick(1.5).unwrap().position, path.keyframes[2].position));
+    assert!(!path.playing());
+    assert_eq!(path.tick(0.1), None);
+
+    /* Takes the short way across a wrapped world's edge */
+    let wrap = Wrap::new(8);
+    let period = wrap.period().unwrap() as f64;
+    let mut path = CameraPath::new();
+    path.add(Vector3::new(period - 6.0, 0.0, 5.0), Vector2::zero(), &wrap);
+    path.add(Vector3::new(2.0, 0.0, 5.0), Vector2::zero(), &wrap);
+    assert_eq!(path.keyframes[1].position, Vector3::new(period + 2.0, 0.0, 5.0));
+}
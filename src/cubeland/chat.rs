@@ -0,0 +1,205 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-game chat: an input line and the recent message history, drawn with
+//! the UI overlay.
+//!
+//! While the input line is open it takes all keyboard input. A submitted
+//! line starting with '/' is a console command rather than a message.
+
+extern crate glfw;
+extern crate cgmath;
+
+use std::collections::RingBuf;
+use std::collections::Deque;
+
+use cgmath::vector::Vector4;
+
+use protocol::MAX_CHAT_LENGTH;
+use ui;
+use ui::Ui;
+
+static MAX_HISTORY : uint = 100;
+static VISIBLE_LINES : uint = 10;
+
+/// Messages start fading out this long after arriving, in nanoseconds, and
+/// are gone FADE_LENGTH later. The whole history is shown while typing.
+static FADE_START : u64 = 8*1000*1000*1000;
+static FADE_LENGTH : u64 = 2*1000*1000*1000;
+
+static MARGIN : f32 = 8.0;
+
+/// What the player submitted from the input line.
+pub enum ChatInput {
+    ChatMessage(String),
+    ChatCommand(Vec<String>),
+}
+
+struct ChatLine {
+    text: String,
+    time: u64,
+}
+
+pub struct Chat {
+    history: RingBuf<ChatLine>,
+    input: Option<String>,
+    /// The key that opens the input line also arrives as a character event,
+    /// which shouldn't be typed into it.
+    skip_char: bool,
+}
+
+impl Chat {
+    pub fn new() -> Chat {
+        Chat {
+            history: RingBuf::new(),
+            input: None,
+            skip_char: false,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.input.is_some()
+    }
+
+    /// Opens the input line, starting it with initial.
+    pub fn open(&mut self, initial: &str) {
+        self.input = Some(initial.to_string());
+        self.skip_char = true;
+    }
+
+    pub fn close(&mut self) {
+        self.input = None;
+    }
+
+    /// Handles a key press while the input line is open. Returns the
+    /// submitted input when the key was Enter.
+    pub fn key(&mut self, key: glfw::Key) -> Option<ChatInput> {
+        self.skip_char = false;
+        match key {
+            glfw::KeyEnter => {
+                let line = self.input.take().unwrap_or(String::new());
+                parse_input(line.as_slice())
+            },
+            glfw::KeyEscape => {
+                self.close();
+                None
+            },
+            glfw::KeyBackspace => {
+                match self.input {
+                    Some(ref mut input) => { input.pop_char(); },
+                    None => {},
+                }
+                None
+            },
+            _ => None,
+        }
+    }
+
+    /// Handles a typed character while the input line is open.
+    pub fn char(&mut self, c: char) {
+        if self.skip_char {
+            self.skip_char = false;
+            return;
+        }
+
+        match self.input {
+            Some(ref mut input) => {
+                if c >= ' ' && input.len() + c.len_utf8_bytes() <= MAX_CHAT_LENGTH {
+                    input.push_char(c);
+                }
+            },
+            None => {},
+        }
+    }
+
+    /// Adds a message from sender to the history.
+    pub fn add_message(&mut self, sender: &str, text: &str, now: u64) {
+        self.add_line(format!("<{}> {}", sender, text), now);
+    }
+
+    /// Adds a line of text to the history as is, for notices from the game.
+    pub fn add_line(&mut self, text: String, now: u64) {
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(ChatLine { text: text, time: now });
+    }
+
+    /// Queues the input line and recent history at the bottom left of the
+    /// window.
+    pub fn draw(&self, ui: &mut Ui, now: u64) {
+        let mut y = ui.window_size().y as f32 - MARGIN - ui::line_height();
+
+        match self.input {
+            Some(ref input) => {
+                let line = format!("> {}_", input);
+                ui.rect(MARGIN - 2.0, y - 2.0,
+                        ui::text_width(line.as_slice()) + 4.0, ui::line_height() + 4.0,
+                        Vector4::new(0.0, 0.0, 0.0, 0.5));
+                ui.text(MARGIN, y, line.as_slice(), Vector4::new(1.0, 1.0, 1.0, 1.0));
+            },
+            None => {},
+        }
+        y -= ui::line_height() + 4.0;
+
+        for line in self.history.iter().rev().take(VISIBLE_LINES) {
+            let alpha = if self.is_open() { 1.0 } else { fade(now - line.time) };
+            if alpha <= 0.0 {
+                /* Older lines have faded too */
+                break;
+            }
+            ui.rect(MARGIN - 2.0, y, ui::text_width(line.text.as_slice()) + 4.0, ui::line_height(),
+                    Vector4::new(0.0, 0.0, 0.0, 0.3 * alpha));
+            ui.text(MARGIN, y, line.text.as_slice(), Vector4::new(1.0, 1.0, 1.0, alpha));
+            y -= ui::line_height();
+        }
+    }
+}
+
+fn parse_input(line: &str) -> Option<ChatInput> {
+    let line = line.trim();
+    if line.is_empty() {
+        None
+    } else if line.starts_with("/") {
+        let words: Vec<String> = line.slice_from(1).words().map(|w| w.to_string()).collect();
+        if words.is_empty() { None } else { Some(ChatCommand(words)) }
+    } else {
+        Some(ChatMessage(line.to_string()))
+    }
+}
+
+/// Opacity of a message of the given age.
+fn fade(age: u64) -> f32 {
+    if age < FADE_START {
+        1.0
+    } else if age < FADE_START + FADE_LENGTH {
+        1.0 - (age - FADE_START) as f32 / FADE_LENGTH as f32
+    } else {
+        0.0
+    }
+}
+
+#[test]
+fn test_parse_input() {
+    match parse_input("  hello there ") {
+        Some(ChatMessage(text)) => assert_eq!(text.as_slice(), "hello there"),
+        _ => fail!("expected a message"),
+    }
+    match parse_input("/world load foo") {
+        Some(ChatCommand(words)) => assert_eq!(words, vec!("world".to_string(), "load".to_string(), "foo".to_string())),
+        _ => fail!("expected a command"),
+    }
+    assert!(parse_input("   ").is_none());
+    assert!(parse_input("/").is_none());
+}
@@ -14,63 +14,350 @@
 
 extern crate cgmath;
 
-use std::comm::{DuplexStream, duplex};
+use std::cmp;
+use std::comm::{DuplexStream, duplex, Empty, Disconnected};
+use std::mem;
+use std::num::Integer;
 use std::rt::default_sched_threads;
-use std::hash::sip::hash;
+use std::hash::Hasher;
 use std::collections::hashmap::{HashSet, HashMap};
+use std::collections::{Deque, PriorityQueue, RingBuf};
 
 use time::precise_time_ns;
 
-use cgmath::vector::Vector;
+use cgmath::vector::{EuclideanVector, Vector};
 use cgmath::vector::Vector3;
 
 use CHUNK_SIZE;
 use VISIBLE_RADIUS;
-use terrain::Terrain;
-use terrain::TerrainGenerator;
-use mesh::Mesh;
+use columns::ColumnIndex;
+use coord::{ChunkCoord, FnvHasher};
+use decoration;
+use diskcache;
+use diskcache::{CacheRequest, CacheStore, CacheTouch};
+use entity::{Entity, Entities};
+use jobs::FrameBudget;
+use journal::Journal;
+use protocol;
+use region::{Region, REGION_SIZE, region_coord, unwrap_chunk_coord};
+use wrap::Wrap;
+use worldborder::WorldBorder;
+use worldheight::WorldHeight;
+use terrain::{Biome, BlockType, BlockHistogram};
+use terrain::{Terrain, TERRAIN_BYTES};
+use terrain::{TerrainGenerator, TerrainNoise, TerrainQuality};
+use mesh::{Mesh, MeshBuffers};
 use ratelimiter::RateLimiter;
+use store;
+use store::{SavedTerrain, SavedJournal};
+use voxeltexture::VoxelTexture;
 
 static MAX_CHUNKS : uint = (VISIBLE_RADIUS*2)*(VISIBLE_RADIUS*2)*(VISIBLE_RADIUS*2)*2;
-static MAX_INFLIGHT : uint = 8;
+/// Bounds on the chunks sent to the workers and not back yet. See
+/// inflight_limit().
+static MIN_INFLIGHT_PER_WORKER : uint = 2;
+static MAX_INFLIGHT : uint = 256;
+/// Seconds of generation to keep queued for each worker.
+static TARGET_QUEUE_TIME : f64 = 0.05;
+
+/// Upper bound on LRU queue operations per call to work(), so that a burst of
+/// expired chunks is evicted over several frames instead of all at once.
+static MAX_EVICTION_STEPS : uint = 32;
+
+/// Blocks above a position that sky_lit() looks through.
+static SKY_SCAN_HEIGHT : i64 = 64;
+
+/// Chunks within this many chunks of the camera keep their translucent
+/// quads sorted for it. Farther away the order rarely changes.
+static TRANSLUCENT_SORT_RADIUS : i64 = 3;
+/// Most chunks whose translucent quads are re-sorted per frame.
+static MAX_TRANSLUCENT_SORTS : uint = 4;
+/// How far the camera moves relative to a chunk before it's re-sorted.
+static TRANSLUCENT_RESORT_DISTANCE : f32 = 1.0;
+
+/// Nanoseconds an unchanged request() is skipped for before it's rescanned
+/// anyway, touching the chunks in it.
+static REQUEST_REFRESH_TIME : u64 = 1000 * 1000 * 1000;
+/// Nanoseconds a queued chunk that's no longer requested stays queued.
+static PENDING_MAX_AGE : u64 = 2 * 1000 * 1000 * 1000;
+
+/// Weight of the newest chunk in the running average of generation times.
+static GEN_TIME_WEIGHT : f64 = 0.1;
+
+/// Nanoseconds before a chunk whose worker failed is requested again. The
+/// delay doubles with each failure, up to MAX_RETRY_DELAY.
+static RETRY_DELAY : u64 = 1000 * 1000 * 1000;
+static MAX_RETRY_DELAY : u64 = 64 * 1000 * 1000 * 1000;
+
+/// Chunks sent to the workers at once in deterministic mode, however many
+/// workers there are.
+static DETERMINISTIC_INFLIGHT : uint = 8;
+
+/// A chunk for a worker to load, or generate if it hasn't been saved.
+struct ChunkRequest {
+    coord: Vector3<i64>,
+    /// Density sampling stride.
+    stride: uint,
+    /// Generate without caves, to be refined later.
+    coarse: bool,
+}
+
+/// Where a chunk is on its way through the loader, for debugging views.
+#[deriving(PartialEq, Show)]
+pub enum ChunkState {
+    /// Requested, but not sent to a worker yet.
+    ChunkQueued,
+    /// Being loaded or generated by a worker.
+    ChunkInflight,
+    ChunkCoarse,
+    ChunkLoaded,
+    /// Loaded, with edits that haven't been saved.
+    ChunkDirty,
+    /// Its worker failed, and it's waiting to be retried.
+    ChunkFailed,
+}
+
+struct Worker {
+    stream: DuplexStream<ChunkRequest, (Box<Chunk>, u64)>,
+    /// Requests sent that haven't come back yet, oldest first. If the
+    /// worker fails, it was on the first one.
+    queue: RingBuf<ChunkRequest>,
+}
+
+/// A chunk whose worker failed while loading or generating it.
+struct FailedChunk {
+    failures: uint,
+    /// When it can be requested again.
+    retry_time: u64,
+}
 
 pub struct ChunkLoader {
-    pub cache : HashMap<(i64, i64, i64), Box<Chunk>>,
+    pub cache : HashMap<ChunkCoord, Box<Chunk>, FnvHasher>,
     needed_chunks : Vec<Vector3<i64>>,
-    inflight: HashSet<(i64, i64, i64)>,
-    streams: Vec<DuplexStream<Vector3<i64>, Box<Chunk>>>,
+    /// The chunks in needed_chunks and when each was last requested.
+    pending: HashMap<ChunkCoord, u64, FnvHasher>,
+    /// Coordinates passed to the last request() that was scanned, and when.
+    last_request: Vec<Vector3<i64>>,
+    last_request_time: u64,
+    /// A chunk in the last request wasn't queued because it was being saved.
+    /// The request is repeated once the saver catches up.
+    waiting_on_save: bool,
+    regions: HashMap<ChunkCoord, Vec<ChunkCoord>, FnvHasher>,
+    inflight: HashSet<ChunkCoord, FnvHasher>,
+    lru: PriorityQueue<LruEntry>,
+    workers: Vec<Worker>,
+    /// Chunks whose worker failed, until they load.
+    failed: HashMap<ChunkCoord, FailedChunk, FnvHasher>,
+    /// Load chunks in the order they were sent to the workers, whatever
+    /// order they finish in. See set_deterministic().
+    deterministic: bool,
+    /// The worker each unanswered request went to, in the order they were
+    /// sent. Only kept in deterministic mode.
+    sent: RingBuf<uint>,
+    saver: DuplexStream<(Vector3<i64>, Vec<u8>), Vector3<i64>>,
+    /// Chunks queued to the saver that it hasn't finished writing yet.
+    saving: HashSet<ChunkCoord, FnvHasher>,
+    /// Directory of the disk cache and the task that writes it, if it's on.
+    cache_dir: Option<Path>,
+    cache_writer: Option<Sender<CacheRequest>>,
     load_rate_display_limiter: RateLimiter,
     load_rate_counter: uint,
+    /// Used by load_sync() on the calling task; the workers have their own.
+    generator: TerrainGenerator,
+    /// Used for meshing on the calling task; the workers have their own.
+    mesh_buffers: MeshBuffers,
+    /// Give each chunk's mesh a VoxelTexture of its terrain. Costs about
+    /// 40KB of video memory per chunk.
+    voxel_textures: bool,
+    /// Send the workers no new chunks, e.g. while the window is in the
+    /// background. Requests still queue up.
+    paused: bool,
+    quality: TerrainQuality,
+    /// Chunk containing the camera, for picking how finely to generate.
+    detail_center: Vector3<i64>,
+    /// Running average of the seconds a worker takes to produce a full
+    /// detail chunk.
+    gen_time: f64,
+    /// Coarse chunks waiting for their full terrain.
+    refine: Vec<Vector3<i64>>,
+    /// Blocks decorations placed in chunks that weren't loaded yet, by chunk.
+    /// They're lost if the game exits before the chunk loads.
+    pending_decorations: HashMap<ChunkCoord, Vec<(Vector3<i64>, BlockType)>, FnvHasher>,
+    chunk_dir: Path,
+    /// Chunks inserted since the last call to take_loaded().
+    loaded: Vec<Vector3<i64>>,
+    /// Blocks set since the last call to take_changed().
+    changed: Vec<Vector3<i64>>,
+    /// Chunks added to the cache (true) or removed from it (false) since
+    /// the last call to take_cache_changes(), in order.
+    cache_changes: Vec<(Vector3<i64>, bool)>,
+    /// Mesh buffers uploaded since the last call to take_uploads().
+    uploads: uint,
+    /// Top block of each column of the loaded chunks.
+    columns: ColumnIndex,
+    pub wrap: Wrap,
+    /// Chunks outside it are never requested.
+    pub world_height: WorldHeight,
+    /// Chunks outside it are never requested either.
+    pub border: Option<WorldBorder>,
+    /// For replacing failed workers.
+    seed: u32,
+    noise: TerrainNoise,
 }
 
 impl ChunkLoader {
-    pub fn new(seed : u32) -> ChunkLoader {
-        let mut streams_iter =
+    /// disk_cache is the directory and size limit in bytes of the disk
+    /// cache (see diskcache.rs), or None to always regenerate evicted chunks.
+    pub fn new(seed : u32, wrap: Wrap, world_height: WorldHeight, chunk_dir: Path, quality: TerrainQuality, noise: TerrainNoise,
+               disk_cache: Option<(Path, u64)>) -> ChunkLoader {
+        let cache_dir = disk_cache.as_ref().map(|&(ref dir, _)| dir.clone());
+        let mut workers_iter =
             range(0, default_sched_threads()).
-            map(|_| ChunkLoader::spawn_worker(seed));
+            map(|_| ChunkLoader::spawn_worker(seed, wrap, world_height, chunk_dir.clone(), cache_dir.clone(), noise.clone()));
 
-        let streams : Vec<DuplexStream<Vector3<i64>, Box<Chunk>>> = streams_iter.collect();
+        let workers : Vec<Worker> = workers_iter.collect();
 
-        println!("spawned {} workers", streams.len());
+        println!("spawned {} workers", workers.len());
 
         ChunkLoader {
-            cache: HashMap::new(),
+            cache: HashMap::with_hasher(FnvHasher),
             needed_chunks: Vec::new(),
-            inflight: HashSet::new(),
-            streams: streams,
+            pending: HashMap::with_hasher(FnvHasher),
+            last_request: Vec::new(),
+            last_request_time: 0,
+            waiting_on_save: false,
+            regions: HashMap::with_hasher(FnvHasher),
+            inflight: HashSet::with_hasher(FnvHasher),
+            lru: PriorityQueue::new(),
+            workers: workers,
+            failed: HashMap::with_hasher(FnvHasher),
+            deterministic: false,
+            sent: RingBuf::new(),
+            saver: ChunkLoader::spawn_saver(chunk_dir.clone()),
+            saving: HashSet::with_hasher(FnvHasher),
+            cache_writer: disk_cache.map(|(dir, max_bytes)| diskcache::spawn_writer(dir, max_bytes)),
+            cache_dir: cache_dir,
             load_rate_display_limiter: RateLimiter::new(1000*1000*1000),
             load_rate_counter: 0,
+            generator: TerrainGenerator::new(seed, wrap.period(), world_height, &noise),
+            mesh_buffers: MeshBuffers::new(),
+            voxel_textures: false,
+            paused: false,
+            quality: quality,
+            detail_center: Vector3::new(0, 0, 0),
+            gen_time: 0.0,
+            refine: Vec::new(),
+            pending_decorations: HashMap::with_hasher(FnvHasher),
+            chunk_dir: chunk_dir,
+            loaded: Vec::new(),
+            changed: Vec::new(),
+            cache_changes: Vec::new(),
+            uploads: 0,
+            columns: ColumnIndex::new(),
+            wrap: wrap,
+            world_height: world_height,
+            border: None,
+            seed: seed,
+            noise: noise,
         }
     }
 
-    fn spawn_worker(seed: u32) -> DuplexStream<Vector3<i64>, Box<Chunk>> {
+    fn spawn_worker(seed: u32, wrap: Wrap, world_height: WorldHeight, chunk_dir: Path, cache_dir: Option<Path>,
+                    noise: TerrainNoise) -> Worker {
         let (loader_stream, worker_stream) = duplex();
 
         spawn(proc() {
-            let terrain_generator = TerrainGenerator::new(seed);
+            let terrain_generator = TerrainGenerator::new(seed, wrap.period(), world_height, &noise);
+            let mut mesh_buffers = MeshBuffers::new();
+            /* Until the loader is dropped */
             loop {
-                let coord : Vector3<i64> = worker_stream.recv();
-                worker_stream.send(chunk_gen(&terrain_generator, coord));
+                let request : ChunkRequest = match worker_stream.recv_opt() {
+                    Ok(request) => request,
+                    Err(()) => break,
+                };
+                let start_time = precise_time_ns();
+                let chunk = chunk_gen(&terrain_generator, &mut mesh_buffers, &chunk_dir, cache_dir.as_ref(),
+                                      request.coord, request.stride, request.coarse);
+                if worker_stream.send_opt((chunk, precise_time_ns() - start_time)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Worker { stream: loader_stream, queue: RingBuf::new() }
+    }
+
+    /// Replaces worker i, whose task failed, and sends the new one the
+    /// requests the old one didn't get to. The chunk it failed on is retried
+    /// after a delay, in case the failure was temporary.
+    fn restart_worker(&mut self, i: uint) {
+        let worker = ChunkLoader::spawn_worker(self.seed, self.wrap, self.world_height, self.chunk_dir.clone(),
+                                               self.cache_dir.clone(), self.noise.clone());
+        let old = mem::replace(self.workers.get_mut(i), worker);
+        let mut requests = old.queue.move_iter();
+        match requests.next() {
+            Some(request) => {
+                let c = request.coord;
+                let key = ChunkCoord::from_vec(c);
+                self.inflight.remove(&key);
+                let failed = self.failed.find_or_insert_with(key, |_| FailedChunk { failures: 0, retry_time: 0 });
+                let delay = cmp::min(RETRY_DELAY << cmp::min(failed.failures, 6), MAX_RETRY_DELAY);
+                failed.failures += 1;
+                failed.retry_time = precise_time_ns() + delay;
+                println!("Chunk worker failed on chunk ({}, {}, {}), retrying it in {}s",
+                         c.x, c.y, c.z, delay / (1000 * 1000 * 1000));
+            },
+            None => println!("Chunk worker {} failed between chunks", i),
+        }
+        for request in requests {
+            self.send_to_worker(i, request);
+        }
+    }
+
+    fn send_to_worker(&mut self, i: uint, request: ChunkRequest) {
+        let worker = self.workers.get_mut(i);
+        worker.queue.push_back(request);
+        /* If it's failed, work() restarts it and sends this again */
+        let _ = worker.stream.send_opt(request);
+    }
+
+    /// Requests the failed chunks whose delay is up again, if they're still
+    /// wanted.
+    fn retry_failed(&mut self) {
+        if self.failed.is_empty() {
+            return;
+        }
+        let now = precise_time_ns();
+        let mut due = Vec::new();
+        for (key, failed) in self.failed.iter() {
+            let c = key.to_vec();
+            if failed.retry_time <= now && !self.inflight.contains(key) && !self.pending.contains_key(key) &&
+                    self.last_request.iter().any(|&r| self.wrap.chunk(r) == c) {
+                due.push(c);
+            }
+        }
+        for &c in due.iter() {
+            self.pending.insert(ChunkCoord::from_vec(c), now);
+            self.needed_chunks.push(c);
+        }
+    }
+
+    fn spawn_saver(chunk_dir: Path) -> DuplexStream<(Vector3<i64>, Vec<u8>), Vector3<i64>> {
+        let (loader_stream, saver_stream) = duplex();
+
+        spawn(proc() {
+            loop {
+                let (coord, data) : (Vector3<i64>, Vec<u8>) = match saver_stream.recv_opt() {
+                    Ok(x) => x,
+                    Err(()) => break,
+                };
+                match store::save_chunk(&chunk_dir, coord, data.as_slice()) {
+                    Ok(()) => {},
+                    Err(e) => println!("Error saving chunk ({}, {}, {}): {}", coord.x, coord.y, coord.z, e),
+                }
+                if saver_stream.send_opt(coord).is_err() {
+                    break;
+                }
             }
         });
 
@@ -78,55 +365,492 @@ impl ChunkLoader {
     }
 
     pub fn get<'a>(&'a self, c: Vector3<i64>) -> Option<&'a Box<Chunk>> {
-        self.cache.find(&(c.x, c.y, c.z))
+        self.cache.find(&ChunkCoord::from_vec(self.wrap.chunk(c)))
+    }
+
+    pub fn get_mut<'a>(&'a mut self, c: Vector3<i64>) -> Option<&'a mut Box<Chunk>> {
+        self.cache.find_mut(&ChunkCoord::from_vec(self.wrap.chunk(c)))
+    }
+
+    /// Returns the loaded chunks within radius of center, grouped by region.
+    ///
+    /// Region coordinates are unwrapped, i.e. near center even if the world
+    /// wraps around. Only regions that have loaded chunks are visited. Chunks within a region
+    /// are sorted nearest first, and regions by their nearest chunk.
+    pub fn find_nearby_regions<'a>(&'a self, center: Vector3<i64>, radius: i64) -> Vec<Region<'a>> {
+        let center_region = region_coord(center);
+        let r = radius / REGION_SIZE + 1;
+
+        let mut regions = Vec::new();
+        let mut visited = HashSet::with_hasher(FnvHasher);
+
+        for x in range(-r, r+1) {
+            for y in range(-r, r+1) {
+                for z in range(-r, r+1) {
+                    let rc = center_region.add_v(&Vector3::new(x, y, z));
+                    let key = ChunkCoord::from_vec(self.wrap.region(rc));
+                    if !visited.insert(key) {
+                        continue;
+                    }
+
+                    let coords = match self.regions.find(&key) {
+                        Some(coords) => coords,
+                        None => continue,
+                    };
+
+                    let mut chunks: Vec<&'a Box<Chunk>> = coords.iter().
+                        filter_map(|k| self.cache.find(k)).
+                        filter(|chunk| dist2(unwrap_chunk_coord(rc, chunk.coord), center) < radius*radius).
+                        collect();
+
+                    if chunks.is_empty() {
+                        continue;
+                    }
+
+                    chunks.sort_by(|a, b| dist2(unwrap_chunk_coord(rc, a.coord), center).
+                                          cmp(&dist2(unwrap_chunk_coord(rc, b.coord), center)));
+                    regions.push(Region { coord: rc, chunks: chunks });
+                }
+            }
+        }
+
+        regions.sort_by(|a, b| dist2(a.chunk_coord(&**a.chunks[0]), center).
+                               cmp(&dist2(b.chunk_coord(&**b.chunks[0]), center)));
+
+        regions
+    }
+
+    /// Sets the chunk the camera is in. Chunks generated afterward near it
+    /// are sampled at full resolution, if the quality setting asks for that.
+    pub fn set_detail_center(&mut self, c: Vector3<i64>) {
+        self.detail_center = c;
+    }
+
+    /// Returns the density sampling stride for generating chunk c.
+    fn stride(&self, c: Vector3<i64>) -> uint {
+        self.quality.stride(self.wrap.chunk_offset(c, self.detail_center))
+    }
+
+    /// Loads chunk c on the calling task if it isn't loaded yet, or only
+    /// its coarse terrain is, blocking until it's generated or read from
+    /// disk.
+    pub fn load_sync<'a>(&'a mut self, c: Vector3<i64>) -> &'a Box<Chunk> {
+        let c = self.wrap.chunk(c);
+        let key = ChunkCoord::from_vec(c);
+
+        let loaded = match self.cache.find(&key) {
+            Some(chunk) => !chunk.coarse,
+            None => false,
+        };
+        if !loaded {
+            /* An evicted copy with edits may still be on its way to disk */
+            while self.saving.contains(&key) {
+                let saved = self.saver.recv();
+                self.saving.remove(&ChunkCoord::from_vec(saved));
+            }
+
+            let stride = self.stride(c);
+            let chunk = chunk_gen(&self.generator, &mut self.mesh_buffers, &self.chunk_dir, self.cache_dir.as_ref(),
+                                  c, stride, false);
+            self.insert(chunk);
+        }
+
+        self.cache.get(&key)
+    }
+
+    /// Returns the blocktype at world position p, loading its chunk with
+    /// load_sync() if needed.
+    pub fn get_block_sync(&mut self, p: Vector3<i64>) -> BlockType {
+        let p = self.wrap.block(p);
+        let c = chunk_coord(p);
+        let o = p.sub_v(&c.mul_s(CHUNK_SIZE as i64));
+        self.load_sync(c).terrain.get(o.x as int, o.y as int, o.z as int).blocktype
+    }
+
+    /// Returns the blocktype at world position p, if its chunk is loaded.
+    pub fn get_block(&self, p: Vector3<i64>) -> Option<BlockType> {
+        let p = self.wrap.block(p);
+        let c = chunk_coord(p);
+        let o = p.sub_v(&c.mul_s(CHUNK_SIZE as i64));
+        self.get(c).map(|chunk| chunk.terrain.get(o.x as int, o.y as int, o.z as int).blocktype)
+    }
+
+    /// Returns the y of the highest block that isn't air in column (x, z),
+    /// if a loaded chunk has one. Unloaded chunks above it could have more.
+    pub fn column_top(&self, x: i64, z: i64) -> Option<i64> {
+        let p = self.wrap.block(Vector3::new(x, 0, z));
+        self.columns.top(p.x, p.z)
+    }
+
+    /// Returns the y of the highest solid block in column (x, z). Starting
+    /// from the top of the loaded column, or the generator's surface if
+    /// nothing is loaded there, loaded chunks are followed up through
+    /// whatever was built or grew on it and down through whatever was dug
+    /// or carved out of it, or is water. Where chunks aren't loaded it's
+    /// the generator's surface, without caves.
+    pub fn surface_height(&self, x: i64, z: i64) -> i64 {
+        let height = self.world_height;
+        let solid = |y: i64| self.get_block(Vector3::new(x, y, z)).map(|b| b.is_solid());
+        let mut y = match self.column_top(x, z) {
+            Some(top) => top,
+            None => self.generator.surface_heights([x], [z])[0].ceil() as i64 - 1,
+        };
+        while y + 1 < height.top && solid(y + 1) == Some(true) {
+            y += 1;
+        }
+        while y >= height.bottom && solid(y) == Some(false) {
+            y -= 1;
+        }
+        y
+    }
+
+    /// Returns the biome of column (x, z). It's decided by the generator's
+    /// surface, so edits don't change it.
+    pub fn biome_at(&self, x: i64, z: i64) -> Biome {
+        Biome::of_surface(self.generator.surface_heights([x], [z])[0].ceil() as i64 - 1)
+    }
+
+    /// Returns how many chunks within radius chunks of center on each axis
+    /// are loaded, how many of those store their terrain sparsely, and the
+    /// sum of their histograms.
+    pub fn block_stats(&self, center: Vector3<i64>, radius: i64) -> (uint, uint, BlockHistogram) {
+        let mut chunks = 0;
+        let mut sparse = 0;
+        let mut histogram = BlockHistogram::new();
+        for dx in range(-radius, radius + 1) {
+            for dy in range(-radius, radius + 1) {
+                for dz in range(-radius, radius + 1) {
+                    match self.get(Vector3::new(center.x + dx, center.y + dy, center.z + dz)) {
+                        Some(chunk) => {
+                            chunks += 1;
+                            if chunk.terrain.is_sparse() {
+                                sparse += 1;
+                            }
+                            histogram.add(&chunk.histogram);
+                        },
+                        None => {},
+                    }
+                }
+            }
+        }
+        (chunks, sparse, histogram)
     }
 
+    /// Whether block p can see the sky: nothing opaque is above it. There
+    /// is no light propagation, so this only looks straight up, and only
+    /// SKY_SCAN_HEIGHT blocks or to the first unloaded chunk. Blocks above
+    /// the top of their loaded column are lit without looking.
+    pub fn sky_lit(&self, p: Vector3<i64>) -> bool {
+        match self.column_top(p.x, p.z) {
+            Some(top) if top > p.y => {},
+            _ => return true,
+        }
+        for dy in range(1, SKY_SCAN_HEIGHT + 1) {
+            match self.get_block(Vector3::new(p.x, p.y + dy, p.z)) {
+                Some(blocktype) if blocktype.is_solid() => return false,
+                Some(_) => {},
+                None => break,
+            }
+        }
+        true
+    }
+
+    /// Sets the block at world position p and remeshes every loaded chunk
+    /// whose terrain (including the one block border) contains it.
+    ///
+    /// Returns the previous blocktype, or None if the owning chunk isn't
+    /// loaded or is coarse, in which case nothing is changed.
+    pub fn set_block(&mut self, p: Vector3<i64>, blocktype: BlockType) -> Option<BlockType> {
+        let p = self.wrap.block(p);
+        let c = chunk_coord(p);
+        let old = match self.get_block(p) {
+            Some(old) => old,
+            None => return None,
+        };
+        match self.get(c) {
+            /* Its terrain is about to be replaced */
+            Some(chunk) if chunk.coarse => return None,
+            _ => {},
+        }
+        self.changed.push(p);
+
+        let mut remesh = HashSet::with_hasher(FnvHasher);
+        self.write_block(p, blocktype, true, &mut remesh);
+        self.remesh(&remesh);
+
+        Some(old)
+    }
+
+    /// Sets each block like set_block(), but remeshes each affected chunk
+    /// only once after all of them are written.
+    ///
+    /// Returns how many were set.
+    pub fn set_blocks(&mut self, blocks: &[(Vector3<i64>, BlockType)]) -> uint {
+        let mut remesh = HashSet::with_hasher(FnvHasher);
+        let mut count = 0;
+        for &(p, blocktype) in blocks.iter() {
+            let p = self.wrap.block(p);
+            match self.get(chunk_coord(p)) {
+                Some(chunk) if !chunk.coarse => {},
+                _ => continue,
+            }
+            self.changed.push(p);
+            self.write_block(p, blocktype, true, &mut remesh);
+            count += 1;
+        }
+        self.remesh(&remesh);
+        count
+    }
+
+    /// Writes blocktype at wrapped world position p into every loaded chunk
+    /// whose terrain (including the one block border) contains it, or only
+    /// into their borders unless owner is set, recording it in their
+    /// journals and adding them to remesh.
+    fn write_block(&mut self, p: Vector3<i64>, blocktype: BlockType, owner: bool,
+                   remesh: &mut HashSet<ChunkCoord, FnvHasher>) {
+        let c = chunk_coord(p);
+        let in_border = |v: i64| v >= -1 && v <= CHUNK_SIZE as i64;
+
+        for dx in range(-1i64, 2) {
+            for dy in range(-1i64, 2) {
+                for dz in range(-1i64, 2) {
+                    let nc = Vector3::new(c.x + dx, c.y + dy, c.z + dz);
+                    let o = p.sub_v(&nc.mul_s(CHUNK_SIZE as i64));
+                    if !in_border(o.x) || !in_border(o.y) || !in_border(o.z) {
+                        continue;
+                    }
+                    if !owner && (dx, dy, dz) == (0, 0, 0) {
+                        continue;
+                    }
+
+                    let key = ChunkCoord::from_vec(self.wrap.chunk(nc));
+                    match self.cache.find_mut(&key) {
+                        Some(chunk) => {
+                            if (dx, dy, dz) == (0, 0, 0) {
+                                let old = chunk.terrain.get(o.x as int, o.y as int, o.z as int).blocktype;
+                                chunk.histogram.replace(old, blocktype);
+                            }
+                            chunk.terrain.set(o.x as int, o.y as int, o.z as int, blocktype);
+                            if (dx, dy, dz) == (0, 0, 0) {
+                                self.columns.set_block(chunk.coord, &*chunk.terrain,
+                                                       o.x as int, o.y as int, o.z as int, blocktype);
+                            }
+                            chunk.journal.record(o.x as int, o.y as int, o.z as int, blocktype);
+                            /* Coarse terrain is never saved */
+                            chunk.dirty = !chunk.coarse;
+                            remesh.insert(key);
+                        },
+                        None => {},
+                    }
+                }
+            }
+        }
+    }
+
+    fn remesh(&mut self, keys: &HashSet<ChunkCoord, FnvHasher>) {
+        for key in keys.iter() {
+            match self.cache.find_mut(key) {
+                Some(chunk) => {
+                    self.uploads += chunk.remesh(&mut self.mesh_buffers);
+                    if self.voxel_textures {
+                        chunk.mesh.voxels = Some(VoxelTexture::new(&*chunk.terrain));
+                    }
+                },
+                None => {},
+            }
+        }
+    }
+
+    /// Writes blocks placed by decoration stages into the chunks they fall
+    /// in, remeshing each of those once. Blocks in chunks that aren't loaded,
+    /// or are coarse, wait until the chunk's full terrain is inserted.
+    fn place_decorations(&mut self, blocks: Vec<(Vector3<i64>, BlockType)>) {
+        let mut remesh = HashSet::with_hasher(FnvHasher);
+        for (p, blocktype) in blocks.move_iter() {
+            let p = self.wrap.block(p);
+            let key = ChunkCoord::from_vec(chunk_coord(p));
+            let ready = match self.cache.find(&key) {
+                Some(chunk) => !chunk.coarse,
+                None => false,
+            };
+            if ready {
+                self.write_block(p, blocktype, true, &mut remesh);
+            } else {
+                self.pending_decorations.find_or_insert_with(key, |_| Vec::new()).push((p, blocktype));
+            }
+        }
+        self.remesh(&remesh);
+    }
+
+    /// Replaces the queue of chunks to load with coords, in priority order.
+    ///
+    /// Chunks queued earlier that aren't in coords stay queued behind them
+    /// for PENDING_MAX_AGE, so moving back and forth over a chunk boundary
+    /// doesn't drop and re-add them. The same coords as last time are only
+    /// rescanned every REQUEST_REFRESH_TIME.
+    ///
+    /// Callers only need to call this when the coords change. Chunks that
+    /// couldn't be queued yet are requested again from work().
     pub fn request(&mut self, coords: &[Vector3<i64>]) {
-        self.needed_chunks.clear();
+        let now = precise_time_ns();
+        if coords == self.last_request.as_slice() && now - self.last_request_time < REQUEST_REFRESH_TIME {
+            return;
+        }
+        self.last_request = coords.to_vec();
+        self.last_request_time = now;
+        self.waiting_on_save = false;
+
+        let old = mem::replace(&mut self.needed_chunks, Vec::new());
+        let old_pending = mem::replace(&mut self.pending, HashMap::with_hasher(FnvHasher));
+        self.queue_needed(coords, now);
+        for c in old.move_iter() {
+            let key = ChunkCoord::from_vec(c);
+            if self.pending.contains_key(&key) {
+                continue;
+            }
+            match old_pending.find(&key) {
+                Some(&time) if now - time < PENDING_MAX_AGE => {
+                    self.pending.insert(key, time);
+                    self.needed_chunks.push(c);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Queues chunks the camera is expected to reach soon. They are loaded
+    /// only after everything passed to the last request().
+    pub fn request_predicted(&mut self, coords: &[Vector3<i64>]) {
+        self.queue_needed(coords, precise_time_ns());
+    }
 
+    /// Appends the chunks in coords that aren't loaded, loading or queued
+    /// already to needed_chunks, and marks them all requested at now.
+    fn queue_needed(&mut self, coords: &[Vector3<i64>], now: u64) {
         for &c in coords.iter() {
-            if self.inflight.contains(&(c.x, c.y, c.z)) {
+            if !self.world_height.contains_chunk(c.y) {
+                continue;
+            }
+            match self.border {
+                Some(ref border) if !border.contains_chunk(c) => continue,
+                _ => {},
+            }
+            let c = self.wrap.chunk(c);
+            let key = ChunkCoord::from_vec(c);
+            if self.inflight.contains(&key) {
                 continue;
             }
 
-            match self.cache.find_mut(&(c.x, c.y, c.z)) {
+            match self.cache.find_mut(&key) {
                 Some(chunk) => {
                     chunk.touch();
                 }
                 None => {
+                    /* Wait out the delay after a failed attempt; retry_failed() requeues it */
+                    match self.failed.find(&key) {
+                        Some(failed) if failed.retry_time > now => continue,
+                        _ => {},
+                    }
+                    /* Wait for an evicted chunk's edits to be written before reloading it */
+                    if self.saving.contains(&key) {
+                        self.waiting_on_save = true;
+                        continue;
+                    }
+                    match self.pending.find_mut(&key) {
+                        Some(time) => {
+                            *time = now;
+                            continue;
+                        },
+                        None => {},
+                    }
+                    self.pending.insert(key, now);
                     self.needed_chunks.push(c);
                 }
             }
         }
     }
 
-    pub fn work(&mut self) {
-        for stream in self.streams.iter() {
-            loop {
-                match stream.try_recv() {
-                    Ok(mut chunk) => {
-                        let c = chunk.coord;
-                        chunk.touch();
-                        chunk.mesh.finish();
-                        self.cache.insert((c.x, c.y, c.z), chunk);
-                        self.inflight.remove(&(c.x, c.y, c.z));
-                        self.load_rate_counter += 1;
+    /// Entities in chunks that get evicted are taken out of entities and
+    /// saved with their chunk. Generated chunks are only inserted, which
+    /// uploads their meshes, while budget has time; the rest wait for the
+    /// next call.
+    pub fn work(&mut self, entities: &mut Entities, budget: &FrameBudget) {
+        if self.deterministic {
+            self.receive_in_order();
+        }
+        for i in range(0, self.workers.len()) {
+            while budget.has_time() && !self.deterministic {
+                let result = self.workers[i].stream.try_recv();
+                match result {
+                    Ok((chunk, time)) => {
+                        self.workers.get_mut(i).queue.pop_front();
+                        self.receive(chunk, time);
+                    },
+                    Err(Empty) => break,
+                    /* Only after everything it finished has been received */
+                    Err(Disconnected) => {
+                        self.restart_worker(i);
+                        break;
                     },
-                    _ => break,
                 }
             }
         }
 
-        while self.cache.len() > MAX_CHUNKS {
-            let (&k, _) = self.cache.iter().min_by(|&(_, chunk)| chunk.used_time).unwrap();
-            self.cache.remove(&k);
+        loop {
+            match self.saver.try_recv() {
+                Ok(c) => { self.saving.remove(&ChunkCoord::from_vec(c)); },
+                Err(_) => break,
+            }
+        }
+
+        if self.waiting_on_save && self.saving.is_empty() {
+            let coords = mem::replace(&mut self.last_request, Vec::new());
+            self.request(coords.as_slice());
         }
 
-        while self.inflight.len() < MAX_INFLIGHT && !self.needed_chunks.is_empty() {
+        self.evict(entities);
+
+        if self.paused {
+            return;
+        }
+
+        self.retry_failed();
+        let max_inflight = if self.deterministic {
+            DETERMINISTIC_INFLIGHT
+        } else {
+            inflight_limit(self.workers.len(), self.gen_time)
+        };
+        while self.inflight.len() < max_inflight && !self.needed_chunks.is_empty() {
             let c = self.needed_chunks.shift().unwrap();
-            self.inflight.insert((c.x, c.y, c.z));
-            let worker_index = hash(&(c.x, c.y, c.z)) as uint % self.streams.len();
-            self.streams[worker_index].send(c);
+            let key = ChunkCoord::from_vec(c);
+            self.pending.remove(&key);
+            if self.cache.contains_key(&key) || !self.inflight.insert(key) {
+                /* Requested twice, e.g. in a wrapped world smaller than the view */
+                continue;
+            }
+            let coarse = self.over_budget();
+            self.send_request(c, coarse);
+        }
+
+        /* Refine coarse chunks once every requested chunk has something to show */
+        if self.needed_chunks.is_empty() && !self.refine.is_empty() {
+            let center = self.detail_center;
+            let wrap = self.wrap;
+            self.refine.sort_by(|a, b| {
+                let (da, db) = (wrap.chunk_offset(*a, center), wrap.chunk_offset(*b, center));
+                da.dot(&da).cmp(&db.dot(&db))
+            });
+        }
+        while self.inflight.len() < max_inflight && self.needed_chunks.is_empty() && !self.refine.is_empty() {
+            let c = self.refine.shift().unwrap();
+            let key = ChunkCoord::from_vec(c);
+            let coarse = match self.cache.find(&key) {
+                Some(chunk) => chunk.coarse,
+                None => false,
+            };
+            if coarse && self.inflight.insert(key) {
+                self.send_request(c, false);
+            }
         }
 
         if self.load_rate_counter > 0 && self.load_rate_display_limiter.limit() {
@@ -134,31 +858,629 @@ impl ChunkLoader {
             self.load_rate_counter = 0;
         }
     }
+
+    fn send_request(&mut self, c: Vector3<i64>, coarse: bool) {
+        let key = ChunkCoord::from_vec(c);
+        let worker_index = FnvHasher.hash(&key) as uint % self.workers.len();
+        let request = ChunkRequest { coord: c, stride: self.stride(c), coarse: coarse };
+        self.send_to_worker(worker_index, request);
+        if self.deterministic {
+            self.sent.push_back(worker_index);
+        }
+    }
+
+    /// Handles a chunk that came back from a worker.
+    fn receive(&mut self, chunk: Box<Chunk>, time: u64) {
+        let key = ChunkCoord::from_vec(chunk.coord);
+        self.inflight.remove(&key);
+        self.failed.remove(&key);
+        if !chunk.coarse {
+            let secs = time as f64 / 1e9;
+            self.gen_time = if self.gen_time == 0.0 {
+                secs
+            } else {
+                self.gen_time * (1.0 - GEN_TIME_WEIGHT) + secs * GEN_TIME_WEIGHT
+            };
+        }
+        /* Already loaded by load_sync(), maybe edited since */
+        let keep_old = match self.cache.find(&key) {
+            Some(old) => !old.coarse || chunk.coarse,
+            None => {
+                self.load_rate_counter += 1;
+                false
+            },
+        };
+        if !keep_old {
+            self.insert(chunk);
+        }
+    }
+
+    /// Waits for every request sent so far and handles the chunks in the
+    /// order they were sent.
+    fn receive_in_order(&mut self) {
+        loop {
+            let i = match self.sent.pop_front() {
+                Some(i) => i,
+                None => break,
+            };
+            let result = self.workers[i].stream.recv_opt();
+            match result {
+                Ok((chunk, time)) => {
+                    self.workers.get_mut(i).queue.pop_front();
+                    self.receive(chunk, time);
+                },
+                /* The chunk it failed on was the one waited for */
+                Err(()) => self.restart_worker(i),
+            }
+        }
+    }
+
+    /// Makes loading reproducible, for tests: the same calls load the same
+    /// chunks in the same order, however the workers are scheduled and
+    /// however many there are. work() waits for every chunk it sent last
+    /// time, ignoring its budget, and chunks are never generated coarse.
+    /// Set it before requesting any chunks.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Returns whether generating the queued chunks in full would take the
+    /// workers longer than the time budget, going by recent chunks.
+    fn over_budget(&self) -> bool {
+        if self.deterministic {
+            return false;
+        }
+        match self.quality.budget {
+            Some(budget) => {
+                let backlog = self.needed_chunks.len() as f64 * self.gen_time / self.workers.len() as f64;
+                backlog > budget
+            },
+            None => false,
+        }
+    }
+
+    /// Re-sorts the translucent quads of nearby chunks back to front for the
+    /// camera, nearest chunks first, at most MAX_TRANSLUCENT_SORTS per call.
+    pub fn sort_translucent(&mut self, camera_position: Vector3<f64>) {
+        let center = containing_chunk(camera_position);
+        let r = TRANSLUCENT_SORT_RADIUS;
+        let mut coords = Vec::new();
+        for dx in range(-r, r + 1) {
+            for dy in range(-r, r + 1) {
+                for dz in range(-r, r + 1) {
+                    coords.push(Vector3::new(center.x + dx, center.y + dy, center.z + dz));
+                }
+            }
+        }
+        coords.sort_by(|a, b| {
+            let (da, db) = (a.sub_v(&center), b.sub_v(&center));
+            da.dot(&da).cmp(&db.dot(&db))
+        });
+
+        let mut sorts = 0;
+        for &c in coords.iter() {
+            if sorts == MAX_TRANSLUCENT_SORTS {
+                break;
+            }
+
+            let origin = c.mul_s(CHUNK_SIZE as i64);
+            let eye = Vector3::new((camera_position.x - origin.x as f64) as f32,
+                                   (camera_position.y - origin.y as f64) as f32,
+                                   (camera_position.z - origin.z as f64) as f32);
+            match self.get_mut(c) {
+                Some(chunk) if chunk.mesh.has_translucent() => {
+                    let stale = match chunk.mesh.sorted_for {
+                        Some(p) => p.sub_v(&eye).length() > TRANSLUCENT_RESORT_DISTANCE,
+                        None => true,
+                    };
+                    if stale {
+                        chunk.mesh.sort_translucent(eye);
+                        sorts += 1;
+                    }
+                },
+                _ => {},
+            }
+        }
+        /* Each sort uploads a new element buffer */
+        self.uploads += sorts;
+    }
+
+    /// Returns the chunks that have been loaded since the last call, or have
+    /// had their coarse terrain replaced.
+    pub fn take_loaded(&mut self) -> Vec<Vector3<i64>> {
+        mem::replace(&mut self.loaded, Vec::new())
+    }
+
+    /// Returns the blocks that have been set since the last call.
+    pub fn take_changed(&mut self) -> Vec<Vector3<i64>> {
+        mem::replace(&mut self.changed, Vec::new())
+    }
+
+    /// Returns the chunks added to or removed from the cache since the last
+    /// call, in order, for keeping track of which are loaded without
+    /// scanning the cache. Replacing a chunk's coarse terrain isn't a change.
+    pub fn take_cache_changes(&mut self) -> Vec<(Vector3<i64>, bool)> {
+        mem::replace(&mut self.cache_changes, Vec::new())
+    }
+
+    /// Returns the number of mesh buffers uploaded to GL since the last
+    /// call, for RenderStats.
+    pub fn take_uploads(&mut self) -> uint {
+        mem::replace(&mut self.uploads, 0)
+    }
+
+    /// Rebuilds chunk c from its base terrain and the first len entries of
+    /// its journal, undoing the edits after them or redoing rewound ones.
+    /// Blocks on its faces are copied into the neighbors' borders.
+    ///
+    /// Returns false if the chunk isn't loaded, is coarse, was loaded whole
+    /// from an old save so its base can't be regenerated, or its journal is
+    /// shorter than len.
+    pub fn rewind(&mut self, c: Vector3<i64>, len: uint) -> bool {
+        let key = ChunkCoord::from_vec(self.wrap.chunk(c));
+        let (coord, changes) = {
+            let chunk = match self.cache.find_mut(&key) {
+                Some(chunk) => chunk,
+                None => return false,
+            };
+            let stride = match chunk.base {
+                Some((stride, _)) if !chunk.coarse => stride,
+                _ => return false,
+            };
+            if !chunk.journal.rewind(len) {
+                return false;
+            }
+
+            let (mut terrain, _) = base_terrain(&self.generator, chunk.coord, stride);
+            chunk.journal.replay(&mut *terrain);
+            let changes = protocol::diff(&*chunk.terrain, &*terrain);
+            chunk.histogram = BlockHistogram::of(&*terrain);
+            self.columns.insert(chunk.coord, &*terrain);
+            chunk.terrain = terrain;
+            chunk.dirty = true;
+            (chunk.coord, changes)
+        };
+
+        let mut remesh = HashSet::with_hasher(FnvHasher);
+        remesh.insert(key);
+        let origin = coord.mul_s(CHUNK_SIZE as i64);
+        for &(index, blocktype) in changes.iter() {
+            let (x, y, z) = protocol::block_position(index);
+            let p = origin.add_v(&Vector3::new(x as i64, y as i64, z as i64));
+            self.changed.push(p);
+            let on_face = |v: int| v == 0 || v == CHUNK_SIZE - 1;
+            if on_face(x) || on_face(y) || on_face(z) {
+                self.write_block(p, blocktype, false, &mut remesh);
+            }
+        }
+        self.remesh(&remesh);
+        true
+    }
+
+    /// Adds a chunk to the cache, or replaces the coarse chunk there with
+    /// its full terrain, then places its decorations and any that other
+    /// chunks left for it.
+    fn insert(&mut self, mut chunk: Box<Chunk>) {
+        let key = ChunkCoord::from_vec(chunk.coord);
+        self.loaded.push(chunk.coord);
+        self.columns.insert(chunk.coord, &*chunk.terrain);
+        chunk.biome_tints = self.generator.climate().chunk_tints(chunk.coord);
+        self.uploads += chunk.mesh.finish();
+        if self.voxel_textures {
+            chunk.mesh.voxels = Some(VoxelTexture::new(&*chunk.terrain));
+        }
+        let coarse = chunk.coarse;
+        if coarse {
+            self.refine.push(chunk.coord);
+        }
+        let decorations = mem::replace(&mut chunk.decorations, Vec::new());
+
+        if self.cache.contains_key(&key) {
+            let old = self.cache.get_mut(&key);
+            /* Keeps the chunk's LRU entry valid */
+            chunk.used_time = old.used_time;
+            /* Already faded in */
+            chunk.shown_time = old.shown_time;
+            *old = chunk;
+        } else {
+            chunk.touch();
+            chunk.shown_time = chunk.used_time;
+            self.lru.push(LruEntry { used_time: chunk.used_time, coord: key });
+            self.cache_changes.push((chunk.coord, true));
+            self.cache.insert(key, chunk);
+            self.regions.find_or_insert_with(
+                ChunkCoord::from_vec(region_coord(key.to_vec())),
+                |_| Vec::new()).push(key);
+        }
+
+        if !coarse {
+            let pending = self.pending_decorations.pop(&key).unwrap_or(Vec::new());
+            self.place_decorations(pending);
+            self.place_decorations(decorations);
+        }
+    }
+
+    /// Returns every chunk the loader knows about and its state. A coarse
+    /// chunk whose full terrain is on its way is listed twice, cached chunks
+    /// first.
+    pub fn chunk_states(&self) -> Vec<(Vector3<i64>, ChunkState)> {
+        let mut states = Vec::with_capacity(self.cache.len() + self.needed_chunks.len() + self.inflight.len());
+        for (coord, chunk) in self.cache.iter() {
+            let state = if chunk.dirty {
+                ChunkDirty
+            } else if chunk.coarse {
+                ChunkCoarse
+            } else {
+                ChunkLoaded
+            };
+            states.push((coord.to_vec(), state));
+        }
+        for &c in self.needed_chunks.iter() {
+            states.push((c, ChunkQueued));
+        }
+        for coord in self.inflight.iter() {
+            states.push((coord.to_vec(), ChunkInflight));
+        }
+        for coord in self.failed.keys() {
+            if !self.cache.contains_key(coord) && !self.pending.contains_key(coord) && !self.inflight.contains(coord) {
+                states.push((coord.to_vec(), ChunkFailed));
+            }
+        }
+        states
+    }
+
+    /// Returns p moved inside the world border, if there is one.
+    pub fn clamp_to_border(&self, p: Vector3<f64>) -> Vector3<f64> {
+        match self.border {
+            Some(ref border) => border.clamp(p),
+            None => p,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn voxel_textures(&self) -> bool {
+        self.voxel_textures
+    }
+
+    /// Uploads or frees the voxel textures of every cached chunk.
+    pub fn set_voxel_textures(&mut self, enabled: bool) {
+        self.voxel_textures = enabled;
+        for (_, chunk) in self.cache.mut_iter() {
+            chunk.mesh.voxels = if enabled {
+                Some(VoxelTexture::new(&*chunk.terrain))
+            } else {
+                None
+            };
+        }
+    }
+
+    /// Evicts least recently used chunks until the cache fits in MAX_CHUNKS,
+    /// giving up after MAX_EVICTION_STEPS queue operations.
+    ///
+    /// Queue entries are refreshed lazily: touching a chunk doesn't update its
+    /// entry, so a popped entry whose time no longer matches the chunk is
+    /// pushed back with the current time instead of being evicted.
+    fn evict(&mut self, entities: &mut Entities) {
+        let mut steps = 0;
+        while self.cache.len() > MAX_CHUNKS && steps < MAX_EVICTION_STEPS {
+            steps += 1;
+
+            let entry = match self.lru.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            let used_time = match self.cache.find(&entry.coord) {
+                Some(chunk) => chunk.used_time,
+                None => continue,
+            };
+
+            if used_time == entry.used_time {
+                self.remove(&entry.coord, entities);
+            } else {
+                self.lru.push(LruEntry { used_time: used_time, coord: entry.coord });
+            }
+        }
+    }
+
+    /// Queues every chunk with unsaved edits to be written by the saver task,
+    /// along with the entities in it. Returns the number of chunks queued.
+    ///
+    /// Entities moving doesn't count as an edit, so their positions are only
+    /// saved with edits and when chunks are unloaded or flushed.
+    pub fn autosave(&mut self, entities: &Entities) -> uint {
+        self.save_chunks(entities, false)
+    }
+
+    /// Saves all chunks with unsaved edits or entities and waits until
+    /// they're written.
+    pub fn flush(&mut self, entities: &Entities) {
+        self.save_chunks(entities, true);
+        while !self.saving.is_empty() {
+            let c = self.saver.recv();
+            self.saving.remove(&ChunkCoord::from_vec(c));
+        }
+    }
+
+    /// Saves everything like flush(), then stops the workers and frees the
+    /// chunks. Meshes are deleted, so the GL context must still be current.
+    pub fn shutdown(mut self, entities: &Entities) {
+        self.flush(entities);
+        /* Workers finish the chunk they're on and exit once their streams close */
+        self.workers.clear();
+        /* The disk cache writer finishes its queue first */
+        self.cache_writer = None;
+        self.cache.clear();
+    }
+
+    /// Queues chunks to be saved with their entities: the dirty ones, and
+    /// if with_entities is set also the ones whose entities may have changed.
+    fn save_chunks(&mut self, entities: &Entities, with_entities: bool) -> uint {
+        let mut by_chunk = entities.by_chunk(&self.wrap);
+        let mut count = 0;
+        for (key, chunk) in self.cache.mut_iter() {
+            let mut chunk_entities = by_chunk.pop(key).unwrap_or(Vec::new());
+            /* Not yet adopted, e.g. loaded since the last frame */
+            chunk_entities.push_all(chunk.entities.as_slice());
+            if chunk.coarse {
+                continue;
+            }
+            if chunk.dirty || (with_entities && chunk.needs_entity_save(chunk_entities.as_slice())) {
+                chunk.dirty = false;
+                queue_save(&self.saver, &mut self.saving, &mut **chunk, chunk_entities.as_slice());
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn remove(&mut self, key: &ChunkCoord, entities: &mut Entities) {
+        match self.cache.pop(key) {
+            Some(mut chunk) => {
+                self.columns.remove(chunk.coord);
+                self.cache_changes.push((chunk.coord, false));
+                let mut chunk_entities = entities.take_chunk(chunk.coord, &self.wrap);
+                chunk_entities.push_all_move(mem::replace(&mut chunk.entities, Vec::new()));
+                /* Coarse terrain is never saved, so mobs that wandered in are lost */
+                if !chunk.coarse && (chunk.dirty || chunk.needs_entity_save(chunk_entities.as_slice())) {
+                    queue_save(&self.saver, &mut self.saving, &mut *chunk, chunk_entities.as_slice());
+                } else {
+                    match (&self.cache_writer, chunk.generated_base()) {
+                        (&Some(ref writer), Some((stride, fingerprint))) => {
+                            writer.send(if chunk.in_disk_cache {
+                                CacheTouch(chunk.coord)
+                            } else {
+                                CacheStore(chunk.coord, diskcache::encode(&*chunk.terrain, stride, fingerprint))
+                            });
+                        },
+                        _ => {},
+                    }
+                }
+            },
+            None => {},
+        }
+
+        let region_key = ChunkCoord::from_vec(region_coord(key.to_vec()));
+        let empty = match self.regions.find_mut(&region_key) {
+            Some(coords) => {
+                match coords.iter().position(|c| c == key) {
+                    Some(i) => { coords.swap_remove(i); },
+                    None => {},
+                }
+                coords.is_empty()
+            },
+            None => false,
+        };
+
+        if empty {
+            self.regions.remove(&region_key);
+        }
+    }
+}
+
+fn queue_save(saver: &DuplexStream<(Vector3<i64>, Vec<u8>), Vector3<i64>>,
+              saving: &mut HashSet<ChunkCoord, FnvHasher>,
+              chunk: &mut Chunk,
+              entities: &[Entity]) {
+    let data = match chunk.base {
+        /* Past a certain number of edits the whole terrain is smaller */
+        Some((stride, fingerprint)) if chunk.journal.saved_size() < TERRAIN_BYTES => {
+            store::encode_journal_chunk(stride, fingerprint, &chunk.journal, chunk.populated, entities)
+        },
+        _ => store::encode_chunk(&*chunk.terrain, chunk.populated, entities),
+    };
+    saver.send((chunk.coord, data));
+    saving.insert(ChunkCoord::from_vec(chunk.coord));
+    chunk.has_saved_entities = !entities.is_empty();
+}
+
+/// Entry in the eviction queue, ordered so that the least recently used chunk
+/// is at the top of the max-heap.
+struct LruEntry {
+    used_time: u64,
+    coord: ChunkCoord,
+}
+
+impl PartialEq for LruEntry {
+    fn eq(&self, other: &LruEntry) -> bool {
+        self.used_time == other.used_time
+    }
+}
+
+impl Eq for LruEntry {}
+
+impl PartialOrd for LruEntry {
+    fn partial_cmp(&self, other: &LruEntry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LruEntry {
+    fn cmp(&self, other: &LruEntry) -> Ordering {
+        other.used_time.cmp(&self.used_time)
+    }
 }
 
 pub struct Chunk {
     pub coord: Vector3<i64>,
     pub terrain: Box<Terrain>,
+    /// Kept in step with terrain by ChunkLoader::set_block().
+    pub histogram: BlockHistogram,
     pub mesh: Box<Mesh>,
+    /// Climate tint at each corner, by Climate::chunk_tints().
+    pub biome_tints: [Vector3<f32>, ..8],
     pub used_time: u64,
+    /// When the chunk was first inserted, for fading it in. A chunk that
+    /// replaces a coarse one or is remeshed keeps the old time.
+    pub shown_time: u64,
+    /// Set when the terrain has edits that haven't been saved.
+    pub dirty: bool,
+    /// Blocks written since the terrain was generated, or loaded whole.
+    pub journal: Journal,
+    /// Stride and generator fingerprint of the terrain the journal applies
+    /// to, or None if it can't be regenerated because it was loaded whole.
+    base: Option<(uint, u64)>,
+    /// Set once the chunk's mobs have been spawned.
+    pub populated: bool,
+    /// Entities the chunk was saved with, until Entities::adopt() takes them.
+    pub entities: Vec<Entity>,
+    /// Set when the saved file has entities, which must be overwritten even
+    /// if they've all left the chunk since.
+    has_saved_entities: bool,
+    /// Blocks the chunk's decorations placed near or past its edges, for
+    /// the loader to write into the neighboring chunks they fall in.
+    pub decorations: Vec<(Vector3<i64>, BlockType)>,
+    /// Set when the terrain is from the cheap first pass, without caves.
+    /// Coarse chunks can't be edited and aren't saved or populated, and are
+    /// replaced when their full terrain is ready.
+    pub coarse: bool,
+    /// Set when the terrain was read from the disk cache, which still has it.
+    in_disk_cache: bool,
 }
 
 impl Chunk {
     pub fn touch(&mut self) {
         self.used_time = precise_time_ns();
     }
+
+    /// Returns the stride and generator fingerprint of the terrain if it's
+    /// exactly what the generator made, the only terrain the disk cache
+    /// takes.
+    fn generated_base(&self) -> Option<(uint, u64)> {
+        if self.coarse || self.dirty || self.journal.total() > 0 {
+            None
+        } else {
+            self.base
+        }
+    }
+
+    fn needs_entity_save(&self, entities: &[Entity]) -> bool {
+        self.has_saved_entities || !entities.is_empty()
+    }
+
+    /// Regenerates and uploads the mesh after the terrain has been edited.
+    /// Returns the number of buffers uploaded.
+    pub fn remesh(&mut self, buffers: &mut MeshBuffers) -> uint {
+        self.mesh = Mesh::gen(&*self.terrain, &self.histogram, buffers);
+        self.mesh.finish()
+    }
+}
+
+/// Returns the coordinate of the chunk containing world position p.
+pub fn containing_chunk(p: Vector3<f64>) -> Vector3<i64> {
+    chunk_coord(Vector3::new(p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64))
 }
 
-pub fn chunk_gen(terrain_generator: &TerrainGenerator, coord: Vector3<i64>) -> Box<Chunk> {
+/// Returns how many chunks to keep sent to workers taking gen_time seconds
+/// per chunk: enough to keep each busy for TARGET_QUEUE_TIME, so none goes
+/// idle between calls to work(), but no more, so new requests don't wait
+/// behind chunks the camera has moved away from. Before any chunk has been
+/// timed, gen_time is 0.
+fn inflight_limit(workers: uint, gen_time: f64) -> uint {
+    let min = workers * MIN_INFLIGHT_PER_WORKER;
+    if gen_time <= 0.0 {
+        return min;
+    }
+    let per_worker = (TARGET_QUEUE_TIME / gen_time).ceil().min(MAX_INFLIGHT as f64) as uint;
+    (workers * per_worker).max(min).min(MAX_INFLIGHT)
+}
+
+fn dist2(a: Vector3<i64>, b: Vector3<i64>) -> i64 {
+    let d = a.sub_v(&b);
+    d.dot(&d)
+}
+
+/// Returns the coordinate of the chunk containing world block position p.
+pub fn chunk_coord(p: Vector3<i64>) -> Vector3<i64> {
+    let s = CHUNK_SIZE as i64;
+    Vector3::new(p.x.div_floor(&s), p.y.div_floor(&s), p.z.div_floor(&s))
+}
+
+/// Generates the terrain of chunk coord with its own decorations, the base
+/// its journal is replayed over. Also returns the blocks the decorations
+/// placed outside it.
+fn base_terrain(terrain_generator: &TerrainGenerator, coord: Vector3<i64>,
+                stride: uint) -> (Box<Terrain>, Vec<(Vector3<i64>, BlockType)>) {
+    let p = Vector3::new(coord.x as f64, coord.y as f64, coord.z as f64).mul_s(CHUNK_SIZE as f64);
+    let mut terrain = terrain_generator.gen(p, stride);
+    let decorations = decoration::decorate(terrain_generator, coord, &mut *terrain);
+    (terrain, decorations)
+}
+
+/// Loads the saved terrain for coord if there is one, otherwise generates it.
+/// New terrain samples density every stride blocks, or is coarse if coarse is
+/// set.
+pub fn chunk_gen(terrain_generator: &TerrainGenerator, mesh_buffers: &mut MeshBuffers,
+                 chunk_dir: &Path, cache_dir: Option<&Path>,
+                 coord: Vector3<i64>, stride: uint, coarse: bool) -> Box<Chunk> {
     let p = Vector3::new(coord.x as f64, coord.y as f64, coord.z as f64).mul_s(CHUNK_SIZE as f64);
     let start_time = precise_time_ns();
-    let terrain = terrain_generator.gen(p);
+    let fingerprint = terrain_generator.fingerprint();
+    let mut in_disk_cache = false;
+    let (terrain, populated, entities, coarse, decorations, journal, base) = match store::load_chunk(chunk_dir, coord) {
+        Some(saved) => match saved.blocks {
+            SavedTerrain(terrain) => (terrain, saved.populated, saved.entities, false, Vec::new(), Journal::new(), None),
+            SavedJournal(saved_stride, saved_fingerprint, journal) => {
+                if saved_fingerprint != fingerprint {
+                    println!("Chunk ({}, {}, {}) was saved with other terrain settings; its edits may not line up",
+                             coord.x, coord.y, coord.z);
+                }
+                /* The decorations outside it were placed when it was first generated */
+                let (mut terrain, _) = base_terrain(terrain_generator, coord, saved_stride);
+                journal.replay(&mut *terrain);
+                (terrain, saved.populated, saved.entities, false, Vec::new(), journal, Some((saved_stride, fingerprint)))
+            },
+        },
+        /* Even when only coarse terrain was asked for, reading the full
+           terrain is cheaper than generating either. Only chunks whose
+           decorations stayed inside them are cached, since the others were
+           saved, so there are none to place */
+        None => match cache_dir.and_then(|dir| diskcache::load(dir, coord, stride, fingerprint)) {
+            Some(terrain) => {
+                in_disk_cache = true;
+                (terrain, false, Vec::new(), false, Vec::new(), Journal::new(), Some((stride, fingerprint)))
+            },
+            None if coarse => (terrain_generator.gen_coarse(p), false, Vec::new(), true, Vec::new(), Journal::new(), None),
+            None => {
+                let (terrain, decorations) = base_terrain(terrain_generator, coord, stride);
+                (terrain, false, Vec::new(), false, decorations, Journal::new(), Some((stride, fingerprint)))
+            },
+        },
+    };
+    let histogram = BlockHistogram::of(&*terrain);
     let terrain_end_time = precise_time_ns();
-    let mesh = Mesh::gen(&*terrain);
+    let mesh = Mesh::gen(&*terrain, &histogram, mesh_buffers);
     let mesh_end_time = precise_time_ns();
 
-    println!("loaded chunk ({}, {}, {}): terrain={}us mesh={}us size={}KB",
-             coord.x, coord.y, coord.z,
+    println!("loaded chunk ({}, {}, {}){}: terrain={}us mesh={}us size={}KB",
+             coord.x, coord.y, coord.z, if in_disk_cache { " from disk cache" } else { "" },
              (terrain_end_time - start_time)/1000,
              (mesh_end_time - terrain_end_time)/1000,
              (mesh.vertices.len() * 16 + mesh.elements.len() * 4)/1000);
@@ -166,7 +1488,31 @@ pub fn chunk_gen(terrain_generator: &TerrainGenerator, coord: Vector3<i64>) -> B
     return box Chunk {
         coord: coord,
         terrain: terrain,
+        histogram: histogram,
         mesh: mesh,
+        /* Set by the loader when it's inserted */
+        biome_tints: [Vector3::new(1.0, 1.0, 1.0), ..8],
         used_time: precise_time_ns(),
+        shown_time: precise_time_ns(),
+        /* Saved so its decorations aren't placed in other chunks again */
+        dirty: !decorations.is_empty(),
+        journal: journal,
+        base: base,
+        populated: populated,
+        has_saved_entities: !entities.is_empty(),
+        entities: entities,
+        decorations: decorations,
+        coarse: coarse,
+        in_disk_cache: in_disk_cache,
     };
 }
+
+#[test]
+fn test_inflight_limit() {
+    assert_eq!(inflight_limit(4, 0.0), 8);
+    /* Slow chunks: one running and one queued per worker */
+    assert_eq!(inflight_limit(4, 0.5), 8);
+    assert_eq!(inflight_limit(4, 0.02), 12);
+    assert_eq!(inflight_limit(16, 0.004), 208);
+    assert_eq!(inflight_limit(64, 1e-6), MAX_INFLIGHT);
+}
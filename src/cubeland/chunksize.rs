@@ -0,0 +1,32 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Chunk edge length in blocks, chosen at compile time with the chunk-16
+//! and chunk-64 features. Smaller chunks mesh and stream faster but there
+//! are more of them to draw.
+//!
+//! Both the game and the generator library declare this module and
+//! re-export the sizes from their crate roots, so their chunks always line
+//! up.
+
+#[cfg(feature = "chunk-16", not(feature = "chunk-64"))]
+pub static CHUNK_SIZEu: uint = 16;
+#[cfg(feature = "chunk-64", not(feature = "chunk-16"))]
+pub static CHUNK_SIZEu: uint = 64;
+#[cfg(not(feature = "chunk-16"), not(feature = "chunk-64"))]
+pub static CHUNK_SIZEu: uint = 32;
+/* Fails to build, naming the problem, rather than defining the size twice */
+#[cfg(feature = "chunk-16", feature = "chunk-64")]
+pub static CHUNK_SIZEu: uint = ENABLE_ONLY_ONE_OF_THE_CHUNK_16_AND_CHUNK_64_FEATURES;
+pub static CHUNK_SIZE: int = CHUNK_SIZEu as int;
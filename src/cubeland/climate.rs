@@ -0,0 +1,113 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Temperature and moisture across the world, which tint grass and any
+//! other block type that asks for it.
+//!
+//! Both change over hundreds of blocks, so the tint is only worked out at
+//! the corners of each chunk, and the chunk shaders blend between them.
+//! Neighboring chunks share corners, so the color runs smoothly across
+//! chunk borders. The climate doesn't change the terrain itself.
+
+extern crate cgmath;
+
+use cgmath::vector::{Vector, Vector3};
+
+use noisesource::{Noise, NoisePerlin};
+use terrain::{WATER_HEIGHT, MOUNTAIN_HEIGHT};
+use CHUNK_SIZE;
+
+/// Frequency of both noises, in cycles per block.
+static CLIMATE_FREQUENCY : f64 = 1.0 / 600.0;
+
+/// Tints at the extremes of temperature and moisture, multiplying the
+/// block's own tint.
+static COLD_DRY : Vector3<f32> = Vector3 { x: 0.9, y: 0.9, z: 1.0 };
+static COLD_WET : Vector3<f32> = Vector3 { x: 0.6, y: 0.85, z: 1.1 };
+static HOT_DRY : Vector3<f32> = Vector3 { x: 1.5, y: 1.05, z: 0.4 };
+static HOT_WET : Vector3<f32> = Vector3 { x: 0.7, y: 1.1, z: 0.6 };
+
+pub struct Climate {
+    temperature: Box<Noise>,
+    moisture: Box<Noise>,
+}
+
+impl Climate {
+    /// With a period, the climate repeats every period blocks along x and
+    /// z, like the terrain of a world that wraps around.
+    pub fn new(seed: u32, period: Option<i64>) -> Climate {
+        Climate {
+            temperature: NoisePerlin.create(seed * 31 + 7, 2, CLIMATE_FREQUENCY, 0.4, period),
+            moisture: NoisePerlin.create(seed * 53 + 11, 2, CLIMATE_FREQUENCY, 0.4, period),
+        }
+    }
+
+    /// From 0, coldest, to 1, hottest. It's colder higher up, so mountain
+    /// tops are cold everywhere.
+    pub fn temperature(&self, p: Vector3<i64>) -> f64 {
+        let altitude = (p.y as f64 - WATER_HEIGHT) / (MOUNTAIN_HEIGHT as f64 - WATER_HEIGHT);
+        unit(self.temperature.get(p.x, 0, p.z) - 0.5 * altitude.max(0.0))
+    }
+
+    /// From 0, driest, to 1, wettest.
+    pub fn moisture(&self, p: Vector3<i64>) -> f64 {
+        unit(self.moisture.get(p.x, 0, p.z))
+    }
+
+    /// Returns the tint at block p.
+    pub fn tint(&self, p: Vector3<i64>) -> Vector3<f32> {
+        let t = self.temperature(p) as f32;
+        let m = self.moisture(p) as f32;
+        let cold = COLD_DRY.mul_s(1.0 - m).add_v(&COLD_WET.mul_s(m));
+        let hot = HOT_DRY.mul_s(1.0 - m).add_v(&HOT_WET.mul_s(m));
+        cold.mul_s(1.0 - t).add_v(&hot.mul_s(t))
+    }
+
+    /// Returns the tint at each corner of chunk c. Bits 0, 1 and 2 of the
+    /// index say whether the corner is on the far side in x, y and z.
+    pub fn chunk_tints(&self, c: Vector3<i64>) -> [Vector3<f32>, ..8] {
+        let origin = c.mul_s(CHUNK_SIZE as i64);
+        let mut tints = [Vector3::new(1.0f32, 1.0, 1.0), ..8];
+        for (i, tint) in tints.mut_iter().enumerate() {
+            let corner = Vector3::new((i & 1) as i64, ((i >> 1) & 1) as i64, ((i >> 2) & 1) as i64);
+            *tint = self.tint(origin.add_v(&corner.mul_s(CHUNK_SIZE as i64)));
+        }
+        tints
+    }
+}
+
+/// Maps noise from roughly -1 to 1 onto 0 to 1. The noise rarely goes far
+/// past 0.5 either way, so that is stretched to the ends.
+fn unit(v: f64) -> f64 {
+    (v + 0.5).max(0.0).min(1.0)
+}
+
+#[test]
+fn test_climate() {
+    let climate = Climate::new(42, None);
+    let low = Vector3::new(100, WATER_HEIGHT as i64, -300);
+    let high = Vector3::new(100, 10 * MOUNTAIN_HEIGHT, -300);
+    assert!(climate.temperature(high) < climate.temperature(low));
+    assert_eq!(climate.temperature(high), 0.0);
+    assert_eq!(climate.moisture(high), climate.moisture(low));
+
+    /* Neighboring chunks agree on the corners they share */
+    let a = climate.chunk_tints(Vector3::new(3, 0, -2));
+    let b = climate.chunk_tints(Vector3::new(4, 0, -2));
+    let c = climate.chunk_tints(Vector3::new(3, 1, -2));
+    assert_eq!(a[1], b[0]);
+    assert_eq!(a[7], b[6]);
+    assert_eq!(a[2], c[0]);
+    assert_eq!(a[7], c[5]);
+}
@@ -0,0 +1,93 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The cloud layer's settings and drift.
+//!
+//! Clouds are a single horizontal sheet at a fixed altitude, textured with
+//! tiling noise. The renderer keeps the sheet centered under the camera, so
+//! moving the clouds only means moving where the texture is sampled.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector2;
+
+/// Blocks covered by one repeat of the cloud texture.
+pub static CLOUD_TILE_SIZE : f64 = 1024.0;
+
+static DEFAULT_HEIGHT : f64 = 140.0;
+static DEFAULT_DENSITY : f32 = 0.4;
+
+/// Blocks per second the clouds drift along x and z.
+static WIND : Vector2<f64> = Vector2 { x: 2.0, y: 1.0 };
+
+pub struct Clouds {
+    pub enabled: bool,
+    /// Altitude of the sheet in blocks.
+    pub height: f64,
+    /// Roughly the fraction of the sky covered, from 0 to 1.
+    density: f32,
+    /// Distance the clouds have drifted, wrapped to one texture repeat.
+    drift: Vector2<f64>,
+}
+
+impl Clouds {
+    pub fn new() -> Clouds {
+        Clouds {
+            enabled: true,
+            height: DEFAULT_HEIGHT,
+            density: DEFAULT_DENSITY,
+            drift: Vector2::new(0.0, 0.0),
+        }
+    }
+
+    pub fn density(&self) -> f32 {
+        self.density
+    }
+
+    pub fn set_density(&mut self, density: f32) {
+        self.density = density.max(0.0).min(1.0);
+    }
+
+    pub fn tick(&mut self, dt: f64) {
+        self.drift.x = wrap_tile(self.drift.x + WIND.x * dt);
+        self.drift.y = wrap_tile(self.drift.y + WIND.y * dt);
+    }
+
+    /// Returns the cloud texture coordinate above world position (x, z), in
+    /// texture repeats. It's wrapped to 0..1 so it stays precise in f32 far
+    /// from spawn.
+    pub fn texture_offset(&self, x: f64, z: f64) -> Vector2<f32> {
+        Vector2::new((wrap_tile(x - self.drift.x) / CLOUD_TILE_SIZE) as f32,
+                     (wrap_tile(z - self.drift.y) / CLOUD_TILE_SIZE) as f32)
+    }
+}
+
+fn wrap_tile(v: f64) -> f64 {
+    v - (v / CLOUD_TILE_SIZE).floor() * CLOUD_TILE_SIZE
+}
+
+#[test]
+fn test_clouds() {
+    let mut clouds = Clouds::new();
+    assert_eq!(clouds.texture_offset(256.0, -256.0), Vector2::new(0.25, 0.75));
+    assert_eq!(clouds.texture_offset(256.0 + CLOUD_TILE_SIZE * 1000.0, 0.0).x, 0.25);
+
+    /* After a second, what was above (256, 0) is downwind by WIND */
+    clouds.tick(1.0);
+    let offset = clouds.texture_offset(256.0 + WIND.x, WIND.y);
+    assert_eq!(offset, Vector2::new(0.25, 0.0));
+
+    clouds.set_density(2.0);
+    assert_eq!(clouds.density(), 1.0);
+}
@@ -0,0 +1,128 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Color schemes for red-green color blindness.
+//!
+//! A scheme recolors block colors, as they go from the registry into the
+//! block table and everything else drawn in a block's color, and every
+//! color drawn on the UI overlay. It moves the part of a color that a
+//! viewer with the deficiency can't see into green and blue, which they
+//! can, so colors that only differ in red and green, like grass and dirt
+//! or the compass's north marker, stay apart. Grays are left alone.
+
+extern crate cgmath;
+
+use cgmath::vector::{Vector3, Vector4};
+
+use lang;
+
+#[deriving(PartialEq, Eq, Show)]
+pub enum ColorScheme {
+    SchemeNormal,
+    /// For missing or weak green cones, the most common.
+    SchemeDeuteranopia,
+    /// For missing or weak red cones.
+    SchemeProtanopia,
+}
+
+static SCHEMES : [ColorScheme, ..3] = [SchemeNormal, SchemeDeuteranopia, SchemeProtanopia];
+
+/// How colors look with each deficiency, by row of linear RGB (Machado,
+/// Oliveira and Fernandes, 2009, at full severity).
+static DEUTERANOPIA : [[f32, ..3], ..3] = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+];
+static PROTANOPIA : [[f32, ..3], ..3] = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+
+/// Share of the unseen difference that's added to green and to blue.
+static SHIFT : f32 = 0.7;
+
+impl ColorScheme {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SchemeNormal => "normal",
+            SchemeDeuteranopia => "deuteranopia",
+            SchemeProtanopia => "protanopia",
+        }
+    }
+
+    /// The name shown in the menu, in the player's language.
+    pub fn title(&self) -> &'static str {
+        lang::text(match *self {
+            SchemeNormal => "colors.normal",
+            SchemeDeuteranopia => "colors.deuteranopia",
+            SchemeProtanopia => "colors.protanopia",
+        })
+    }
+
+    pub fn from_name(name: &str) -> Option<ColorScheme> {
+        SCHEMES.iter().find(|scheme| scheme.name() == name).map(|&scheme| scheme)
+    }
+
+    /// The scheme after this one, for cycling through them from the menu.
+    pub fn next(&self) -> ColorScheme {
+        let i = SCHEMES.iter().position(|scheme| scheme == self).unwrap();
+        SCHEMES[(i + 1) % SCHEMES.len()]
+    }
+
+    /// Returns color as drawn with this scheme.
+    pub fn apply(&self, color: Vector3<f32>) -> Vector3<f32> {
+        let seen = match *self {
+            SchemeNormal => return color,
+            SchemeDeuteranopia => &DEUTERANOPIA,
+            SchemeProtanopia => &PROTANOPIA,
+        };
+        let row = |i: uint| seen[i][0] * color.x + seen[i][1] * color.y + seen[i][2] * color.z;
+        /* What the viewer misses, which is mostly in red */
+        let lost = Vector3::new(color.x - row(0), color.y - row(1), color.z - row(2));
+        let unit = |v: f32| v.max(0.0).min(1.0);
+        Vector3::new(color.x,
+                     unit(color.y + SHIFT * lost.x + lost.y),
+                     unit(color.z + SHIFT * lost.x + lost.z))
+    }
+
+    /// Like apply(), keeping alpha.
+    pub fn apply_rgba(&self, color: Vector4<f32>) -> Vector4<f32> {
+        let rgb = self.apply(Vector3::new(color.x, color.y, color.z));
+        Vector4::new(rgb.x, rgb.y, rgb.z, color.w)
+    }
+}
+
+#[test]
+fn test_color_scheme() {
+    let red = Vector3::new(1.0f32, 0.0, 0.0);
+    let green = Vector3::new(0.0f32, 1.0, 0.0);
+    assert_eq!(SchemeNormal.apply(red), red);
+    assert_eq!(SchemeNormal.apply_rgba(Vector4::new(0.3, 0.6, 0.1, 0.5)), Vector4::new(0.3, 0.6, 0.1, 0.5));
+
+    for &scheme in [SchemeDeuteranopia, SchemeProtanopia].iter() {
+        /* Red turns toward blue, where it stands out from green */
+        let (r, g) = (scheme.apply(red), scheme.apply(green));
+        assert!(r.z > g.z + 0.3);
+        let gray = scheme.apply_rgba(Vector4::new(0.5, 0.5, 0.5, 0.25));
+        assert!((gray.x - 0.5).abs() < 1e-3 && (gray.y - 0.5).abs() < 1e-3 && (gray.z - 0.5).abs() < 1e-3);
+        assert_eq!(gray.w, 0.25);
+    }
+
+    assert_eq!(ColorScheme::from_name("protanopia"), Some(SchemeProtanopia));
+    assert_eq!(ColorScheme::from_name("purple"), None);
+    assert_eq!(SchemeProtanopia.next(), SchemeNormal);
+    assert_eq!(SchemeNormal.next().next(), SchemeProtanopia);
+}
@@ -0,0 +1,150 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std::collections::{HashMap, TreeMap};
+use std::num::Integer;
+
+use cgmath::vector::Vector3;
+
+use CHUNK_SIZE;
+use CHUNK_SIZEu;
+use coord::{ChunkCoord, FnvHasher};
+use terrain::{Terrain, BlockType, BlockAir};
+
+/// The highest block that isn't air in each column of blocks, over the
+/// loaded chunks.
+///
+/// Each loaded chunk keeps the local y of the top block in each of its
+/// columns, so a query only looks at the chunks stacked in one chunk
+/// column, from the top down, instead of at blocks. Unloaded chunks count
+/// as empty.
+pub struct ColumnIndex {
+    /// By chunk column (y is always 0), then by chunk y: the top of each
+    /// column of the chunk, by column_index(), or -1 if it's all air.
+    chunks: HashMap<ChunkCoord, TreeMap<i64, Vec<i8>>, FnvHasher>,
+}
+
+impl ColumnIndex {
+    pub fn new() -> ColumnIndex {
+        ColumnIndex { chunks: HashMap::with_hasher(FnvHasher) }
+    }
+
+    /// Indexes the terrain of chunk c, replacing what was there.
+    pub fn insert(&mut self, c: Vector3<i64>, t: &Terrain) {
+        let mut tops = Vec::with_capacity(CHUNK_SIZEu * CHUNK_SIZEu);
+        for x in range(0, CHUNK_SIZE) {
+            for z in range(0, CHUNK_SIZE) {
+                tops.push(column_top(t, x, z, CHUNK_SIZE - 1));
+            }
+        }
+        self.chunks.find_or_insert_with(column_key(c), |_| TreeMap::new()).insert(c.y, tops);
+    }
+
+    pub fn remove(&mut self, c: Vector3<i64>) {
+        let key = column_key(c);
+        let empty = match self.chunks.find_mut(&key) {
+            Some(stack) => {
+                stack.remove(&c.y);
+                stack.is_empty()
+            },
+            None => false,
+        };
+        if empty {
+            self.chunks.remove(&key);
+        }
+    }
+
+    /// Records that block (x, y, z) of chunk c, whose terrain is t, was set
+    /// to blocktype.
+    pub fn set_block(&mut self, c: Vector3<i64>, t: &Terrain, x: int, y: int, z: int, blocktype: BlockType) {
+        let tops = match self.chunks.find_mut(&column_key(c)).and_then(|stack| stack.find_mut(&c.y)) {
+            Some(tops) => tops,
+            None => return,
+        };
+        let top = tops.get_mut(column_index(x, z));
+        if blocktype != BlockAir {
+            if y > *top as int {
+                *top = y as i8;
+            }
+        } else if y == *top as int {
+            *top = column_top(t, x, z, y - 1);
+        }
+    }
+
+    /// Returns the y of the highest block that isn't air in world column
+    /// (x, z), if any loaded chunk has one.
+    pub fn top(&self, x: i64, z: i64) -> Option<i64> {
+        let c = Vector3::new(x.div_floor(&(CHUNK_SIZE as i64)), 0, z.div_floor(&(CHUNK_SIZE as i64)));
+        let i = column_index((x - c.x * CHUNK_SIZE as i64) as int, (z - c.z * CHUNK_SIZE as i64) as int);
+        let stack = match self.chunks.find(&column_key(c)) {
+            Some(stack) => stack,
+            None => return None,
+        };
+        for (&cy, tops) in stack.rev_iter() {
+            if tops[i] >= 0 {
+                return Some(cy * CHUNK_SIZE as i64 + tops[i] as i64);
+            }
+        }
+        None
+    }
+}
+
+fn column_key(c: Vector3<i64>) -> ChunkCoord {
+    ChunkCoord { x: c.x, y: 0, z: c.z }
+}
+
+fn column_index(x: int, z: int) -> uint {
+    x as uint * CHUNK_SIZEu + z as uint
+}
+
+/// Returns the highest y from 0 to from of a block in column (x, z) of t
+/// that isn't air, or -1.
+fn column_top(t: &Terrain, x: int, z: int, from: int) -> i8 {
+    let mut y = from;
+    while y >= 0 && t.get(x, y, z).blocktype == BlockAir {
+        y -= 1;
+    }
+    y as i8
+}
+
+#[test]
+fn test_column_index() {
+    use terrain::{BlockStone, BlockWater};
+
+    let mut index = ColumnIndex::new();
+    let mut low = Terrain::new();
+    low.set(1, 5, 2, BlockStone);
+    low.set(1, 9, 2, BlockWater);
+    let mut high = Terrain::new();
+    high.set(1, 3, 2, BlockStone);
+    index.insert(Vector3::new(-1, -1, 0), &*low);
+    index.insert(Vector3::new(-1, 1, 0), &*high);
+
+    let (x, z) = (1 - CHUNK_SIZE as i64, 2);
+    assert_eq!(index.top(x, z), Some(CHUNK_SIZE as i64 + 3));
+    assert_eq!(index.top(x + 1, z), None);
+
+    /* Digging out the top block finds the next one down */
+    high.set(1, 3, 2, BlockAir);
+    index.set_block(Vector3::new(-1, 1, 0), &*high, 1, 3, 2, BlockAir);
+    assert_eq!(index.top(x, z), Some(9 - CHUNK_SIZE as i64));
+    low.set(1, 9, 2, BlockAir);
+    index.set_block(Vector3::new(-1, -1, 0), &*low, 1, 9, 2, BlockAir);
+    assert_eq!(index.top(x, z), Some(5 - CHUNK_SIZE as i64));
+
+    index.remove(Vector3::new(-1, -1, 0));
+    assert_eq!(index.top(x, z), None);
+}
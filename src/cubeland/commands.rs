@@ -0,0 +1,244 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Console commands, typed at the chat prompt after a '/', on stdin or over
+//! an admin connection.
+//!
+//! parse() checks a command's words and arguments without touching the game,
+//! and main.rs carries it out.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector3;
+
+use camera::{MIN_SENSITIVITY, MAX_SENSITIVITY};
+use entity::EntityKind;
+use photo;
+use weather::Weather;
+
+/// Most entities one spawn command makes, so a typo can't flood the world
+/// and its save file.
+pub static MAX_SPAWN_COUNT : uint = 100;
+
+/// Chunks on each side of the camera's that 'stats blocks' covers by
+/// default.
+static DEFAULT_STATS_RADIUS : i64 = 4;
+
+#[deriving(PartialEq, Show)]
+pub enum Command {
+    CommandWorlds,
+    /// Name and seed, or None for the default seed.
+    CommandCreateWorld(String, Option<u32>),
+    CommandLoadWorld(String),
+    CommandDeleteWorld(String),
+    /// To the surface of column (x, z).
+    CommandTeleportSurface(f64, f64),
+    /// To the nearest clear space to (x, y, z).
+    CommandTeleport(f64, f64, f64),
+    CommandBorder,
+    CommandPlayers,
+    CommandSave,
+    CommandCreative(bool),
+    CommandShowPermissions(String),
+    /// Player name, flag name and value. Unknown flags are only caught when
+    /// setting them.
+    CommandSetPermission(String, String, bool),
+    CommandSpawn(EntityKind, uint),
+    CommandEntities,
+    /// Radius in chunks.
+    CommandBlockStats(i64),
+    CommandUpdates,
+    CommandJournal,
+    /// Journal entries to keep.
+    CommandRewind(uint),
+    CommandEntityDebug(bool),
+    CommandSimPaused(bool),
+    /// Ticks to step by.
+    CommandSimStep(uint),
+    CommandVoxelTextures(bool),
+    CommandDebugWindow(bool),
+    CommandHorizon(bool),
+    CommandClouds(bool),
+    CommandCloudHeight(f64),
+    CommandCloudDensity(f32),
+    CommandShowWeather,
+    /// None goes back to the weather changing by itself.
+    CommandForceWeather(Option<Weather>),
+    CommandBobbing(bool),
+    CommandShowSensitivity,
+    CommandSensitivity(f64),
+    CommandInvertY(bool),
+    /// Seconds, or None for no smoothing.
+    CommandSmoothing(Option<f64>),
+    CommandPhoto,
+    CommandPhotoScale(u32),
+    CommandShowPath,
+    CommandPathAdd,
+    CommandPathUndo,
+    CommandPathClear,
+    /// Seconds the path takes.
+    CommandPathPlay(f64),
+    CommandPathStop,
+    CommandSun(Vector3<f32>),
+}
+
+/// Parses a command, or returns the message to show for a bad one.
+pub fn parse(words: &[&str]) -> Result<Command, String> {
+    let usage = |text: &str| Err(format!("Usage: {}", text));
+
+    match words {
+        ["worlds"] => Ok(CommandWorlds),
+        ["world", "create", name] => Ok(CommandCreateWorld(name.to_string(), None)),
+        ["world", "create", name, seed] => match from_str::<u32>(seed) {
+            Some(seed) => Ok(CommandCreateWorld(name.to_string(), Some(seed))),
+            None => Err(format!("Invalid seed {}", seed)),
+        },
+        ["world", "load", name] => Ok(CommandLoadWorld(name.to_string())),
+        ["world", "delete", name] => Ok(CommandDeleteWorld(name.to_string())),
+        ["tp", x, z] => match (from_str::<f64>(x), from_str::<f64>(z)) {
+            (Some(x), Some(z)) => Ok(CommandTeleportSurface(x, z)),
+            _ => usage("tp X Z or tp X Y Z"),
+        },
+        ["tp", x, y, z] => match (from_str::<f64>(x), from_str::<f64>(y), from_str::<f64>(z)) {
+            (Some(x), Some(y), Some(z)) => Ok(CommandTeleport(x, y, z)),
+            _ => usage("tp X Z or tp X Y Z"),
+        },
+        ["border"] => Ok(CommandBorder),
+        ["players"] => Ok(CommandPlayers),
+        ["save"] => Ok(CommandSave),
+        ["gamemode", "creative"] => Ok(CommandCreative(true)),
+        ["gamemode", "survival"] => Ok(CommandCreative(false)),
+        ["gamemode", _] => usage("gamemode creative|survival"),
+        ["perm", name] => Ok(CommandShowPermissions(name.to_string())),
+        ["perm", name, flag, "on"] => Ok(CommandSetPermission(name.to_string(), flag.to_string(), true)),
+        ["perm", name, flag, "off"] => Ok(CommandSetPermission(name.to_string(), flag.to_string(), false)),
+        ["perm", _, _, _] => usage("perm NAME FLAG on|off"),
+        ["spawn", name] | ["spawn", name, _] => {
+            let count = if words.len() == 3 { from_str::<uint>(words[2]) } else { Some(1) };
+            match (EntityKind::from_name(name), count) {
+                (Some(kind), Some(count)) if count <= MAX_SPAWN_COUNT => Ok(CommandSpawn(kind, count)),
+                _ => Err(format!("Usage: spawn pig|sheep|BLOCK [COUNT], with COUNT up to {}", MAX_SPAWN_COUNT)),
+            }
+        },
+        ["entities"] => Ok(CommandEntities),
+        ["stats", "blocks"] => Ok(CommandBlockStats(DEFAULT_STATS_RADIUS)),
+        ["stats", "blocks", "radius", r] => match from_str::<i64>(r) {
+            Some(r) if r >= 0 => Ok(CommandBlockStats(r)),
+            _ => usage("stats blocks [radius CHUNKS]"),
+        },
+        ["updates"] => Ok(CommandUpdates),
+        ["journal"] => Ok(CommandJournal),
+        ["journal", "rewind", len] => match from_str::<uint>(len) {
+            Some(len) => Ok(CommandRewind(len)),
+            None => usage("journal rewind ENTRIES"),
+        },
+        ["entities", "debug", "on"] => Ok(CommandEntityDebug(true)),
+        ["entities", "debug", "off"] => Ok(CommandEntityDebug(false)),
+        ["sim", "pause"] => Ok(CommandSimPaused(true)),
+        ["sim", "resume"] => Ok(CommandSimPaused(false)),
+        ["sim", "step"] | ["sim", "step", _] => {
+            let count = if words.len() == 3 { from_str::<uint>(words[2]) } else { Some(1) };
+            match count {
+                Some(count) => Ok(CommandSimStep(count)),
+                None => usage("sim step [TICKS]"),
+            }
+        },
+        ["graphics", "voxels", "on"] => Ok(CommandVoxelTextures(true)),
+        ["graphics", "voxels", "off"] => Ok(CommandVoxelTextures(false)),
+        ["debug", "window", "on"] => Ok(CommandDebugWindow(true)),
+        ["debug", "window", "off"] => Ok(CommandDebugWindow(false)),
+        ["horizon", "on"] => Ok(CommandHorizon(true)),
+        ["horizon", "off"] => Ok(CommandHorizon(false)),
+        ["clouds", "on"] => Ok(CommandClouds(true)),
+        ["clouds", "off"] => Ok(CommandClouds(false)),
+        ["clouds", "height", h] => match from_str::<f64>(h) {
+            Some(h) => Ok(CommandCloudHeight(h)),
+            None => usage("clouds height BLOCKS"),
+        },
+        ["clouds", "density", d] => match from_str::<f32>(d) {
+            Some(d) => Ok(CommandCloudDensity(d)),
+            None => usage("clouds density 0-1"),
+        },
+        ["weather"] => Ok(CommandShowWeather),
+        ["weather", "auto"] => Ok(CommandForceWeather(None)),
+        ["weather", name] => match Weather::from_name(name) {
+            Some(w) => Ok(CommandForceWeather(Some(w))),
+            None => usage("weather clear|rain|snow|auto"),
+        },
+        ["camera", "bobbing", "on"] => Ok(CommandBobbing(true)),
+        ["camera", "bobbing", "off"] => Ok(CommandBobbing(false)),
+        ["camera", "sensitivity"] => Ok(CommandShowSensitivity),
+        ["camera", "sensitivity", s] => match from_str::<f64>(s) {
+            Some(s) if s >= MIN_SENSITIVITY && s <= MAX_SENSITIVITY => Ok(CommandSensitivity(s)),
+            _ => Err(format!("Usage: camera sensitivity {}-{}", MIN_SENSITIVITY, MAX_SENSITIVITY)),
+        },
+        ["camera", "invert", "on"] => Ok(CommandInvertY(true)),
+        ["camera", "invert", "off"] => Ok(CommandInvertY(false)),
+        ["camera", "smoothing", "off"] => Ok(CommandSmoothing(None)),
+        ["camera", "smoothing", t] => match from_str::<f64>(t) {
+            Some(t) if t > 0.0 => Ok(CommandSmoothing(Some(t))),
+            _ => usage("camera smoothing SECONDS|off"),
+        },
+        ["photo"] => Ok(CommandPhoto),
+        ["photo", "scale", n] => match from_str::<u32>(n) {
+            Some(n) if n >= photo::MIN_SCALE && n <= photo::MAX_SCALE => Ok(CommandPhotoScale(n)),
+            _ => Err(format!("Usage: photo scale {}-{}", photo::MIN_SCALE, photo::MAX_SCALE)),
+        },
+        ["path"] => Ok(CommandShowPath),
+        ["path", "add"] => Ok(CommandPathAdd),
+        ["path", "undo"] => Ok(CommandPathUndo),
+        ["path", "clear"] => Ok(CommandPathClear),
+        ["path", "play", t] => match from_str::<f64>(t) {
+            Some(t) if t >= 0.0 => Ok(CommandPathPlay(t)),
+            _ => usage("path play SECONDS"),
+        },
+        ["path", "stop"] => Ok(CommandPathStop),
+        ["sun", x, y, z] => match (from_str::<f32>(x), from_str::<f32>(y), from_str::<f32>(z)) {
+            (Some(x), Some(y), Some(z)) if y < 0.0 => Ok(CommandSun(Vector3::new(x, y, z))),
+            _ => usage("sun X Y Z, with Y negative"),
+        },
+        _ => Err(format!("Unknown command: {}", words.connect(" "))),
+    }
+}
+
+#[test]
+fn test_parse() {
+    let parse_line = |line: &str| {
+        let words: Vec<&str> = line.words().collect();
+        parse(words.as_slice())
+    };
+
+    assert_eq!(parse_line("world create test 7"), Ok(CommandCreateWorld("test".to_string(), Some(7))));
+    assert_eq!(parse_line("world create test x"), Err("Invalid seed x".to_string()));
+    assert_eq!(parse_line("tp 1 -2.5"), Ok(CommandTeleportSurface(1.0, -2.5)));
+    assert_eq!(parse_line("tp 1 2 z"), Err("Usage: tp X Z or tp X Y Z".to_string()));
+    assert_eq!(parse_line("perm alice edit off"),
+               Ok(CommandSetPermission("alice".to_string(), "edit".to_string(), false)));
+    assert_eq!(parse_line("perm alice edit maybe"), Err("Usage: perm NAME FLAG on|off".to_string()));
+    assert_eq!(parse_line("gamemode survival"), Ok(CommandCreative(false)));
+    assert_eq!(parse_line("stats blocks"), Ok(CommandBlockStats(DEFAULT_STATS_RADIUS)));
+    assert_eq!(parse_line("stats blocks radius -1"), Err("Usage: stats blocks [radius CHUNKS]".to_string()));
+    assert_eq!(parse_line("sim step"), Ok(CommandSimStep(1)));
+    assert_eq!(parse_line("clouds off"), Ok(CommandClouds(false)));
+    assert_eq!(parse_line("camera smoothing off"), Ok(CommandSmoothing(None)));
+    assert_eq!(parse_line("camera smoothing 0"), Err("Usage: camera smoothing SECONDS|off".to_string()));
+    assert_eq!(parse_line("weather auto"), Ok(CommandForceWeather(None)));
+    assert_eq!(parse_line("sun 0 -1 0"), Ok(CommandSun(Vector3::new(0.0, -1.0, 0.0))));
+    assert_eq!(parse_line("sun 0 1 0"), Err("Usage: sun X Y Z, with Y negative".to_string()));
+    assert_eq!(parse_line("horizon maybe"), Err("Unknown command: horizon maybe".to_string()));
+
+    assert_eq!(parse_line("spawn pig 3"), Ok(CommandSpawn(::entity::Pig, 3)));
+    assert!(parse_line(format!("spawn pig {}", MAX_SPAWN_COUNT + 1).as_slice()).is_err());
+    assert!(parse_line("spawn dragon").is_err());
+}
@@ -0,0 +1,105 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compass strip and coordinates at the top of the window.
+//!
+//! North is -z and east is +x, so the heading in degrees clockwise from
+//! north is the camera's yaw.
+
+extern crate cgmath;
+
+use std::f64::consts::PI;
+
+use cgmath::vector::{Vector3, Vector4};
+
+use ui;
+use ui::Ui;
+
+/// Degrees of heading across the whole strip.
+static STRIP_DEGREES : f64 = 180.0;
+static PIXELS_PER_DEGREE : f32 = 2.0;
+static TICK_DEGREES : int = 15;
+static MARGIN : f32 = 8.0;
+
+static LABELS : [&'static str, ..8] = ["N", "NE", "E", "SE", "S", "SW", "W", "NW"];
+
+/// Returns the heading in degrees clockwise from north, from 0 up to 360,
+/// for a yaw in radians.
+pub fn heading(yaw: f64) -> f64 {
+    let degrees = (yaw * 180.0 / PI) % 360.0;
+    if degrees < 0.0 { degrees + 360.0 } else { degrees }
+}
+
+/// Returns the ticks within the strip for heading, as degrees right of the
+/// middle and the label at that tick, if any.
+fn ticks(heading: f64) -> Vec<(f64, Option<&'static str>)> {
+    let half = STRIP_DEGREES / 2.0;
+    let first = ((heading - half) / TICK_DEGREES as f64).ceil() as int * TICK_DEGREES;
+    let mut ticks = Vec::new();
+    let mut degrees = first;
+    while degrees as f64 <= heading + half {
+        let wrapped = ((degrees % 360) + 360) % 360;
+        let label = if wrapped % 45 == 0 { Some(LABELS[(wrapped / 45) as uint]) } else { None };
+        ticks.push((degrees as f64 - heading, label));
+        degrees += TICK_DEGREES;
+    }
+    ticks
+}
+
+/// Queues the strip for yaw at the top middle of the window, with the
+/// block containing position and the heading under it.
+pub fn draw(ui: &mut Ui, yaw: f64, position: Vector3<f64>) {
+    let heading = heading(yaw);
+    let window_size = ui.window_size();
+    let width = STRIP_DEGREES as f32 * PIXELS_PER_DEGREE;
+    let middle = window_size.x as f32 / 2.0;
+    let height = ui::line_height() * 1.5;
+    let white = Vector4::new(1.0, 1.0, 1.0, 1.0);
+
+    ui.rect(middle - width / 2.0, MARGIN, width, height, Vector4::new(0.0, 0.0, 0.0, 0.5));
+    for &(offset, label) in ticks(heading).iter() {
+        let x = middle + offset as f32 * PIXELS_PER_DEGREE;
+        match label {
+            Some(label) => {
+                let color = if label == "N" { Vector4::new(1.0, 0.4, 0.3, 1.0) } else { white };
+                ui.text(x - ui::text_width(label) / 2.0, MARGIN + 2.0, label, color);
+            },
+            None => ui.rect(x - 1.0, MARGIN + height - 8.0, 2.0, 6.0, white),
+        }
+    }
+    ui.rect(middle - 1.0, MARGIN + height - 2.0, 2.0, 6.0, Vector4::new(1.0, 1.0, 0.6, 1.0));
+
+    let line = format!("{} {} {}  {:.0f}", position.x.floor() as i64, position.y.floor() as i64,
+                       position.z.floor() as i64, heading);
+    let y = MARGIN + height + 6.0;
+    let x = middle - ui::text_width(line.as_slice()) / 2.0;
+    ui.rect(x - 2.0, y, ui::text_width(line.as_slice()) + 4.0, ui::line_height(),
+            Vector4::new(0.0, 0.0, 0.0, 0.5));
+    ui.text(x, y, line.as_slice(), white);
+}
+
+#[test]
+fn test_heading() {
+    assert_eq!(heading(0.0), 0.0);
+    assert!((heading(PI / 2.0) - 90.0).abs() < 1e-9);
+    assert!((heading(-PI / 2.0) - 270.0).abs() < 1e-9);
+    assert!((heading(5.0 * PI) - 180.0).abs() < 1e-9);
+
+    /* Facing east, north is at the left edge and south at the right */
+    let labels: Vec<(f64, &'static str)> = ticks(90.0).iter().filter_map(|&(offset, label)| {
+        label.map(|label| (offset, label))
+    }).collect();
+    assert_eq!(labels, vec!((-90.0, "N"), (-45.0, "NE"), (0.0, "E"), (45.0, "SE"), (90.0, "S")));
+    assert!(ticks(350.0).iter().any(|&(offset, label)| offset == 10.0 && label == Some("N")));
+}
@@ -0,0 +1,169 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::comm::{Receiver, Sender, channel};
+use std::io;
+use std::io::{BufferedReader, IoResult, Listener, Acceptor};
+use std::io::net::tcp::{TcpListener, TcpStream};
+use std::io::stdio;
+
+/// Only local connections are accepted; tunnel over SSH to administer a
+/// game on another machine.
+static ADMIN_ADDRESS : &'static str = "127.0.0.1";
+
+/// Line-based command console reading from stdin, and optionally from
+/// admin connections over TCP.
+///
+/// Lines are read on separate tasks so the main loop can poll for commands
+/// without blocking.
+pub struct Console {
+    lines: Receiver<String>,
+    /// For the admin connections' tasks.
+    sender: Sender<String>,
+}
+
+/// Sent to the task that copies output to the admin connections.
+enum AdminEvent {
+    AdminConnected(TcpStream),
+    AdminOutput(Vec<u8>),
+}
+
+impl Console {
+    pub fn new() -> Console {
+        let (tx, rx) = channel();
+        let stdin_tx = tx.clone();
+
+        spawn(proc() {
+            for line in io::stdin().lines() {
+                match line {
+                    Ok(line) => {
+                        if stdin_tx.send_opt(line).is_err() {
+                            break;
+                        }
+                    },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Console { lines: rx, sender: tx }
+    }
+
+    /// Accepts admin connections on port. A connection's first line must be
+    /// password; after that each line is a command, like one typed on stdin,
+    /// and everything the calling task prints is copied to the connection.
+    /// Call on the main loop's task.
+    pub fn listen(&self, port: u16, password: String) -> Result<(), String> {
+        let mut acceptor = match TcpListener::bind(ADMIN_ADDRESS, port).listen() {
+            Ok(acceptor) => acceptor,
+            Err(e) => return Err(format!("{}:{}: {}", ADMIN_ADDRESS, port, e)),
+        };
+
+        let (output_tx, output_rx) = channel();
+        spawn(proc() broadcast(output_rx));
+        stdio::set_stdout(box AdminWriter { output: output_tx.clone() });
+
+        let lines = self.sender.clone();
+        spawn(proc() {
+            for stream in acceptor.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let lines = lines.clone();
+                        let output = output_tx.clone();
+                        let password = password.clone();
+                        spawn(proc() serve(stream, password, lines, output));
+                    },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Returns the words of the next pending command, if any.
+    pub fn poll(&self) -> Option<Vec<String>> {
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => {
+                    let words: Vec<String> = line.as_slice().words().map(|w| w.to_string()).collect();
+                    if !words.is_empty() {
+                        return Some(words);
+                    }
+                },
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// Reads one admin connection's lines into the console after checking its
+/// password.
+fn serve(stream: TcpStream, password: String, lines: Sender<String>, output: Sender<AdminEvent>) {
+    let mut writer = stream.clone();
+    let mut reader = BufferedReader::new(stream.clone());
+
+    let authenticated = match reader.read_line() {
+        Ok(line) => line.as_slice().trim() == password.as_slice(),
+        Err(_) => false,
+    };
+    if !authenticated {
+        let _ = writer.write_line("Wrong password");
+        return;
+    }
+    let _ = writer.write_line("Ready");
+    if output.send_opt(AdminConnected(stream)).is_err() {
+        return;
+    }
+
+    for line in reader.lines() {
+        match line {
+            Ok(line) => {
+                if lines.send_opt(line.as_slice().trim_right().to_string()).is_err() {
+                    break;
+                }
+            },
+            Err(_) => break,
+        }
+    }
+}
+
+/// Copies output to every admin connection, dropping the ones that fail.
+fn broadcast(events: Receiver<AdminEvent>) {
+    let mut streams: Vec<TcpStream> = Vec::new();
+    for event in events.iter() {
+        match event {
+            AdminConnected(stream) => streams.push(stream),
+            AdminOutput(bytes) => {
+                streams.retain(|stream| {
+                    let mut stream = stream.clone();
+                    stream.write(bytes.as_slice()).is_ok()
+                });
+            },
+        }
+    }
+}
+
+/// Stdout of the main task while admin connections are accepted: writes to
+/// the real stdout and copies to the connections.
+struct AdminWriter {
+    output: Sender<AdminEvent>,
+}
+
+impl Writer for AdminWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        let _ = self.output.send_opt(AdminOutput(buf.to_vec()));
+        stdio::stdout_raw().write(buf)
+    }
+}
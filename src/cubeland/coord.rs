@@ -0,0 +1,75 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std::hash::{Hash, Hasher};
+use std::io::IoResult;
+
+use cgmath::vector::Vector3;
+
+/// Key for chunk-indexed maps and sets.
+#[deriving(PartialEq, Eq, Hash, Clone, Show)]
+pub struct ChunkCoord {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+}
+
+impl ChunkCoord {
+    pub fn from_vec(v: Vector3<i64>) -> ChunkCoord {
+        ChunkCoord { x: v.x, y: v.y, z: v.z }
+    }
+
+    pub fn to_vec(&self) -> Vector3<i64> {
+        Vector3::new(self.x, self.y, self.z)
+    }
+}
+
+/// FNV-1a hasher. Much cheaper than the default SipHash for the small,
+/// trusted keys used in the chunk cache.
+#[deriving(Clone)]
+pub struct FnvHasher;
+
+pub struct FnvState(u64);
+
+impl Hasher<FnvState> for FnvHasher {
+    fn hash<T: Hash<FnvState>>(&self, t: &T) -> u64 {
+        let mut state = FnvState(0xcbf29ce484222325);
+        t.hash(&mut state);
+        let FnvState(ret) = state;
+        ret
+    }
+}
+
+impl Writer for FnvState {
+    fn write(&mut self, bytes: &[u8]) -> IoResult<()> {
+        let FnvState(mut hash) = *self;
+        for byte in bytes.iter() {
+            hash = hash ^ (*byte as u64);
+            hash = hash * 0x100000001b3;
+        }
+        *self = FnvState(hash);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_fnv_hasher() {
+    let a = FnvHasher.hash(&ChunkCoord { x: 1, y: 2, z: 3 });
+    let b = FnvHasher.hash(&ChunkCoord { x: 1, y: 2, z: 3 });
+    let c = FnvHasher.hash(&ChunkCoord { x: 3, y: 2, z: 1 });
+    assert_eq!(a, b);
+    assert!(a != c);
+}
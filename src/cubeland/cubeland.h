@@ -0,0 +1,61 @@
+/*
+ * Copyright 2014 Rich Lane.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*
+ * Cubeland's terrain generator, from libcubelandgen. See ffi.rs.
+ */
+
+#ifndef CUBELAND_H
+#define CUBELAND_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct cubeland_generator cubeland_generator;
+
+/* Chunk edge length in blocks the library was built with. */
+int cubeland_chunk_size(void);
+
+/*
+ * Generator for the world with this seed and vertical extent in blocks,
+ * with lava at the default depth. Returns NULL unless bottom < top.
+ */
+cubeland_generator *cubeland_generator_new(uint32_t seed, int64_t bottom, int64_t top);
+void cubeland_generator_free(cubeland_generator *generator);
+
+/*
+ * Writes the blocks of chunk (x, y, z) to out, which holds len bytes, at
+ * least cubeland_chunk_size() cubed. Block (bx, by, bz) of the chunk goes to
+ * out[(bx * size + by) * size + bz]: 0 air, 1 grass, 2 stone, 3 dirt,
+ * 4 water, 5 a layer of snow, 6 sand, 7 lava. Returns 0, or -1 if out is
+ * too small.
+ */
+int cubeland_generate_chunk(const cubeland_generator *generator,
+                            int64_t x, int64_t y, int64_t z,
+                            uint8_t *out, size_t len);
+
+/* Height of the surface at column (x, z), before caves are carved out. */
+double cubeland_surface_height(const cubeland_generator *generator, int64_t x, int64_t z);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
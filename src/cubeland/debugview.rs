@@ -0,0 +1,293 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A second window with a live top-down map of the chunk loader, for
+//! debugging streaming and culling.
+//!
+//! Every chunk the loader knows about is drawn as a translucent square
+//! colored by its state, so columns with more chunks look more solid. The
+//! view frustum is drawn over it as lines. North (-z) is up and the camera
+//! is always in the middle.
+//!
+//! The window's context shares objects with the main one, so it reuses the
+//! line shaders instead of having its own.
+
+extern crate gl;
+extern crate glfw;
+extern crate hgl;
+extern crate cgmath;
+
+use std::mem;
+use std::ptr;
+
+use gl::types::*;
+
+use glfw::Context;
+
+use cgmath::matrix::{Matrix, Matrix4};
+use cgmath::array::Array2;
+use cgmath::vector::Vector2;
+use cgmath::vector::Vector3;
+use cgmath::vector::Vector4;
+
+use chunk;
+use chunk::{ChunkLoader, ChunkState, ChunkQueued, ChunkInflight, ChunkCoarse, ChunkLoaded, ChunkDirty, ChunkFailed};
+use renderer::load_program;
+use CHUNK_SIZE;
+use VISIBLE_RADIUS;
+
+static WINDOW_SIZE : Vector2<u32> = Vector2 { x: 512, y: 512 };
+
+/// Chunks shown between the camera and the nearest edge of the window.
+static VIEW_RADIUS : f32 = VISIBLE_RADIUS as f32 + 2.0;
+
+/// Blocks left empty around each chunk's square, so the grid shows.
+static CHUNK_GAP : f32 = 1.0;
+
+static BACKGROUND_COLOR : Vector4<f32> = Vector4 { x: 0.1, y: 0.1, z: 0.1, w: 1.0 };
+static FRUSTUM_COLOR : Vector4<f32> = Vector4 { x: 1.0, y: 1.0, z: 1.0, w: 1.0 };
+
+/// Layout of the line program's vertex buffer. Positions are in blocks from
+/// the camera, with y pointing north.
+struct MapVertex {
+    position: Vector3<f32>,
+    color: Vector4<f32>,
+}
+
+pub struct DebugView {
+    window: glfw::Window,
+    events: Receiver<(f64, glfw::WindowEvent)>,
+    program: GLuint,
+    vs: GLuint,
+    fs: GLuint,
+    uniform_view: GLint,
+    uniform_projection: GLint,
+    attr_position: GLuint,
+    attr_color: GLuint,
+}
+
+impl DebugView {
+    /// Opens the window. The main window's context is current again when
+    /// this returns.
+    pub fn open(glfw: &glfw::Glfw, main: &glfw::Window) -> Result<DebugView, String> {
+        let (window, events) = match main.create_shared(WINDOW_SIZE.x, WINDOW_SIZE.y,
+                                                         "Cubeland chunks", glfw::Windowed) {
+            Some(x) => x,
+            None => return Err("failed to create GLFW window".to_string()),
+        };
+
+        window.make_current();
+        /* Don't let this window's buffer swaps wait for vsync on top of the main one's */
+        glfw.set_swap_interval(0);
+        main.make_current();
+
+        let (program, vs, fs) = try!(load_program("line"));
+        let uniform_view = unsafe { "view".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_projection = unsafe { "projection".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
+        assert!(attr_position as u32 != gl::INVALID_VALUE);
+        let attr_color = unsafe { "color".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
+        assert!(attr_color as u32 != gl::INVALID_VALUE);
+
+        Ok(DebugView {
+            window: window,
+            events: events,
+            program: program,
+            vs: vs,
+            fs: fs,
+            uniform_view: uniform_view,
+            uniform_projection: uniform_projection,
+            attr_position: attr_position,
+            attr_color: attr_color,
+        })
+    }
+
+    pub fn should_close(&self) -> bool {
+        self.window.should_close()
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.window.is_focused()
+    }
+
+    /// Draws the map around the camera and the given frustum corners, as
+    /// returned by Renderer::frustum_corners(), and switches back to the
+    /// main window's context.
+    pub fn draw(&mut self, main: &glfw::Window, chunk_loader: &ChunkLoader,
+                camera_position: Vector3<f64>, frustum: &[Vector3<f64>]) {
+        for _ in glfw::flush_messages(&self.events) {}
+
+        self.window.make_current();
+
+        let (w, h) = self.window.get_framebuffer_size();
+        gl::Viewport(0, 0, w as GLint, h as GLint);
+        gl::ClearColor(BACKGROUND_COLOR.x, BACKGROUND_COLOR.y, BACKGROUND_COLOR.z, BACKGROUND_COLOR.w);
+        gl::Clear(gl::COLOR_BUFFER_BIT);
+
+        let squares = chunk_vertices(chunk_loader, camera_position);
+        let lines = frustum_vertices(chunk_loader, camera_position, frustum);
+        let projection = map_projection(w as f32, h as f32);
+        let view = Matrix4::<f32>::identity();
+
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::UseProgram(self.program);
+        unsafe {
+            gl::UniformMatrix4fv(self.uniform_projection, 1, gl::FALSE, projection.ptr());
+            gl::UniformMatrix4fv(self.uniform_view, 1, gl::FALSE, view.ptr());
+        }
+        gl::EnableVertexAttribArray(self.attr_position);
+        gl::EnableVertexAttribArray(self.attr_color);
+
+        self.draw_vertices(squares.as_slice(), gl::TRIANGLES);
+        self.draw_vertices(lines.as_slice(), gl::LINES);
+
+        gl::DisableVertexAttribArray(self.attr_position);
+        gl::DisableVertexAttribArray(self.attr_color);
+        gl::UseProgram(0);
+        gl::Disable(gl::BLEND);
+
+        self.window.swap_buffers();
+        main.make_current();
+    }
+
+    fn draw_vertices(&self, vertices: &[MapVertex], mode: GLenum) {
+        if vertices.is_empty() {
+            return;
+        }
+
+        let vbo = hgl::Vbo::from_data(vertices, hgl::StreamDraw);
+        let stride = mem::size_of::<MapVertex>() as GLint;
+        vbo.bind();
+        unsafe {
+            gl::VertexAttribPointer(self.attr_position, 3, gl::FLOAT,
+                                    gl::FALSE as GLboolean, stride,
+                                    ptr::null());
+            gl::VertexAttribPointer(self.attr_color, 4, gl::FLOAT,
+                                    gl::FALSE as GLboolean, stride,
+                                    mem::transmute(offset_of!(MapVertex, color)));
+        }
+        gl::DrawArrays(mode, 0, vertices.len() as GLint);
+    }
+}
+
+impl Drop for DebugView {
+    fn drop(&mut self) {
+        /* Shared with the main context, which is current */
+        gl::DeleteProgram(self.program);
+        gl::DeleteShader(self.vs);
+        gl::DeleteShader(self.fs);
+    }
+}
+
+fn state_color(state: ChunkState) -> Vector4<f32> {
+    match state {
+        ChunkQueued => Vector4::new(0.5, 0.5, 0.5, 0.3),
+        ChunkInflight => Vector4::new(1.0, 1.0, 0.0, 0.5),
+        ChunkCoarse => Vector4::new(1.0, 0.5, 0.0, 0.3),
+        ChunkLoaded => Vector4::new(0.0, 0.8, 0.2, 0.3),
+        ChunkDirty => Vector4::new(1.0, 0.0, 0.0, 0.5),
+        ChunkFailed => Vector4::new(1.0, 0.0, 1.0, 0.5),
+    }
+}
+
+/// Maps blocks from the camera to clip space, keeping squares square and
+/// VIEW_RADIUS chunks in view along the window's shorter side.
+fn map_projection(width: f32, height: f32) -> Matrix4<f32> {
+    let s = 1.0 / (VIEW_RADIUS * CHUNK_SIZE as f32);
+    let (sx, sy) = if width > height {
+        (s * height / width, s)
+    } else {
+        (s, s * width / height)
+    };
+    Matrix4::new(sx, 0.0, 0.0, 0.0,
+                 0.0, sy, 0.0, 0.0,
+                 0.0, 0.0, 1.0, 0.0,
+                 0.0, 0.0, 0.0, 1.0)
+}
+
+/// Returns two triangles per known chunk, positioned like MapVertex.
+fn chunk_vertices(chunk_loader: &ChunkLoader, camera_position: Vector3<f64>) -> Vec<MapVertex> {
+    let camera_chunk = chunk::containing_chunk(camera_position);
+    let size = CHUNK_SIZE as f64;
+    /* The camera's position within its chunk's column */
+    let local_x = (camera_position.x - camera_chunk.x as f64 * size) as f32;
+    let local_z = (camera_position.z - camera_chunk.z as f64 * size) as f32;
+
+    let states = chunk_loader.chunk_states();
+    let mut vertices = Vec::with_capacity(states.len() * 6);
+    for &(c, state) in states.iter() {
+        /* Chunks across the seam of a wrapped world go next to the camera */
+        let offset = chunk_loader.wrap.chunk_offset(c, camera_chunk);
+        let x0 = (offset.x * CHUNK_SIZE as i64) as f32 - local_x + CHUNK_GAP;
+        let x1 = x0 + CHUNK_SIZE as f32 - 2.0 * CHUNK_GAP;
+        let z0 = (offset.z * CHUNK_SIZE as i64) as f32 - local_z + CHUNK_GAP;
+        let z1 = z0 + CHUNK_SIZE as f32 - 2.0 * CHUNK_GAP;
+
+        let color = state_color(state);
+        let corner = |x: f32, z: f32| MapVertex { position: Vector3::new(x, -z, 0.0), color: color };
+        vertices.push(corner(x0, z0));
+        vertices.push(corner(x1, z0));
+        vertices.push(corner(x0, z1));
+        vertices.push(corner(x0, z1));
+        vertices.push(corner(x1, z0));
+        vertices.push(corner(x1, z1));
+    }
+    vertices
+}
+
+/// Returns the frustum's 12 edges seen from above, positioned like
+/// MapVertex.
+fn frustum_vertices(chunk_loader: &ChunkLoader, camera_position: Vector3<f64>,
+                    frustum: &[Vector3<f64>]) -> Vec<MapVertex> {
+    if frustum.len() != 8 {
+        return Vec::new();
+    }
+
+    let point = |i: uint| {
+        let d = chunk_loader.wrap.position_offset(frustum[i], camera_position);
+        MapVertex { position: Vector3::new(d.x as f32, -d.z as f32, 0.0), color: FRUSTUM_COLOR }
+    };
+
+    let mut vertices = Vec::with_capacity(24);
+    for i in range(0u, 4) {
+        let j = (i + 1) % 4;
+        /* Near rectangle, far rectangle and the edges joining them */
+        vertices.push(point(i));
+        vertices.push(point(j));
+        vertices.push(point(i + 4));
+        vertices.push(point(j + 4));
+        vertices.push(point(i));
+        vertices.push(point(i + 4));
+    }
+    vertices
+}
+
+#[test]
+fn test_map_projection() {
+    let edge = VIEW_RADIUS * CHUNK_SIZE as f32;
+    let clip = |m: &Matrix4<f32>, x: f32, y: f32| {
+        let v = m.mul_v(&Vector4::new(x, y, 0.0, 1.0));
+        (v.x, v.y)
+    };
+    let near = |a: f32, b: f32| (a - b).abs() < 1e-6;
+
+    /* The shorter side reaches exactly VIEW_RADIUS chunks */
+    let wide = map_projection(1024.0, 512.0);
+    let (x, y) = clip(&wide, 2.0 * edge, edge);
+    assert!(near(x, 1.0) && near(y, 1.0));
+    let tall = map_projection(512.0, 1024.0);
+    let (x, y) = clip(&tall, edge, -2.0 * edge);
+    assert!(near(x, 1.0) && near(y, -1.0));
+}
@@ -0,0 +1,206 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoration stages: features placed on newly generated terrain, like
+//! boulders, that may reach past the edges of the chunk being generated,
+//! and the snow lying on top of it all where it's cold.
+//!
+//! A stage writes blocks through Decorations, in world coordinates. Blocks
+//! that fall in a neighboring chunk's terrain are handed to the chunk loader,
+//! which writes them into that chunk now if it's loaded or when it loads, so
+//! features aren't cut off at chunk edges.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vector3;
+
+use CHUNK_SIZE;
+use featurerng::FeatureRng;
+use terrain::{BlockType, BlockAir, BlockGrass, BlockStone, BlockSnow, Terrain, TerrainGenerator};
+
+/// One in this many chunks with a grass surface gets a boulder.
+static BOULDER_CHANCE : u32 = 8;
+
+/// Temperature (see climate.rs) below which snow lies on the ground.
+static SNOW_TEMPERATURE : f64 = 0.2;
+
+/// Runs every decoration stage on the newly generated terrain of chunk c,
+/// made by generator. Returns the blocks placed in other chunks' terrain.
+pub fn decorate(generator: &TerrainGenerator, c: Vector3<i64>, terrain: &mut Terrain) -> Vec<(Vector3<i64>, BlockType)> {
+    let shared = {
+        let mut decorations = Decorations::new(c, terrain);
+        boulders(generator.seed(), c, &mut decorations);
+        decorations.finish()
+    };
+    snow(generator, c, terrain);
+    shared
+}
+
+pub struct Decorations<'a> {
+    origin: Vector3<i64>,
+    terrain: &'a mut Terrain,
+    /// Blocks placed in other chunks' terrain, including their borders.
+    shared: Vec<(Vector3<i64>, BlockType)>,
+}
+
+impl<'a> Decorations<'a> {
+    pub fn new(c: Vector3<i64>, terrain: &'a mut Terrain) -> Decorations<'a> {
+        Decorations { origin: c.mul_s(CHUNK_SIZE as i64), terrain: terrain, shared: Vec::new() }
+    }
+
+    /// Returns the blocktype at world position p, or None if it's outside
+    /// the chunk's terrain.
+    pub fn get(&self, p: Vector3<i64>) -> Option<BlockType> {
+        let o = p.sub_v(&self.origin);
+        if in_terrain(o) {
+            Some(self.terrain.get(o.x as int, o.y as int, o.z as int).blocktype)
+        } else {
+            None
+        }
+    }
+
+    /// Places blocktype at world position p, whichever chunk it's in.
+    pub fn set(&mut self, p: Vector3<i64>, blocktype: BlockType) {
+        let o = p.sub_v(&self.origin);
+        if in_terrain(o) {
+            self.terrain.set(o.x as int, o.y as int, o.z as int, blocktype);
+        }
+
+        /* Blocks on the chunk's edges are in its neighbors' borders */
+        let inner = |v: i64| v >= 1 && v < CHUNK_SIZE as i64 - 1;
+        if !inner(o.x) || !inner(o.y) || !inner(o.z) {
+            self.shared.push((p, blocktype));
+        }
+    }
+
+    /// Returns the blocks placed in other chunks' terrain.
+    pub fn finish(self) -> Vec<(Vector3<i64>, BlockType)> {
+        self.shared
+    }
+}
+
+/// Whether chunk offset o is within its terrain, including the border.
+fn in_terrain(o: Vector3<i64>) -> bool {
+    let within = |v: i64| v >= -1 && v <= CHUNK_SIZE as i64;
+    within(o.x) && within(o.y) && within(o.z)
+}
+
+/// Half-buried balls of stone lying on the grass.
+fn boulders(seed: u32, c: Vector3<i64>, decorations: &mut Decorations) {
+    let mut rng = FeatureRng::new(seed, c, "boulders");
+    if !rng.chance(1, BOULDER_CHANCE) {
+        return;
+    }
+
+    let size = CHUNK_SIZE as i64;
+    let origin = c.mul_s(size);
+    let x = origin.x + rng.below(size as u32) as i64;
+    let z = origin.z + rng.below(size as u32) as i64;
+    let radius = 1 + rng.below(2) as i64;
+
+    /* Topmost grass in the column with air above it, within the chunk */
+    let mut y = origin.y + size - 1;
+    while y >= origin.y && decorations.get(Vector3::new(x, y, z)) != Some(BlockGrass) {
+        y -= 1;
+    }
+    if y < origin.y || decorations.get(Vector3::new(x, y + 1, z)) != Some(BlockAir) {
+        return;
+    }
+
+    for dx in range(-radius, radius + 1) {
+        for dy in range(-radius, radius + 1) {
+            for dz in range(-radius, radius + 1) {
+                if dx*dx + dy*dy + dz*dz <= radius*radius + 1 {
+                    decorations.set(Vector3::new(x + dx, y + dy, z + dz), BlockStone);
+                }
+            }
+        }
+    }
+}
+
+/// A layer of snow on the first uncovered top of an opaque block at or
+/// above the surface of each column, where it's cold enough. Cave floors
+/// are below the surface, so they stay bare. The border is covered too, the
+/// same way the neighbors cover themselves, so no snow is handed to them.
+fn snow(generator: &TerrainGenerator, c: Vector3<i64>, terrain: &mut Terrain) {
+    let size = CHUNK_SIZE as i64;
+    let origin = c.mul_s(size);
+    let xs: Vec<i64> = range(origin.x - 1, origin.x + size + 1).collect();
+    let zs: Vec<i64> = range(origin.z - 1, origin.z + size + 1).collect();
+    let heights = generator.surface_heights(xs.as_slice(), zs.as_slice());
+
+    for (i, &x) in xs.iter().enumerate() {
+        for (j, &z) in zs.iter().enumerate() {
+            let surface = heights[i * zs.len() + j].ceil() as i64;
+            /* The block under the bottom of the border isn't known */
+            for y in range(surface.max(origin.y), origin.y + size + 1) {
+                let o = Vector3::new(x, y, z).sub_v(&origin);
+                let (ox, oy, oz) = (o.x as int, o.y as int, o.z as int);
+                if terrain.get(ox, oy, oz).blocktype != BlockAir {
+                    continue;
+                }
+                if terrain.get(ox, oy - 1, oz).is_opaque() &&
+                        generator.climate().temperature(Vector3::new(x, y, z)) < SNOW_TEMPERATURE {
+                    terrain.set(ox, oy, oz, BlockSnow);
+                }
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_decorations() {
+    let mut terrain = Terrain::new();
+    let c = Vector3::new(1i64, 0, -1);
+    let origin = c.mul_s(CHUNK_SIZE as i64);
+    let shared = {
+        let mut decorations = Decorations::new(c, &mut *terrain);
+        /* Inside, on the edge, in the border, and in a neighbor only */
+        decorations.set(origin.add_v(&Vector3::new(5, 5, 5)), BlockStone);
+        decorations.set(origin.add_v(&Vector3::new(0, 5, 5)), BlockStone);
+        decorations.set(origin.add_v(&Vector3::new(5, CHUNK_SIZE as i64, 5)), BlockStone);
+        decorations.set(origin.add_v(&Vector3::new(5, 5, -3)), BlockStone);
+        assert_eq!(decorations.get(origin.add_v(&Vector3::new(5, 5, 5))), Some(BlockStone));
+        assert_eq!(decorations.get(origin.add_v(&Vector3::new(5, 5, -3))), None);
+        decorations.finish()
+    };
+
+    assert_eq!(shared.len(), 3);
+    assert_eq!(terrain.get(5, 5, 5).blocktype, BlockStone);
+    assert_eq!(terrain.get(0, 5, 5).blocktype, BlockStone);
+    assert_eq!(terrain.get(5, CHUNK_SIZE as int, 5).blocktype, BlockStone);
+}
+
+#[test]
+fn test_snow() {
+    use terrain::{BlockWater, TerrainNoise};
+    use worldheight::WorldHeight;
+
+    let generator = TerrainGenerator::new(42, None, WorldHeight::default(), &TerrainNoise::new());
+    /* High above the terrain, where it's always cold */
+    let c = Vector3::new(0i64, 64, 0);
+    let mut terrain = Terrain::new();
+    terrain.set(5, 5, 5, BlockStone);
+    terrain.set(5, 6, 5, BlockStone);
+    terrain.set(-1, 3, 4, BlockStone);
+    terrain.set(8, 2, 8, BlockWater);
+    snow(&generator, c, &mut *terrain);
+
+    assert_eq!(terrain.get(5, 7, 5).blocktype, BlockSnow);
+    assert_eq!(terrain.get(-1, 4, 4).blocktype, BlockSnow);
+    assert_eq!(terrain.get(8, 3, 8).blocktype, BlockAir);
+    assert_eq!(terrain.get(3, 0, 3).blocktype, BlockAir);
+}
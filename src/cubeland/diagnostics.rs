@@ -0,0 +1,96 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Startup information for bug reports, printed with --diagnostics.
+//!
+//! Everything that can be known before the window exists is printed before
+//! it's created, so a crash while creating it still leaves something to
+//! report.
+
+extern crate gl;
+extern crate glfw;
+extern crate libc;
+
+use std::os;
+use std::rt::default_sched_threads;
+use std::str;
+
+use gl::types::*;
+
+/// Prints the CPU and worker counts and the monitors' video modes.
+pub fn print_system(glfw: &glfw::Glfw) {
+    println!("CPUs: {}", os::num_cpus());
+    println!("Chunk workers: {}", default_sched_threads());
+    println!("GLFW: {}", glfw::get_version_string());
+
+    glfw.with_connected_monitors(|monitors| {
+        for monitor in monitors.iter() {
+            println!("Monitor {}:", monitor.get_name());
+            for mode in monitor.get_video_modes().iter() {
+                println!("  {}x{} {}-{}-{} bits {} Hz", mode.width, mode.height,
+                         mode.red_bits, mode.green_bits, mode.blue_bits, mode.refresh_rate);
+            }
+        }
+    });
+}
+
+/// Prints what the driver says about the current context. Call after
+/// loading the GL functions.
+pub fn print_gl() {
+    println!("GL vendor: {}", gl_string(gl::VENDOR));
+    println!("GL renderer: {}", gl_string(gl::RENDERER));
+    println!("GL version: {}", gl_string(gl::VERSION));
+    println!("GLSL version: {}", gl_string(gl::SHADING_LANGUAGE_VERSION));
+
+    let mut samples : GLint = 0;
+    unsafe {
+        gl::GetIntegerv(gl::SAMPLES, &mut samples);
+    }
+    println!("MSAA samples: {}", samples);
+
+    let extensions = gl_extensions();
+    println!("GL extensions ({}): {}", extensions.len(), extensions.connect(" "));
+}
+
+fn gl_string(name: GLenum) -> String {
+    unsafe {
+        let s = gl::GetString(name);
+        if s.is_null() {
+            "unknown".to_string()
+        } else {
+            str::raw::from_c_str(s as *const libc::c_char)
+        }
+    }
+}
+
+/// Core profiles only list extensions one at a time, and older contexts
+/// only as one string.
+fn gl_extensions() -> Vec<String> {
+    let mut count : GLint = 0;
+    if gl::GetStringi::is_loaded() {
+        unsafe {
+            gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+        }
+        /* Before GL 3 the query fails and count stays 0 */
+        gl::GetError();
+    }
+
+    if count > 0 {
+        range(0, count as GLuint).map(|i| unsafe {
+            str::raw::from_c_str(gl::GetStringi(gl::EXTENSIONS, i) as *const libc::c_char)
+        }).collect()
+    } else {
+        gl_string(gl::EXTENSIONS).as_slice().words().map(|s| s.to_string()).collect()
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Second tier of the chunk cache: the generated terrain of evicted chunks,
+//! kept on disk so that revisiting them skips generation.
+//!
+//! Only chunks that are exactly what the generator made are cached. Edited
+//! chunks are saved instead (see store.rs), and a saved chunk always wins
+//! over a cached one. Each file records the stride and generator
+//! fingerprint it was made with and is ignored unless both match.
+//!
+//! A single task writes the files and deletes the least recently evicted
+//! ones past a size limit. File modification times carry the order over to
+//! the next run. Workers read the files directly.
+
+extern crate cgmath;
+
+use std::collections::{HashMap, TreeMap};
+use std::io;
+use std::io::{File, IoResult, BufReader, MemWriter};
+use std::io::fs;
+
+use time;
+
+use cgmath::vector::Vector3;
+
+use coord::{ChunkCoord, FnvHasher};
+use store::checksum;
+use terrain::{Terrain, TERRAIN_BYTES};
+
+static MAGIC : &'static [u8] = b"CUBECCH1";
+
+/// Bytes before the terrain: magic, fingerprint, stride and checksum.
+static HEADER_BYTES : uint = 24;
+
+pub enum CacheRequest {
+    /// Write encode()d data for a chunk.
+    CacheStore(Vector3<i64>, Vec<u8>),
+    /// A chunk loaded from the cache was evicted again unchanged.
+    CacheTouch(Vector3<i64>),
+}
+
+struct DiskCache {
+    dir: Path,
+    max_bytes: u64,
+    /// Size of each file and its place in by_use.
+    entries: HashMap<ChunkCoord, (u64, u64), FnvHasher>,
+    /// Least recently used first.
+    by_use: TreeMap<u64, ChunkCoord>,
+    next_use: u64,
+    total_bytes: u64,
+}
+
+impl DiskCache {
+    /// Picks up the files already in dir, oldest first.
+    fn open(dir: Path, max_bytes: u64) -> DiskCache {
+        let mut cache = DiskCache {
+            dir: dir,
+            max_bytes: max_bytes,
+            entries: HashMap::with_hasher(FnvHasher),
+            by_use: TreeMap::new(),
+            next_use: 0,
+            total_bytes: 0,
+        };
+
+        let mut files = Vec::new();
+        for path in fs::readdir(&cache.dir).unwrap_or(Vec::new()).move_iter() {
+            let c = match path.filename_str().and_then(parse_filename) {
+                Some(c) => c,
+                None => continue,
+            };
+            match fs::stat(&path) {
+                Ok(stat) => files.push((stat.modified, c, stat.size)),
+                Err(_) => {},
+            }
+        }
+        files.sort_by(|&(a, _, _), &(b, _, _)| a.cmp(&b));
+        for &(_, c, size) in files.iter() {
+            cache.add(c, size);
+        }
+        cache.trim();
+        cache
+    }
+
+    fn add(&mut self, c: Vector3<i64>, size: u64) {
+        let key = ChunkCoord::from_vec(c);
+        self.forget(&key);
+        let use_index = self.next_use;
+        self.next_use += 1;
+        self.entries.insert(key, (size, use_index));
+        self.by_use.insert(use_index, key);
+        self.total_bytes += size;
+    }
+
+    fn forget(&mut self, key: &ChunkCoord) {
+        match self.entries.pop(key) {
+            Some((size, use_index)) => {
+                self.by_use.remove(&use_index);
+                self.total_bytes -= size;
+            },
+            None => {},
+        }
+    }
+
+    fn store(&mut self, c: Vector3<i64>, data: &[u8]) {
+        match write_file(&self.dir, c, data) {
+            Ok(()) => self.add(c, data.len() as u64),
+            Err(e) => println!("Error caching chunk ({}, {}, {}): {}", c.x, c.y, c.z, e),
+        }
+        self.trim();
+    }
+
+    fn touch(&mut self, c: Vector3<i64>) {
+        let size = match self.entries.find(&ChunkCoord::from_vec(c)) {
+            Some(&(size, _)) => size,
+            None => return,
+        };
+        self.add(c, size);
+        let now = time::get_time();
+        let ms = now.sec as u64 * 1000 + now.nsec as u64 / (1000 * 1000);
+        let _ = fs::change_file_times(&cache_path(&self.dir, c), ms, ms);
+    }
+
+    /// Deletes the least recently used files until the rest fit.
+    fn trim(&mut self) {
+        while self.total_bytes > self.max_bytes {
+            let key = match self.by_use.iter().next() {
+                Some((_, &key)) => key,
+                None => break,
+            };
+            self.forget(&key);
+            let _ = fs::unlink(&cache_path(&self.dir, key.to_vec()));
+        }
+    }
+}
+
+/// Starts the task that writes the cache in dir, keeping it under max_bytes.
+pub fn spawn_writer(dir: Path, max_bytes: u64) -> Sender<CacheRequest> {
+    let (sender, receiver) = channel();
+    spawn(proc() {
+        let mut cache = DiskCache::open(dir, max_bytes);
+        for request in receiver.iter() {
+            match request {
+                CacheStore(c, data) => cache.store(c, data.as_slice()),
+                CacheTouch(c) => cache.touch(c),
+            }
+        }
+    });
+    sender
+}
+
+/// Serializes terrain generated with stride by a generator with fingerprint.
+pub fn encode(terrain: &Terrain, stride: uint, fingerprint: u64) -> Vec<u8> {
+    let bytes = terrain.to_bytes();
+    let mut w = MemWriter::with_capacity(HEADER_BYTES + bytes.len());
+    w.write(MAGIC).unwrap();
+    w.write_le_u64(fingerprint).unwrap();
+    w.write_le_u32(stride as u32).unwrap();
+    w.write_le_u32(checksum(bytes.as_slice())).unwrap();
+    w.write(bytes.as_slice()).unwrap();
+    w.unwrap()
+}
+
+/// Returns the cached terrain of chunk c, if it was generated with stride
+/// by a generator with fingerprint.
+pub fn load(dir: &Path, c: Vector3<i64>, stride: uint, fingerprint: u64) -> Option<Box<Terrain>> {
+    let path = cache_path(dir, c);
+    if !path.exists() {
+        return None;
+    }
+    match File::open(&path).read_to_end() {
+        Ok(data) => decode(data.as_slice(), stride, fingerprint),
+        Err(_) => None,
+    }
+}
+
+fn decode(data: &[u8], stride: uint, fingerprint: u64) -> Option<Box<Terrain>> {
+    if data.len() != HEADER_BYTES + TERRAIN_BYTES || data.slice_to(MAGIC.len()) != MAGIC {
+        return None;
+    }
+    let mut r = BufReader::new(data.slice(MAGIC.len(), HEADER_BYTES));
+    let saved_fingerprint = r.read_le_u64().unwrap();
+    let saved_stride = r.read_le_u32().unwrap() as uint;
+    let saved_checksum = r.read_le_u32().unwrap();
+    let bytes = data.slice_from(HEADER_BYTES);
+    if saved_fingerprint != fingerprint || saved_stride != stride || checksum(bytes) != saved_checksum {
+        return None;
+    }
+    Terrain::from_bytes(bytes)
+}
+
+fn cache_path(dir: &Path, c: Vector3<i64>) -> Path {
+    dir.join(format!("{}_{}_{}.cache", c.x, c.y, c.z))
+}
+
+fn parse_filename(name: &str) -> Option<Vector3<i64>> {
+    if !name.ends_with(".cache") {
+        return None;
+    }
+    let parts: Vec<Option<i64>> = name.slice_to(name.len() - 6).split('_').map(|s| from_str(s)).collect();
+    match parts.as_slice() {
+        [Some(x), Some(y), Some(z)] => Some(Vector3::new(x, y, z)),
+        _ => None,
+    }
+}
+
+/// Not synced: a file lost in a crash just means generating the chunk again.
+fn write_file(dir: &Path, c: Vector3<i64>, data: &[u8]) -> IoResult<()> {
+    try!(fs::mkdir_recursive(dir, io::UserDir));
+    let path = cache_path(dir, c);
+    let tmp_path = path.with_extension("cache.tmp");
+    try!(File::create(&tmp_path).write(data));
+    fs::rename(&tmp_path, &path)
+}
+
+#[test]
+fn test_encode_decode() {
+    let mut terrain = Terrain::new();
+    terrain.set(3, 4, 5, ::terrain::BlockStone);
+    let data = encode(&*terrain, 4, 1234);
+    assert_eq!(data.len(), HEADER_BYTES + TERRAIN_BYTES);
+    assert_eq!(decode(data.as_slice(), 4, 1234).unwrap().to_bytes(), terrain.to_bytes());
+    assert!(decode(data.as_slice(), 2, 1234).is_none());
+    assert!(decode(data.as_slice(), 4, 4321).is_none());
+    assert!(decode(data.slice_to(data.len() - 1), 4, 1234).is_none());
+
+    assert_eq!(parse_filename("-3_0_12.cache"), Some(Vector3::new(-3, 0, 12)));
+    assert_eq!(parse_filename("1_2.cache"), None);
+    assert_eq!(parse_filename("1_2_3.cache.tmp"), None);
+}
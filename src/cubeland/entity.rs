@@ -0,0 +1,826 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Things in the world other than blocks.
+//!
+//! Entities are axis-aligned boxes that fall with gravity and collide with
+//! solid blocks. Each axis is moved separately, so an entity hitting a wall
+//! still slides along it. Entities in chunks that aren't loaded are frozen
+//! until their chunk loads, rather than falling through the missing terrain.
+//!
+//! Mobs are spawned deterministically from the world seed the first time a
+//! chunk with a grass surface loads. They wander about, turning back at drops
+//! of more than one block.
+//!
+//! Entities belong to whichever chunk contains their position, so one that
+//! walks across a border is handed to the neighbouring chunk. They are saved
+//! with that chunk and taken out of the simulation when it's unloaded.
+//!
+//! Entities are indexed in a spatial hash, rebuilt each tick, so that mobs
+//! pushing each other apart and the player picking up items only look at
+//! entities nearby.
+//!
+//! Entities are stored as columns of components rather than a struct each:
+//! a Transform for where the box is, Physics for how it moves, Render for
+//! how it's drawn and, for mobs, AI. Each system is a function over only the
+//! columns it uses, so physics doesn't walk over AI state and a new kind of
+//! entity is a new combination of components rather than another match arm
+//! in every loop. Entity holds one entity's components, for saving it and
+//! for handing it between chunks.
+//!
+//! The systems run in fixed steps of STEP_LENGTH, so entities move the same
+//! way whatever the frame rate.
+
+extern crate cgmath;
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io::IoResult;
+use std::mem;
+
+use cgmath::vector::{EuclideanVector, Vector};
+use cgmath::vector::Vector3;
+use cgmath::vector::Vector4;
+
+use CHUNK_SIZE;
+use blocks::Palette;
+use chunk;
+use chunk::{Chunk, ChunkLoader};
+use coord::{ChunkCoord, FnvHasher};
+use featurerng::FeatureRng;
+use lcg;
+use renderer::DebugDraw;
+use spatial::SpatialHash;
+use terrain::{Biome, BiomeMountains, BiomeOcean, BlockType, BlockAir, BlockGrass};
+use wrap::Wrap;
+
+static GRAVITY : f64 = 20.0;
+static TERMINAL_VELOCITY : f64 = 50.0;
+/// Fraction of horizontal velocity lost per second on the ground.
+static GROUND_FRICTION : f64 = 5.0;
+/// Most steps run in one tick. Time beyond that is dropped, so a stalled
+/// frame doesn't make the next one slower too.
+static MAX_STEPS : uint = 6;
+
+/// Seconds before an uncollected item drop disappears.
+static ITEM_LIFETIME : f64 = 300.0;
+static ITEM_SIZE : f64 = 0.25;
+/// How close the player has to be to collect an item drop.
+static PICKUP_RADIUS : f64 = 2.0;
+/// Radians per second that item drops spin around the vertical axis.
+static ITEM_SPIN : f64 = 1.5;
+
+/// One in this many chunks with a grass surface gets a mob.
+static MOB_CHUNK_CHANCE : u32 = 4;
+static MOB_SPEED : f64 = 1.5;
+static MOB_JUMP_SPEED : f64 = 7.0;
+/// Longest time a mob keeps walking or standing before deciding again.
+static MOB_THINK_TIME : f64 = 6.0;
+/// Speed at which two overlapping mobs are pushed apart.
+static MOB_PUSH_SPEED : f64 = 2.0;
+
+/// Size of the spatial hash cells. Several times the size of an entity, so
+/// most are in a single cell.
+static GRID_CELL_SIZE : f64 = 4.0;
+
+/// Seconds simulated by each run of the systems.
+static STEP_LENGTH : f64 = 1.0 / 60.0;
+
+static TAG_ITEM_DROP : u8 = 0;
+static TAG_PIG : u8 = 1;
+static TAG_SHEEP : u8 = 2;
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum EntityKind {
+    /// A broken block waiting to be collected.
+    ItemDrop(BlockType),
+    Pig,
+    Sheep,
+}
+
+impl EntityKind {
+    /// Parses a mob name, or a block name for an item drop of that block.
+    pub fn from_name(name: &str) -> Option<EntityKind> {
+        match name {
+            "pig" => Some(Pig),
+            "sheep" => Some(Sheep),
+            _ => BlockType::from_name(name).map(|blocktype| ItemDrop(blocktype)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ItemDrop(_) => "item",
+            Pig => "pig",
+            Sheep => "sheep",
+        }
+    }
+
+    pub fn color(&self) -> Vector4<f32> {
+        match *self {
+            ItemDrop(blocktype) => blocktype.color(),
+            Pig => Vector4::new(0.95, 0.6, 0.65, 1.0),
+            Sheep => Vector4::new(0.9, 0.9, 0.85, 1.0),
+        }
+    }
+
+    fn half_extent(&self) -> Vector3<f64> {
+        match *self {
+            ItemDrop(_) => Vector3::new(ITEM_SIZE / 2.0, ITEM_SIZE / 2.0, ITEM_SIZE / 2.0),
+            Pig => Vector3::new(0.45, 0.4, 0.45),
+            Sheep => Vector3::new(0.45, 0.6, 0.45),
+        }
+    }
+
+    fn is_mob(&self) -> bool {
+        match *self {
+            ItemDrop(_) => false,
+            Pig | Sheep => true,
+        }
+    }
+}
+
+/// Where an entity is.
+#[deriving(Clone)]
+pub struct Transform {
+    /// Center of the entity's box.
+    pub position: Vector3<f64>,
+    /// Half the size of the box along each axis.
+    pub half_extent: Vector3<f64>,
+    /// Rotation around the vertical axis to draw it with, in radians.
+    pub yaw: f64,
+}
+
+impl Transform {
+    pub fn min(&self) -> Vector3<f64> {
+        self.position.sub_v(&self.half_extent)
+    }
+
+    pub fn max(&self) -> Vector3<f64> {
+        self.position.add_v(&self.half_extent)
+    }
+}
+
+/// How an entity moves.
+#[deriving(Clone)]
+pub struct Physics {
+    pub velocity: Vector3<f64>,
+    pub on_ground: bool,
+    /// Whether it slows down on the ground. Mobs set their own speed.
+    pub friction: bool,
+    /// Radians per second it turns around the vertical axis.
+    pub spin: f64,
+}
+
+/// How an entity is drawn.
+#[deriving(Clone)]
+pub struct Render {
+    pub color: Vector4<f32>,
+}
+
+/// A mob's AI: the state of its wandering.
+#[deriving(Clone)]
+pub struct Wander {
+    /// Direction the mob faces, in radians around the vertical axis.
+    pub heading: f64,
+    pub walking: bool,
+    /// Seconds until the mob picks a new heading.
+    think_time: f64,
+    rng: u32,
+}
+
+/// One entity's components.
+#[deriving(Clone)]
+pub struct Entity {
+    pub id: u64,
+    pub kind: EntityKind,
+    /// Seconds since it was spawned.
+    pub age: f64,
+    pub transform: Transform,
+    pub physics: Physics,
+    pub ai: Option<Wander>,
+}
+
+impl Entity {
+    /// Returns an entity with the components its kind has. rng seeds a mob's
+    /// wandering.
+    fn new(kind: EntityKind, position: Vector3<f64>, velocity: Vector3<f64>,
+           age: f64, yaw: f64, rng: u32) -> Entity {
+        let ai = if kind.is_mob() {
+            Some(Wander { heading: yaw, walking: false, think_time: 0.0, rng: rng })
+        } else {
+            None
+        };
+        Entity {
+            id: 0,
+            kind: kind,
+            age: age,
+            transform: Transform { position: position, half_extent: kind.half_extent(), yaw: yaw },
+            physics: Physics {
+                velocity: velocity,
+                on_ground: false,
+                friction: !kind.is_mob(),
+                spin: if kind.is_mob() { 0.0 } else { ITEM_SPIN },
+            },
+            ai: ai,
+        }
+    }
+
+    /// Returns the chunk the entity belongs to.
+    pub fn chunk(&self, wrap: &Wrap) -> Vector3<i64> {
+        wrap.chunk(chunk::containing_chunk(self.transform.position))
+    }
+
+    pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+        let (tag, blocktype) = match self.kind {
+            ItemDrop(blocktype) => (TAG_ITEM_DROP, blocktype),
+            Pig => (TAG_PIG, BlockAir),
+            Sheep => (TAG_SHEEP, BlockAir),
+        };
+        try!(w.write_u8(tag));
+        try!(w.write_u8(blocktype.to_u8()));
+        for &v in [self.transform.position, self.physics.velocity].iter() {
+            try!(w.write_le_f64(v.x));
+            try!(w.write_le_f64(v.y));
+            try!(w.write_le_f64(v.z));
+        }
+        try!(w.write_le_f64(self.age));
+        try!(w.write_le_f64(self.transform.yaw));
+        Ok(())
+    }
+
+    /// Reads an entity written by write(), with palette giving the block
+    /// types it was written with. It gets a real id when it's added to
+    /// Entities. Returns None if the data is invalid.
+    pub fn read(r: &mut Reader, palette: &Palette) -> IoResult<Option<Entity>> {
+        let tag = try!(r.read_u8());
+        let blocktype = palette.get(try!(r.read_u8()));
+        let mut v = [0.0f64, ..6];
+        for x in v.mut_iter() {
+            *x = try!(r.read_le_f64());
+        }
+        let age = try!(r.read_le_f64());
+        let yaw = try!(r.read_le_f64());
+
+        let kind = match (tag, blocktype) {
+            (TAG_ITEM_DROP, Some(blocktype)) => ItemDrop(blocktype),
+            (TAG_PIG, _) => Pig,
+            (TAG_SHEEP, _) => Sheep,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(Entity::new(kind,
+                            Vector3::new(v[0], v[1], v[2]),
+                            Vector3::new(v[3], v[4], v[5]),
+                            age, yaw, (age * 1000.0) as u32)))
+    }
+}
+
+/// All the simulated entities, one column per component.
+pub struct Entities {
+    /* Each has an element per entity, in the same order */
+    ids: Vec<u64>,
+    kinds: Vec<EntityKind>,
+    ages: Vec<f64>,
+    transforms: Vec<Transform>,
+    physics: Vec<Physics>,
+    renders: Vec<Render>,
+    ai: Vec<Option<Wander>>,
+
+    next_id: u64,
+    /// Indices of entities by their boxes.
+    grid: SpatialHash,
+    paused: bool,
+    /// Ticks still to run while paused.
+    steps: uint,
+    /// Seconds not simulated yet, less than a step.
+    pending_time: f64,
+}
+
+impl Entities {
+    pub fn new() -> Entities {
+        Entities {
+            ids: Vec::new(),
+            kinds: Vec::new(),
+            ages: Vec::new(),
+            transforms: Vec::new(),
+            physics: Vec::new(),
+            renders: Vec::new(),
+            ai: Vec::new(),
+            next_id: 1,
+            grid: SpatialHash::new(GRID_CELL_SIZE),
+            paused: false,
+            steps: 0,
+            pending_time: 0.0,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Freezes or resumes the simulation.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.steps = 0;
+        self.pending_time = 0.0;
+    }
+
+    /// Runs count more steps while paused.
+    pub fn step(&mut self, count: uint) {
+        self.steps += count;
+    }
+
+    /// Draws each entity's box and, for walking mobs, where they're headed
+    /// before they next change their mind, for this frame.
+    pub fn debug_draw(&self, debug: &mut DebugDraw) {
+        for (t, ai) in self.transforms.iter().zip(self.ai.iter()) {
+            let color = match *ai {
+                Some(ref wander) if wander.walking => Vector4::new(0.2, 1.0, 0.2, 1.0),
+                Some(_) => Vector4::new(1.0, 1.0, 0.2, 1.0),
+                None => Vector4::new(1.0, 1.0, 1.0, 1.0),
+            };
+            debug.aabb(t.min(), t.max(), color, 0.0);
+
+            match *ai {
+                Some(ref wander) if wander.walking => {
+                    let distance = MOB_SPEED * wander.think_time;
+                    let to = Vector3::new(t.position.x + wander.heading.sin() * distance,
+                                          t.position.y,
+                                          t.position.z + wander.heading.cos() * distance);
+                    debug.line(t.position, to, Vector4::new(1.0, 0.2, 0.2, 1.0), 0.0);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    pub fn transforms<'a>(&'a self) -> &'a [Transform] {
+        self.transforms.as_slice()
+    }
+
+    pub fn renders<'a>(&'a self) -> &'a [Render] {
+        self.renders.as_slice()
+    }
+
+    pub fn len(&self) -> uint {
+        self.ids.len()
+    }
+
+    /// Returns the components of the entity at index i.
+    pub fn get(&self, i: uint) -> Entity {
+        Entity {
+            id: self.ids[i],
+            kind: self.kinds[i],
+            age: self.ages[i],
+            transform: self.transforms[i].clone(),
+            physics: self.physics[i].clone(),
+            ai: self.ai[i].clone(),
+        }
+    }
+
+    /// Returns copies of all entities.
+    pub fn to_vec(&self) -> Vec<Entity> {
+        range(0, self.len()).map(|i| self.get(i)).collect()
+    }
+
+    pub fn spawn(&mut self, kind: EntityKind, position: Vector3<f64>, velocity: Vector3<f64>) -> u64 {
+        let rng = self.next_id as u32;
+        self.add(Entity::new(kind, position, velocity, 0.0, 0.0, rng))
+    }
+
+    /// Adds an entity, giving it a new id, and returns the id.
+    pub fn add(&mut self, entity: Entity) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.grid.insert(self.ids.len(), entity.transform.min(), entity.transform.max());
+        self.ids.push(id);
+        self.kinds.push(entity.kind);
+        self.ages.push(entity.age);
+        self.transforms.push(entity.transform);
+        self.physics.push(entity.physics);
+        self.renders.push(Render { color: entity.kind.color() });
+        self.ai.push(entity.ai);
+        id
+    }
+
+    /// Removes the entity at index i, moving the last one into its place.
+    /// The grid is stale until it's rebuilt.
+    fn remove(&mut self, i: uint) {
+        self.ids.swap_remove(i);
+        self.kinds.swap_remove(i);
+        self.ages.swap_remove(i);
+        self.transforms.swap_remove(i);
+        self.physics.swap_remove(i);
+        self.renders.swap_remove(i);
+        self.ai.swap_remove(i);
+    }
+
+    /// Returns the indices of the entities whose boxes may overlap the box
+    /// from min to max.
+    pub fn near(&self, min: Vector3<f64>, max: Vector3<f64>) -> Vec<uint> {
+        self.grid.query(min, max)
+    }
+
+    /// Reindexes the entities after they've moved or been removed.
+    fn rebuild_grid(&mut self) {
+        self.grid.clear();
+        for (i, t) in self.transforms.iter().enumerate() {
+            self.grid.insert(i, t.min(), t.max());
+        }
+    }
+
+    /// Takes over the entities a newly loaded chunk was saved with, and
+    /// spawns its mobs if this is the first time it has been loaded. Coarse
+    /// chunks are populated once their full terrain replaces them. biome is
+    /// the biome in the middle of the chunk.
+    pub fn adopt(&mut self, chunk: &mut Chunk, seed: u32, biome: Biome) {
+        for entity in mem::replace(&mut chunk.entities, Vec::new()).move_iter() {
+            self.add(entity);
+        }
+
+        if !chunk.populated && !chunk.coarse {
+            chunk.populated = true;
+            if self.spawn_mobs(&*chunk, seed, biome) {
+                /* Save the chunk so its mobs aren't spawned again next time */
+                chunk.dirty = true;
+            }
+        }
+    }
+
+    /// Returns copies of all entities, grouped by the chunk they belong to.
+    pub fn by_chunk(&self, wrap: &Wrap) -> HashMap<ChunkCoord, Vec<Entity>, FnvHasher> {
+        let mut map = HashMap::with_hasher(FnvHasher);
+        for i in range(0, self.len()) {
+            let e = self.get(i);
+            map.find_or_insert_with(ChunkCoord::from_vec(e.chunk(wrap)), |_| Vec::new()).push(e);
+        }
+        map
+    }
+
+    /// Removes and returns the entities that belong to chunk c.
+    pub fn take_chunk(&mut self, c: Vector3<i64>, wrap: &Wrap) -> Vec<Entity> {
+        let mut taken = Vec::new();
+        /* Backwards, so swap_remove only moves entities already looked at */
+        let mut i = self.len();
+        while i > 0 {
+            i -= 1;
+            if wrap.chunk(chunk::containing_chunk(self.transforms[i].position)) == c {
+                taken.push(self.get(i));
+                self.remove(i);
+            }
+        }
+        if !taken.is_empty() {
+            self.rebuild_grid();
+        }
+        taken
+    }
+
+    /// Spawns the mobs for a chunk, returning true if there were any. Whether
+    /// a chunk gets a mob, and where, depends only on the seed and the chunk
+    /// coordinate.
+    fn spawn_mobs(&mut self, chunk: &Chunk, seed: u32, biome: Biome) -> bool {
+        let c = chunk.coord;
+        let mut rng = FeatureRng::new(seed, c, "mobs");
+        if !rng.chance(1, MOB_CHUNK_CHANCE) || biome == BiomeOcean {
+            return false;
+        }
+
+        let size = CHUNK_SIZE as int;
+        let x = rng.below(size as u32) as int;
+        let z = rng.below(size as u32) as int;
+        /* Pigs don't climb mountains */
+        let kind = if rng.chance(1, 2) && biome != BiomeMountains { Pig } else { Sheep };
+
+        /* Topmost grass with two blocks of air above it, within the chunk */
+        let mut y = size - 3;
+        while y >= 0 {
+            if chunk.terrain.get(x, y, z).blocktype == BlockGrass &&
+                    !chunk.terrain.get(x, y + 1, z).blocktype.is_solid() &&
+                    !chunk.terrain.get(x, y + 2, z).blocktype.is_solid() {
+                break;
+            }
+            y -= 1;
+        }
+        if y < 0 {
+            return false;
+        }
+
+        let origin = c.mul_s(CHUNK_SIZE as i64);
+        let half_extent = kind.half_extent();
+        let position = Vector3::new(origin.x as f64 + x as f64 + 0.5,
+                                    origin.y as f64 + y as f64 + 1.0 + half_extent.y + 0.01,
+                                    origin.z as f64 + z as f64 + 0.5);
+        self.spawn(kind, position, Vector3::new(0.0, 0.0, 0.0));
+        true
+    }
+
+    /// Advances the simulation by tick_length seconds, in whole steps, or by
+    /// one pending step if it's paused.
+    pub fn tick(&mut self, chunk_loader: &ChunkLoader, tick_length: f64) {
+        if self.paused {
+            if self.steps > 0 {
+                self.steps -= 1;
+                self.run_systems(chunk_loader);
+            }
+            return;
+        }
+
+        self.pending_time += tick_length;
+        let mut steps = 0u;
+        while self.pending_time >= STEP_LENGTH && steps < MAX_STEPS {
+            self.pending_time -= STEP_LENGTH;
+            self.run_systems(chunk_loader);
+            steps += 1;
+        }
+        if self.pending_time >= STEP_LENGTH {
+            self.pending_time = 0.0;
+        }
+    }
+
+    /// Runs each system for one step.
+    fn run_systems(&mut self, chunk_loader: &ChunkLoader) {
+        let dt = STEP_LENGTH;
+        for age in self.ages.mut_iter() {
+            *age += dt;
+        }
+
+        /* Entities in chunks that aren't loaded are frozen */
+        let active: Vec<bool> = self.transforms.iter().map(|t| {
+            chunk_loader.get_block(block_containing(t.position)).is_some()
+        }).collect();
+
+        ai_system(self.transforms.as_mut_slice(), self.physics.as_mut_slice(),
+                  self.ai.as_mut_slice(), active.as_slice(), chunk_loader, dt);
+        let pushes = mob_pushes(self.ids.as_slice(), self.kinds.as_slice(),
+                                self.transforms.as_slice(), &self.grid);
+        for ((p, push), &is_active) in self.physics.mut_iter().zip(pushes.iter()).zip(active.iter()) {
+            if is_active {
+                p.velocity = p.velocity.add_v(push);
+            }
+        }
+        physics_system(self.transforms.as_mut_slice(), self.physics.as_mut_slice(),
+                       active.as_slice(), chunk_loader, dt);
+
+        self.expire();
+        self.rebuild_grid();
+    }
+
+    /// Removes item drops that have been around longer than ITEM_LIFETIME.
+    fn expire(&mut self) {
+        let mut i = self.len();
+        while i > 0 {
+            i -= 1;
+            let expired = match self.kinds[i] {
+                ItemDrop(_) => self.ages[i] >= ITEM_LIFETIME,
+                Pig | Sheep => false,
+            };
+            if expired {
+                self.remove(i);
+            }
+        }
+    }
+
+    /// Removes the item drops within PICKUP_RADIUS of p that take accepts.
+    pub fn pick_up(&mut self, p: Vector3<f64>, take: |BlockType| -> bool) {
+        let radius = Vector3::new(PICKUP_RADIUS, PICKUP_RADIUS, PICKUP_RADIUS);
+        let mut picked = Vec::new();
+        for &i in self.grid.query(p.sub_v(&radius), p.add_v(&radius)).iter() {
+            let d = self.transforms[i].position.sub_v(&p);
+            match self.kinds[i] {
+                ItemDrop(blocktype) => {
+                    if d.dot(&d) < PICKUP_RADIUS * PICKUP_RADIUS && take(blocktype) {
+                        picked.push(i);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        if !picked.is_empty() {
+            /* Highest index first, so swap_remove doesn't move any still to be removed */
+            picked.sort();
+            for &i in picked.iter().rev() {
+                self.remove(i);
+            }
+            self.rebuild_grid();
+        }
+    }
+}
+
+/// Steers each active mob.
+fn ai_system(transforms: &mut [Transform], physics: &mut [Physics], ai: &mut [Option<Wander>],
+             active: &[bool], chunk_loader: &ChunkLoader, dt: f64) {
+    for (((t, p), ai), &is_active) in transforms.mut_iter().zip(physics.mut_iter()).zip(ai.mut_iter()).zip(active.iter()) {
+        if !is_active {
+            continue;
+        }
+        match *ai {
+            Some(ref mut wander) => {
+                wander_step(t, p, wander, chunk_loader, dt);
+                t.yaw = wander.heading;
+            },
+            None => {},
+        }
+    }
+}
+
+/// Returns the horizontal velocity to add to each entity to push
+/// overlapping mobs apart.
+fn mob_pushes(ids: &[u64], kinds: &[EntityKind], transforms: &[Transform], grid: &SpatialHash) -> Vec<Vector3<f64>> {
+    transforms.iter().enumerate().map(|(i, t)| {
+        let mut push = Vector3::new(0.0, 0.0, 0.0);
+        if !kinds[i].is_mob() {
+            return push;
+        }
+
+        for &j in grid.query(t.min(), t.max()).iter() {
+            let other = &transforms[j];
+            if j == i || !kinds[j].is_mob() || !overlaps(t, other) {
+                continue;
+            }
+            let d = Vector3::new(t.position.x - other.position.x, 0.0, t.position.z - other.position.z);
+            let len = d.length();
+            push = if len > 0.001 {
+                push.add_v(&d.mul_s(MOB_PUSH_SPEED / len))
+            } else {
+                /* Exactly on top of each other; split them by id */
+                push.add_v(&Vector3::new(if ids[i] < ids[j] { -MOB_PUSH_SPEED } else { MOB_PUSH_SPEED }, 0.0, 0.0))
+            };
+        }
+        push
+    }).collect()
+}
+
+/// Moves each active entity with gravity, stopping it at solid blocks, and
+/// turns the ones that spin.
+fn physics_system(transforms: &mut [Transform], physics: &mut [Physics],
+                  active: &[bool], chunk_loader: &ChunkLoader, dt: f64) {
+    for ((t, p), &is_active) in transforms.mut_iter().zip(physics.mut_iter()).zip(active.iter()) {
+        t.yaw = (t.yaw + p.spin * dt) % (2.0 * PI);
+        if !is_active {
+            continue;
+        }
+
+        p.velocity.y = (p.velocity.y - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+
+        if !move_by(t, chunk_loader, Vector3::new(p.velocity.x * dt, 0.0, 0.0)) {
+            p.velocity.x = 0.0;
+        }
+        if !move_by(t, chunk_loader, Vector3::new(0.0, 0.0, p.velocity.z * dt)) {
+            p.velocity.z = 0.0;
+        }
+        p.on_ground = false;
+        if !move_by(t, chunk_loader, Vector3::new(0.0, p.velocity.y * dt, 0.0)) {
+            p.on_ground = p.velocity.y < 0.0;
+            p.velocity.y = 0.0;
+        }
+
+        if p.on_ground && p.friction {
+            let f = 1.0 - (GROUND_FRICTION * dt).min(1.0);
+            p.velocity.x *= f;
+            p.velocity.z *= f;
+        }
+    }
+}
+
+/// Sets a mob's horizontal velocity, every so often switching between
+/// standing still and walking in a random direction. A mob about to walk off
+/// a drop of more than one block turns around instead, and one walking into
+/// a one block step jumps.
+fn wander_step(t: &Transform, p: &mut Physics, wander: &mut Wander, chunk_loader: &ChunkLoader, dt: f64) {
+    let position = t.position;
+    let feet = position.y - t.half_extent.y + 0.01;
+    let reach = t.half_extent.x + 0.3;
+
+    wander.think_time -= dt;
+    if wander.think_time <= 0.0 {
+        wander.walking = lcg::random(&mut wander.rng) < 0.6;
+        wander.heading = lcg::random(&mut wander.rng) * 2.0 * PI;
+        wander.think_time = (0.3 + 0.7 * lcg::random(&mut wander.rng)) * MOB_THINK_TIME;
+    }
+
+    p.velocity.x = 0.0;
+    p.velocity.z = 0.0;
+    if !wander.walking {
+        return;
+    }
+
+    let (dx, dz) = (wander.heading.sin(), wander.heading.cos());
+    let ahead = |dy: f64| {
+        let q = Vector3::new(position.x + dx * reach, feet + dy, position.z + dz * reach);
+        chunk_loader.get_block(block_containing(q))
+    };
+
+    /* Blocks in chunks that aren't loaded count as solid */
+    let is_solid = |b: Option<BlockType>| b.map_or(true, |b| b.is_solid());
+    let (below, below2) = (ahead(-1.0), ahead(-2.0));
+    if below.is_none() || below2.is_none() || (!is_solid(below) && !is_solid(below2)) {
+        wander.heading += PI;
+        wander.walking = false;
+        return;
+    }
+
+    if p.on_ground && is_solid(ahead(0.0)) {
+        if is_solid(ahead(1.0)) || is_solid(ahead(2.0)) {
+            wander.heading += PI;
+            wander.walking = false;
+            return;
+        }
+        p.velocity.y = MOB_JUMP_SPEED;
+    }
+
+    p.velocity.x = dx * MOB_SPEED;
+    p.velocity.z = dz * MOB_SPEED;
+}
+
+fn overlaps(a: &Transform, b: &Transform) -> bool {
+    (a.position.x - b.position.x).abs() < a.half_extent.x + b.half_extent.x &&
+        (a.position.y - b.position.y).abs() < a.half_extent.y + b.half_extent.y &&
+        (a.position.z - b.position.z).abs() < a.half_extent.z + b.half_extent.z
+}
+
+/// Moves t by delta unless that would put it inside a solid block. Returns
+/// whether it moved.
+fn move_by(t: &mut Transform, chunk_loader: &ChunkLoader, delta: Vector3<f64>) -> bool {
+    let p = t.position.add_v(&delta);
+    if collides(chunk_loader, p.sub_v(&t.half_extent), p.add_v(&t.half_extent)) {
+        false
+    } else {
+        t.position = p;
+        true
+    }
+}
+
+/// Returns true if the box from min to max overlaps a solid block, or a
+/// block in a chunk that isn't loaded.
+pub fn collides(chunk_loader: &ChunkLoader, min: Vector3<f64>, max: Vector3<f64>) -> bool {
+    let lo = block_containing(min);
+    let hi = block_containing(max);
+    for x in range(lo.x, hi.x + 1) {
+        for y in range(lo.y, hi.y + 1) {
+            for z in range(lo.z, hi.z + 1) {
+                match chunk_loader.get_block(Vector3::new(x, y, z)) {
+                    Some(blocktype) if !blocktype.is_solid() => {},
+                    _ => return true,
+                }
+            }
+        }
+    }
+    false
+}
+
+pub fn block_containing(p: Vector3<f64>) -> Vector3<i64> {
+    Vector3::new(p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64)
+}
+
+#[test]
+fn test_pick_up() {
+    use terrain::{BlockDirt, BlockStone};
+
+    let mut entities = Entities::new();
+    entities.spawn(ItemDrop(BlockDirt), Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, 0.0));
+    entities.spawn(ItemDrop(BlockStone), Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 0.0, 0.0));
+    entities.spawn(ItemDrop(BlockDirt), Vector3::new(10.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+
+    let mut taken = Vec::new();
+    entities.pick_up(Vector3::new(0.0, 0.0, 0.0), |blocktype| {
+        taken.push(blocktype);
+        blocktype == BlockDirt
+    });
+    assert_eq!(taken.len(), 2);
+    assert_eq!(entities.len(), 2);
+}
+
+#[test]
+fn test_take_chunk() {
+    use terrain::BlockDirt;
+
+    let mut entities = Entities::new();
+    let size = CHUNK_SIZE as f64;
+    entities.spawn(ItemDrop(BlockDirt), Vector3::new(1.0, 1.0, 1.0), Vector3::new(0.0, 0.0, 0.0));
+    entities.spawn(Pig, Vector3::new(size + 1.0, 1.0, 1.0), Vector3::new(0.0, 0.0, 0.0));
+    entities.spawn(Sheep, Vector3::new(2.0, 1.0, 1.0), Vector3::new(0.0, 0.0, 0.0));
+
+    let taken = entities.take_chunk(Vector3::new(0, 0, 0), &Wrap::none());
+    assert_eq!(taken.len(), 2);
+    assert!(taken.iter().all(|e| e.transform.position.x < size));
+
+    /* The pig's components stayed together when it was moved into a freed slot */
+    assert_eq!(entities.len(), 1);
+    let pig = entities.get(0);
+    assert_eq!(pig.kind.name(), "pig");
+    assert_eq!(pig.transform.position, Vector3::new(size + 1.0, 1.0, 1.0));
+    assert!(pig.ai.is_some() && !pig.physics.friction);
+}
@@ -25,7 +25,7 @@ pub struct FBO {
 }
 
 impl FBO {
-    pub fn new(size: GLint) -> FBO {
+    pub fn new(width: GLint, height: GLint) -> Result<FBO, String> {
         //RGBA8 2D texture
         let mut color_tex = 0;
         unsafe { gl::GenTextures(1, &mut color_tex); }
@@ -34,7 +34,7 @@ impl FBO {
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
-        unsafe { gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, size, size, 0, gl::BGRA, gl::UNSIGNED_BYTE, std::ptr::null()); }
+        unsafe { gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, width, height, 0, gl::BGRA, gl::UNSIGNED_BYTE, std::ptr::null()); }
 
         // 24 bit depth texture
         let mut depth_tex = 0;
@@ -47,7 +47,7 @@ impl FBO {
         gl::TexParameteri(gl::TEXTURE_2D, gl::DEPTH_TEXTURE_MODE, gl::INTENSITY as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_R_TO_TEXTURE as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
-        unsafe { gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as GLint, size, size, 0, gl::DEPTH_COMPONENT, gl::UNSIGNED_BYTE, std::ptr::null()); }
+        unsafe { gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as GLint, width, height, 0, gl::DEPTH_COMPONENT, gl::UNSIGNED_BYTE, std::ptr::null()); }
 
         let mut fbo = 0;
         unsafe { gl::GenFramebuffers(1, &mut fbo); }
@@ -59,23 +59,31 @@ impl FBO {
         gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, depth_tex, 0/*mipmap level*/);
 
         //Does the GPU support current FBO configuration?
-        match gl::CheckFramebufferStatus(gl::FRAMEBUFFER) {
-            gl::FRAMEBUFFER_COMPLETE => {
-                println!("Framebuffer complete");
-            },
-            _ => {
-                println!("Framebuffer error");
-            }
-        }
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
 
         gl::BindTexture(gl::TEXTURE_2D, 0);
         gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
 
-        FBO {
+        let fbo = FBO {
             color_tex: color_tex,
             depth_tex: depth_tex,
             fbo: fbo,
+        };
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("framebuffer incomplete (status 0x{:x})", status));
         }
+        Ok(fbo)
+    }
+
+    /// Directs drawing to this FBO instead of the window.
+    pub fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+    }
+
+    /// Directs drawing back to the window.
+    pub fn unbind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
     }
 }
 
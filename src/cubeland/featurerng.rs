@@ -0,0 +1,98 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Random numbers for placing generated features: mobs, and later ores,
+//! decorations and structures.
+//!
+//! Each (world seed, chunk, feature) gets its own stream, so what one
+//! feature draws never shifts what another sees. Adding a feature, or
+//! changing how many numbers one uses, leaves every other feature where it
+//! was. Streams are integer only, so they're the same on every platform.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector3;
+
+use gradient;
+
+/// SplitMix64 generator seeded from a world seed, chunk coordinate and
+/// feature name.
+pub struct FeatureRng {
+    state: u64,
+}
+
+impl FeatureRng {
+    /// feature names the stream, e.g. "mobs". Renaming a feature moves it.
+    pub fn new(seed: u32, c: Vector3<i64>, feature: &str) -> FeatureRng {
+        let h = gradient::hash(seed, c.x, c.y, c.z) as u64;
+        FeatureRng { state: mix(h << 32 ^ fnv1a(feature)) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state += 0x9E3779B97F4A7C15;
+        mix(self.state)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Returns a number from 0 to n - 1.
+    pub fn below(&mut self, n: u32) -> u32 {
+        ((self.next_u32() as u64 * n as u64) >> 32) as u32
+    }
+
+    /// Returns true with probability numerator / denominator.
+    pub fn chance(&mut self, numerator: u32, denominator: u32) -> bool {
+        self.below(denominator) < numerator
+    }
+}
+
+/// SplitMix64's output function.
+fn mix(v: u64) -> u64 {
+    let mut z = v;
+    z = (z ^ (z >> 30)) * 0xBF58476D1CE4E5B9;
+    z = (z ^ (z >> 27)) * 0x94D049BB133111EB;
+    z ^ (z >> 31)
+}
+
+/// FNV-1a of the name's bytes. Hashed here rather than with FnvHasher, since
+/// how std's Hash feeds a str to the hasher isn't promised to stay the same.
+fn fnv1a(name: &str) -> u64 {
+    let mut h = 0xcbf29ce484222325u64;
+    for &byte in name.as_bytes().iter() {
+        h = (h ^ byte as u64) * 0x100000001b3;
+    }
+    h
+}
+
+#[test]
+fn test_feature_rng() {
+    let c = Vector3::new(3i64, -1, 7);
+    let mut a = FeatureRng::new(42, c, "mobs");
+    let mut b = FeatureRng::new(42, c, "mobs");
+    let first = a.next_u64();
+    assert_eq!(first, b.next_u64());
+
+    /* Streams for other features, chunks and seeds are independent */
+    assert!(FeatureRng::new(42, c, "ores").next_u64() != first);
+    assert!(FeatureRng::new(42, Vector3::new(3, -1, 8), "mobs").next_u64() != first);
+    assert!(FeatureRng::new(43, c, "mobs").next_u64() != first);
+
+    for _ in range(0u, 1000) {
+        assert!(a.below(6) < 6);
+    }
+    assert!(!a.chance(0, 5));
+    assert!(a.chance(5, 5));
+}
@@ -0,0 +1,164 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C interface to the terrain generator, for map viewers and editors written
+//! in other languages. Declared in cubeland.h.
+//!
+//! This is the root of a separate library crate that only includes the
+//! generator's modules, so it doesn't pull in GL or GLFW. Chunks come out
+//! exactly as a default game generates them, decorations included, except
+//! for blocks that neighboring chunks' decorations spill into them.
+
+#![crate_name = "cubelandgen"]
+#![crate_type = "dylib"]
+#![crate_type = "staticlib"]
+
+extern crate cgmath;
+extern crate libc;
+
+use std::mem;
+use std::slice;
+
+use libc::{c_double, c_int, size_t};
+
+use cgmath::vector::{Vector, Vector3};
+
+use terrain::{TerrainGenerator, TerrainNoise, TerrainQuality};
+use worldheight::WorldHeight;
+
+pub use chunksize::{CHUNK_SIZE, CHUNK_SIZEu};
+
+mod blocks;
+mod chunksize;
+mod climate;
+mod coord;
+mod decoration;
+mod featurerng;
+mod gradient;
+mod noisesource;
+mod terrain;
+mod worldheight;
+
+/// Opaque to C; cubeland_generator in cubeland.h.
+pub struct Generator {
+    terrain: TerrainGenerator,
+}
+
+/// Returns the chunk edge length in blocks this library was built with.
+#[no_mangle]
+pub extern "C" fn cubeland_chunk_size() -> c_int {
+    CHUNK_SIZE as c_int
+}
+
+/// Returns a generator for the world with the given seed and vertical
+/// extent, as written in its world.txt, or null if bottom isn't below top.
+/// Lava is at the default depth, whatever the world's lava line says.
+/// Free it with cubeland_generator_free().
+#[no_mangle]
+pub extern "C" fn cubeland_generator_new(seed: u32, bottom: i64, top: i64) -> *mut Generator {
+    match WorldHeight::new(bottom, top) {
+        Some(height) => unsafe {
+            mem::transmute(box Generator {
+                terrain: TerrainGenerator::new(seed, None, height, &TerrainNoise::new()),
+            })
+        },
+        None => 0 as *mut Generator,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn cubeland_generator_free(generator: *mut Generator) {
+    if !generator.is_null() {
+        let _: Box<Generator> = unsafe { mem::transmute(generator) };
+    }
+}
+
+/// Generates chunk (x, y, z) into out, which must hold at least
+/// cubeland_chunk_size() cubed bytes. The block at (bx, by, bz) within the
+/// chunk goes to out[(bx * size + by) * size + bz], as its block type: 0 air,
+/// 1 grass, 2 stone, 3 dirt, 4 water, 5 snow, 6 sand, 7 lava. Returns 0, or
+/// -1 if out is null or too small.
+#[no_mangle]
+pub extern "C" fn cubeland_generate_chunk(generator: *const Generator,
+                                          x: i64, y: i64, z: i64,
+                                          out: *mut u8, len: size_t) -> c_int {
+    let n = CHUNK_SIZEu;
+    if generator.is_null() || out.is_null() || (len as uint) < n * n * n {
+        return -1;
+    }
+
+    let generator = unsafe { &(*generator).terrain };
+    let blocks = generate_chunk(generator, Vector3::new(x, y, z));
+    unsafe {
+        slice::raw::mut_buf_as_slice(out, n * n * n, |buf| {
+            buf.copy_from(blocks.as_slice());
+        });
+    }
+    0
+}
+
+/// Returns the height in blocks of the surface at column (x, z), before
+/// caves are carved out of it.
+#[no_mangle]
+pub extern "C" fn cubeland_surface_height(generator: *const Generator, x: i64, z: i64) -> c_double {
+    if generator.is_null() {
+        return 0.0;
+    }
+
+    let generator = unsafe { &(*generator).terrain };
+    generator.surface_heights([x], [z])[0] as c_double
+}
+
+/// Returns the blocks of chunk c in the order cubeland_generate_chunk()
+/// documents, without the border.
+fn generate_chunk(generator: &TerrainGenerator, c: Vector3<i64>) -> Vec<u8> {
+    let p = Vector3::new(c.x as f64, c.y as f64, c.z as f64).mul_s(CHUNK_SIZE as f64);
+    let mut terrain = generator.gen(p, TerrainQuality::new().stride);
+    decoration::decorate(generator, c, &mut *terrain);
+
+    let mut blocks = Vec::with_capacity(CHUNK_SIZEu * CHUNK_SIZEu * CHUNK_SIZEu);
+    for bx in range(0, CHUNK_SIZE) {
+        for by in range(0, CHUNK_SIZE) {
+            for bz in range(0, CHUNK_SIZE) {
+                blocks.push(terrain.get(bx, by, bz).blocktype.to_u8());
+            }
+        }
+    }
+    blocks
+}
+
+#[test]
+fn test_generate_chunk() {
+    let n = CHUNK_SIZEu;
+    let generator = cubeland_generator_new(42, -128, 256);
+    assert!(!generator.is_null());
+    assert!(cubeland_generator_new(42, 10, 10).is_null());
+
+    let mut out = Vec::from_elem(n * n * n, 255u8);
+    assert_eq!(cubeland_generate_chunk(generator as *const Generator, 0, 0, 0,
+                                       out.as_mut_ptr(), (n * n * n - 1) as size_t), -1);
+    assert_eq!(cubeland_generate_chunk(generator as *const Generator, 0, 0, 0,
+                                       out.as_mut_ptr(), (n * n * n) as size_t), 0);
+    /* Only the block types documented above */
+    assert!(out.iter().all(|&b| b <= 7));
+
+    /* Below the bottom of the world is solid stone */
+    assert_eq!(cubeland_generate_chunk(generator as *const Generator, 0, -1000, 0,
+                                       out.as_mut_ptr(), (n * n * n) as size_t), 0);
+    assert!(out.iter().all(|&b| b == 2));
+
+    let h = cubeland_surface_height(generator as *const Generator, 5, 7);
+    assert!(h >= -128.0 && h <= 256.0);
+    cubeland_generator_free(generator);
+}
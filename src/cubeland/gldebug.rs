@@ -0,0 +1,104 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Printing the driver's debug messages (KHR_debug).
+//!
+//! With a debug context the driver reports errors, undefined behavior,
+//! performance hints and the like through a callback. Messages are printed
+//! as they arrive, on the thread that made the offending call, so a
+//! backtrace taken there points at it.
+//!
+//! Also names glGetError() codes, without needing GLU.
+
+extern crate gl;
+extern crate libc;
+
+use std::ptr;
+use std::str;
+
+use gl::types::*;
+use libc::c_void;
+
+/// Installs the callback if the context supports KHR_debug. Notifications,
+/// the least severe messages, are left out. Returns whether the callback was
+/// installed.
+pub fn install() -> bool {
+    if !gl::DebugMessageCallback::is_loaded() || !gl::DebugMessageControl::is_loaded() {
+        return false;
+    }
+
+    unsafe {
+        gl::DebugMessageCallback(callback, ptr::null());
+        gl::DebugMessageControl(gl::DONT_CARE, gl::DONT_CARE, gl::DEBUG_SEVERITY_NOTIFICATION,
+                                0, ptr::null(), gl::FALSE);
+    }
+    gl::Enable(gl::DEBUG_OUTPUT);
+    gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+    true
+}
+
+extern "system" fn callback(source: GLenum, gltype: GLenum, id: GLuint, severity: GLenum,
+                            length: GLsizei, message: *const GLchar, _: *mut c_void) {
+    let text = unsafe { str::raw::from_buf_len(message as *const u8, length as uint) };
+    println!("GL {} {} {} {}: {}",
+             severity_name(severity), source_name(source), type_name(gltype), id, text);
+}
+
+fn severity_name(severity: GLenum) -> &'static str {
+    match severity {
+        gl::DEBUG_SEVERITY_HIGH => "high",
+        gl::DEBUG_SEVERITY_MEDIUM => "medium",
+        gl::DEBUG_SEVERITY_LOW => "low",
+        gl::DEBUG_SEVERITY_NOTIFICATION => "notification",
+        _ => "unknown-severity",
+    }
+}
+
+fn source_name(source: GLenum) -> &'static str {
+    match source {
+        gl::DEBUG_SOURCE_API => "api",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window-system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader-compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third-party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    }
+}
+
+fn type_name(gltype: GLenum) -> &'static str {
+    match gltype {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined-behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        _ => "other",
+    }
+}
+
+/// Returns the description of a glGetError() code, as gluErrorString()
+/// words it.
+pub fn error_name(error: GLenum) -> &'static str {
+    match error {
+        gl::NO_ERROR => "no error",
+        gl::INVALID_ENUM => "invalid enumerant",
+        gl::INVALID_VALUE => "invalid value",
+        gl::INVALID_OPERATION => "invalid operation",
+        gl::STACK_OVERFLOW => "stack overflow",
+        gl::STACK_UNDERFLOW => "stack underflow",
+        gl::OUT_OF_MEMORY => "out of memory",
+        gl::INVALID_FRAMEBUFFER_OPERATION => "invalid framebuffer operation",
+        _ => "unknown error",
+    }
+}
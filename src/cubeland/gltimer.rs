@@ -46,3 +46,10 @@ impl GLTimer {
         before_time - after_time
     }
 }
+
+impl Drop for GLTimer {
+    fn drop(&mut self) {
+        let queries = [self.before_query, self.after_query];
+        unsafe { gl::DeleteQueries(2, &queries[0]) };
+    }
+}
@@ -0,0 +1,456 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic noise for terrain generation: Perlin-style gradient noise,
+//! simplex noise and value noise.
+//!
+//! All arithmetic is done in fixed point on integers, so the same seed and
+//! position give bit-identical results on every platform and compiler. That
+//! isn't true of floating point noise, where libm functions and fused
+//! multiply-adds differ between systems. Points are sampled on the integer
+//! block grid. The result is converted to f64 only at the end, exactly, so
+//! later arithmetic that uses only +, - and * on it stays deterministic too.
+//!
+//! Gradient and value noise can repeat along x and z, for worlds that wrap
+//! around. The period is then stretched to a whole number of lattice cells
+//! and the lattice points are wrapped before hashing, so the noise is
+//! seamless. Simplex noise's lattice is skewed, so it can't repeat on a
+//! square period.
+
+use std::cmp;
+use std::num::Integer;
+
+use noisesource::Noise;
+
+/// Fractional bits of noise values and sample coordinates.
+static FRAC_BITS : uint = 16;
+static ONE : i64 = 1 << FRAC_BITS;
+
+/// Unskew factor of 3D simplex noise, 1/6. The skew factor is 1/3.
+static SIMPLEX_G3 : i64 = ONE / 6;
+/// Squared radius of each simplex corner's influence, 0.6.
+static SIMPLEX_RADIUS2 : i64 = 39322;
+
+/// Fractional bits of frequencies. Frequencies are far below one, so they
+/// need more precision than coordinates.
+static FREQ_BITS : uint = 32;
+
+/// Fixed point fractal sum of octaves of some noise basis, each twice the
+/// frequency of the last.
+struct Octaves {
+    seed: u32,
+    octaves: uint,
+    frequency: i64,
+    persistence: i64,
+    /// Blocks along x and z the noise repeats over, and the lattice cells
+    /// the first octave fits into them.
+    period: Option<(i64, i64)>,
+}
+
+impl Octaves {
+    /// frequency and persistence are rounded to fixed point here, and
+    /// rounding a constant gives the same result everywhere. With a period,
+    /// frequency is only used to pick the number of cells.
+    fn new(seed: u32, octaves: uint, frequency: f64, persistence: f64, period: Option<i64>) -> Octaves {
+        Octaves {
+            seed: seed,
+            octaves: octaves,
+            frequency: (frequency * (1u64 << FREQ_BITS) as f64).round() as i64,
+            persistence: (persistence * ONE as f64).round() as i64,
+            period: period.map(|period| (period, cmp::max((period as f64 * frequency).round() as i64, 1))),
+        }
+    }
+
+    /// Converts block coordinate v along x or z to a fixed point noise
+    /// coordinate, at frequency, the octave's.
+    fn scale_xz(&self, v: i64, octave: uint, frequency: i64) -> i64 {
+        match self.period {
+            Some((period, cells)) => v.mod_floor(&period) * ((cells << octave) * ONE) / period,
+            None => scale(v, frequency),
+        }
+    }
+
+    /// Lattice cells along x and z the octave repeats over, or 0 if it
+    /// doesn't.
+    fn cells(&self, octave: uint) -> i64 {
+        match self.period {
+            Some((_, cells)) => cells << octave,
+            None => 0,
+        }
+    }
+
+    fn sum(&self, basis: fn(u32, i64, i64, i64, i64) -> i64, x: i64, y: i64, z: i64) -> f64 {
+        let mut total = 0;
+        let mut amplitude = ONE;
+        let mut frequency = self.frequency;
+
+        for octave in range(0, self.octaves) {
+            let seed = self.seed + octave as u32;
+            let n = basis(seed, self.scale_xz(x, octave, frequency), scale(y, frequency),
+                          self.scale_xz(z, octave, frequency), self.cells(octave));
+            total += (n * amplitude) >> FRAC_BITS;
+            amplitude = (amplitude * self.persistence) >> FRAC_BITS;
+            frequency *= 2;
+        }
+
+        total as f64 / ONE as f64
+    }
+
+    /// Like sum() with basis lattice3(at_corner, ..), for every x in xs and z
+    /// in zs at height y, stored x-major in out. Each lattice point's hash
+    /// and each coordinate's fade are computed once per octave rather than
+    /// once per sample, and the results are identical to sum()'s.
+    fn sum_grid(&self, at_corner: fn(u32, i64, i64, i64) -> i64,
+                xs: &[i64], y: i64, zs: &[i64], out: &mut [f64]) {
+        assert_eq!(out.len(), xs.len() * zs.len());
+        let mut totals = Vec::from_elem(out.len(), 0i64);
+        let mut amplitude = ONE;
+        let mut frequency = self.frequency;
+
+        for octave in range(0, self.octaves) {
+            let seed = self.seed + octave as u32;
+            let cells = self.cells(octave);
+            let xs: Vec<i64> = xs.iter().map(|&x| self.scale_xz(x, octave, frequency)).collect();
+            let zs: Vec<i64> = zs.iter().map(|&z| self.scale_xz(z, octave, frequency)).collect();
+            let xaxis = LatticeAxis::new(xs.as_slice());
+            let zaxis = LatticeAxis::new(zs.as_slice());
+            let sy = scale(y, frequency);
+            let (iy, fy) = (sy >> FRAC_BITS, sy & (ONE - 1));
+            let v = fade(fy);
+
+            /* Hashes of the lattice points above and below y, z-major */
+            let nz = zaxis.points.len();
+            let mut hashes = Vec::with_capacity(xaxis.points.len() * nz * 2);
+            for &px in xaxis.points.iter() {
+                for &pz in zaxis.points.iter() {
+                    hashes.push(hash(seed, wrap_cell(px, cells), iy, wrap_cell(pz, cells)));
+                    hashes.push(hash(seed, wrap_cell(px, cells), iy + 1, wrap_cell(pz, cells)));
+                }
+            }
+
+            for (i, &(x0, x1, fx, u)) in xaxis.samples.iter().enumerate() {
+                for (j, &(z0, z1, fz, w)) in zaxis.samples.iter().enumerate() {
+                    let c = |px: uint, dx: i64, dy: i64, pz: uint, dz: i64| {
+                        at_corner(hashes[(px * nz + pz) * 2 + dy as uint],
+                                  fx - dx * ONE, fy - dy * ONE, fz - dz * ONE)
+                    };
+
+                    let n = lerp(w,
+                                 lerp(v,
+                                      lerp(u, c(x0, 0, 0, z0, 0), c(x1, 1, 0, z0, 0)),
+                                      lerp(u, c(x0, 0, 1, z0, 0), c(x1, 1, 1, z0, 0))),
+                                 lerp(v,
+                                      lerp(u, c(x0, 0, 0, z1, 1), c(x1, 1, 0, z1, 1)),
+                                      lerp(u, c(x0, 0, 1, z1, 1), c(x1, 1, 1, z1, 1))));
+                    *totals.get_mut(i * zs.len() + j) += (n * amplitude) >> FRAC_BITS;
+                }
+            }
+
+            amplitude = (amplitude * self.persistence) >> FRAC_BITS;
+            frequency *= 2;
+        }
+
+        for (o, &total) in out.mut_iter().zip(totals.iter()) {
+            *o = total as f64 / ONE as f64;
+        }
+    }
+}
+
+/// Fixed point noise coordinates along one axis, split into lattice cells.
+/// Neighboring coordinates mostly share cells, so each lattice point is
+/// listed once.
+struct LatticeAxis {
+    points: Vec<i64>,
+    /// For each coordinate, the indices in points of its cell's lower and
+    /// upper ends, its offset into the cell and the fade of that.
+    samples: Vec<(uint, uint, i64, i64)>,
+}
+
+impl LatticeAxis {
+    fn new(coords: &[i64]) -> LatticeAxis {
+        let mut axis = LatticeAxis { points: Vec::new(), samples: Vec::with_capacity(coords.len()) };
+        for &c in coords.iter() {
+            let (cell, f) = (c >> FRAC_BITS, c & (ONE - 1));
+            let lower = axis.point(cell);
+            let upper = axis.point(cell + 1);
+            axis.samples.push((lower, upper, f, fade(f)));
+        }
+        axis
+    }
+
+    /// Returns the index of lattice point p, adding it if it's new.
+    fn point(&mut self, p: i64) -> uint {
+        match self.points.iter().position(|&q| q == p) {
+            Some(i) => i,
+            None => {
+                self.points.push(p);
+                self.points.len() - 1
+            },
+        }
+    }
+}
+
+/// Fractal sum of improved Perlin noise octaves.
+pub struct GradientNoise {
+    octaves: Octaves,
+}
+
+impl GradientNoise {
+    /// With a period, the noise repeats every period blocks along x and z.
+    pub fn new(seed: u32, octaves: uint, frequency: f64, persistence: f64, period: Option<i64>) -> GradientNoise {
+        GradientNoise { octaves: Octaves::new(seed, octaves, frequency, persistence, period) }
+    }
+}
+
+impl Noise for GradientNoise {
+    fn get(&self, x: i64, y: i64, z: i64) -> f64 {
+        self.octaves.sum(noise3, x, y, z)
+    }
+
+    fn get_grid(&self, xs: &[i64], y: i64, zs: &[i64], out: &mut [f64]) {
+        self.octaves.sum_grid(grad, xs, y, zs, out)
+    }
+}
+
+/// Fractal sum of simplex noise octaves. It has fewer axis-aligned
+/// artifacts than Perlin noise.
+pub struct SimplexNoise {
+    octaves: Octaves,
+}
+
+impl SimplexNoise {
+    pub fn new(seed: u32, octaves: uint, frequency: f64, persistence: f64) -> SimplexNoise {
+        SimplexNoise { octaves: Octaves::new(seed, octaves, frequency, persistence, None) }
+    }
+}
+
+impl Noise for SimplexNoise {
+    fn get(&self, x: i64, y: i64, z: i64) -> f64 {
+        self.octaves.sum(simplex3, x, y, z)
+    }
+}
+
+/// Fractal sum of value noise octaves: random values at lattice points,
+/// smoothly interpolated. Blobbier than gradient noise, and cheaper.
+pub struct ValueNoise {
+    octaves: Octaves,
+}
+
+impl ValueNoise {
+    /// With a period, the noise repeats every period blocks along x and z.
+    pub fn new(seed: u32, octaves: uint, frequency: f64, persistence: f64, period: Option<i64>) -> ValueNoise {
+        ValueNoise { octaves: Octaves::new(seed, octaves, frequency, persistence, period) }
+    }
+}
+
+impl Noise for ValueNoise {
+    fn get(&self, x: i64, y: i64, z: i64) -> f64 {
+        self.octaves.sum(value3, x, y, z)
+    }
+
+    fn get_grid(&self, xs: &[i64], y: i64, zs: &[i64], out: &mut [f64]) {
+        self.octaves.sum_grid(value_corner, xs, y, zs, out)
+    }
+}
+
+/// Converts a block coordinate to a fixed point noise coordinate.
+fn scale(v: i64, frequency: i64) -> i64 {
+    (v * frequency) >> (FREQ_BITS - FRAC_BITS)
+}
+
+/// Improved Perlin noise at fixed point (x, y, z), repeating every cells
+/// lattice cells along x and z unless that's 0.
+fn noise3(seed: u32, x: i64, y: i64, z: i64, cells: i64) -> i64 {
+    lattice3(grad, seed, x, y, z, cells)
+}
+
+/// Noise interpolated between values at the integer lattice points around
+/// fixed point (x, y, z). at_corner gives the value at a lattice point from
+/// its hash and the offset from it to (x, y, z).
+fn lattice3(at_corner: fn(u32, i64, i64, i64) -> i64, seed: u32, x: i64, y: i64, z: i64, cells: i64) -> i64 {
+    /* Arithmetic shift rounds toward negative infinity, like floor() */
+    let (ix, iy, iz) = (x >> FRAC_BITS, y >> FRAC_BITS, z >> FRAC_BITS);
+    let (fx, fy, fz) = (x & (ONE - 1), y & (ONE - 1), z & (ONE - 1));
+    let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        at_corner(hash(seed, wrap_cell(ix + dx, cells), iy + dy, wrap_cell(iz + dz, cells)),
+                  fx - dx * ONE, fy - dy * ONE, fz - dz * ONE)
+    };
+
+    lerp(w,
+         lerp(v,
+              lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+              lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+         lerp(v,
+              lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+              lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+}
+
+/// Simplex noise at fixed point (x, y, z). It never repeats, so cells is
+/// always 0.
+fn simplex3(seed: u32, x: i64, y: i64, z: i64, _cells: i64) -> i64 {
+    /* Skew to find the cube of simplices containing the point */
+    let s = (x + y + z).div_floor(&3);
+    let (i, j, k) = ((x + s) >> FRAC_BITS, (y + s) >> FRAC_BITS, (z + s) >> FRAC_BITS);
+    let t = ((i + j + k) * ONE).div_floor(&6);
+    let (x0, y0, z0) = (x - (i * ONE - t), y - (j * ONE - t), z - (k * ONE - t));
+
+    /* Corners of the simplex, in the order they're visited from the origin */
+    let (c1, c2) = if x0 >= y0 {
+        if y0 >= z0 {
+            ((1, 0, 0), (1, 1, 0))
+        } else if x0 >= z0 {
+            ((1, 0, 0), (1, 0, 1))
+        } else {
+            ((0, 0, 1), (1, 0, 1))
+        }
+    } else {
+        if y0 < z0 {
+            ((0, 0, 1), (0, 1, 1))
+        } else if x0 < z0 {
+            ((0, 1, 0), (0, 1, 1))
+        } else {
+            ((0, 1, 0), (1, 1, 0))
+        }
+    };
+
+    let mut total = 0;
+    for (n, &(di, dj, dk)) in [(0, 0, 0), c1, c2, (1, 1, 1)].iter().enumerate() {
+        /* Unskew the corner's offset */
+        let g = n as i64 * SIMPLEX_G3;
+        let (cx, cy, cz) = (x0 - di * ONE + g, y0 - dj * ONE + g, z0 - dk * ONE + g);
+        let falloff = SIMPLEX_RADIUS2 - ((cx * cx + cy * cy + cz * cz) >> FRAC_BITS);
+        if falloff > 0 {
+            let f2 = (falloff * falloff) >> FRAC_BITS;
+            let f4 = (f2 * f2) >> FRAC_BITS;
+            total += (f4 * grad(hash(seed, i + di, j + dj, k + dk), cx, cy, cz)) >> FRAC_BITS;
+        }
+    }
+
+    /* Scales the result to roughly -1 to 1 */
+    32 * total
+}
+
+/// Value noise at fixed point (x, y, z), repeating like noise3().
+fn value3(seed: u32, x: i64, y: i64, z: i64, cells: i64) -> i64 {
+    lattice3(value_corner, seed, x, y, z, cells)
+}
+
+/// Wraps lattice coordinate i into 0 to cells, unless cells is 0.
+fn wrap_cell(i: i64, cells: i64) -> i64 {
+    if cells == 0 { i } else { i.mod_floor(&cells) }
+}
+
+/// 17 bits of the hash, so -ONE to ONE.
+fn value_corner(hash: u32, _x: i64, _y: i64, _z: i64) -> i64 {
+    (hash >> 15) as i64 - ONE
+}
+
+/// 6t^5 - 15t^4 + 10t^3
+fn fade(t: i64) -> i64 {
+    let t3 = (((t * t) >> FRAC_BITS) * t) >> FRAC_BITS;
+    let inner = ((t * (6 * t - 15 * ONE)) >> FRAC_BITS) + 10 * ONE;
+    (t3 * inner) >> FRAC_BITS
+}
+
+fn lerp(t: i64, a: i64, b: i64) -> i64 {
+    a + (((b - a) * t) >> FRAC_BITS)
+}
+
+/// Dot product of the offset from a corner with one of the 12 gradients
+/// pointing at the edges of a cube.
+fn grad(hash: u32, x: i64, y: i64, z: i64) -> i64 {
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+/// Hashes a seed and integer position to 32 well-mixed bits.
+pub fn hash(seed: u32, x: i64, y: i64, z: i64) -> u32 {
+    let mut h = seed ^ 0x9E3779B9;
+    for &v in [x, y, z].iter() {
+        h = (h ^ v as u32 ^ (v >> 32) as u32) * 0x85EBCA6B;
+        h ^= h >> 13;
+    }
+    h *= 0xC2B2AE35;
+    h ^ (h >> 16)
+}
+
+#[test]
+fn test_gradient_noise() {
+    /* Reference values; these must never change for a given seed */
+    let density = GradientNoise::new(42, 4, 0.015, 0.5, None);
+    assert_eq!(density.get(100, -20, 7), 3326.0 / 65536.0);
+    let height = GradientNoise::new(42 * 71, 8, 0.001, 0.5, None);
+    assert_eq!(height.get(-12345, 0, 678), 8885.0 / 65536.0);
+}
+
+#[test]
+fn test_simplex_and_value_noise() {
+    /* Reference values; these must never change for a given seed */
+    assert_eq!(SimplexNoise::new(42, 4, 0.015, 0.5).get(100, -20, 7), 3964.0 / 65536.0);
+    assert_eq!(SimplexNoise::new(42 * 71, 8, 0.001, 0.5).get(-12345, 0, 678), 20566.0 / 65536.0);
+    assert_eq!(ValueNoise::new(42, 4, 0.015, 0.5, None).get(100, -20, 7), -6502.0 / 65536.0);
+    assert_eq!(ValueNoise::new(42 * 71, 8, 0.001, 0.5, None).get(-12345, 0, 678), -5539.0 / 65536.0);
+}
+
+#[test]
+fn test_noise_grid() {
+    /* Includes a jump, like at the seam of a wrapped world */
+    let xs = [250, 251, 252, 0, 1, 2, -7];
+    let zs = [-5, -4, 10000, 3];
+    let noises = [box GradientNoise::new(3, 8, 0.01, 0.5, None) as Box<Noise>,
+                  box ValueNoise::new(3, 8, 0.01, 0.5, None) as Box<Noise>,
+                  box GradientNoise::new(3, 8, 0.01, 0.5, Some(253)) as Box<Noise>,
+                  box ValueNoise::new(3, 8, 0.01, 0.5, Some(253)) as Box<Noise>];
+    for noise in noises.iter() {
+        let mut out = [0.0, ..28];
+        noise.get_grid(xs.as_slice(), 9, zs.as_slice(), out.as_mut_slice());
+        for (i, &x) in xs.iter().enumerate() {
+            for (j, &z) in zs.iter().enumerate() {
+                assert_eq!(out[i * zs.len() + j], noise.get(x, 9, z));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_periodic_noise() {
+    /* 250 blocks doesn't fit a whole number of cells at this frequency */
+    let noises = [box GradientNoise::new(5, 8, 0.015, 0.5, Some(250)) as Box<Noise>,
+                  box ValueNoise::new(5, 8, 0.015, 0.5, Some(250)) as Box<Noise>,
+                  box GradientNoise::new(5, 2, 0.0001, 0.5, Some(250)) as Box<Noise>];
+    for noise in noises.iter() {
+        for &(x, y, z) in [(0, 3, 0), (17, -40, 249), (-1, 8, 120)].iter() {
+            assert_eq!(noise.get(x, y, z), noise.get(x + 250, y, z));
+            assert_eq!(noise.get(x, y, z), noise.get(x - 500, y, z + 750));
+        }
+        /* No cliff across the seam, any more than between other neighbors */
+        let step = |x: i64| (noise.get(x, 0, 40) - noise.get(x - 1, 0, 40)).abs();
+        assert!(step(250) < 0.2);
+        assert!(step(0) == step(250));
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Animation of the block held in the lower right corner of the view.
+//!
+//! The block bobs while the camera moves and swings forward when the player
+//! clicks. Positions are in view space, where the camera looks down -z.
+
+extern crate cgmath;
+
+use std::f64::consts::PI;
+
+use cgmath::vector::Vector3;
+
+/// Where the center of the held block sits when still.
+static REST_POSITION : Vector3<f32> = Vector3 { x: 0.55, y: -0.45, z: -0.9 };
+/// Edge length of the held block in view space.
+pub static HELD_BLOCK_SIZE : f32 = 0.3;
+
+/// Radians of bob cycle per second while moving.
+static BOB_RATE : f64 = 9.0;
+static BOB_WIDTH : f32 = 0.02;
+static BOB_HEIGHT : f32 = 0.03;
+/// Seconds for the bob to fade in when moving starts, or out when it stops.
+static BOB_FADE_TIME : f64 = 0.2;
+
+static SWING_TIME : f64 = 0.25;
+/// Radians the block pitches forward at the bottom of a swing.
+static SWING_ANGLE : f32 = 0.8;
+/// Distance the block dips down and forward at the bottom of a swing.
+static SWING_REACH : f32 = 0.12;
+
+pub struct Hand {
+    bob_phase: f64,
+    /// How much of the bob is showing, from 0 to 1.
+    bob_amount: f64,
+    /// Progress through the current swing, from 0 to 1.
+    swing: Option<f64>,
+}
+
+impl Hand {
+    pub fn new() -> Hand {
+        Hand { bob_phase: 0.0, bob_amount: 0.0, swing: None }
+    }
+
+    /// Starts a swing, unless one is already under way.
+    pub fn swing(&mut self) {
+        if self.swing.is_none() {
+            self.swing = Some(0.0);
+        }
+    }
+
+    pub fn tick(&mut self, dt: f64, moving: bool) {
+        let step = dt / BOB_FADE_TIME;
+        self.bob_amount = if moving {
+            (self.bob_amount + step).min(1.0)
+        } else {
+            (self.bob_amount - step).max(0.0)
+        };
+        if self.bob_amount > 0.0 {
+            self.bob_phase = (self.bob_phase + BOB_RATE * dt) % (2.0 * PI);
+        }
+
+        self.swing = match self.swing {
+            Some(t) if t + dt / SWING_TIME < 1.0 => Some(t + dt / SWING_TIME),
+            _ => None,
+        };
+    }
+
+    /// Returns the view space position of the center of the held block and
+    /// how far it's pitched forward, in radians.
+    pub fn pose(&self) -> (Vector3<f32>, f32) {
+        let bob = self.bob_amount as f32;
+        let (s, c) = (self.bob_phase.sin() as f32, self.bob_phase.cos() as f32);
+        let mut position = Vector3::new(REST_POSITION.x + c * BOB_WIDTH * bob,
+                                        REST_POSITION.y - s.abs() * BOB_HEIGHT * bob,
+                                        REST_POSITION.z);
+
+        let swing = match self.swing {
+            Some(t) => (t * PI).sin() as f32,
+            None => 0.0,
+        };
+        position.y -= swing * SWING_REACH;
+        position.z -= swing * SWING_REACH;
+
+        (position, swing * SWING_ANGLE)
+    }
+}
+
+#[test]
+fn test_hand() {
+    let mut hand = Hand::new();
+    assert_eq!(hand.pose(), (REST_POSITION, 0.0));
+
+    hand.swing();
+    hand.tick(SWING_TIME / 2.0, false);
+    let (position, pitch) = hand.pose();
+    assert_eq!(pitch, SWING_ANGLE);
+    assert!(position.y < REST_POSITION.y);
+
+    hand.tick(SWING_TIME, false);
+    assert_eq!(hand.pose(), (REST_POSITION, 0.0));
+
+    /* Bobbing moves the block while walking and settles after stopping */
+    hand.tick(0.1, true);
+    let (position, _) = hand.pose();
+    assert!(position != REST_POSITION);
+    hand.tick(BOB_FADE_TIME, false);
+    let (position, _) = hand.pose();
+    assert_eq!(position, REST_POSITION);
+}
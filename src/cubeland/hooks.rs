@@ -0,0 +1,239 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Script hooks that run simple commands in response to world events.
+//!
+//! A hook script is a text file with one hook per line:
+//!
+//!     on <event> <command> [args...]
+//!
+//! Events are `block_placed`, `block_broken` and `chunk_entered`. Commands are
+//! `log <text...>`, `set <dx> <dy> <dz> <blocktype>` and
+//! `spawn <dx> <dy> <dz> <entity>`, where the offset is relative to the block
+//! (or chunk origin) the event happened at and the entity is a mob or block
+//! name, as for the `spawn` console command. Scripts can only touch loaded
+//! chunks and are limited to MAX_EDITS_PER_EVENT edits and
+//! MAX_SPAWNS_PER_EVENT spawns per event. Edits made by scripts do not fire
+//! further events.
+
+extern crate cgmath;
+
+use std::io::File;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vector3;
+
+use CHUNK_SIZE;
+use chunk::ChunkLoader;
+use entity::{Entities, EntityKind};
+use terrain::BlockType;
+
+static MAX_EDITS_PER_EVENT : uint = 64;
+static MAX_SPAWNS_PER_EVENT : uint = 8;
+
+pub enum WorldEvent {
+    BlockPlaced(Vector3<i64>, BlockType),
+    BlockBroken(Vector3<i64>, BlockType),
+    ChunkEntered(Vector3<i64>),
+}
+
+#[deriving(PartialEq, Eq)]
+enum EventKind {
+    EventBlockPlaced,
+    EventBlockBroken,
+    EventChunkEntered,
+}
+
+enum Command {
+    CommandLog(String),
+    CommandSet(Vector3<i64>, BlockType),
+    CommandSpawn(Vector3<i64>, EntityKind),
+}
+
+struct Hook {
+    kind: EventKind,
+    command: Command,
+}
+
+pub struct ScriptHooks {
+    hooks: Vec<Hook>,
+}
+
+impl ScriptHooks {
+    pub fn empty() -> ScriptHooks {
+        ScriptHooks { hooks: Vec::new() }
+    }
+
+    pub fn load(path: &Path) -> Result<ScriptHooks, String> {
+        let src = match File::open(path).read_to_string() {
+            Ok(src) => src,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        let mut hooks = Vec::new();
+
+        for (i, line) in src.as_slice().lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+
+            match parse_hook(line) {
+                Ok(hook) => hooks.push(hook),
+                Err(msg) => return Err(format!("{}:{}: {}", path.display(), i + 1, msg)),
+            }
+        }
+
+        Ok(ScriptHooks { hooks: hooks })
+    }
+
+    /// Runs the hooks for event. Their edits are made together after all of
+    /// them have run, so each affected chunk is remeshed once.
+    pub fn fire(&self, event: &WorldEvent, chunk_loader: &mut ChunkLoader, entities: &mut Entities) {
+        let (kind, origin) = match *event {
+            BlockPlaced(p, _) => (EventBlockPlaced, p),
+            BlockBroken(p, _) => (EventBlockBroken, p),
+            ChunkEntered(c) => (EventChunkEntered, c.mul_s(CHUNK_SIZE as i64)),
+        };
+
+        let mut edits = Vec::new();
+        let mut spawns = 0;
+
+        for hook in self.hooks.iter().filter(|hook| hook.kind == kind) {
+            match hook.command {
+                CommandLog(ref text) => {
+                    println!("hook: {} ({}, {}, {})", text, origin.x, origin.y, origin.z);
+                },
+                CommandSet(offset, blocktype) => {
+                    if edits.len() >= MAX_EDITS_PER_EVENT {
+                        println!("hook: edit limit reached");
+                        break;
+                    }
+                    edits.push((origin.add_v(&offset), blocktype));
+                },
+                CommandSpawn(offset, entity_kind) => {
+                    if spawns >= MAX_SPAWNS_PER_EVENT {
+                        println!("hook: spawn limit reached");
+                        break;
+                    }
+                    let p = origin.add_v(&offset);
+                    if chunk_loader.get_block(p).is_none() {
+                        continue;
+                    }
+                    spawns += 1;
+                    let center = Vector3::new(p.x as f64 + 0.5, p.y as f64 + 0.5, p.z as f64 + 0.5);
+                    entities.spawn(entity_kind, center, Vector3::zero());
+                },
+            }
+        }
+
+        chunk_loader.set_blocks(edits.as_slice());
+    }
+}
+
+fn parse_hook(line: &str) -> Result<Hook, String> {
+    let words: Vec<&str> = line.words().collect();
+
+    if words.len() < 3 || words[0] != "on" {
+        return Err(String::from_str("expected 'on <event> <command>'"));
+    }
+
+    let kind = match words[1] {
+        "block_placed" => EventBlockPlaced,
+        "block_broken" => EventBlockBroken,
+        "chunk_entered" => EventChunkEntered,
+        other => return Err(format!("unknown event '{}'", other)),
+    };
+
+    let args = words.slice_from(3);
+    let command = match words[2] {
+        "log" => CommandLog(args.connect(" ")),
+        "set" => {
+            if args.len() != 4 {
+                return Err(String::from_str("expected 'set <dx> <dy> <dz> <blocktype>'"));
+            }
+            let offset = try!(parse_offset(args));
+            let blocktype = match BlockType::from_name(args[3]) {
+                Some(blocktype) => blocktype,
+                None => return Err(format!("unknown blocktype '{}'", args[3])),
+            };
+            CommandSet(offset, blocktype)
+        },
+        "spawn" => {
+            if args.len() != 4 {
+                return Err(String::from_str("expected 'spawn <dx> <dy> <dz> <entity>'"));
+            }
+            let offset = try!(parse_offset(args));
+            let entity_kind = match EntityKind::from_name(args[3]) {
+                Some(entity_kind) => entity_kind,
+                None => return Err(format!("unknown entity '{}'", args[3])),
+            };
+            CommandSpawn(offset, entity_kind)
+        },
+        other => return Err(format!("unknown command '{}'", other)),
+    };
+
+    Ok(Hook { kind: kind, command: command })
+}
+
+fn parse_offset(args: &[&str]) -> Result<Vector3<i64>, String> {
+    match (from_str::<i64>(args[0]), from_str::<i64>(args[1]), from_str::<i64>(args[2])) {
+        (Some(x), Some(y), Some(z)) => Ok(Vector3::new(x, y, z)),
+        _ => Err(String::from_str("invalid offset")),
+    }
+}
+
+#[test]
+fn test_parse_hook() {
+    let hook = parse_hook("on chunk_entered log entered  chunk").unwrap();
+    assert!(hook.kind == EventChunkEntered);
+    match hook.command {
+        CommandLog(text) => assert_eq!(text.as_slice(), "entered chunk"),
+        _ => fail!("expected log"),
+    }
+
+    let hook = parse_hook("on block_placed set 0 -1 2 stone").unwrap();
+    assert!(hook.kind == EventBlockPlaced);
+    match hook.command {
+        CommandSet(offset, blocktype) => {
+            assert_eq!(offset, Vector3::new(0, -1, 2));
+            assert!(blocktype == ::terrain::BlockStone);
+        },
+        _ => fail!("expected set"),
+    }
+
+    let hook = parse_hook("on block_broken spawn 0 1 0 pig").unwrap();
+    assert!(hook.kind == EventBlockBroken);
+    match hook.command {
+        CommandSpawn(offset, entity_kind) => {
+            assert_eq!(offset, Vector3::new(0, 1, 0));
+            assert_eq!(entity_kind.name(), "pig");
+        },
+        _ => fail!("expected spawn"),
+    }
+
+    let error = |line: &str| parse_hook(line).err().unwrap();
+    assert_eq!(error("block_placed log hi"), String::from_str("expected 'on <event> <command>'"));
+    assert_eq!(error("on block_placed"), String::from_str("expected 'on <event> <command>'"));
+    assert_eq!(error("on block_moved log hi"), String::from_str("unknown event 'block_moved'"));
+    assert_eq!(error("on block_placed set 0 1 stone"),
+               String::from_str("expected 'set <dx> <dy> <dz> <blocktype>'"));
+    assert_eq!(error("on block_placed set 0 x 0 stone"), String::from_str("invalid offset"));
+    assert_eq!(error("on block_placed set 0 1 0 cheese"), String::from_str("unknown blocktype 'cheese'"));
+    assert_eq!(error("on block_placed spawn 0 1 0"),
+               String::from_str("expected 'spawn <dx> <dy> <dz> <entity>'"));
+    assert_eq!(error("on block_placed spawn 0 1 x pig"), String::from_str("invalid offset"));
+    assert_eq!(error("on block_placed spawn 0 1 0 dragon"), String::from_str("unknown entity 'dragon'"));
+    assert_eq!(error("on block_placed explode 3"), String::from_str("unknown command 'explode'"));
+}
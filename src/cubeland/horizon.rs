@@ -0,0 +1,316 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cheap stand-in for the terrain beyond the visible radius.
+//!
+//! The horizon is a heightfield grid centered under the camera, sampled from
+//! the generator's height noise every HORIZON_SPACING blocks on a worker
+//! task. It has no caves, decorations or edits. The renderer skips the part
+//! of it inside the loaded chunks, so it only shows past their edge.
+//!
+//! Inside that edge, chunk columns that haven't loaded yet get a flat
+//! placeholder at the grid's height instead of a hole into the sky.
+
+extern crate cgmath;
+extern crate gl;
+extern crate hgl;
+
+use std::comm::{DuplexStream, duplex};
+
+use gl::types::*;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vector3;
+
+use CHUNK_SIZE;
+use colorscheme::{ColorScheme, SchemeNormal};
+use terrain::{BlockGrass, BlockSand, BlockWater, TerrainGenerator, TerrainNoise, WATER_HEIGHT};
+use worldheight::WorldHeight;
+
+/// Blocks between grid vertices.
+pub static HORIZON_SPACING : i64 = 16;
+/// Grid cells along each side. The grid reaches HORIZON_CELLS / 2 *
+/// HORIZON_SPACING blocks from its center, about as far as fog lets you see.
+static HORIZON_CELLS : i64 = 120;
+/// Farthest the grid reaches horizontally from the camera, in blocks.
+pub static HORIZON_DISTANCE : i64 = (HORIZON_CELLS / 2 + 4) * HORIZON_SPACING;
+/// The grid is rebuilt when the camera's column, rounded to this many blocks,
+/// changes. A multiple of HORIZON_SPACING, so vertices stay on the same
+/// columns and the horizon doesn't shimmer.
+static HORIZON_SNAP : i64 = HORIZON_SPACING * 4;
+/// Blocks the grid is lowered by, so that where it overlaps loaded chunks
+/// the real terrain is in front.
+static HORIZON_SINK : f32 = 3.0;
+/// Brightness change per block of rise per block along the grid.
+static SLOPE_SHADE : f32 = 0.5;
+
+/// A column of chunks whose chunk at the surface isn't loaded, drawn as a
+/// square at the horizon's height.
+pub struct Placeholder {
+    /// Corner of the square with the least x and z.
+    pub corner : Vector3<f64>,
+    pub color : Vector3<f32>,
+}
+
+/// Layout of the horizon vertex buffer.
+pub struct HorizonVertex {
+    /// Relative to the grid center.
+    pub position : Vector3<f32>,
+    pub color : Vector3<f32>,
+}
+
+pub struct Horizon {
+    pub enabled: bool,
+    /// Column the uploaded grid is centered on.
+    center: Option<(i64, i64)>,
+    /// Column of the grid the worker is building.
+    requested: Option<(i64, i64)>,
+    vertex_buffer: Option<hgl::Vbo>,
+    /// What was uploaded to vertex_buffer, before recoloring, kept to place
+    /// placeholders.
+    vertices: Vec<HorizonVertex>,
+    /// Recolors the grid as it's uploaded.
+    color_scheme: ColorScheme,
+    /// The grid's topology never changes, so this is built once.
+    element_buffer: hgl::Ebo,
+    element_count: uint,
+    stream: DuplexStream<(i64, i64), ((i64, i64), Vec<HorizonVertex>)>,
+}
+
+impl Horizon {
+    pub fn new(seed: u32, period: Option<i64>, world_height: WorldHeight, noise: &TerrainNoise) -> Horizon {
+        let elements = grid_elements();
+        Horizon {
+            enabled: true,
+            center: None,
+            requested: None,
+            vertex_buffer: None,
+            vertices: Vec::new(),
+            color_scheme: SchemeNormal,
+            element_buffer: hgl::Ebo::from_indices(elements.as_slice()),
+            element_count: elements.len(),
+            stream: Horizon::spawn_worker(seed, period, world_height, noise.clone()),
+        }
+    }
+
+    fn spawn_worker(seed: u32, period: Option<i64>, world_height: WorldHeight, noise: TerrainNoise) -> DuplexStream<(i64, i64), ((i64, i64), Vec<HorizonVertex>)> {
+        let (horizon_stream, worker_stream) = duplex();
+
+        spawn(proc() {
+            let generator = TerrainGenerator::new(seed, period, world_height, &noise);
+            loop {
+                let center : (i64, i64) = match worker_stream.recv_opt() {
+                    Ok(x) => x,
+                    Err(()) => break,
+                };
+                let vertices = grid_vertices(&generator, center);
+                if worker_stream.send_opt((center, vertices)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        horizon_stream
+    }
+
+    /// Uploads a finished grid, and asks for a new one if the camera has
+    /// moved far enough from the current one. Call once per frame.
+    pub fn update(&mut self, eye: Vector3<f64>) {
+        if !self.enabled {
+            return;
+        }
+
+        match self.stream.try_recv() {
+            Ok((center, vertices)) => {
+                self.vertices = vertices;
+                self.upload();
+                self.center = Some(center);
+                self.requested = None;
+            },
+            Err(_) => {},
+        }
+
+        let snap = |v: f64| (v / HORIZON_SNAP as f64).floor() as i64 * HORIZON_SNAP;
+        let (wanted_x, wanted_z) = (snap(eye.x), snap(eye.z));
+
+        /* After a jump, e.g. across the seam of a wrapped world, the old grid
+           would be in the wrong place */
+        match self.center {
+            Some((x, z)) if (x - wanted_x).abs() > HORIZON_SNAP * 4 ||
+                            (z - wanted_z).abs() > HORIZON_SNAP * 4 => {
+                self.center = None;
+                self.vertex_buffer = None;
+                self.vertices = Vec::new();
+            },
+            _ => {},
+        }
+
+        let wanted = (wanted_x, wanted_z);
+        if self.requested.is_none() && self.center != Some(wanted) {
+            self.stream.send(wanted);
+            self.requested = Some(wanted);
+        }
+    }
+
+    /// Recolors the grid for scheme. Placeholders keep the grid's own colors.
+    pub fn set_color_scheme(&mut self, scheme: ColorScheme) {
+        if scheme != self.color_scheme {
+            self.color_scheme = scheme;
+            if self.vertex_buffer.is_some() {
+                self.upload();
+            }
+        }
+    }
+
+    fn upload(&mut self) {
+        let vertices: Vec<HorizonVertex> = self.vertices.iter().map(|v| {
+            HorizonVertex { position: v.position, color: self.color_scheme.apply(v.color) }
+        }).collect();
+        self.vertex_buffer = Some(hgl::Vbo::from_data(vertices.as_slice(), hgl::StaticDraw));
+    }
+
+    /// Returns the world position of the grid center, the vertex buffer and
+    /// how many elements to draw, once a grid has been built.
+    pub fn buffers<'a>(&'a self) -> Option<(Vector3<f64>, &'a hgl::Vbo, &'a hgl::Ebo, uint)> {
+        match (self.center, &self.vertex_buffer) {
+            (Some((x, z)), &Some(ref vbo)) if self.enabled =>
+                Some((Vector3::new(x as f64, 0.0, z as f64), vbo, &self.element_buffer, self.element_count)),
+            _ => None,
+        }
+    }
+
+    /// Returns the grid vertex nearest column (x, z), if the grid reaches it.
+    fn nearest_vertex<'a>(&'a self, x: f64, z: f64) -> Option<&'a HorizonVertex> {
+        let (cx, cz) = match self.center {
+            Some(center) if self.enabled && !self.vertices.is_empty() => center,
+            _ => return None,
+        };
+        let n = HORIZON_CELLS + 1;
+        let half = HORIZON_CELLS / 2 * HORIZON_SPACING;
+        let cell = |v: f64, c: i64| ((v - (c - half) as f64) / HORIZON_SPACING as f64).round() as i64;
+        let (i, j) = (cell(x, cx), cell(z, cz));
+        if i < 0 || i >= n || j < 0 || j >= n {
+            return None;
+        }
+        Some(&self.vertices[(i * n + j) as uint])
+    }
+
+    /// Returns a placeholder for each chunk column within radius chunks of
+    /// center for which is_missing says the chunk at the surface isn't
+    /// loaded. Nothing is returned until a grid has been built.
+    pub fn placeholders(&self, center: Vector3<i64>, radius: i64, is_missing: |Vector3<i64>| -> bool) -> Vec<Placeholder> {
+        let size = CHUNK_SIZE as i64;
+        let half = (size / 2) as f64;
+        let mut placeholders = Vec::new();
+        for dx in range(-radius, radius + 1) {
+            for dz in range(-radius, radius + 1) {
+                if dx * dx + dz * dz >= radius * radius {
+                    continue;
+                }
+                let (x, z) = ((center.x + dx) * size, (center.z + dz) * size);
+                let vertex = match self.nearest_vertex(x as f64 + half, z as f64 + half) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let height = vertex.position.y as f64 + HORIZON_SINK as f64;
+                let c = Vector3::new(center.x + dx, (height / size as f64).floor() as i64, center.z + dz);
+                if !is_missing(c) {
+                    continue;
+                }
+                placeholders.push(Placeholder {
+                    corner: Vector3::new(x as f64, vertex.position.y as f64, z as f64),
+                    color: vertex.color,
+                });
+            }
+        }
+        placeholders
+    }
+}
+
+/// Returns the vertices of the grid centered on column center, row by row
+/// along z.
+fn grid_vertices(generator: &TerrainGenerator, center: (i64, i64)) -> Vec<HorizonVertex> {
+    let (cx, cz) = center;
+    let n = HORIZON_CELLS + 1;
+    let half = HORIZON_CELLS / 2 * HORIZON_SPACING;
+
+    /* One extra sample on each side for the slopes at the edges */
+    let xs: Vec<i64> = range(-1, n + 1).map(|i| cx - half + i * HORIZON_SPACING).collect();
+    let zs: Vec<i64> = range(-1, n + 1).map(|i| cz - half + i * HORIZON_SPACING).collect();
+    let heights = generator.surface_heights(xs.as_slice(), zs.as_slice());
+    let height = |i: i64, j: i64| heights[((i + 1) * (n + 2) + j + 1) as uint] as f32;
+    let sandy = generator.sandy(xs.as_slice(), zs.as_slice(), heights.as_slice());
+
+    let grass = BlockGrass.color();
+    let sand = BlockSand.color();
+    let water = BlockWater.color();
+
+    let mut vertices = Vec::with_capacity((n * n) as uint);
+    for i in range(0, n) {
+        for j in range(0, n) {
+            let h = height(i, j);
+            let (y, color) = if (h as f64) < WATER_HEIGHT {
+                (WATER_HEIGHT as f32, water)
+            } else {
+                let rise = (height(i - 1, j) - height(i + 1, j)) + (height(i, j - 1) - height(i, j + 1));
+                let shade = (1.0 + SLOPE_SHADE * rise / (2 * HORIZON_SPACING) as f32).max(0.6).min(1.2);
+                let ground = if sandy[((i + 1) * (n + 2) + j + 1) as uint] { sand } else { grass };
+                (h, ground.mul_s(shade))
+            };
+            vertices.push(HorizonVertex {
+                position: Vector3::new((xs[(i + 1) as uint] - cx) as f32,
+                                       y - HORIZON_SINK,
+                                       (zs[(j + 1) as uint] - cz) as f32),
+                color: Vector3::new(color.x, color.y, color.z),
+            });
+        }
+    }
+
+    vertices
+}
+
+/// Returns two triangles per grid cell, facing up.
+fn grid_elements() -> Vec<GLuint> {
+    let n = HORIZON_CELLS + 1;
+    let mut elements = Vec::with_capacity((HORIZON_CELLS * HORIZON_CELLS * 6) as uint);
+    for i in range(0, HORIZON_CELLS) {
+        for j in range(0, HORIZON_CELLS) {
+            let a = (i * n + j) as GLuint;
+            let b = a + 1;
+            let c = a + n as GLuint;
+            let d = c + 1;
+            elements.push_all([a, b, c, c, b, d]);
+        }
+    }
+    elements
+}
+
+#[test]
+fn test_horizon_grid() {
+    let n = (HORIZON_CELLS + 1) as uint;
+    let elements = grid_elements();
+    assert_eq!(elements.len(), (HORIZON_CELLS * HORIZON_CELLS * 6) as uint);
+    assert_eq!(elements.iter().max(), Some(&((n * n - 1) as GLuint)));
+
+    let generator = TerrainGenerator::new(42, None, WorldHeight::default(), &TerrainNoise::new());
+    let vertices = grid_vertices(&generator, (640, -128));
+    assert_eq!(vertices.len(), n * n);
+    let half = (HORIZON_CELLS / 2 * HORIZON_SPACING) as f32;
+    assert_eq!(vertices[0].position.x, -half);
+    assert_eq!(vertices[0].position.z, -half);
+    assert_eq!(vertices[n * n - 1].position.x, half);
+    assert_eq!(vertices[n * n - 1].position.z, half);
+    /* Nothing is below the water */
+    assert!(vertices.iter().all(|v| v.position.y >= WATER_HEIGHT as f32 - HORIZON_SINK));
+}
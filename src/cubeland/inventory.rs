@@ -0,0 +1,154 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The player's blocks and the hotbar that shows them.
+//!
+//! Picking up a broken block adds it to its stack and placing one takes from
+//! the selected stack. In creative mode placing is free and the counts are
+//! left alone.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector4;
+
+use lang;
+use terrain::{BlockType, BlockGrass, BlockDirt, BlockStone, BlockWater};
+use ui;
+use ui::Ui;
+
+/// Block types in hotbar order, selected with the number keys.
+pub static HOTBAR : [BlockType, ..4] = [BlockGrass, BlockDirt, BlockStone, BlockWater];
+
+static MAX_STACK : uint = 999;
+
+static SLOT_WIDTH : f32 = 120.0;
+static MARGIN : f32 = 8.0;
+
+pub struct Inventory {
+    counts: [uint, ..4],
+    selected: uint,
+    pub creative: bool,
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        Inventory { counts: [0, ..4], selected: 0, creative: false }
+    }
+
+    pub fn count(&self, blocktype: BlockType) -> uint {
+        match slot(blocktype) {
+            Some(i) => self.counts[i],
+            None => 0,
+        }
+    }
+
+    pub fn set_count(&mut self, blocktype: BlockType, count: uint) {
+        match slot(blocktype) {
+            Some(i) => self.counts[i] = count.min(MAX_STACK),
+            None => {},
+        }
+    }
+
+    /// Adds a broken block. Returns false if there's no room for it.
+    pub fn add(&mut self, blocktype: BlockType) -> bool {
+        match slot(blocktype) {
+            Some(i) if self.counts[i] < MAX_STACK => {
+                self.counts[i] += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn select(&mut self, index: uint) {
+        if index < HOTBAR.len() {
+            self.selected = index;
+        }
+    }
+
+    pub fn selected(&self) -> BlockType {
+        HOTBAR[self.selected]
+    }
+
+    /// Index of the selected slot in HOTBAR.
+    pub fn selected_slot(&self) -> uint {
+        self.selected
+    }
+
+    /// Takes one of the selected block to place, or returns None if there
+    /// are none left.
+    pub fn take_selected(&mut self) -> Option<BlockType> {
+        if self.creative {
+            return Some(self.selected());
+        }
+
+        let count = &mut self.counts[self.selected];
+        if *count == 0 {
+            None
+        } else {
+            *count -= 1;
+            Some(HOTBAR[self.selected])
+        }
+    }
+
+    /// Queues the hotbar at the bottom middle of the window.
+    pub fn draw(&self, ui: &mut Ui) {
+        let window_size = ui.window_size();
+        let y = window_size.y as f32 - MARGIN - ui::line_height() * 2.0;
+        let mut x = (window_size.x as f32 - SLOT_WIDTH * HOTBAR.len() as f32) / 2.0;
+
+        for (i, &blocktype) in HOTBAR.iter().enumerate() {
+            let background = if i == self.selected {
+                Vector4::new(1.0, 1.0, 1.0, 0.5)
+            } else {
+                Vector4::new(0.0, 0.0, 0.0, 0.5)
+            };
+            ui.rect(x + 2.0, y, SLOT_WIDTH - 4.0, ui::line_height() * 2.0, background);
+            ui.rect(x + 6.0, y + 4.0, ui::line_height() * 2.0 - 8.0, ui::line_height() * 2.0 - 8.0,
+                    blocktype.color());
+
+            let count = if self.creative {
+                "-".to_string()
+            } else {
+                self.counts[i].to_string()
+            };
+            let text_x = x + ui::line_height() * 2.0;
+            ui.text(text_x, y, format!("{} {}", i + 1, lang::name("block", blocktype.name())).as_slice(),
+                    Vector4::new(1.0, 1.0, 1.0, 1.0));
+            ui.text(text_x, y + ui::line_height(), count.as_slice(),
+                    Vector4::new(1.0, 1.0, 0.6, 1.0));
+
+            x += SLOT_WIDTH;
+        }
+    }
+}
+
+fn slot(blocktype: BlockType) -> Option<uint> {
+    HOTBAR.iter().position(|&b| b == blocktype)
+}
+
+#[test]
+fn test_inventory() {
+    let mut inventory = Inventory::new();
+    assert!(inventory.take_selected().is_none());
+
+    assert!(inventory.add(BlockGrass));
+    assert_eq!(inventory.take_selected(), Some(BlockGrass));
+    assert!(inventory.take_selected().is_none());
+
+    inventory.creative = true;
+    inventory.select(2);
+    assert_eq!(inventory.take_selected(), Some(BlockStone));
+    assert_eq!(inventory.count(BlockStone), 0);
+}
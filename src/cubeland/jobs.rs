@@ -0,0 +1,229 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Background jobs for any subsystem, and a budget for finishing them on
+//! the main thread.
+//!
+//! A JobPool runs jobs on a few worker tasks, highest priority first and in
+//! the order they were submitted within a priority. Each subsystem submits
+//! through its own JobQueue, which collects its jobs' results. The main
+//! loop takes the results a few at a time, as a FrameBudget allows, for
+//! the parts that have to happen there, like uploading to GL or touching
+//! game state.
+//!
+//! Chunk generation has its own workers (see chunk.rs), since requests for
+//! a chunk always go to the same one.
+
+use std::collections::PriorityQueue;
+use std::task;
+
+use sync::{Arc, Mutex};
+use time::precise_time_ns;
+
+#[deriving(PartialEq, Eq, PartialOrd, Ord, Show)]
+pub enum Priority {
+    PriorityLow,
+    PriorityNormal,
+    PriorityHigh,
+}
+
+struct QueuedJob {
+    priority: Priority,
+    /// Order of submission, to run jobs of the same priority first come,
+    /// first served.
+    seq: u64,
+    job: proc(): Send,
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &QueuedJob) -> bool {
+        self.seq == other.seq
+    }
+}
+
+impl Eq for QueuedJob {}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &QueuedJob) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The greatest is run first.
+impl Ord for QueuedJob {
+    fn cmp(&self, other: &QueuedJob) -> Ordering {
+        match self.priority.cmp(&other.priority) {
+            Equal => other.seq.cmp(&self.seq),
+            ordering => ordering,
+        }
+    }
+}
+
+struct Queue {
+    jobs: PriorityQueue<QueuedJob>,
+    next_seq: u64,
+    /// Set when the pool is dropped. Workers finish the queued jobs first.
+    closed: bool,
+}
+
+pub struct JobPool {
+    queue: Arc<Mutex<Queue>>,
+    workers: uint,
+    /// Each worker sends once as it exits.
+    exited: Receiver<()>,
+}
+
+impl JobPool {
+    pub fn new(workers: uint) -> JobPool {
+        let queue = Arc::new(Mutex::new(Queue {
+            jobs: PriorityQueue::new(),
+            next_seq: 0,
+            closed: false,
+        }));
+        let (exit_sender, exited) = channel();
+        for _ in range(0, workers) {
+            let queue = queue.clone();
+            let exit_sender = exit_sender.clone();
+            spawn(proc() {
+                loop {
+                    let job = {
+                        let mut guard = queue.lock();
+                        while guard.jobs.is_empty() && !guard.closed {
+                            guard.cond.wait();
+                        }
+                        match guard.jobs.pop() {
+                            Some(queued) => queued.job,
+                            None => break,
+                        }
+                    };
+                    /* In its own task, so a job that fails doesn't take the worker with it */
+                    match task::try(job) {
+                        Ok(()) => {},
+                        Err(_) => println!("Background job failed"),
+                    }
+                }
+                let _ = exit_sender.send_opt(());
+            });
+        }
+        JobPool { queue: queue, workers: workers, exited: exited }
+    }
+
+    /// Waits for the queued jobs to run and the workers to exit. Results
+    /// that weren't taken are dropped.
+    pub fn shutdown(self) {
+        self.close();
+        for _ in range(0, self.workers) {
+            let _ = self.exited.recv_opt();
+        }
+    }
+
+    fn close(&self) {
+        let mut guard = self.queue.lock();
+        guard.closed = true;
+        guard.cond.broadcast();
+    }
+
+    /// Returns a new queue whose jobs run in this pool and produce Ts.
+    pub fn queue<T: Send>(&self) -> JobQueue<T> {
+        let (sender, receiver) = channel();
+        JobQueue { queue: self.queue.clone(), sender: sender, receiver: receiver }
+    }
+}
+
+/// Closes the pool without waiting for it.
+impl Drop for JobPool {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// One subsystem's jobs and their results.
+pub struct JobQueue<T> {
+    queue: Arc<Mutex<Queue>>,
+    sender: Sender<T>,
+    receiver: Receiver<T>,
+}
+
+impl<T: Send> JobQueue<T> {
+    /// Queues job to run on a worker. Its result is returned by finish().
+    /// A job that fails has no result.
+    pub fn submit(&self, priority: Priority, job: proc(): Send -> T) {
+        let sender = self.sender.clone();
+        let mut guard = self.queue.lock();
+        let seq = guard.next_seq;
+        guard.next_seq += 1;
+        guard.jobs.push(QueuedJob {
+            priority: priority,
+            seq: seq,
+            /* The queue may be gone by the time it's done */
+            job: proc() { let _ = sender.send_opt(job()); },
+        });
+        guard.cond.signal();
+    }
+
+    /// Returns the result of a finished job, if there is one and budget
+    /// has time left for finishing it.
+    pub fn finish(&self, budget: &FrameBudget) -> Option<T> {
+        if !budget.has_time() {
+            return None;
+        }
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Main thread time left this frame for finishing background work.
+pub struct FrameBudget {
+    deadline: u64,
+}
+
+impl FrameBudget {
+    /// Allows ns nanoseconds from now.
+    pub fn new(ns: u64) -> FrameBudget {
+        FrameBudget { deadline: precise_time_ns() + ns }
+    }
+
+    pub fn has_time(&self) -> bool {
+        precise_time_ns() < self.deadline
+    }
+}
+
+#[test]
+fn test_job_pool() {
+    /* One worker, held up by the first job until the rest are queued */
+    let pool = JobPool::new(1);
+    let queue = pool.queue();
+    let (start, wait) = channel();
+    queue.submit(PriorityNormal, proc() { wait.recv(); 0u });
+    queue.submit(PriorityLow, proc() 1u);
+    queue.submit(PriorityHigh, proc() 2u);
+    queue.submit(PriorityNormal, proc() fail!("test failure"));
+    queue.submit(PriorityNormal, proc() 3u);
+    start.send(());
+
+    let budget = FrameBudget::new(60 * 1000 * 1000 * 1000);
+    let mut results = Vec::new();
+    while results.len() < 4 {
+        match queue.finish(&budget) {
+            Some(v) => results.push(v),
+            None => task::deschedule(),
+        }
+    }
+    assert_eq!(results, vec![0, 2, 3, 1]);
+    assert!(queue.finish(&FrameBudget::new(0)).is_none());
+
+    /* Queued jobs run before the workers exit */
+    queue.submit(PriorityLow, proc() 4u);
+    pool.shutdown();
+    assert_eq!(queue.finish(&budget), Some(4));
+}
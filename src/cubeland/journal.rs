@@ -0,0 +1,229 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-chunk journals of block edits.
+//!
+//! A chunk's terrain is its generated base, its own decorations included,
+//! with its journal replayed over it. Every block written into the chunk
+//! after that is appended: edits, blocks that neighboring chunks'
+//! decorations spill into it, and copies of neighbors' edits into its
+//! border. Nothing is ever changed in place, so an earlier state can be
+//! rebuilt by replaying part of the journal over a fresh base.
+//!
+//! Rewinding keeps border entries, since they mirror neighbors that haven't
+//! been rewound. Recording after a rewind drops the rewound edits.
+//!
+//! Chunks are usually saved as their journal, a few bytes per changed block
+//! instead of the whole terrain. The edits since some point are also all a
+//! peer that generates the base itself needs to catch up.
+
+use std::collections::HashMap;
+use std::io::IoResult;
+
+use CHUNK_SIZE;
+use blocks::Palette;
+use protocol;
+use terrain::{BlockType, Terrain};
+
+/// Bytes per entry in a saved journal.
+pub static ENTRY_BYTES : uint = 5;
+
+/// Blocks along each axis of a chunk's terrain, border included.
+static SIDE : int = CHUNK_SIZE + 2;
+
+pub struct Journal {
+    /// Index into the bordered terrain (see entry_index) and the blocktype
+    /// written there, oldest first.
+    entries: Vec<(u32, BlockType)>,
+    /// Entries before this are in the terrain. Border entries after it are
+    /// too.
+    applied: uint,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal { entries: Vec::new(), applied: 0 }
+    }
+
+    /// Entries in the terrain, not counting rewound ones.
+    pub fn len(&self) -> uint {
+        self.applied
+    }
+
+    /// Entries including rewound ones, the furthest rewind() can go forward.
+    pub fn total(&self) -> uint {
+        self.entries.len()
+    }
+
+    /// Appends blocktype written at (x, y, z) in terrain coordinates, where
+    /// -1 and CHUNK_SIZE are the border.
+    pub fn record(&mut self, x: int, y: int, z: int, blocktype: BlockType) {
+        if self.applied < self.entries.len() {
+            let kept: Vec<(u32, BlockType)> = self.entries.slice_from(self.applied).iter().
+                filter(|&&(index, _)| is_border(index)).map(|&e| e).collect();
+            self.entries.truncate(self.applied);
+            self.entries.push_all(kept.as_slice());
+        }
+        self.entries.push((entry_index(x, y, z), blocktype));
+        self.applied = self.entries.len();
+    }
+
+    /// Writes the entries in the terrain over terrain, which should be the
+    /// chunk's base.
+    pub fn replay(&self, terrain: &mut Terrain) {
+        for (i, &(index, blocktype)) in self.entries.iter().enumerate() {
+            if i < self.applied || is_border(index) {
+                let (x, y, z) = entry_position(index);
+                terrain.set(x, y, z, blocktype);
+            }
+        }
+    }
+
+    /// Moves to just after the first len entries. The terrain must be
+    /// rebuilt with replay(). Returns false if there are fewer entries.
+    pub fn rewind(&mut self, len: uint) -> bool {
+        if len > self.entries.len() {
+            return false;
+        }
+        self.applied = len;
+        true
+    }
+
+    /// Returns the edits to the chunk itself, not its border, from entry
+    /// start to the end of the terrain's, by protocol::block_index().
+    pub fn edits_since(&self, start: uint) -> Vec<(u32, BlockType)> {
+        let end = self.applied;
+        if start >= end {
+            return Vec::new();
+        }
+        self.entries.slice(start, end).iter().filter(|&&(index, _)| !is_border(index)).map(|&(index, blocktype)| {
+            let (x, y, z) = entry_position(index);
+            (protocol::block_index(x, y, z), blocktype)
+        }).collect()
+    }
+
+    /// Returns the entries in the terrain with only the last write to each
+    /// block, which replay to the same terrain.
+    fn compacted(&self) -> Vec<(u32, BlockType)> {
+        let mut last = HashMap::new();
+        for (i, &(index, blocktype)) in self.entries.iter().enumerate() {
+            if i < self.applied || is_border(index) {
+                last.insert(index, (i, blocktype));
+            }
+        }
+        let mut entries: Vec<(uint, u32, BlockType)> = last.iter().map(|(&index, &(i, blocktype))| (i, index, blocktype)).collect();
+        entries.sort_by(|&(a, _, _), &(b, _, _)| a.cmp(&b));
+        entries.move_iter().map(|(_, index, blocktype)| (index, blocktype)).collect()
+    }
+
+    /// Bytes write() would take.
+    pub fn saved_size(&self) -> uint {
+        4 + self.compacted().len() * ENTRY_BYTES
+    }
+
+    /// Writes the entries in the terrain, compacted. Rewound edits aren't
+    /// saved.
+    pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+        let entries = self.compacted();
+        try!(w.write_le_u32(entries.len() as u32));
+        for &(index, blocktype) in entries.iter() {
+            try!(w.write_le_u32(index));
+            try!(w.write_u8(blocktype.to_u8()));
+        }
+        Ok(())
+    }
+
+    /// Reads a journal written by write(), with palette giving the block
+    /// types it was written with. Returns None if the data is invalid.
+    pub fn read(r: &mut Reader, palette: &Palette) -> IoResult<Option<Journal>> {
+        let count = try!(r.read_le_u32()) as uint;
+        let mut entries = Vec::new();
+        for _ in range(0, count) {
+            let index = try!(r.read_le_u32());
+            match palette.get(try!(r.read_u8())) {
+                Some(blocktype) if (index as int) < SIDE * SIDE * SIDE => entries.push((index, blocktype)),
+                _ => return Ok(None),
+            }
+        }
+        let applied = entries.len();
+        Ok(Some(Journal { entries: entries, applied: applied }))
+    }
+}
+
+/// Same order as Terrain::to_bytes.
+fn entry_index(x: int, y: int, z: int) -> u32 {
+    (((x + 1) * SIDE + (y + 1)) * SIDE + (z + 1)) as u32
+}
+
+fn entry_position(index: u32) -> (int, int, int) {
+    let i = index as int;
+    (i / (SIDE * SIDE) - 1, (i / SIDE) % SIDE - 1, i % SIDE - 1)
+}
+
+fn is_border(index: u32) -> bool {
+    let (x, y, z) = entry_position(index);
+    let outside = |v: int| v < 0 || v >= CHUNK_SIZE;
+    outside(x) || outside(y) || outside(z)
+}
+
+#[test]
+fn test_replay_and_rewind() {
+    use terrain::{BlockAir, BlockDirt, BlockStone};
+
+    let mut journal = Journal::new();
+    journal.record(0, 0, 0, BlockStone);
+    journal.record(-1, 2, 3, BlockDirt);
+    journal.record(0, 0, 0, BlockDirt);
+    journal.record(1, 1, 1, BlockStone);
+    assert_eq!(journal.len(), 4);
+
+    /* Rewinding drops the later edits but keeps the border */
+    assert!(journal.rewind(1));
+    assert!(!journal.rewind(5));
+    let mut terrain = Terrain::new();
+    journal.replay(&mut *terrain);
+    assert!(terrain.get(0, 0, 0).blocktype == BlockStone);
+    assert!(terrain.get(-1, 2, 3).blocktype == BlockDirt);
+    assert!(terrain.get(1, 1, 1).blocktype == BlockAir);
+
+    /* Recording forgets what was rewound, except the border */
+    journal.record(2, 2, 2, BlockStone);
+    assert_eq!(journal.total(), 3);
+    assert!(!journal.rewind(4));
+    assert_eq!(journal.edits_since(0), vec!((protocol::block_index(0, 0, 0), BlockStone),
+                                           (protocol::block_index(2, 2, 2), BlockStone)));
+}
+
+#[test]
+fn test_write_read() {
+    use std::io::{MemWriter, BufReader};
+    use terrain::{BlockDirt, BlockStone};
+
+    let mut journal = Journal::new();
+    journal.record(0, 0, 0, BlockStone);
+    journal.record(CHUNK_SIZE, 0, 0, BlockDirt);
+    journal.record(0, 0, 0, BlockDirt);
+
+    let mut w = MemWriter::new();
+    journal.write(&mut w).unwrap();
+    let bytes = w.unwrap();
+    assert_eq!(bytes.len(), journal.saved_size());
+    assert_eq!(bytes.len(), 4 + 2 * ENTRY_BYTES);
+
+    let read = Journal::read(&mut BufReader::new(bytes.as_slice()), &Palette::builtin()).unwrap().unwrap();
+    let (mut a, mut b) = (Terrain::new(), Terrain::new());
+    journal.replay(&mut *a);
+    read.replay(&mut *b);
+    assert_eq!(a.to_bytes(), b.to_bytes());
+}
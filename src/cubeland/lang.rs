@@ -0,0 +1,268 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Text shown to the player, looked up by key so it can be translated.
+//!
+//! English is built in. Another language comes from locale files named
+//! after it, like de.toml, in the lang directory next to the shaders and
+//! then in the one in the config directory, where the player can override
+//! single strings. Each table of a file is the first part of its keys:
+//!
+//!     [menu]
+//!     resume = "Fortsetzen"
+//!     setting = "{0}: {1}"
+//!
+//! sets menu.resume and menu.setting. {0}, {1} and so on are filled in by
+//! format(), in whatever order the translation needs. Keys a file leaves
+//! out stay English, so a partial translation still works.
+//!
+//! Like the block registry, the text is set once by init(), or is English
+//! from the first time it's used, and never changes after that.
+
+extern crate toml;
+
+use std::collections::HashMap;
+use std::io::File;
+use std::mem;
+use std::sync::one::{Once, ONCE_INIT};
+
+/// Every key, with its English text.
+static ENGLISH : [(&'static str, &'static str), ..52] = [
+    ("menu.paused", "Paused"),
+    ("menu.settings", "Settings"),
+    ("menu.resume", "Resume"),
+    ("menu.save_and_quit", "Save and quit"),
+    ("menu.back", "Back"),
+    ("menu.setting", "{0}: {1}"),
+    ("menu.on", "on"),
+    ("menu.off", "off"),
+    ("setting.compass", "Compass"),
+    ("setting.block_info", "Block info"),
+    ("setting.horizon", "Horizon"),
+    ("setting.wireframe", "Wireframe"),
+    ("setting.anisotropy", "Anisotropic filtering"),
+    ("setting.colors", "Colors"),
+    ("setting.invert_y", "Invert mouse"),
+    ("colors.normal", "normal"),
+    ("colors.deuteranopia", "deuteranopia"),
+    ("colors.protanopia", "protanopia"),
+    ("block.grass", "grass"),
+    ("block.stone", "stone"),
+    ("block.dirt", "dirt"),
+    ("block.water", "water"),
+    ("block.snow", "snow"),
+    ("block.sand", "sand"),
+    ("block.lava", "lava"),
+    ("speed.walk", "walk"),
+    ("speed.sprint", "sprint"),
+    ("speed.fast-fly", "fast-fly"),
+    ("speed.ultra", "ultra"),
+    ("hud.loading", "Loading {0}..."),
+    ("hud.block", "Block {0} {1} {2}"),
+    ("hud.chunk", "Chunk {0} {1} {2} ({3}), at {4} {5} {6}"),
+    ("hud.coarse", "coarse"),
+    ("hud.full", "full"),
+    ("hud.edited", "{0}, edited"),
+    ("hud.not_loaded", "not loaded"),
+    ("hud.hit", "{0}, {1} away, face {2} {3} {4} is {5}"),
+    ("hud.sky_lit", "sky lit"),
+    ("hud.shaded", "shaded"),
+    ("hud.no_block", "No block in sight"),
+    ("hud.draw_calls", "{0} draw calls, {1} triangles"),
+    ("hud.chunks", "{0} chunks drawn, {1} culled"),
+    ("hud.uploads", "{0} buffer uploads, {1} state changes"),
+    ("chat.mode", "Mode: {0}"),
+    ("chat.creative", "creative"),
+    ("chat.survival", "survival"),
+    ("chat.photo_mode", "Photo mode: Enter takes a photo, P returns"),
+    ("chat.freecam", "Freecam: V returns to the player"),
+    ("chat.keyframe", "Keyframe {0}"),
+    ("chat.burned", "Burned in lava"),
+    ("chat.saved", "Saved {0}"),
+    ("chat.speed", "Speed: {0} ({1} blocks/s)"),
+];
+
+/// The text of every key in one language.
+pub struct Language {
+    texts: HashMap<String, String>,
+}
+
+impl Language {
+    pub fn english() -> Language {
+        let mut texts = HashMap::new();
+        for &(key, text) in ENGLISH.iter() {
+            texts.insert(key.to_string(), text.to_string());
+        }
+        Language { texts: texts }
+    }
+
+    /// Replaces the text of the keys set by src, the contents of a locale
+    /// file. Nothing is replaced if any of it is wrong.
+    pub fn merge(&mut self, src: &str) -> Result<(), String> {
+        let mut parser = toml::Parser::new(src);
+        let table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let e = &parser.errors[0];
+                let (line, col) = parser.to_linecol(e.lo);
+                return Err(format!("line {}, column {}: {}", line + 1, col + 1, e.desc));
+            },
+        };
+
+        let mut texts = Vec::new();
+        for (section, value) in table.iter() {
+            let section_table = match value.as_table() {
+                Some(t) => t,
+                None => return Err(format!("{} isn't a table", section)),
+            };
+            for (name, value) in section_table.iter() {
+                let key = format!("{}.{}", section, name);
+                if !self.texts.contains_key(&key) {
+                    return Err(format!("unknown key {}", key));
+                }
+                match value.as_str() {
+                    Some(text) => texts.push((key, text.to_string())),
+                    None => return Err(format!("{} isn't a string", key)),
+                }
+            }
+        }
+
+        for (key, text) in texts.move_iter() {
+            self.texts.insert(key, text);
+        }
+        Ok(())
+    }
+
+    pub fn find<'a>(&'a self, key: &str) -> Option<&'a str> {
+        self.texts.find_equiv(&key).map(|text| text.as_slice())
+    }
+}
+
+static mut LANGUAGE : *const Language = 0 as *const Language;
+static LANGUAGE_INIT : Once = ONCE_INIT;
+
+/// Switches to the language with the given code, like "de", from its
+/// locale files in dirs, in order. Call it before any text is looked up;
+/// after that it only complains. Returns false if code isn't English and
+/// none of dirs has a file for it.
+pub fn init(code: &str, dirs: &[Path]) -> bool {
+    let mut found = code == "en";
+    let mut built = false;
+    LANGUAGE_INIT.doit(|| {
+        let mut language = box Language::english();
+        for dir in dirs.iter() {
+            let path = dir.join(format!("{}.toml", code));
+            if !path.exists() {
+                continue;
+            }
+            found = true;
+            let result = File::open(&path).read_to_string().map_err(|e| e.to_string()).
+                and_then(|src| language.merge(src.as_slice()));
+            match result {
+                Ok(()) => {},
+                Err(msg) => println!("Error loading text from {}: {}", path.display(), msg),
+            }
+        }
+        unsafe { LANGUAGE = mem::transmute(language); }
+        built = true;
+    });
+    if !built {
+        println!("Text was shown before the language was set; staying in the first one");
+    }
+    found
+}
+
+fn language() -> &'static Language {
+    LANGUAGE_INIT.doit(|| {
+        unsafe { LANGUAGE = mem::transmute(box Language::english()); }
+    });
+    unsafe { &*LANGUAGE }
+}
+
+/// Returns the text for key, or the key itself if there isn't any.
+pub fn text(key: &'static str) -> &'static str {
+    language().find(key).unwrap_or(key)
+}
+
+/// Returns the text for key, for keys that are put together at run time.
+pub fn find(key: &str) -> Option<&'static str> {
+    language().find(key)
+}
+
+/// Returns the text for the name of something defined elsewhere, like a
+/// block type, under the kind.name key. Names without one, like those of
+/// block types from a mod's files, are shown as they are.
+pub fn name(kind: &str, name: &'static str) -> &'static str {
+    find(format!("{}.{}", kind, name).as_slice()).unwrap_or(name)
+}
+
+/// Returns the text for key with args in its placeholders.
+pub fn format(key: &'static str, args: &[String]) -> String {
+    fill(text(key), args)
+}
+
+/// Replaces each {i} in template with args[i]. Anything else in braces,
+/// like a placeholder with no arg, is left as it is.
+fn fill(template: &str, args: &[String]) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    loop {
+        match rest.find('{') {
+            Some(open) => {
+                out.push_str(rest.slice_to(open));
+                let after = rest.slice_from(open + 1);
+                let arg = after.find('}').and_then(|close| {
+                    from_str::<uint>(after.slice_to(close)).and_then(|i| {
+                        if i < args.len() { Some((close, i)) } else { None }
+                    })
+                });
+                match arg {
+                    Some((close, i)) => {
+                        out.push_str(args[i].as_slice());
+                        rest = after.slice_from(close + 1);
+                    },
+                    None => {
+                        out.push_char('{');
+                        rest = after;
+                    },
+                }
+            },
+            None => {
+                out.push_str(rest);
+                return out;
+            },
+        }
+    }
+}
+
+#[test]
+fn test_language() {
+    let mut language = Language::english();
+    assert_eq!(language.find("menu.resume"), Some("Resume"));
+    assert_eq!(language.find("menu.nonsense"), None);
+
+    assert!(language.merge("[menu]\nresume = \"Fortsetzen\"\nsetting = \"{1} <- {0}\"\n").is_ok());
+    assert_eq!(language.find("menu.resume"), Some("Fortsetzen"));
+    assert_eq!(language.find("menu.back"), Some("Back"));
+
+    /* A file with a mistake changes nothing */
+    assert!(language.merge("[menu]\nback = \"Zurueck\"\nbakc = \"Zurueck\"\n").is_err());
+    assert!(language.merge("[menu]\nback = 3\n").is_err());
+    assert!(language.merge("resume = \"Fortsetzen\"\n").is_err());
+    assert_eq!(language.find("menu.back"), Some("Back"));
+
+    let args = ["Compass".to_string(), "on".to_string()];
+    assert_eq!(fill(language.find("menu.setting").unwrap(), args.as_slice()), "on <- Compass".to_string());
+    assert_eq!(fill("{0} {2} {x} {", args.as_slice()), "Compass {2} {x} {".to_string());
+}
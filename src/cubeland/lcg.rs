@@ -0,0 +1,42 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A tiny linear congruential generator for effects that only need to look
+//! random, like mob wandering, weather particles and crack textures. Its
+//! low bits are poor, so callers use the high ones. Generated terrain and
+//! features use featurerng instead.
+
+/// Advances the state and returns it.
+pub fn next(rng: &mut u32) -> u32 {
+    *rng = *rng * 1103515245 + 12345;
+    *rng
+}
+
+/// Returns a pseudorandom number from 0 to 1.
+pub fn random(rng: &mut u32) -> f64 {
+    (next(rng) >> 8) as f64 / (1u32 << 24) as f64
+}
+
+#[test]
+fn test_lcg() {
+    let mut a = 7u32;
+    let mut b = 7u32;
+    assert_eq!(next(&mut a), 7 * 1103515245 + 12345);
+    next(&mut b);
+    assert_eq!(a, b);
+    for _ in range(0, 1000) {
+        let r = random(&mut a);
+        assert!(r >= 0.0 && r < 1.0);
+    }
+}
@@ -23,53 +23,552 @@ extern crate glfw;
 extern crate gl;
 extern crate cgmath;
 extern crate noise;
+extern crate flate;
 
-use time::precise_time_ns;
+use std::collections::HashSet;
 
-use gl::types::*;
+use time::precise_time_ns;
 
 use glfw::Context;
 
 use cgmath::matrix::Matrix;
-use cgmath::vector::Vector;
+use cgmath::vector::{EuclideanVector, Vector};
 use cgmath::vector::Vector2;
 use cgmath::vector::Vector3;
+use cgmath::vector::Vector4;
+
+pub use chunksize::{CHUNK_SIZE, CHUNK_SIZEu};
 
-use chunk::Chunk;
 use chunk::ChunkLoader;
+use coord::{ChunkCoord, FnvHasher};
 
 #[cfg(target_os = "linux")]
-#[link(name="GLU")]
 #[link(name="glfw")]
 extern {}
 
+#[cfg(target_os = "windows")]
+#[link(name="glfw3")]
+#[link(name="opengl32")]
+#[link(name="gdi32")]
+extern {}
+
+#[cfg(target_os = "macos")]
+#[link(name="glfw3")]
+#[link(name="Cocoa", kind="framework")]
+#[link(name="OpenGL", kind="framework")]
+#[link(name="IOKit", kind="framework")]
+#[link(name="CoreVideo", kind="framework")]
+extern {}
+
 mod offset_of;
+mod blocks;
+mod blockdefs;
+mod coord;
 mod chunk;
+mod chunksize;
+mod columns;
 mod ratelimiter;
 mod texture;
 mod renderer;
 mod camera;
+mod camerapath;
 mod terrain;
 mod mesh;
+mod hooks;
+mod region;
+mod wrap;
+mod player;
+mod worlds;
+mod console;
+mod commands;
+mod store;
+mod protocol;
+mod ui;
+mod chat;
+mod permissions;
+mod gradient;
+mod noisesource;
+mod featurerng;
+mod decoration;
+mod climate;
+mod png;
+mod mapexport;
+mod raycast;
+mod mining;
+mod inventory;
+mod entity;
+mod spatial;
+mod gldebug;
+mod clouds;
+mod weather;
+mod hand;
+mod blockupdates;
+mod fbo;
+mod photo;
+mod voxeltexture;
+mod horizon;
+mod worldheight;
+mod diagnostics;
+mod debugview;
+mod platformpaths;
+mod worldborder;
+mod journal;
+mod compass;
+mod colorscheme;
+mod lang;
+mod nearby;
+mod diskcache;
+mod jobs;
+mod lcg;
+mod menu;
+mod preview;
 
 pub static VISIBLE_RADIUS: uint = 8;
-pub static CHUNK_SIZEu: uint = 32;
-pub static CHUNK_SIZE: int = CHUNK_SIZEu as int;
 pub static WORLD_SEED: u32 = 42;
+static DEFAULT_WORLD: &'static str = "default";
+/// Default size limit of each world's cache of generated terrain.
+static DEFAULT_DISK_CACHE_MB: u64 = 256;
+/// Anisotropic filtering level unless --anisotropy says otherwise, and
+/// when it's turned back on from the menu after being started off.
+static DEFAULT_ANISOTROPY: f32 = 8.0;
+
+/// Chunks are requested ahead of a camera moving at least this fast, in
+/// blocks per second, along the path it will cover in PREDICTION_TIME seconds.
+static PREDICTION_MIN_SPEED : f64 = 100.0;
+static PREDICTION_TIME : f64 = 3.0;
+/// Radius in chunks requested around each predicted position.
+static PREDICTION_RADIUS : i64 = 2;
+
+/// Height of the camera above the block a teleported player stands on.
+static EYE_HEIGHT : f64 = 1.6;
+/// Size of the box marking where the player was left in freecam, around
+/// the eye.
+static PLAYER_HALF_WIDTH : f64 = 0.3;
+static PLAYER_HEAD_ROOM : f64 = 0.2;
+static PLAYER_MARKER_COLOR : Vector4<f32> = Vector4 { x: 0.2, y: 0.6, z: 1.0, w: 1.0 };
+
+/// How far away the player can mine blocks.
+static REACH : f64 = 6.0;
+/// How far away the block info readout looks for a block.
+static BLOCK_INFO_REACH : f64 = 128.0;
+
+/// How far in front of the camera the spawn command puts entities.
+static SPAWN_DISTANCE : f64 = 3.0;
+/// New players start on the nearest land to the origin, looking at columns
+/// this far apart in rings out to SPAWN_SEARCH_RINGS of them.
+static SPAWN_SEARCH_SPACING : i64 = 16;
+static SPAWN_SEARCH_RINGS : i64 = 64;
+
+/// Upward speed a broken block's item drop starts with.
+static ITEM_DROP_POP : f64 = 4.0;
+
+/// Sky and fog while the camera is underwater. The dense fog limits
+/// visibility to a few blocks.
+static WATER_FOG_COLOR : Vector3<f32> = Vector3 { x: 0.05, y: 0.15, z: 0.45 };
+static WATER_FOG_DENSITY : f32 = 0.06;
+/// Tint over the whole view underwater.
+static WATER_OVERLAY_COLOR : Vector4<f32> = Vector4 { x: 0.1, y: 0.2, z: 0.7, w: 0.3 };
+/// The same inside lava, which is even harder to see through.
+static LAVA_FOG_COLOR : Vector3<f32> = Vector3 { x: 0.8, y: 0.25, z: 0.0 };
+static LAVA_FOG_DENSITY : f32 = 0.5;
+static LAVA_OVERLAY_COLOR : Vector4<f32> = Vector4 { x: 1.0, y: 0.3, z: 0.0, w: 0.6 };
+/// Seconds a player in survival mode lasts in lava before burning up and
+/// going back to the spawn point.
+static LAVA_BURN_TIME : f64 = 1.0;
 
 static DEFAULT_WINDOW_SIZE : Vector2<u32> = Vector2 { x: 800, y: 600 };
 
+/// Frame rate the main loop is held to while the window is unfocused or
+/// minimized.
+static BACKGROUND_FPS : u64 = 5;
+
+/// Workers for background jobs other than chunk generation.
+static JOB_WORKERS : uint = 2;
+/// Main thread time per frame for finishing background work, like
+/// uploading new chunks' meshes.
+static FRAME_JOB_BUDGET : u64 = 4*1000*1000;
+
+/// Environment variable with the password admin connections must send.
+static ADMIN_PASSWORD_VAR : &'static str = "CUBELAND_ADMIN_PASSWORD";
+
 #[start]
 fn start(argc: int, argv: *const *const u8) -> int {
     native::start(argc, argv, main)
 }
 
+enum Action {
+    ActionPlay,
+    ActionListWorlds,
+    ActionCreateWorld(String),
+    ActionDeleteWorld(String),
+    ActionExportMap,
+    /// Show terrain generated with the settings in a file. See preview.rs.
+    ActionPreview(Path),
+}
+
+struct Options {
+    action: Action,
+    world: String,
+    /// Seed for newly created worlds.
+    seed: u32,
+    /// Vertical extent of newly created worlds.
+    world_height: worldheight::WorldHeight,
+    /// Border of newly created worlds.
+    world_border: Option<worldborder::WorldBorder>,
+    /// Height below which newly created worlds' caves fill with lava, if
+    /// not the default for their bottom. Some(None) turns lava off.
+    lava_height: Option<Option<i64>>,
+    /// World width in chunks when the world wraps around.
+    wrap_size: Option<i64>,
+    /// Seconds between autosaves of edited chunks.
+    autosave_interval: u64,
+    /// Name shown on chat messages.
+    name: String,
+    /// Create a debug GL context and print the driver's debug messages.
+    gl_debug: bool,
+    /// Upload each chunk's terrain as a 3D texture for per-pixel effects.
+    voxel_textures: bool,
+    /// Print system and driver information at startup.
+    diagnostics: bool,
+    /// Start with the least demanding graphics settings, for drivers that
+    /// crash with the usual ones.
+    safe_mode: bool,
+    /// Anisotropic filtering level of the mipmapped textures. 1 is off.
+    anisotropy: f32,
+    /// Recolors blocks and the UI for color blindness.
+    color_scheme: colorscheme::ColorScheme,
+    /// Language code of the text shown in the game, like "de".
+    lang: String,
+    /// Whether lang came from --lang rather than the environment.
+    lang_given: bool,
+    /// How finely new terrain is generated.
+    terrain_quality: terrain::TerrainQuality,
+    /// Noise backends new terrain is generated with.
+    terrain_noise: terrain::TerrainNoise,
+    /// Regions around the origin exported by --map.
+    map_radius: i64,
+    /// Directory --map writes tiles to.
+    map_dir: Path,
+    /// Local port to accept admin console connections on.
+    admin_port: Option<u16>,
+    /// Megabytes of evicted chunks' generated terrain kept on disk, or 0 to
+    /// regenerate them instead.
+    disk_cache_mb: u64,
+    /// Multiplier of how far the camera turns per pixel of mouse movement.
+    mouse_sensitivity: f64,
+    /// Moving the mouse up looks down.
+    invert_y: bool,
+    /// Load chunks in a reproducible order, for tests.
+    deterministic_gen: bool,
+    /// Print the renderer's average counts per frame with the frame rate.
+    render_stats: bool,
+}
+
+fn parse_args() -> Options {
+    let args = std::os::args();
+    let mut options = Options {
+        action: ActionPlay,
+        world: DEFAULT_WORLD.to_string(),
+        seed: WORLD_SEED,
+        world_height: worldheight::WorldHeight::default(),
+        world_border: None,
+        lava_height: None,
+        wrap_size: None,
+        autosave_interval: 10,
+        /* USERNAME on Windows */
+        name: std::os::getenv("USER").or(std::os::getenv("USERNAME")).unwrap_or("player".to_string()),
+        gl_debug: false,
+        voxel_textures: false,
+        diagnostics: false,
+        safe_mode: false,
+        anisotropy: DEFAULT_ANISOTROPY,
+        color_scheme: colorscheme::SchemeNormal,
+        lang: std::os::getenv("LANG").and_then(|lang| lang_code(lang.as_slice())).unwrap_or("en".to_string()),
+        lang_given: false,
+        terrain_quality: terrain::TerrainQuality::new(),
+        terrain_noise: terrain::TerrainNoise::new(),
+        map_radius: 4,
+        map_dir: platformpaths::data_dir().join("map"),
+        admin_port: None,
+        disk_cache_mb: DEFAULT_DISK_CACHE_MB,
+        mouse_sensitivity: 1.0,
+        invert_y: false,
+        deterministic_gen: false,
+        render_stats: false,
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_slice() {
+            "--wrap" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<i64>(args[i].as_slice()) {
+                    Some(size) if size > 0 => options.wrap_size = Some(size),
+                    _ => fail!("--wrap expects a positive number of chunks"),
+                }
+            },
+            "--autosave" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<u64>(args[i].as_slice()) {
+                    Some(secs) if secs > 0 => options.autosave_interval = secs,
+                    _ => fail!("--autosave expects a positive number of seconds"),
+                }
+            },
+            "--terrain-stride" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<uint>(args[i].as_slice()) {
+                    Some(stride) if terrain::valid_stride(stride) => options.terrain_quality.stride = stride,
+                    _ => fail!("--terrain-stride expects a number of blocks that divides {}", CHUNK_SIZE),
+                }
+            },
+            "--terrain-detail-radius" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<i64>(args[i].as_slice()) {
+                    Some(radius) if radius >= 0 => options.terrain_quality.detail_radius = radius,
+                    _ => fail!("--terrain-detail-radius expects a number of chunks"),
+                }
+            },
+            "--terrain-budget" if i + 1 < args.len() => {
+                i += 1;
+                options.terrain_quality.budget = match args[i].as_slice() {
+                    "off" => None,
+                    secs => match from_str::<f64>(secs) {
+                        Some(secs) if secs >= 0.0 => Some(secs),
+                        _ => fail!("--terrain-budget expects a number of seconds or off"),
+                    },
+                };
+            },
+            "--density-noise" if i + 1 < args.len() => {
+                i += 1;
+                match noisesource::NoiseKind::from_name(args[i].as_slice()) {
+                    Some(kind) => options.terrain_noise.density = kind,
+                    None => fail!("--density-noise expects perlin, simplex or value"),
+                }
+            },
+            "--height-noise" if i + 1 < args.len() => {
+                i += 1;
+                match noisesource::NoiseKind::from_name(args[i].as_slice()) {
+                    Some(kind) => options.terrain_noise.height = kind,
+                    None => fail!("--height-noise expects perlin, simplex or value"),
+                }
+            },
+            "--world" if i + 1 < args.len() => {
+                i += 1;
+                options.world = args[i].clone();
+            },
+            "--seed" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<u32>(args[i].as_slice()) {
+                    Some(seed) => options.seed = seed,
+                    None => fail!("--seed expects a number"),
+                }
+            },
+            "--world-height" if i + 2 < args.len() => {
+                i += 2;
+                let height = match (from_str::<i64>(args[i - 1].as_slice()), from_str::<i64>(args[i].as_slice())) {
+                    (Some(bottom), Some(top)) => worldheight::WorldHeight::new(bottom, top),
+                    _ => None,
+                };
+                match height {
+                    Some(height) => options.world_height = height,
+                    None => fail!("--world-height expects a bottom and a higher top in blocks"),
+                }
+            },
+            "--world-border" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<i64>(args[i].as_slice()).and_then(worldborder::WorldBorder::new) {
+                    Some(border) => options.world_border = Some(border),
+                    None => fail!("--world-border expects a positive radius in blocks"),
+                }
+            },
+            "--lava-height" if i + 1 < args.len() => {
+                i += 1;
+                options.lava_height = match args[i].as_slice() {
+                    "off" => Some(None),
+                    y => match from_str::<i64>(y) {
+                        Some(y) => Some(Some(y)),
+                        None => fail!("--lava-height expects a height in blocks or off"),
+                    },
+                };
+            },
+            "--name" if i + 1 < args.len() => {
+                i += 1;
+                options.name = args[i].clone();
+            },
+            "--mouse-sensitivity" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<f64>(args[i].as_slice()) {
+                    Some(s) if s >= camera::MIN_SENSITIVITY && s <= camera::MAX_SENSITIVITY => options.mouse_sensitivity = s,
+                    _ => fail!("--mouse-sensitivity expects a number from {} to {}", camera::MIN_SENSITIVITY, camera::MAX_SENSITIVITY),
+                }
+            },
+            "--invert-y" => options.invert_y = true,
+            "--deterministic-gen" => options.deterministic_gen = true,
+            "--gl-debug" => options.gl_debug = true,
+            "--voxel-textures" => options.voxel_textures = true,
+            "--diagnostics" => options.diagnostics = true,
+            "--safe-mode" => options.safe_mode = true,
+            "--render-stats" => options.render_stats = true,
+            "--anisotropy" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<f32>(args[i].as_slice()) {
+                    Some(level) if level >= 1.0 => options.anisotropy = level,
+                    _ => fail!("--anisotropy expects a filtering level, 1 for off"),
+                }
+            },
+            "--color-scheme" if i + 1 < args.len() => {
+                i += 1;
+                match colorscheme::ColorScheme::from_name(args[i].as_slice()) {
+                    Some(scheme) => options.color_scheme = scheme,
+                    None => fail!("--color-scheme expects normal, deuteranopia or protanopia"),
+                }
+            },
+            "--lang" if i + 1 < args.len() => {
+                i += 1;
+                options.lang = args[i].clone();
+                options.lang_given = true;
+            },
+            "--list-worlds" => options.action = ActionListWorlds,
+            "--map" => options.action = ActionExportMap,
+            "--preview" if i + 1 < args.len() => {
+                i += 1;
+                options.action = ActionPreview(Path::new(args[i].as_slice()));
+            },
+            "--radius" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<i64>(args[i].as_slice()) {
+                    Some(radius) if radius >= 0 => options.map_radius = radius,
+                    _ => fail!("--radius expects a number of regions"),
+                }
+            },
+            "--out" if i + 1 < args.len() => {
+                i += 1;
+                options.map_dir = Path::new(args[i].as_slice());
+            },
+            "--disk-cache" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<u64>(args[i].as_slice()) {
+                    Some(mb) => options.disk_cache_mb = mb,
+                    None => fail!("--disk-cache expects a number of megabytes, or 0 for none"),
+                }
+            },
+            "--admin-port" if i + 1 < args.len() => {
+                i += 1;
+                match from_str::<u16>(args[i].as_slice()) {
+                    Some(port) => options.admin_port = Some(port),
+                    None => fail!("--admin-port expects a port number"),
+                }
+            },
+            "--create-world" if i + 1 < args.len() => {
+                i += 1;
+                options.action = ActionCreateWorld(args[i].clone());
+            },
+            "--delete-world" if i + 1 < args.len() => {
+                i += 1;
+                options.action = ActionDeleteWorld(args[i].clone());
+            },
+            arg => fail!("unknown argument {}", arg),
+        }
+        i += 1;
+    }
+
+    /* Either may come first */
+    if options.wrap_size.is_some() &&
+            !(options.terrain_noise.density.tiles() && options.terrain_noise.height.tiles()) {
+        fail!("--wrap needs perlin or value noise; simplex noise can't repeat around the world");
+    }
+
+    /* The default depends on the bottom, which --world-height may set after it */
+    match options.lava_height {
+        Some(lava) => options.world_height.lava = lava,
+        None => {},
+    }
+
+    options
+}
+
+/// Returns the language code of a locale like "de_DE.UTF-8", if it has one.
+fn lang_code(locale: &str) -> Option<String> {
+    let code = locale.split(|c: char| c == '_' || c == '.' || c == '@').next().unwrap();
+    match code {
+        "" | "C" | "POSIX" => None,
+        code => Some(code.to_string()),
+    }
+}
+
 fn main() {
+   let options = parse_args();
+
+   blocks::init(load_block_defs());
+   if !lang::init(options.lang.as_slice(), [Path::new("lang"), platformpaths::config_dir().join("lang")]) &&
+           options.lang_given {
+       println!("No text for language {}, using English", options.lang);
+   }
+
+   let mut world = match options.action {
+       ActionListWorlds => {
+           print_worlds();
+           return;
+       },
+       ActionCreateWorld(ref name) => {
+           create_world(name.as_slice(), options.seed, options.world_height, options.world_border);
+           return;
+       },
+       ActionDeleteWorld(ref name) => {
+           match worlds::delete(name.as_slice()) {
+               Ok(()) => println!("Deleted world {}", name),
+               Err(msg) => println!("Error deleting world: {}", msg),
+           }
+           return;
+       },
+       ActionExportMap => {
+           let wrap = match options.wrap_size {
+               Some(size) => wrap::Wrap::new(size),
+               None => wrap::Wrap::none(),
+           };
+           let dir = &options.map_dir;
+           match mapexport::export(options.seed, wrap.period(), options.world_height, &options.terrain_noise,
+                                   options.terrain_quality.stride, options.map_radius, dir) {
+               Ok(count) => println!("Wrote {} map tiles to {}", count, dir.display()),
+               Err(msg) => println!("Error exporting map: {}", msg),
+           }
+           return;
+       },
+       ActionPreview(ref path) => {
+           preview::run(path);
+           return;
+       },
+       ActionPlay => {
+           let name = options.world.as_slice();
+           match worlds::open(name) {
+               Ok(world) => world,
+               Err(_) if name == DEFAULT_WORLD => match worlds::create(name, options.seed, options.world_height, options.world_border) {
+                   Ok(world) => world,
+                   Err(msg) => fail!("Error creating world: {}", msg),
+               },
+               Err(msg) => fail!("Error opening world: {}", msg),
+           }
+       },
+   };
+
+   println!("Playing world {} (seed {})", world.name, world.seed);
+
    let c: Option<glfw::ErrorCallback<()>> = None;
    let glfw = glfw::init(c).unwrap();
 
+   if options.diagnostics {
+       diagnostics::print_system(&glfw);
+   }
+
    if true {
-        glfw.window_hint(glfw::Samples(8));
+        if options.safe_mode {
+            println!("Safe mode: no MSAA, driver default swap interval, no voxel textures or horizon");
+        } else {
+            glfw.window_hint(glfw::Samples(8));
+        }
+        if options.gl_debug {
+            glfw.window_hint(glfw::OpenglDebugContext(true));
+        }
 
         let (window, events) = glfw.create_window(
             DEFAULT_WINDOW_SIZE.x, DEFAULT_WINDOW_SIZE.y,
@@ -82,86 +581,294 @@ fn main() {
 
         gl::load_with(|x| glfw.get_proc_address(x));
 
-        glfw.set_swap_interval(1);
+        if options.diagnostics {
+            diagnostics::print_gl();
+        }
+
+        if options.gl_debug && !gldebug::install() {
+            println!("GL debug output isn't supported by this driver");
+        }
+
+        if !options.safe_mode {
+            glfw.set_swap_interval(1);
+        }
 
-        let mut renderer = renderer::Renderer::new(DEFAULT_WINDOW_SIZE);
+        /* Bigger than the window on high DPI displays, e.g. Retina Macs */
+        let (framebuffer_width, framebuffer_height) = window.get_framebuffer_size();
+        let framebuffer_size = Vector2 { x: framebuffer_width as u32, y: framebuffer_height as u32 };
+        let mut renderer = renderer::Renderer::new(framebuffer_size);
+        renderer.set_anisotropy(if options.safe_mode { 1.0 } else { options.anisotropy });
+        let mut ui = ui::Ui::new(framebuffer_size);
+        let mut chat = chat::Chat::new();
 
-        let mut chunk_loader = ChunkLoader::new(WORLD_SEED);
+        let wrap = match options.wrap_size {
+            Some(size) => wrap::Wrap::new(size),
+            None => wrap::Wrap::none(),
+        };
+
+        let mut chunk_loader = ChunkLoader::new(world.seed, wrap, world.height, world.chunk_dir(), options.terrain_quality.clone(), options.terrain_noise.clone(),
+                                                disk_cache(&world, &options));
+        chunk_loader.border = world.border;
+        chunk_loader.set_voxel_textures(options.voxel_textures && !options.safe_mode);
+        chunk_loader.set_deterministic(options.deterministic_gen);
+        let mut autosave_limiter = ratelimiter::RateLimiter::new(options.autosave_interval*1000*1000*1000);
 
         let mut camera = camera::Camera::new(Vector3::new(0.0, 20.0, 00.0));
+        camera.set_sensitivity(options.mouse_sensitivity);
+        camera.set_invert_y(options.invert_y);
+        let mut camera_path = camerapath::CameraPath::new();
+        /* Where the camera was when photo mode started, while it's on */
+        let mut photo_mode: Option<Vector3<f64>> = None;
+        /* Where the player was left and which way they faced, while the camera is detached */
+        let mut freecam: Option<(Vector3<f64>, Vector2<f64>)> = None;
+        let mut photo_scale = photo::DEFAULT_SCALE;
+        let mut inventory = inventory::Inventory::new();
+        restore_player(&world, &window, &mut camera, &mut renderer, &mut inventory, &chunk_loader);
+
+        let mut permissions = load_permissions(&world);
+
+        let console = console::Console::new();
+        match options.admin_port {
+            Some(port) => {
+                /* Not an argument, so it doesn't show up in ps */
+                let password = match std::os::getenv(ADMIN_PASSWORD_VAR) {
+                    Some(ref password) if !password.is_empty() => password.clone(),
+                    _ => fail!("--admin-port needs a password in {}", ADMIN_PASSWORD_VAR),
+                };
+                match console.listen(port, password) {
+                    Ok(()) => println!("Accepting admin connections on port {}", port),
+                    Err(msg) => fail!("Error starting admin console: {}", msg),
+                }
+            },
+            None => {},
+        }
 
         let mut fps_display_limiter = ratelimiter::RateLimiter::new(1000*1000*1000);
         let mut fps_frame_counter: uint = 0;
+        /* The renderer's counts for the last frame, and summed since the
+           frame rate was last printed */
+        let mut frame_stats = renderer::RenderStats::new();
+        let mut fps_stats = renderer::RenderStats::new();
 
         let mut last_tick = precise_time_ns();
 
+        /* Whether the player wants the cursor grabbed while playing. It's
+           let go when the window loses focus, and a click grabs it again */
         let mut grabbed = true;
+        /* Whether it actually is */
+        let mut cursor_captured = true;
+
+        let mut mining = mining::Mining::new();
+        /* Seconds the player has spent in lava since they were last out of it */
+        let mut burning = 0.0f64;
+        let mut entities = entity::Entities::new();
+        let mut show_entity_debug = false;
+        let mut show_block_info = false;
+        let mut show_render_stats = false;
+        let mut show_compass = true;
+        let mut debug_view: Option<debugview::DebugView> = None;
+        let mut debug_draw = renderer::DebugDraw::new();
+        let mut clouds = clouds::Clouds::new();
+        let mut horizon = horizon::Horizon::new(world.seed, wrap.period(), world.height, &options.terrain_noise);
+        horizon.enabled = !options.safe_mode;
+        let mut color_scheme = options.color_scheme;
+        renderer.set_color_scheme(color_scheme);
+        ui.set_color_scheme(color_scheme);
+        horizon.set_color_scheme(color_scheme);
+        let mut weather = weather::WeatherState::new(world.seed);
+        let mut hand = hand::Hand::new();
+        let mut block_updates = blockupdates::BlockUpdates::new(world.seed);
+        let job_pool = jobs::JobPool::new(JOB_WORKERS);
+        let photo_jobs = job_pool.queue();
+
+        let mut script_hooks = load_hooks();
+        let mut camera_chunk = chunk::containing_chunk(camera.position);
+        let mut nearby_chunks = nearby::NearbyChunks::new(VISIBLE_RADIUS as i64);
+        /* Chunk the loader was last asked for chunks around. None to ask
+           again next frame, e.g. for a new chunk loader */
+        let mut requested_chunk = Some(camera_chunk);
 
         // Preload chunks
         {
-            let deadline = precise_time_ns() + 1000*1000*100;
-            request_nearby_chunks(&mut chunk_loader, camera.position);
+            let preload_time = 1000*1000*100;
+            let deadline = precise_time_ns() + preload_time;
+            let budget = jobs::FrameBudget::new(preload_time);
+            request_nearby_chunks(&mut chunk_loader, &mut nearby_chunks, camera.position);
             while precise_time_ns() < deadline {
-                chunk_loader.work();
+                chunk_loader.work(&mut entities, &budget);
                 std::task::deschedule();
             }
             println!("Preloaded {} chunks", chunk_loader.cache.len());
         }
 
+        let mut focused = true;
+        let mut iconified = false;
+
+        let mut state = menu::StateLoading;
+        /* Until the camera's chunk arrives. The menu can be opened meanwhile,
+           and resuming from it goes back to the loading screen. */
+        let mut loading = true;
+        let mut menu = menu::Menu::new();
+
         while !window.should_close() {
+            let frame_start = precise_time_ns();
             glfw.poll_events();
+            let mut command = console.poll();
+            let mut take_photo = false;
+            let mut menu_choice = None;
+            let old_state = state;
+
             for (_, event) in glfw::flush_messages(&events) {
                 match event {
                     glfw::FramebufferSizeEvent(w, h) => {
                         renderer.set_window_size(Vector2 { x: w as u32, y: h as u32 });
+                        ui.set_window_size(Vector2 { x: w as u32, y: h as u32 });
                     },
-                    glfw::KeyEvent(key, _, action, _) => {
-                        match (action, key) {
-                            // Camera movement
-                            (glfw::Press, glfw::KeyW) |
-                            (glfw::Release, glfw::KeyS) => {
-                                camera.accelerate(Vector3::new(0.0, 0.0, -1.0));
+                    glfw::FocusEvent(f) => {
+                        focused = f;
+                        if !focused {
+                            grabbed = false;
+                        }
+                    },
+                    glfw::IconifyEvent(i) => iconified = i,
+                    glfw::CharEvent(c) if state == menu::StateConsole => chat.char(c),
+                    glfw::CursorPosEvent(x, y) if state.in_menu() => {
+                        menu.hover(state, ui.window_size(), ui_cursor(&window, &ui, x, y));
+                    },
+                    glfw::MouseButtonEvent(glfw::MouseButtonLeft, glfw::Press, _) if state.in_menu() => {
+                        let (x, y) = window.get_cursor_pos();
+                        menu_choice = menu.click(state, ui.window_size(), ui_cursor(&window, &ui, x, y));
+                    },
+                    glfw::ScrollEvent(_, y) if state == menu::StatePlaying => {
+                        if camera.scroll_speed(y) {
+                            show_speed(&mut chat, &camera);
+                        }
+                    },
+                    /* Only grabs, so clicking back into the window doesn't mine */
+                    glfw::MouseButtonEvent(glfw::MouseButtonLeft, glfw::Press, _) if !grabbed && state == menu::StatePlaying => {
+                        grabbed = true;
+                    },
+                    glfw::MouseButtonEvent(glfw::MouseButtonLeft, glfw::Press, _) if cursor_captured && state == menu::StatePlaying && photo_mode.is_none() && freecam.is_none() => {
+                        hand.swing();
+                    },
+                    glfw::MouseButtonEvent(glfw::MouseButtonRight, glfw::Press, _) if cursor_captured && state == menu::StatePlaying && photo_mode.is_none() && freecam.is_none() => {
+                        hand.swing();
+                        if permissions.allow_edit(options.name.as_slice()) {
+                            place_block(&mut chunk_loader, &mut entities, &camera, &mut inventory, &script_hooks);
+                        }
+                    },
+                    glfw::KeyEvent(key, _, action, _) if state == menu::StateConsole => {
+                        match action {
+                            glfw::Press | glfw::Repeat => {
+                                match chat.key(key) {
+                                    Some(chat::ChatMessage(text)) => {
+                                        chat.add_message(options.name.as_slice(), text.as_slice(), precise_time_ns());
+                                    },
+                                    Some(chat::ChatCommand(words)) => command = Some(words),
+                                    None => {},
+                                }
+                                if !chat.is_open() {
+                                    state = menu::StatePlaying;
+                                }
                             },
-                            (glfw::Press, glfw::KeyS) |
-                            (glfw::Release, glfw::KeyW) => {
-                                camera.accelerate(Vector3::new(0.0, 0.0, 1.0));
+                            glfw::Release => {
+                                if key == glfw::KeyLeftShift {
+                                    camera.boost(false);
+                                }
                             },
-                            (glfw::Press, glfw::KeyA) |
-                            (glfw::Release, glfw::KeyD) => {
-                                camera.accelerate(Vector3::new(-1.0, 0.0, 0.0));
+                        }
+                    },
+                    glfw::KeyEvent(key, _, action, _) if state.in_menu() => {
+                        match (action, key) {
+                            (glfw::Press, glfw::KeyEscape) => state = state.escape(),
+                            (glfw::Press, _) | (glfw::Repeat, _) => menu_choice = menu.key(state, key),
+                            _ => {},
+                        }
+                    },
+                    /* Nothing to play until the camera's chunk is there, but the
+                       menu still opens in case it never arrives */
+                    glfw::KeyEvent(key, _, action, _) if state == menu::StateLoading => {
+                        match (action, key) {
+                            (glfw::Press, glfw::KeyEscape) => state = state.escape(),
+                            (glfw::Press, glfw::KeyG) => grabbed = !grabbed,
+                            _ => {},
+                        }
+                    },
+                    glfw::KeyEvent(key, _, action, _) => {
+                        match (action, key) {
+                            (glfw::Press, glfw::KeyLeftShift) => camera.boost(true),
+                            (glfw::Release, glfw::KeyLeftShift) => camera.boost(false),
+                            (glfw::Press, glfw::KeyF2) => show_render_stats = !show_render_stats,
+                            (glfw::Press, glfw::KeyF3) => show_block_info = !show_block_info,
+                            (glfw::Press, glfw::KeyF4) => show_compass = !show_compass,
+                            (glfw::Press, glfw::KeyF) => {
+                                camera.cycle_speed_preset();
+                                show_speed(&mut chat, &camera);
                             },
-                            (glfw::Press, glfw::KeyD) |
-                            (glfw::Release, glfw::KeyA) => {
-                                camera.accelerate(Vector3::new(1.0, 0.0, 0.0));
+
+                            (glfw::Press, glfw::Key1) => inventory.select(0),
+                            (glfw::Press, glfw::Key2) => inventory.select(1),
+                            (glfw::Press, glfw::Key3) => inventory.select(2),
+                            (glfw::Press, glfw::Key4) => inventory.select(3),
+                            (glfw::Press, glfw::KeyC) => {
+                                inventory.creative = !inventory.creative;
+                                let mode = lang::text(if inventory.creative { "chat.creative" } else { "chat.survival" });
+                                chat.add_line(lang::format("chat.mode", [mode.to_string()]), precise_time_ns());
                             },
-                            (glfw::Press, glfw::KeyLeftControl) |
-                            (glfw::Release, glfw::KeySpace) => {
-                                camera.accelerate(Vector3::new(0.0, -1.0, 0.0));
+                            (glfw::Press, glfw::KeyT) => {
+                                chat.open("");
+                                state = menu::StateConsole;
                             },
-                            (glfw::Press, glfw::KeySpace) |
-                            (glfw::Release, glfw::KeyLeftControl) => {
-                                camera.accelerate(Vector3::new(0.0, 1.0, 0.0));
+                            (glfw::Press, glfw::KeySlash) => {
+                                chat.open("/");
+                                state = menu::StateConsole;
                             },
-                            (glfw::Press, glfw::KeyLeftShift) => camera.fast(true),
-                            (glfw::Release, glfw::KeyLeftShift) => camera.fast(false),
-
                             (glfw::Press, glfw::KeyR) => {
+                                let count = blocks::reload_looks(load_block_defs());
+                                println!("Reloaded the looks of {} block types", count);
+                                /* Rebuilds the block table from the new looks */
                                 renderer.reload_resources();
+                                /* Voxel textures hold the blocks' light */
+                                let voxel_textures = chunk_loader.voxel_textures();
+                                chunk_loader.set_voxel_textures(voxel_textures);
+                                ui.reload_resources();
+                                script_hooks = load_hooks();
                             },
-                            (glfw::Press, glfw::KeyEscape) => {
-                                window.set_should_close(true);
-                            },
-                            (glfw::Press, glfw::KeyG) => {
-                                grabbed = !grabbed;
-                                if grabbed {
-                                    window.set_cursor_mode(glfw::CursorDisabled);
-                                } else {
-                                    window.set_cursor_mode(glfw::CursorNormal);
-                                }
-                            },
+                            (glfw::Press, glfw::KeyEscape) => state = state.escape(),
+                            (glfw::Press, glfw::KeyG) => grabbed = !grabbed,
                             (glfw::Press, glfw::KeyL) => {
                                 renderer.toggle_wireframe_mode();
                             },
+                            (glfw::Press, glfw::KeyP) => {
+                                photo_mode = match photo_mode {
+                                    Some(p) => {
+                                        camera.position = p;
+                                        None
+                                    },
+                                    None => {
+                                        chat.add_line(lang::text("chat.photo_mode").to_string(), precise_time_ns());
+                                        Some(camera.position)
+                                    },
+                                };
+                            },
+                            (glfw::Press, glfw::KeyEnter) if photo_mode.is_some() => take_photo = true,
+                            (glfw::Press, glfw::KeyV) if photo_mode.is_none() => {
+                                freecam = match freecam {
+                                    Some((p, angle)) => {
+                                        camera.position = p;
+                                        camera.set_angle(angle);
+                                        None
+                                    },
+                                    None => {
+                                        chat.add_line(lang::text("chat.freecam").to_string(), precise_time_ns());
+                                        Some((camera.position, camera.angle))
+                                    },
+                                };
+                            },
+                            (glfw::Press, glfw::KeyK) => {
+                                camera_path.add(camera.position, camera.angle, &chunk_loader.wrap);
+                                chat.add_line(lang::format("chat.keyframe", [camera_path.len().to_string()]), precise_time_ns());
+                            },
                             _ => {},
                         }
                     },
@@ -169,7 +876,149 @@ fn main() {
                 }
             }
 
-            if grabbed {
+            match menu_choice {
+                Some(menu::ItemResume) => state = menu::StatePlaying,
+                Some(menu::ItemSettings) => state = menu::StateMenu,
+                Some(menu::ItemBack) => state = menu::StatePaused,
+                /* Saving happens on the way out */
+                Some(menu::ItemSaveAndQuit) => window.set_should_close(true),
+                Some(menu::ItemToggle(setting)) => match setting {
+                    menu::SettingCompass => show_compass = !show_compass,
+                    menu::SettingBlockInfo => show_block_info = !show_block_info,
+                    menu::SettingHorizon => horizon.enabled = !horizon.enabled,
+                    menu::SettingWireframe => renderer.toggle_wireframe_mode(),
+                    menu::SettingAnisotropy => {
+                        let level = if renderer.anisotropy() > 1.0 {
+                            1.0
+                        } else if options.anisotropy > 1.0 {
+                            options.anisotropy
+                        } else {
+                            DEFAULT_ANISOTROPY
+                        };
+                        renderer.set_anisotropy(level);
+                    },
+                    menu::SettingColorScheme => {
+                        color_scheme = color_scheme.next();
+                        renderer.set_color_scheme(color_scheme);
+                        ui.set_color_scheme(color_scheme);
+                        horizon.set_color_scheme(color_scheme);
+                    },
+                    menu::SettingInvertY => {
+                        let invert_y = !camera.invert_y();
+                        camera.set_invert_y(invert_y);
+                    },
+                },
+                None => {},
+            }
+
+            match command {
+                Some(words) => {
+                    let words: Vec<&str> = words.iter().map(|w| w.as_slice()).collect();
+                    match commands::parse(words.as_slice()) {
+                        /* Loading another world replaces most of the game */
+                        Ok(commands::CommandLoadWorld(name)) => {
+                            match worlds::open(name.as_slice()) {
+                                Ok(w) => {
+                                    match photo_mode.take() {
+                                        Some(p) => camera.position = p,
+                                        None => {},
+                                    }
+                                    match freecam.take() {
+                                        Some((p, angle)) => {
+                                            camera.position = p;
+                                            camera.set_angle(angle);
+                                        },
+                                        None => {},
+                                    }
+                                    chunk_loader.flush(&entities);
+                                    save_player(&world, &camera, &renderer, &inventory);
+                                    world = w;
+                                    let voxel_textures = chunk_loader.voxel_textures();
+                                    chunk_loader = ChunkLoader::new(world.seed, wrap, world.height, world.chunk_dir(), options.terrain_quality.clone(), options.terrain_noise.clone(),
+                                                                    disk_cache(&world, &options));
+                                    chunk_loader.border = world.border;
+                                    chunk_loader.set_voxel_textures(voxel_textures);
+                                    chunk_loader.set_deterministic(options.deterministic_gen);
+                                    let (sensitivity, invert_y) = (camera.sensitivity(), camera.invert_y());
+                                    camera = camera::Camera::new(Vector3::new(0.0, 20.0, 00.0));
+                                    camera.set_sensitivity(sensitivity);
+                                    camera.set_invert_y(invert_y);
+                                    camera_path = camerapath::CameraPath::new();
+                                    inventory = inventory::Inventory::new();
+                                    entities = entity::Entities::new();
+                                    weather = weather::WeatherState::new(world.seed);
+                                    let horizon_enabled = horizon.enabled;
+                                    horizon = horizon::Horizon::new(world.seed, wrap.period(), world.height, &options.terrain_noise);
+                                    horizon.enabled = horizon_enabled;
+                                    horizon.set_color_scheme(color_scheme);
+                                    block_updates = blockupdates::BlockUpdates::new(world.seed);
+                                    restore_player(&world, &window, &mut camera, &mut renderer, &mut inventory, &chunk_loader);
+                                    camera_chunk = chunk::containing_chunk(camera.position);
+                                    requested_chunk = None;
+                                    permissions = load_permissions(&world);
+                                    chat.close();
+                                    state = menu::StateLoading;
+                                    loading = true;
+                                    println!("Playing world {} (seed {})", world.name, world.seed);
+                                },
+                                Err(msg) => println!("Error opening world: {}", msg),
+                            }
+                        },
+                        Ok(command) => run_command(command, &mut GameState {
+                            options: &options,
+                            world: &world,
+                            glfw: &glfw,
+                            window: &window,
+                            camera_chunk: camera_chunk,
+                            chunk_loader: &mut chunk_loader,
+                            camera: &mut camera,
+                            camera_path: &mut camera_path,
+                            inventory: &mut inventory,
+                            entities: &mut entities,
+                            permissions: &mut permissions,
+                            block_updates: &block_updates,
+                            renderer: &mut renderer,
+                            horizon: &mut horizon,
+                            clouds: &mut clouds,
+                            weather: &mut weather,
+                            debug_view: &mut debug_view,
+                            show_entity_debug: &mut show_entity_debug,
+                            take_photo: &mut take_photo,
+                            photo_scale: &mut photo_scale,
+                        }),
+                        Err(msg) => println!("{}", msg),
+                    }
+                },
+                None => {},
+            }
+
+            if state != old_state {
+                if state.in_menu() {
+                    camera.boost(false);
+                }
+                menu.reset();
+            }
+
+            /* Menus and chat need the cursor, and other windows shouldn't
+               have it taken from them */
+            let capture = grabbed && focused && (state == menu::StatePlaying || state == menu::StateLoading);
+            if capture != cursor_captured {
+                cursor_captured = capture;
+                if capture {
+                    window.set_cursor_mode(glfw::CursorDisabled);
+                    camera.recenter();
+                } else {
+                    window.set_cursor_mode(glfw::CursorNormal);
+                }
+            }
+
+            camera.velocity = if state == menu::StatePlaying {
+                movement_input(&window)
+            } else {
+                Vector3::zero()
+            };
+
+            if cursor_captured {
                 let (cursor_x, cursor_y) = window.get_cursor_pos();
                 camera.look(Vector2 { x: cursor_x, y: cursor_y });
             }
@@ -178,81 +1027,932 @@ fn main() {
             let tick_length = (now - last_tick) as f64 / (1000.0 * 1000.0 * 1000.0);
             last_tick = now;
 
+            let old_position = camera.position;
             camera.tick(tick_length);
+            let keyframe = if state.running() { camera_path.tick(tick_length) } else { None };
+            match keyframe {
+                Some(key) => {
+                    /* Keyframes can lie outside a wrapped world; stay next to the camera */
+                    camera.position = old_position.add_v(&chunk_loader.wrap.position_offset(key.position, old_position));
+                    camera.set_angle(key.angle);
+                },
+                None => {},
+            }
+            /* The photo mode camera and freecam go back where they started, so they can go anywhere */
+            let detached = photo_mode.is_some() || freecam.is_some();
+            if !detached {
+                camera.position = permissions.check_move(options.name.as_slice(), old_position, camera.position, tick_length);
+            }
+            camera.position = chunk_loader.wrap.position(camera.position);
+            if !detached {
+                camera.position = chunk_loader.clamp_to_border(camera.position);
+            }
+            /* Everything the player does happens where they were left */
+            let player_position = match freecam {
+                Some((p, _)) => p,
+                None => camera.position,
+            };
+
+            /* None while the camera's chunk is still loading */
+            let camera_block = chunk_loader.get_block(entity::block_containing(camera.position));
+            let feet_block = chunk_loader.get_block(entity::block_containing(
+                camera.position.sub_v(&Vector3::new(0.0, EYE_HEIGHT, 0.0))));
+            camera.set_in_water(camera_block == Some(terrain::BlockWater) || camera_block == Some(terrain::BlockLava));
+
+            if loading && state == menu::StatePlaying {
+                state = menu::StateLoading;
+            }
+            if loading && camera_chunk_ready(&chunk_loader, camera.position) {
+                loading = false;
+                if state == menu::StateLoading {
+                    state = menu::StatePlaying;
+                }
+            }
+
+            {
+                let mining_target = if cursor_captured && state == menu::StatePlaying && !detached &&
+                        window.get_mouse_button(glfw::MouseButtonLeft) == glfw::Press &&
+                        permissions.allow_edit(options.name.as_slice()) {
+                    target_block(&chunk_loader, &camera)
+                } else {
+                    None
+                };
+
+                if mining_target.is_some() {
+                    /* Keep swinging while mining */
+                    hand.swing();
+                }
+
+                match mining.tick(mining_target, tick_length) {
+                    Some(p) => match chunk_loader.set_block(p, terrain::BlockAir) {
+                        Some(old) => {
+                            if !inventory.creative {
+                                let center = Vector3::new(p.x as f64 + 0.5, p.y as f64 + 0.5, p.z as f64 + 0.5);
+                                entities.spawn(entity::ItemDrop(old), center, Vector3::new(0.0, ITEM_DROP_POP, 0.0));
+                            }
+                            script_hooks.fire(&hooks::BlockBroken(p, old), &mut chunk_loader, &mut entities);
+                        },
+                        None => {},
+                    },
+                    None => {},
+                }
+            }
+
+            /* Photo mode and menus freeze the world */
+            let frozen = photo_mode.is_some() || !state.running();
+            if !frozen {
+                clouds.tick(tick_length);
+                hand.tick(tick_length, camera.velocity != Vector3::zero());
+                weather.tick(tick_length, camera.position, &chunk_loader);
+                renderer.tick(tick_length);
+            }
+
+            /* There's no health to take, so lava just sends players in survival
+               mode back to spawn */
+            let in_lava = camera_block == Some(terrain::BlockLava) || feet_block == Some(terrain::BlockLava);
+            if in_lava && !frozen && !detached && !inventory.creative {
+                burning += tick_length;
+                if burning >= LAVA_BURN_TIME {
+                    burning = 0.0;
+                    camera.position = chunk_loader.wrap.position(spawn_position(&chunk_loader));
+                    chat.add_line(lang::text("chat.burned").to_string(), precise_time_ns());
+                    loading = true;
+                    if state == menu::StatePlaying {
+                        state = menu::StateLoading;
+                    }
+                }
+            } else if !in_lava {
+                burning = 0.0;
+            }
+
+            if camera_block == Some(terrain::BlockWater) {
+                renderer.set_sky(WATER_FOG_COLOR, WATER_FOG_DENSITY);
+            } else if camera_block == Some(terrain::BlockLava) {
+                renderer.set_sky(LAVA_FOG_COLOR, LAVA_FOG_DENSITY);
+            } else {
+                renderer.set_sky(weather.sky_color(), weather.fog_density());
+            }
+            if !frozen {
+                block_updates.run(tick_length, &mut chunk_loader);
+                entities.tick(&chunk_loader, tick_length);
+                entities.pick_up(player_position.sub_v(&Vector3::new(0.0, EYE_HEIGHT / 2.0, 0.0)),
+                                 |blocktype| inventory.add(blocktype));
+            }
+
+            {
+                let c = chunk::containing_chunk(camera.position);
+                if c != camera_chunk {
+                    camera_chunk = c;
+                    if !detached {
+                        script_hooks.fire(&hooks::ChunkEntered(c), &mut chunk_loader, &mut entities);
+                    }
+                }
+            }
+
+            chunk_loader.sort_translucent(camera.position);
+            horizon.update(camera.position);
+            /* The far plane reaches the horizon and the top and bottom of the world */
+            renderer.set_far_plane(world.height.view_distance(camera.position.y, horizon::HORIZON_DISTANCE as f64) as f32);
+
+            /* Render from the bobbed eye; everything else uses the camera position */
+            let eye = camera.eye();
+
+            /* Chunks outside the border never load, so they aren't missing */
+            let placeholders = horizon.placeholders(camera_chunk, VISIBLE_RADIUS as i64, |c| {
+                chunk_loader.get(c).is_none() && chunk_loader.border.map_or(true, |border| border.contains_chunk(c))
+            });
+
+            if take_photo {
+                let regions = chunk_loader.find_nearby_regions(camera_chunk, VISIBLE_RADIUS as i64);
+                let result = photo::capture(&mut renderer, photo_scale, |renderer| {
+                    renderer.render(regions.as_slice(), &clouds, &horizon, eye, camera.angle);
+                    renderer.render_placeholders(placeholders.as_slice(), eye, camera.angle);
+                    renderer.render_entities(&entities, eye, camera.angle);
+                    renderer.render_weather(&weather, eye, camera.angle);
+                });
+                match result {
+                    Ok((size, rgb)) => {
+                        /* Encoding takes a while at large scales */
+                        let dir = world.photo_dir();
+                        photo_jobs.submit(jobs::PriorityLow, proc() photo::save(&dir, size, rgb.as_slice()));
+                    },
+                    Err(msg) => println!("Error taking photo: {}", msg),
+                }
+            }
 
             {
-                let chunks = find_nearby_chunks(&chunk_loader, camera.position);
+                let regions = chunk_loader.find_nearby_regions(camera_chunk, VISIBLE_RADIUS as i64);
 
                 renderer.render(
-                    chunks.slice(0, chunks.len()),
-                    Vector3 { x: camera.position.x as f32, y: camera.position.y as f32, z: camera.position.z as f32 },
+                    regions.as_slice(),
+                    &clouds,
+                    &horizon,
+                    eye,
                     camera.angle)
             }
+            renderer.render_placeholders(placeholders.as_slice(), eye, camera.angle);
+
+            renderer.render_entities(&entities, eye, camera.angle);
+            if show_entity_debug {
+                entities.debug_draw(&mut debug_draw);
+            }
+            renderer.render_weather(&weather, eye, camera.angle);
+            match world.border {
+                Some(ref border) => renderer.render_border(border, eye, camera.angle),
+                None => {},
+            }
+            match freecam {
+                Some((p, _)) => {
+                    let half_width = Vector3::new(PLAYER_HALF_WIDTH, 0.0, PLAYER_HALF_WIDTH);
+                    debug_draw.aabb(p.sub_v(&half_width).sub_v(&Vector3::new(0.0, EYE_HEIGHT, 0.0)),
+                                    p.add_v(&half_width).add_v(&Vector3::new(0.0, PLAYER_HEAD_ROOM, 0.0)),
+                                    PLAYER_MARKER_COLOR, 0.0);
+                },
+                None => {},
+            }
+            if photo_mode.is_none() {
+                renderer.render_debug(&debug_draw, eye, camera.angle);
+            }
+            if !detached {
+                match mining.crack() {
+                    Some((p, stage)) => renderer.render_crack(p, stage, eye, camera.angle),
+                    None => {},
+                }
+
+                let selected = inventory.selected();
+                if inventory.creative || inventory.count(selected) > 0 {
+                    renderer.render_held_block(selected, &hand, camera.angle);
+                }
+            }
+            debug_draw.expire(tick_length);
+
+            frame_stats = renderer.stats();
+            frame_stats.buffer_uploads += chunk_loader.take_uploads();
+            renderer.reset_stats();
+            fps_stats.add(&frame_stats);
+
+            draw_camera_overlay(&mut ui, camera_block);
+            if !detached {
+                inventory.draw(&mut ui);
+                if show_block_info {
+                    draw_block_info(&mut ui, &chunk_loader, &camera);
+                }
+            }
+            if show_compass && photo_mode.is_none() {
+                compass::draw(&mut ui, camera.angle.y, camera.position);
+            }
+            if show_render_stats && photo_mode.is_none() {
+                draw_render_stats(&mut ui, &frame_stats);
+            }
+            chat.draw(&mut ui, now);
+            match state {
+                menu::StateLoading => draw_loading(&mut ui, world.name.as_slice()),
+                menu::StatePaused | menu::StateMenu => {
+                    let wireframe = renderer.wireframe_mode();
+                    let anisotropy = renderer.anisotropy() > 1.0;
+                    let invert_y = camera.invert_y();
+                    menu.draw(&mut ui, state, |setting| match setting {
+                        menu::SettingCompass => menu::on_off(show_compass),
+                        menu::SettingBlockInfo => menu::on_off(show_block_info),
+                        menu::SettingHorizon => menu::on_off(horizon.enabled),
+                        menu::SettingWireframe => menu::on_off(wireframe),
+                        menu::SettingAnisotropy => menu::on_off(anisotropy),
+                        menu::SettingColorScheme => color_scheme.title(),
+                        menu::SettingInvertY => menu::on_off(invert_y),
+                    });
+                },
+                _ => {},
+            }
+            ui.draw();
 
             window.swap_buffers();
 
-            request_nearby_chunks(&mut chunk_loader, camera.position);
-            chunk_loader.work();
+            if debug_view.as_ref().map_or(false, |view| view.should_close()) {
+                debug_view = None;
+            }
+            let debug_view_focused = match debug_view {
+                Some(ref mut view) => {
+                    let frustum = renderer.frustum_corners(eye, camera.angle);
+                    view.draw(&window, &chunk_loader, camera.position, frustum.as_slice());
+                    view.is_focused()
+                },
+                None => false,
+            };
+
+            /* Nobody's watching, so don't load anything new */
+            let background = (!focused && !debug_view_focused) || iconified;
+            if background != chunk_loader.paused() {
+                println!("{}", if background { "In the background, throttling" } else { "Back in the foreground" });
+                chunk_loader.set_paused(background);
+            }
+
+            /* Only when the camera enters another chunk; the queue and the
+               workers carry on in between */
+            if !background && requested_chunk != Some(camera_chunk) {
+                request_nearby_chunks(&mut chunk_loader, &mut nearby_chunks, camera.position);
+                let coords = predicted_chunk_coords(camera.position, camera.absolute_velocity());
+                chunk_loader.request_predicted(coords.as_slice());
+                requested_chunk = Some(camera_chunk);
+            }
+            let budget = jobs::FrameBudget::new(FRAME_JOB_BUDGET);
+            chunk_loader.work(&mut entities, &budget);
+            loop {
+                match photo_jobs.finish(&budget) {
+                    Some(Ok(path)) => chat.add_line(lang::format("chat.saved", [path.display().to_string()]), precise_time_ns()),
+                    Some(Err(msg)) => println!("Error taking photo: {}", msg),
+                    None => break,
+                }
+            }
+
+            for &c in chunk_loader.take_loaded().iter() {
+                let middle = c.mul_s(CHUNK_SIZE as i64).add_v(&Vector3::new(CHUNK_SIZE as i64 / 2, 0, CHUNK_SIZE as i64 / 2));
+                let biome = chunk_loader.biome_at(middle.x, middle.z);
+                match chunk_loader.get_mut(c) {
+                    Some(chunk) => entities.adopt(&mut **chunk, world.seed, biome),
+                    None => {},
+                }
+            }
+
+            if autosave_limiter.limit() {
+                let count = chunk_loader.autosave(&entities);
+                if count > 0 {
+                    println!("Autosaving {} chunks", count);
+                }
+            }
 
             check_gl("main loop");
 
             fps_frame_counter += 1;
             if fps_display_limiter.limit() {
                 println!("{} frames per second", fps_frame_counter);
+                if options.render_stats {
+                    for line in fps_stats.per_frame(fps_frame_counter).lines().iter() {
+                        println!("  {} per frame", line);
+                    }
+                }
                 fps_frame_counter = 0;
+                fps_stats = renderer::RenderStats::new();
             }
+
+            if background {
+                let frame_ns = 1000*1000*1000 / BACKGROUND_FPS;
+                let elapsed = precise_time_ns() - frame_start;
+                if elapsed < frame_ns {
+                    std::io::timer::sleep((frame_ns - elapsed) / (1000*1000));
+                }
+            }
+        }
+
+        match photo_mode {
+            Some(p) => camera.position = p,
+            None => {},
+        }
+        match freecam {
+            Some((p, angle)) => {
+                camera.position = p;
+                camera.set_angle(angle);
+            },
+            None => {},
         }
+        /* Everything is saved before anything is torn down, in case
+           something fails along the way */
+        println!("Shutting down");
+        save_player(&world, &camera, &renderer, &inventory);
+        chunk_loader.shutdown(&entities);
+        /* Lets photos being written finish */
+        job_pool.shutdown();
+
+        /* GL objects have to be deleted while the context is current.
+           Whatever else holds some is dropped at the end of this block,
+           before the window that was created ahead of it */
+        drop(horizon);
+        drop(renderer);
+        drop(ui);
     }
 }
 
-fn nearby_chunk_coords(p: Vector3<f64>) -> Vec<Vector3<i64>> {
-    let cur_chunk_coord = Vector3::new(p.x as i64, p.y as i64, p.z as i64).div_s(CHUNK_SIZE as i64);
-    let r = VISIBLE_RADIUS as i64;
+fn print_worlds() {
+    match worlds::list() {
+        Ok(names) => {
+            for name in names.iter() {
+                println!("{}", name);
+            }
+        },
+        Err(msg) => println!("Error listing worlds: {}", msg),
+    }
+}
 
-    let mut coords = Vec::new();
+fn create_world(name: &str, seed: u32, height: worldheight::WorldHeight,
+                border: Option<worldborder::WorldBorder>) {
+    match worlds::create(name, seed, height, border) {
+        Ok(world) => println!("Created world {} with seed {}", world.name, world.seed),
+        Err(msg) => println!("Error creating world: {}", msg),
+    }
+}
+
+fn restore_player(world: &worlds::World,
+                  window: &glfw::Window,
+                  camera: &mut camera::Camera,
+                  renderer: &mut renderer::Renderer,
+                  inventory: &mut inventory::Inventory,
+                  chunk_loader: &ChunkLoader) {
+    let path = world.player_path();
+    if !path.exists() {
+        camera.position = chunk_loader.wrap.position(spawn_position(chunk_loader));
+        return;
+    }
 
-    for x in range(-r, r+1) {
-        for y in range(-r, r+1) {
-            for z in range(-r, r+1) {
+    match player::PlayerState::load(&path) {
+        Ok(state) => {
+            camera.position = chunk_loader.wrap.position(state.position);
+            let (cursor_x, cursor_y) = window.get_cursor_pos();
+            camera.look(Vector2 { x: cursor_x, y: cursor_y });
+            camera.set_angle(state.angle);
+            match camera::SpeedPreset::from_name(state.speed.as_slice()) {
+                Some(preset) => camera.set_speed(preset, Some(state.speed_scale)),
+                None => println!("Unknown speed preset {}", state.speed),
+            }
+            renderer.set_wireframe_mode(state.wireframe);
+            inventory.creative = state.creative;
+            if state.hotbar < inventory::HOTBAR.len() {
+                inventory.select(state.hotbar);
+            } else {
+                println!("Hotbar slot {} out of range", state.hotbar);
+            }
+            for &(ref name, count) in state.inventory.iter() {
+                match terrain::BlockType::from_name(name.as_slice()) {
+                    Some(blocktype) => inventory.set_count(blocktype, count),
+                    None => println!("Unknown block type {} in inventory", name),
+                }
+            }
+        },
+        Err(msg) => println!("Error loading player state: {}", msg),
+    }
+}
+
+fn save_player(world: &worlds::World,
+               camera: &camera::Camera,
+               renderer: &renderer::Renderer,
+               inventory: &inventory::Inventory) {
+    let state = player::PlayerState {
+        position: camera.position,
+        angle: camera.angle,
+        speed: camera.speed_preset().name().to_string(),
+        speed_scale: camera.speed_scale(),
+        wireframe: renderer.wireframe_mode(),
+        creative: inventory.creative,
+        hotbar: inventory.selected_slot(),
+        inventory: inventory::HOTBAR.iter().
+            map(|b| (b.name().to_string(), inventory.count(*b))).
+            filter(|&(_, count)| count > 0).
+            collect(),
+    };
+
+    match state.save(&world.player_path()) {
+        Ok(()) => {},
+        Err(e) => println!("Error saving player state: {}", e),
+    }
+}
+
+/// The parts of the game console commands can see and change, other than
+/// loading a world, which main() does itself.
+struct GameState<'a> {
+    options: &'a Options,
+    world: &'a worlds::World,
+    glfw: &'a glfw::Glfw,
+    window: &'a glfw::Window,
+    camera_chunk: Vector3<i64>,
+    chunk_loader: &'a mut ChunkLoader,
+    camera: &'a mut camera::Camera,
+    camera_path: &'a mut camerapath::CameraPath,
+    inventory: &'a mut inventory::Inventory,
+    entities: &'a mut entity::Entities,
+    permissions: &'a mut permissions::Permissions,
+    block_updates: &'a blockupdates::BlockUpdates,
+    renderer: &'a mut renderer::Renderer,
+    horizon: &'a mut horizon::Horizon,
+    clouds: &'a mut clouds::Clouds,
+    weather: &'a mut weather::WeatherState,
+    debug_view: &'a mut Option<debugview::DebugView>,
+    show_entity_debug: &'a mut bool,
+    take_photo: &'a mut bool,
+    photo_scale: &'a mut u32,
+}
+
+/// Carries out a console command parsed by commands::parse().
+fn run_command(command: commands::Command, game: &mut GameState) {
+    match command {
+        commands::CommandWorlds => print_worlds(),
+        commands::CommandCreateWorld(name, seed) => {
+            create_world(name.as_slice(), seed.unwrap_or(WORLD_SEED),
+                         game.options.world_height, game.options.world_border);
+        },
+        commands::CommandLoadWorld(_) => fail!("main() loads worlds"),
+        commands::CommandDeleteWorld(ref name) if *name == game.world.name => {
+            println!("Can't delete the loaded world");
+        },
+        commands::CommandDeleteWorld(name) => {
+            match worlds::delete(name.as_slice()) {
+                Ok(()) => println!("Deleted world {}", name),
+                Err(msg) => println!("Error deleting world: {}", msg),
+            }
+        },
+        commands::CommandTeleportSurface(x, z) => {
+            let target = game.chunk_loader.clamp_to_border(Vector3::new(x, 0.0, z));
+            let p = surface_position(&mut *game.chunk_loader, target.x.floor() as i64, target.z.floor() as i64);
+            game.camera.position = game.chunk_loader.wrap.position(p);
+        },
+        commands::CommandTeleport(x, y, z) => {
+            let target = game.chunk_loader.clamp_to_border(Vector3::new(x, y, z));
+            let p = clear_position(&mut *game.chunk_loader, target.x.floor() as i64, target.y.floor() as i64, target.z.floor() as i64);
+            game.camera.position = game.chunk_loader.wrap.position(p);
+        },
+        commands::CommandBorder => match game.world.border {
+            Some(border) => println!("World border {} blocks from the origin", border.radius),
+            None => println!("World {} has no border", game.world.name),
+        },
+        commands::CommandPlayers => {
+            let name = game.options.name.as_slice();
+            let p = game.camera.position;
+            println!("{} at {:.1} {:.1} {:.1}, {}: {}", name, p.x, p.y, p.z,
+                     if game.inventory.creative { "creative" } else { "survival" },
+                     game.permissions.flags(name).names().connect(" "));
+        },
+        commands::CommandSave => {
+            game.chunk_loader.flush(&*game.entities);
+            save_player(game.world, &*game.camera, &*game.renderer, &*game.inventory);
+            println!("Saved world {}", game.world.name);
+        },
+        commands::CommandCreative(creative) => game.inventory.creative = creative,
+        commands::CommandShowPermissions(name) => {
+            println!("{}: {}", name, game.permissions.flags(name.as_slice()).names().connect(" "));
+        },
+        commands::CommandSetPermission(name, flag, value) => {
+            if game.permissions.set(name.as_slice(), flag.as_slice(), value) {
+                match game.permissions.save() {
+                    Ok(()) => println!("{}: {}", name, game.permissions.flags(name.as_slice()).names().connect(" ")),
+                    Err(e) => println!("Error saving permissions: {}", e),
+                }
+            } else {
+                println!("Unknown flag {}", flag);
+            }
+        },
+        commands::CommandSpawn(kind, count) => {
+            let p = game.camera.position.add_v(&game.camera.direction().mul_s(SPAWN_DISTANCE));
+            for _ in range(0, count) {
+                game.entities.spawn(kind, p, Vector3::zero());
+            }
+        },
+        commands::CommandEntities => {
+            println!("{} entities{}", game.entities.len(), if game.entities.paused() { ", paused" } else { "" });
+        },
+        commands::CommandBlockStats(radius) => print_block_stats(&*game.chunk_loader, game.camera_chunk, radius),
+        commands::CommandUpdates => println!("{} block updates scheduled", game.block_updates.len()),
+        commands::CommandJournal => {
+            let c = game.camera_chunk;
+            match game.chunk_loader.get(c) {
+                Some(chunk) => println!("Chunk ({}, {}, {}) journal: {} entries, {} with rewound ones",
+                                        c.x, c.y, c.z, chunk.journal.len(), chunk.journal.total()),
+                None => println!("Chunk not loaded"),
+            }
+        },
+        commands::CommandRewind(len) => {
+            if !game.permissions.allow_edit(game.options.name.as_slice()) {
+                println!("{} can't edit", game.options.name);
+            } else if !game.chunk_loader.rewind(game.camera_chunk, len) {
+                println!("Can't rewind this chunk to {} journal entries", len);
+            }
+        },
+        commands::CommandEntityDebug(show) => *game.show_entity_debug = show,
+        commands::CommandSimPaused(paused) => game.entities.set_paused(paused),
+        commands::CommandSimStep(count) => {
+            game.entities.set_paused(true);
+            game.entities.step(count);
+        },
+        commands::CommandVoxelTextures(enabled) => game.chunk_loader.set_voxel_textures(enabled),
+        commands::CommandDebugWindow(true) if game.debug_view.is_none() => {
+            match debugview::DebugView::open(game.glfw, game.window) {
+                Ok(view) => *game.debug_view = Some(view),
+                Err(msg) => println!("Error opening debug window: {}", msg),
+            }
+        },
+        commands::CommandDebugWindow(true) => {},
+        commands::CommandDebugWindow(false) => *game.debug_view = None,
+        commands::CommandHorizon(enabled) => game.horizon.enabled = enabled,
+        commands::CommandClouds(enabled) => game.clouds.enabled = enabled,
+        commands::CommandCloudHeight(h) => game.clouds.height = h,
+        commands::CommandCloudDensity(d) => game.clouds.set_density(d),
+        commands::CommandShowWeather => println!("{}", game.weather.weather().name()),
+        commands::CommandForceWeather(w) => game.weather.force(w),
+        commands::CommandBobbing(bobbing) => game.camera.bobbing = bobbing,
+        commands::CommandShowSensitivity => println!("Mouse sensitivity {}", game.camera.sensitivity()),
+        commands::CommandSensitivity(s) => game.camera.set_sensitivity(s),
+        commands::CommandInvertY(invert_y) => game.camera.set_invert_y(invert_y),
+        commands::CommandSmoothing(t) => game.camera.smoothing = t,
+        commands::CommandPhoto => *game.take_photo = true,
+        commands::CommandPhotoScale(n) => *game.photo_scale = n,
+        commands::CommandShowPath => {
+            println!("{} keyframes{}", game.camera_path.len(), if game.camera_path.playing() { ", playing" } else { "" });
+        },
+        commands::CommandPathAdd => game.camera_path.add(game.camera.position, game.camera.angle, &game.chunk_loader.wrap),
+        commands::CommandPathUndo => if !game.camera_path.remove_last() {
+            println!("No keyframes");
+        },
+        commands::CommandPathClear => game.camera_path.clear(),
+        commands::CommandPathPlay(t) => if !game.camera_path.play(t) {
+            println!("A path needs at least 2 keyframes");
+        },
+        commands::CommandPathStop => game.camera_path.stop(),
+        commands::CommandSun(direction) => game.renderer.set_sun_direction(direction),
+    }
+}
+
+/// Prints how many of each blocktype the loaded chunks within radius chunks
+/// of center hold.
+fn print_block_stats(chunk_loader: &ChunkLoader, center: Vector3<i64>, radius: i64) {
+    let (chunks, sparse, histogram) = chunk_loader.block_stats(center, radius);
+    let side = (radius * 2 + 1) as uint;
+    println!("{} of {} chunks loaded, {} stored sparsely", chunks, side * side * side, sparse);
+    let total = histogram.total();
+    if total == 0 {
+        return;
+    }
+    for &blocktype in blocks::registry().types().iter() {
+        let count = histogram.count(blocktype);
+        println!("{}: {} ({:.1f}%)", blocktype.name(), count, count as f64 * 100.0 / total as f64);
+    }
+}
+
+/// Tints the whole view when the camera is inside a block: blue underwater,
+/// orange in lava and black inside solid blocks, which would otherwise show
+/// their insides.
+fn draw_camera_overlay(ui: &mut ui::Ui, camera_block: Option<terrain::BlockType>) {
+    let color = match camera_block {
+        Some(blocktype) if blocktype == terrain::BlockWater => WATER_OVERLAY_COLOR,
+        Some(blocktype) if blocktype == terrain::BlockLava => LAVA_OVERLAY_COLOR,
+        Some(blocktype) if blocktype.is_solid() => Vector4::new(0.0, 0.0, 0.0, 1.0),
+        _ => return,
+    };
+    let window_size = ui.window_size();
+    ui.rect(0.0, 0.0, window_size.x as f32, window_size.y as f32, color);
+}
+
+fn draw_loading(ui: &mut ui::Ui, world_name: &str) {
+    let window_size = ui.window_size();
+    let text = lang::format("hud.loading", [world_name.to_string()]);
+    ui.rect(0.0, 0.0, window_size.x as f32, window_size.y as f32, Vector4::new(0.0, 0.0, 0.0, 0.5));
+    ui.text((window_size.x as f32 - ui::text_width(text.as_slice())) / 2.0,
+            (window_size.y as f32 - ui::line_height()) / 2.0,
+            text.as_slice(), Vector4::new(1.0, 1.0, 1.0, 1.0));
+}
+
+/// Converts a cursor position in screen coordinates to UI pixels, which
+/// differ on high DPI displays.
+fn ui_cursor(window: &glfw::Window, ui: &ui::Ui, x: f64, y: f64) -> Vector2<f32> {
+    let (width, height) = window.get_size();
+    let size = ui.window_size();
+    Vector2::new((x * size.x as f64 / width as f64) as f32, (y * size.y as f64 / height as f64) as f32)
+}
+
+/// Whether the chunk around p is loaded, or will never be because it's
+/// outside the world.
+fn camera_chunk_ready(chunk_loader: &ChunkLoader, p: Vector3<f64>) -> bool {
+    let c = chunk::containing_chunk(p);
+    chunk_loader.get(c).is_some() || !chunk_loader.world_height.contains_chunk(c.y) ||
+        chunk_loader.border.map_or(false, |border| !border.contains_chunk(c))
+}
+
+/// Describes the first non-air block under the crosshair, for debugging
+/// generation. Only loaded chunks are searched.
+fn draw_block_info(ui: &mut ui::Ui, chunk_loader: &ChunkLoader, camera: &camera::Camera) {
+    let is_block = |p: Vector3<i64>| {
+        match chunk_loader.get_block(p) {
+            Some(blocktype) => blocktype != terrain::BlockAir,
+            None => false,
+        }
+    };
+
+    let lines = match raycast::raycast(camera.position, camera.direction(), BLOCK_INFO_REACH, is_block) {
+        Some(hit) => {
+            let p = chunk_loader.wrap.block(hit.block);
+            let c = chunk::chunk_coord(p);
+            let local = p.sub_v(&c.mul_s(CHUNK_SIZE as i64));
+            let blocktype = chunk_loader.get_block(p).unwrap_or(terrain::BlockAir);
+            let sky = lang::text(if chunk_loader.sky_lit(p.add_v(&hit.normal)) { "hud.sky_lit" } else { "hud.shaded" });
+            let flags = match chunk_loader.get(c) {
+                Some(chunk) => {
+                    let detail = lang::text(if chunk.coarse { "hud.coarse" } else { "hud.full" });
+                    if chunk.dirty {
+                        lang::format("hud.edited", [detail.to_string()])
+                    } else {
+                        detail.to_string()
+                    }
+                },
+                None => lang::text("hud.not_loaded").to_string(),
+            };
+            vec!(lang::format("hud.block", [p.x.to_string(), p.y.to_string(), p.z.to_string()]),
+                 lang::format("hud.chunk", [c.x.to_string(), c.y.to_string(), c.z.to_string(), flags,
+                                            local.x.to_string(), local.y.to_string(), local.z.to_string()]),
+                 lang::format("hud.hit", [lang::name("block", blocktype.name()).to_string(),
+                                          format!("{:.1f}", hit.distance), hit.normal.x.to_string(),
+                                          hit.normal.y.to_string(), hit.normal.z.to_string(), sky.to_string()]))
+        },
+        None => vec!(lang::text("hud.no_block").to_string()),
+    };
+
+    let x = 8.0;
+    let mut y = 8.0;
+    for line in lines.iter() {
+        ui.rect(x - 2.0, y, ui::text_width(line.as_slice()) + 4.0, ui::line_height(),
+                Vector4::new(0.0, 0.0, 0.0, 0.5));
+        ui.text(x, y, line.as_slice(), Vector4::new(1.0, 1.0, 1.0, 1.0));
+        y += ui::line_height();
+    }
+}
+
+/// Lists what the renderer did last frame in the top right corner.
+fn draw_render_stats(ui: &mut ui::Ui, stats: &renderer::RenderStats) {
+    let right = ui.window_size().x as f32 - 8.0;
+    let mut y = 8.0;
+    for line in stats.lines().iter() {
+        let width = ui::text_width(line.as_slice());
+        ui.rect(right - width - 2.0, y, width + 4.0, ui::line_height(), Vector4::new(0.0, 0.0, 0.0, 0.5));
+        ui.text(right - width, y, line.as_slice(), Vector4::new(1.0, 1.0, 1.0, 1.0));
+        y += ui::line_height();
+    }
+}
+
+/// Returns the camera velocity for the movement keys currently held down.
+fn movement_input(window: &glfw::Window) -> Vector3<f64> {
+    let keys = [
+        (glfw::KeyW, Vector3::new(0.0, 0.0, -1.0)),
+        (glfw::KeyS, Vector3::new(0.0, 0.0, 1.0)),
+        (glfw::KeyA, Vector3::new(-1.0, 0.0, 0.0)),
+        (glfw::KeyD, Vector3::new(1.0, 0.0, 0.0)),
+        (glfw::KeyLeftControl, Vector3::new(0.0, -1.0, 0.0)),
+        (glfw::KeySpace, Vector3::new(0.0, 1.0, 0.0)),
+    ];
+
+    let mut velocity = Vector3::zero();
+    for &(key, direction) in keys.iter() {
+        if window.get_key(key) == glfw::Press {
+            velocity.add_self_v(&direction);
+        }
+    }
+    velocity
+}
+
+/// Returns a camera position standing on the highest solid block of column
+/// (x, z), generating the chunk at the generator's surface if needed so
+/// that caves there are accounted for.
+fn surface_position(chunk_loader: &mut ChunkLoader, x: i64, z: i64) -> Vector3<f64> {
+    let estimate = chunk_loader.surface_height(x, z);
+    let c = chunk::chunk_coord(chunk_loader.wrap.block(Vector3::new(x, estimate, z)));
+    chunk_loader.load_sync(c);
+    let y = chunk_loader.surface_height(x, z);
+    clear_position(chunk_loader, x, y + 1, z)
+}
+
+/// Returns a camera position with its feet at the first block at or above
+/// (x, y, z) that has room for a player, so a teleport never ends up inside
+/// solid terrain.
+fn clear_position(chunk_loader: &mut ChunkLoader, x: i64, y: i64, z: i64) -> Vector3<f64> {
+    let mut y = y;
+    loop {
+        let feet = chunk_loader.get_block_sync(Vector3::new(x, y, z));
+        let head = chunk_loader.get_block_sync(Vector3::new(x, y + 1, z));
+        if !feet.is_solid() && !head.is_solid() {
+            return standing_position(x, y, z);
+        }
+        if y >= chunk_loader.world_height.top {
+            /* Solid all the way up; give up and stand on top of the world */
+            return standing_position(x, y, z);
+        }
+        y += 1;
+    }
+}
+
+/// Returns the mineable block the camera is looking at within REACH, if its
+/// chunk is loaded.
+fn target_block(chunk_loader: &ChunkLoader, camera: &camera::Camera) -> Option<(Vector3<i64>, terrain::BlockType)> {
+    target(chunk_loader, camera).
+        and_then(|hit| chunk_loader.get_block(hit.block).map(|blocktype| (hit.block, blocktype)))
+}
+
+fn target(chunk_loader: &ChunkLoader, camera: &camera::Camera) -> Option<raycast::RayHit> {
+    let is_solid = |p: Vector3<i64>| {
+        match chunk_loader.get_block(p) {
+            Some(blocktype) => blocktype.hardness().is_some(),
+            None => false,
+        }
+    };
+
+    raycast::raycast(camera.position, camera.direction(), REACH, is_solid)
+}
+
+/// Places the selected block against the face the camera is looking at.
+fn place_block(chunk_loader: &mut ChunkLoader,
+               entities: &mut entity::Entities,
+               camera: &camera::Camera,
+               inventory: &mut inventory::Inventory,
+               script_hooks: &hooks::ScriptHooks) {
+    let hit = match target(chunk_loader, camera) {
+        Some(hit) => hit,
+        None => return,
+    };
+
+    let p = hit.block.add_v(&hit.normal);
+    /* Don't wall the player in anywhere from the feet up to the eyes */
+    let camera_block = entity::block_containing(camera.position);
+    let feet_block = entity::block_containing(camera.position.sub_v(&Vector3::new(0.0, EYE_HEIGHT, 0.0)));
+    if p.x == camera_block.x && p.z == camera_block.z && p.y >= feet_block.y && p.y <= camera_block.y {
+        return;
+    }
+
+    match chunk_loader.get_block(p) {
+        Some(blocktype) if !blocktype.is_solid() => {},
+        _ => return,
+    }
+
+    let blocktype = match inventory.take_selected() {
+        Some(blocktype) => blocktype,
+        None => return,
+    };
+
+    chunk_loader.set_block(p, blocktype);
+    script_hooks.fire(&hooks::BlockPlaced(p, blocktype), chunk_loader, entities);
+}
+
+/// Returns the disk cache directory and size limit for ChunkLoader::new().
+fn disk_cache(world: &worlds::World, options: &Options) -> Option<(Path, u64)> {
+    if options.disk_cache_mb == 0 {
+        None
+    } else {
+        Some((world.cache_dir(), options.disk_cache_mb * 1024 * 1024))
+    }
+}
+
+/// Returns where a player new to the world starts: standing on the land
+/// nearest the origin, within the border. Nothing is loaded to find it, so
+/// it can be over a cave opening.
+fn spawn_position(chunk_loader: &ChunkLoader) -> Vector3<f64> {
+    let column = |x: i64, z: i64| {
+        let p = chunk_loader.clamp_to_border(Vector3::new(x as f64, 0.0, z as f64));
+        (p.x.floor() as i64, p.z.floor() as i64)
+    };
+    for ring in range(0, SPAWN_SEARCH_RINGS) {
+        for i in range(-ring, ring + 1) {
+            for &(dx, dz) in [(i, -ring), (i, ring), (-ring, i), (ring, i)].iter() {
+                let (x, z) = column(dx * SPAWN_SEARCH_SPACING, dz * SPAWN_SEARCH_SPACING);
+                if chunk_loader.biome_at(x, z).is_land() {
+                    return standing_position(x, chunk_loader.surface_height(x, z) + 1, z);
+                }
+            }
+        }
+    }
+    /* All ocean; float at the origin */
+    let (x, z) = column(0, 0);
+    standing_position(x, chunk_loader.surface_height(x, z).max(terrain::WATER_HEIGHT as i64) + 1, z)
+}
+
+fn standing_position(x: i64, y: i64, z: i64) -> Vector3<f64> {
+    Vector3::new(x as f64 + 0.5, y as f64 + EYE_HEIGHT, z as f64 + 0.5)
+}
+
+fn show_speed(chat: &mut chat::Chat, camera: &camera::Camera) {
+    let name = lang::name("speed", camera.speed_preset().name());
+    chat.add_line(lang::format("chat.speed", [name.to_string(), format!("{:.0}", camera.speed())]),
+                  precise_time_ns());
+}
+
+fn request_nearby_chunks(chunk_loader: &mut ChunkLoader, nearby: &mut nearby::NearbyChunks, p: Vector3<f64>) {
+    let center = chunk::containing_chunk(p);
+    chunk_loader.set_detail_center(center);
+    chunk_loader.request(nearby.around(center));
+}
+
+/// Returns chunks ahead of a fast moving camera that are outside the visible
+/// radius now but will be inside it soon, nearest to the camera's path first.
+fn predicted_chunk_coords(p: Vector3<f64>, velocity: Vector3<f64>) -> Vec<Vector3<i64>> {
+    let speed = velocity.length();
+    if speed < PREDICTION_MIN_SPEED {
+        return Vec::new();
+    }
+
+    let cur_chunk_coord = chunk::containing_chunk(p);
+    let r = VISIBLE_RADIUS as i64;
+    let pr = PREDICTION_RADIUS;
+    let v = Vector3::new(velocity.x as i64, velocity.y as i64, velocity.z as i64);
+
+    let mut offsets = Vec::new();
+    for x in range(-pr, pr+1) {
+        for y in range(-pr, pr+1) {
+            for z in range(-pr, pr+1) {
                 let c = Vector3::new(x, y, z);
-                if c.dot(&c) < r*r {
-                    coords.push(c);
+                if c.dot(&c) <= pr*pr {
+                    offsets.push(c);
                 }
             }
         }
     }
+    offsets.sort_by(|b,a| b.dot(b).cmp(&a.dot(a)));
 
-    coords.sort_by(|b,a| b.dot(b).cmp(&a.dot(a)));
+    let mut seen = HashSet::with_hasher(FnvHasher);
+    let mut coords = Vec::new();
 
-    for c in coords.mut_iter() {
-        c.add_self_v(&cur_chunk_coord);
+    /* Sample the path once per chunk travelled */
+    let steps = (speed * PREDICTION_TIME / CHUNK_SIZE as f64).ceil() as uint;
+    for i in range(1, steps + 1) {
+        let t = i as f64 * CHUNK_SIZE as f64 / speed;
+        let center = chunk::containing_chunk(p.add_v(&velocity.mul_s(t)));
+        for offset in offsets.iter() {
+            let c = center.add_v(offset);
+            let d = c.sub_v(&cur_chunk_coord);
+            /* Skip chunks already in the visible sphere or behind the camera */
+            if d.dot(&d) < r*r || d.dot(&v) <= 0 {
+                continue;
+            }
+            if seen.insert(ChunkCoord::from_vec(c)) {
+                coords.push(c);
+            }
+        }
     }
 
     coords
 }
 
-fn find_nearby_chunks<'a>(chunk_loader: &'a ChunkLoader, p: Vector3<f64>) -> Vec<&'a Box<Chunk>> {
-    let coords = nearby_chunk_coords(p);
-    coords.iter().
-        filter_map(|&c| chunk_loader.get(c)).
-        collect()
+fn load_permissions(world: &worlds::World) -> permissions::Permissions {
+    match permissions::Permissions::load(world.permissions_path()) {
+        Ok(permissions) => permissions,
+        Err(msg) => {
+            println!("Error loading permissions: {}", msg);
+            permissions::Permissions::defaults()
+        },
+    }
 }
 
-fn request_nearby_chunks(chunk_loader: &mut ChunkLoader, p: Vector3<f64>) {
-    let coords = nearby_chunk_coords(p);
-    chunk_loader.request(coords.slice(0, coords.len()));
+/// Reads the block types defined in the config directory.
+fn load_block_defs() -> Vec<blocks::BlockInfo> {
+    blockdefs::load_dirs([platformpaths::config_dir().join("blocks")])
 }
 
-extern "C" {
-    fn gluErrorString(error: GLenum) -> *const GLubyte;
+/// Loads hooks.txt from the config directory, or the example in scripts/
+/// if there isn't one.
+fn load_hooks() -> hooks::ScriptHooks {
+    let path = platformpaths::config_dir().join("hooks.txt");
+    let path = if path.exists() { path } else { Path::new("scripts/hooks.txt") };
+    match hooks::ScriptHooks::load(&path) {
+        Ok(hooks) => hooks,
+        Err(msg) => {
+            println!("Error loading script hooks: {}", msg);
+            hooks::ScriptHooks::empty()
+        },
+    }
 }
 
 fn check_gl(message : &str) {
     let err = gl::GetError();
     if err != gl::NO_ERROR {
-        unsafe {
-            let err = std::str::raw::from_c_str(gluErrorString(err) as *const i8);
-            fail!("GL error {} at {}", err, message);
-        }
+        fail!("GL error {} at {}", gldebug::error_name(err), message);
     }
 }
@@ -0,0 +1,161 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Offline export of top-down map tiles of a seed's terrain, one PNG per
+//! region column, for building a zoomable map outside the game.
+//!
+//! Each pixel is a block column, colored by its topmost block and shaded by
+//! its slope toward the northwest. Tiles are rendered headless, spread over
+//! one task per scheduler thread like the chunk loader's workers. Only
+//! generated terrain is drawn: saved edits and decorations aren't.
+
+extern crate cgmath;
+
+use std::io::File;
+use std::io::fs;
+use std::io;
+use std::rt::default_sched_threads;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vector3;
+
+use CHUNK_SIZE;
+use png;
+use region::REGION_SIZE;
+use terrain::{BlockType, BlockAir, TerrainGenerator, TerrainNoise};
+use worldheight::WorldHeight;
+
+/// Blocks along each side of a tile.
+static TILE_SIZE : i64 = REGION_SIZE * CHUNK_SIZE as i64;
+/// Brightness change per block of height difference from the northwest.
+static SLOPE_SHADE : f32 = 0.15;
+
+/// Writes the tiles within radius regions of the origin along x and z to
+/// dir as tile_X_Z.png, returning how many were written.
+pub fn export(seed: u32, period: Option<i64>, world_height: WorldHeight, noise: &TerrainNoise,
+              stride: uint, radius: i64, dir: &Path) -> Result<uint, String> {
+    match fs::mkdir_recursive(dir, io::UserDir) {
+        Ok(()) => {},
+        Err(e) => return Err(format!("{}: {}", dir.display(), e)),
+    }
+
+    let mut tiles = Vec::new();
+    for x in range(-radius, radius + 1) {
+        for z in range(-radius, radius + 1) {
+            tiles.push((x, z));
+        }
+    }
+
+    let workers = default_sched_threads();
+    let (tx, rx) = channel();
+    for w in range(0, workers) {
+        let mine: Vec<(i64, i64)> = tiles.iter().enumerate().
+            filter(|&(i, _)| i % workers == w).
+            map(|(_, &tile)| tile).
+            collect();
+        let tx = tx.clone();
+        let dir = dir.clone();
+        let noise = noise.clone();
+        spawn(proc() {
+            let generator = TerrainGenerator::new(seed, period, world_height, &noise);
+            for &(x, z) in mine.iter() {
+                let path = dir.join(format!("tile_{}_{}.png", x, z));
+                let data = render_tile(&generator, world_height, stride, x, z);
+                let result = File::create(&path).write(data.as_slice()).
+                    map_err(|e| format!("{}: {}", path.display(), e));
+                tx.send(result);
+            }
+        });
+    }
+
+    let mut error = None;
+    for i in range(0, tiles.len()) {
+        match rx.recv() {
+            Ok(()) => println!("map: {}/{} tiles", i + 1, tiles.len()),
+            Err(e) => if error.is_none() { error = Some(e) },
+        }
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(tiles.len()),
+    }
+}
+
+/// Renders the tile for the region column at (x, z) as a PNG.
+fn render_tile(generator: &TerrainGenerator, world_height: WorldHeight, stride: uint,
+               x: i64, z: i64) -> Vec<u8> {
+    let size = TILE_SIZE as uint;
+    let chunk_size = CHUNK_SIZE as uint;
+    /* Chunk layers searched for each column's topmost block, from the top */
+    let (bottom_chunk, top_chunk) = world_height.chunk_range();
+
+    /* Height and blocktype of the topmost block of each column, by row */
+    let mut surface: Vec<Option<(i64, BlockType)>> = Vec::from_elem(size * size, None);
+    for cx in range(0, REGION_SIZE) {
+        for cz in range(0, REGION_SIZE) {
+            let c = Vector3::new(x * REGION_SIZE + cx, 0, z * REGION_SIZE + cz);
+            let mut remaining = chunk_size * chunk_size;
+            let mut cy = top_chunk;
+            while remaining > 0 && cy >= bottom_chunk {
+                let p = Vector3::new(c.x as f64, cy as f64, c.z as f64).mul_s(CHUNK_SIZE as f64);
+                let terrain = generator.gen(p, stride);
+                for bx in range(0, chunk_size) {
+                    for bz in range(0, chunk_size) {
+                        let i = (cz as uint * chunk_size + bz) * size + cx as uint * chunk_size + bx;
+                        if surface[i].is_some() {
+                            continue;
+                        }
+                        for by in range(0, CHUNK_SIZE).rev() {
+                            let blocktype = terrain.get(bx as int, by, bz as int).blocktype;
+                            if blocktype != BlockAir {
+                                *surface.get_mut(i) = Some((cy * CHUNK_SIZE as i64 + by as i64, blocktype));
+                                remaining -= 1;
+                                break;
+                            }
+                        }
+                    }
+                }
+                cy -= 1;
+            }
+        }
+    }
+
+    let mut rgb = Vec::with_capacity(size * size * 3);
+    for row in range(0, size) {
+        for col in range(0, size) {
+            let shaded = match surface[row * size + col] {
+                Some((height, blocktype)) => {
+                    let northwest = if row > 0 && col > 0 {
+                        match surface[(row - 1) * size + col - 1] {
+                            Some((h, _)) => h,
+                            None => height,
+                        }
+                    } else {
+                        height
+                    };
+                    let shade = (1.0 + (height - northwest) as f32 * SLOPE_SHADE).max(0.6).min(1.4);
+                    let color = blocktype.color();
+                    Vector3::new(color.x, color.y, color.z).mul_s(shade)
+                },
+                None => Vector3::new(0.0, 0.0, 0.0),
+            };
+            rgb.push((shaded.x.min(1.0) * 255.0) as u8);
+            rgb.push((shaded.y.min(1.0) * 255.0) as u8);
+            rgb.push((shaded.z.min(1.0) * 255.0) as u8);
+        }
+    }
+
+    png::encode(size as u32, size as u32, rgb.as_slice())
+}
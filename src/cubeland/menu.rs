@@ -0,0 +1,255 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What the main loop is doing, and the pause menu drawn with the UI
+//! overlay.
+//!
+//! Escape goes from playing to the pause menu and back, and from the
+//! settings page to the pause menu. The world stands still while a menu is
+//! open or the camera's chunk is loading, but not while chat is open.
+
+extern crate glfw;
+extern crate cgmath;
+
+use std::cmp;
+
+use cgmath::vector::{Vector2, Vector4};
+
+use lang;
+use ui;
+use ui::Ui;
+
+static ROW_HEIGHT : f32 = 40.0;
+static ITEM_WIDTH : f32 = 320.0;
+
+#[deriving(PartialEq, Eq, Show)]
+pub enum GameState {
+    /// Waiting for the camera's chunk, e.g. after switching worlds.
+    StateLoading,
+    StatePlaying,
+    /// The pause menu is open.
+    StatePaused,
+    /// The chat input line is open.
+    StateConsole,
+    /// The settings page of the pause menu is open.
+    StateMenu,
+}
+
+impl GameState {
+    /// Whether blocks, entities and weather are updated.
+    pub fn running(&self) -> bool {
+        match *self {
+            StatePlaying | StateConsole => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the cursor is released for a menu.
+    pub fn in_menu(&self) -> bool {
+        match *self {
+            StatePaused | StateMenu => true,
+            _ => false,
+        }
+    }
+
+    /// The state Escape leads to. Chat handles Escape itself. Loading
+    /// pauses too, so a chunk that never arrives doesn't lock the player out
+    /// of the menu.
+    pub fn escape(&self) -> GameState {
+        match *self {
+            StatePlaying | StateLoading => StatePaused,
+            StatePaused => StatePlaying,
+            StateMenu => StatePaused,
+            state => state,
+        }
+    }
+}
+
+/// Settings that can be changed from the menu. Their values live with
+/// whatever they control. Choosing one turns it on or off, or moves it on
+/// to its next value.
+#[deriving(PartialEq, Eq, Show)]
+pub enum Setting {
+    SettingCompass,
+    SettingBlockInfo,
+    SettingHorizon,
+    SettingWireframe,
+    SettingAnisotropy,
+    SettingColorScheme,
+    SettingInvertY,
+}
+
+impl Setting {
+    pub fn name(&self) -> &'static str {
+        lang::text(match *self {
+            SettingCompass => "setting.compass",
+            SettingBlockInfo => "setting.block_info",
+            SettingHorizon => "setting.horizon",
+            SettingWireframe => "setting.wireframe",
+            SettingAnisotropy => "setting.anisotropy",
+            SettingColorScheme => "setting.colors",
+            SettingInvertY => "setting.invert_y",
+        })
+    }
+}
+
+#[deriving(PartialEq, Eq, Show)]
+pub enum MenuItem {
+    ItemResume,
+    ItemSettings,
+    ItemSaveAndQuit,
+    ItemToggle(Setting),
+    /// Back to the pause menu.
+    ItemBack,
+}
+
+static PAUSE_ITEMS : [MenuItem, ..3] = [ItemResume, ItemSettings, ItemSaveAndQuit];
+static SETTINGS_ITEMS : [MenuItem, ..8] = [
+    ItemToggle(SettingCompass),
+    ItemToggle(SettingBlockInfo),
+    ItemToggle(SettingHorizon),
+    ItemToggle(SettingWireframe),
+    ItemToggle(SettingAnisotropy),
+    ItemToggle(SettingColorScheme),
+    ItemToggle(SettingInvertY),
+    ItemBack,
+];
+
+/// The items on the page shown in state.
+fn items(state: GameState) -> &'static [MenuItem] {
+    match state {
+        StateMenu => SETTINGS_ITEMS.as_slice(),
+        _ => PAUSE_ITEMS.as_slice(),
+    }
+}
+
+pub struct Menu {
+    selected: uint,
+}
+
+impl Menu {
+    pub fn new() -> Menu {
+        Menu { selected: 0 }
+    }
+
+    /// Selects the first item, for a page that was just opened.
+    pub fn reset(&mut self) {
+        self.selected = 0;
+    }
+
+    /// Handles a key press on the page shown in state. Returns the item
+    /// chosen with Enter.
+    pub fn key(&mut self, state: GameState, key: glfw::Key) -> Option<MenuItem> {
+        let items = items(state);
+        match key {
+            glfw::KeyUp => self.selected = (self.selected + items.len() - 1) % items.len(),
+            glfw::KeyDown => self.selected = (self.selected + 1) % items.len(),
+            glfw::KeyEnter => return Some(items[cmp::min(self.selected, items.len() - 1)]),
+            _ => {},
+        }
+        None
+    }
+
+    /// Selects the item under the cursor, in UI pixels, if any.
+    pub fn hover(&mut self, state: GameState, window_size: Vector2<u32>, cursor: Vector2<f32>) {
+        match item_at(state, window_size, cursor) {
+            Some(i) => self.selected = i,
+            None => {},
+        }
+    }
+
+    /// Returns the item clicked on, if any.
+    pub fn click(&mut self, state: GameState, window_size: Vector2<u32>, cursor: Vector2<f32>) -> Option<MenuItem> {
+        item_at(state, window_size, cursor).map(|i| {
+            self.selected = i;
+            items(state)[i]
+        })
+    }
+
+    /// Queues the page shown in state over a dimmed view. value gives the
+    /// current value of each setting, as shown after its name.
+    pub fn draw(&self, ui: &mut Ui, state: GameState, value: |Setting| -> &'static str) {
+        let window_size = ui.window_size();
+        ui.rect(0.0, 0.0, window_size.x as f32, window_size.y as f32, Vector4::new(0.0, 0.0, 0.0, 0.5));
+
+        let title = lang::text(if state == StateMenu { "menu.settings" } else { "menu.paused" });
+        let (x, top) = layout(state, window_size);
+        ui.text((window_size.x as f32 - ui::text_width(title)) / 2.0, top - ROW_HEIGHT, title,
+                Vector4::new(1.0, 1.0, 1.0, 1.0));
+
+        for (i, item) in items(state).iter().enumerate() {
+            let label = match *item {
+                ItemResume => lang::text("menu.resume").to_string(),
+                ItemSettings => lang::text("menu.settings").to_string(),
+                ItemSaveAndQuit => lang::text("menu.save_and_quit").to_string(),
+                ItemToggle(setting) => lang::format("menu.setting", [setting.name().to_string(),
+                                                                     value(setting).to_string()]),
+                ItemBack => lang::text("menu.back").to_string(),
+            };
+            let y = top + i as f32 * ROW_HEIGHT;
+            let background = if i == self.selected {
+                Vector4::new(1.0, 1.0, 1.0, 0.5)
+            } else {
+                Vector4::new(0.0, 0.0, 0.0, 0.5)
+            };
+            ui.rect(x, y, ITEM_WIDTH, ROW_HEIGHT - 4.0, background);
+            ui.text(x + (ITEM_WIDTH - ui::text_width(label.as_slice())) / 2.0,
+                    y + (ROW_HEIGHT - 4.0 - ui::line_height()) / 2.0,
+                    label.as_slice(), Vector4::new(1.0, 1.0, 1.0, 1.0));
+        }
+    }
+}
+
+/// The value shown for a setting that's on or off.
+pub fn on_off(on: bool) -> &'static str {
+    lang::text(if on { "menu.on" } else { "menu.off" })
+}
+
+/// Left edge and top of the items, centered in the window.
+fn layout(state: GameState, window_size: Vector2<u32>) -> (f32, f32) {
+    let height = items(state).len() as f32 * ROW_HEIGHT;
+    ((window_size.x as f32 - ITEM_WIDTH) / 2.0, (window_size.y as f32 - height) / 2.0)
+}
+
+fn item_at(state: GameState, window_size: Vector2<u32>, cursor: Vector2<f32>) -> Option<uint> {
+    let (x, top) = layout(state, window_size);
+    if cursor.x < x || cursor.x >= x + ITEM_WIDTH || cursor.y < top {
+        return None;
+    }
+    let i = ((cursor.y - top) / ROW_HEIGHT) as uint;
+    if i < items(state).len() { Some(i) } else { None }
+}
+
+#[test]
+fn test_menu() {
+    assert_eq!(StatePlaying.escape(), StatePaused);
+    assert_eq!(StateMenu.escape(), StatePaused);
+    assert_eq!(StatePaused.escape(), StatePlaying);
+    assert_eq!(StateLoading.escape(), StatePaused);
+    assert!(StateConsole.running() && !StatePaused.running() && !StateLoading.running());
+
+    let mut menu = Menu::new();
+    assert_eq!(menu.key(StatePaused, glfw::KeyEnter), Some(ItemResume));
+    menu.key(StatePaused, glfw::KeyUp);
+    assert_eq!(menu.key(StatePaused, glfw::KeyEnter), Some(ItemSaveAndQuit));
+    menu.reset();
+    menu.key(StateMenu, glfw::KeyDown);
+    assert_eq!(menu.key(StateMenu, glfw::KeyEnter), Some(ItemToggle(SettingBlockInfo)));
+
+    let window_size = Vector2::new(800u32, 600);
+    let (x, top) = layout(StateMenu, window_size);
+    assert_eq!(menu.click(StateMenu, window_size, Vector2::new(x + 1.0, top + ROW_HEIGHT * 7.5)), Some(ItemBack));
+    assert_eq!(menu.click(StateMenu, window_size, Vector2::new(x - 1.0, top)), None);
+    assert_eq!(menu.click(StateMenu, window_size, Vector2::new(x + 1.0, top - 1.0)), None);
+}
@@ -17,25 +17,39 @@ extern crate hgl;
 
 use std;
 
-use collections::bitv::BitvSet;
-
 use gl::types::*;
 
 use cgmath::vector::Vector;
 use cgmath::vector::Vector3;
 
 use CHUNK_SIZE;
-use terrain::Terrain;
+use CHUNK_SIZEu;
+use blocks;
+use terrain::{Block, BlockHistogram, Terrain};
 use terrain::BlockAir;
+use voxeltexture::VoxelTexture;
+#[cfg(test)]
+use terrain::{BlockType, BlockStone, BlockDirt, BlockWater, BlockSnow};
 
 static NUM_FACES : uint = 6;
 
 // Layout of the vertex buffer sent to the GPU
+#[deriving(Clone)]
 pub struct VertexData {
     pub position : Vector3<f32>,
     pub blocktype : f32,
 }
 
+/// Layout of the translucent vertex buffer. Translucent quads are sorted
+/// back to front rather than grouped by face, so each vertex carries the
+/// index of its face.
+#[deriving(Clone)]
+pub struct TranslucentVertexData {
+    pub position : Vector3<f32>,
+    pub blocktype : f32,
+    pub face : f32,
+}
+
 pub struct Face {
     pub index: uint,
     pub normal: Vector3<f32>,
@@ -51,30 +65,93 @@ pub struct Mesh {
     pub vertices: Vec<VertexData>,
     pub elements: Vec<GLuint>,
     pub face_ranges: [(uint, uint), ..NUM_FACES],
+    pub translucent_buffer: Option<hgl::Vbo>,
+    pub translucent_element_buffer: Option<hgl::Ebo>,
+    pub translucent_vertices: Vec<TranslucentVertexData>,
+    /// Center of each translucent quad, kept after finish() for sorting.
+    translucent_centers: Vec<Vector3<f32>>,
+    /// Chunk-relative eye position the translucent quads were last sorted
+    /// for.
+    pub sorted_for: Option<Vector3<f32>>,
+    /// The terrain as a 3D texture, when voxel textures are enabled.
+    pub voxels: Option<VoxelTexture>,
 }
 
-impl Mesh {
-    pub fn gen(t: &Terrain) -> Box<Mesh> {
-        let mut vertices : Vec<VertexData> = Vec::new();
-        let mut elements : Vec<GLuint> = Vec::new();
+/// Returns the range of block coordinates along an axis to search for faces
+/// pointing along normal. In a chunk that hides its interior, such as an
+/// all-opaque one, faces can only be on the outermost layer.
+fn search_range(normal: int, hides_interior: bool) -> (int, int) {
+    if !hides_interior || normal == 0 {
+        (0, CHUNK_SIZE as int)
+    } else if normal > 0 {
+        (CHUNK_SIZE as int - 1, CHUNK_SIZE as int)
+    } else {
+        (0, 1)
+    }
+}
 
-        static expected_vertices : uint = 8000;
-        static expected_elements : uint = expected_vertices * 3 / 2;
-        vertices.reserve(expected_vertices);
-        elements.reserve(expected_elements);
+/// Scratch space for building meshes. Each task that meshes chunks keeps
+/// one, so the storage is reused from chunk to chunk instead of allocated
+/// afresh for each.
+pub struct MeshBuffers {
+    vertices: Vec<VertexData>,
+    elements: Vec<GLuint>,
+    translucent_vertices: Vec<TranslucentVertexData>,
+    translucent_centers: Vec<Vector3<f32>>,
+    /// Faces that still need a quad, by blocktype, in face-relative
+    /// coordinates: column (i, j) has bit k for the block at
+    /// di*i + dj*j + dk*k. Meshing removes every face that gets inserted,
+    /// so the bitmaps are empty between passes.
+    unmeshed_faces: Vec<BlockBitmap>,
+}
+
+impl MeshBuffers {
+    pub fn new() -> MeshBuffers {
+        MeshBuffers {
+            vertices: Vec::new(),
+            elements: Vec::new(),
+            translucent_vertices: Vec::new(),
+            translucent_centers: Vec::new(),
+            unmeshed_faces: Vec::from_fn(blocks::registry().len(), |_| BlockBitmap::new()),
+        }
+    }
+}
+
+impl Mesh {
+    /// histogram must match t. It lets chunks that are all air, all opaque
+    /// or all water skip most of the search for faces. The mesh is built in
+    /// buffers, and gets copies of just the parts that were used.
+    pub fn gen(t: &Terrain, histogram: &BlockHistogram, buffers: &mut MeshBuffers) -> Box<Mesh> {
+        let vertices = &mut buffers.vertices;
+        let elements = &mut buffers.elements;
+        let translucent_vertices = &mut buffers.translucent_vertices;
+        let translucent_centers = &mut buffers.translucent_centers;
+        let unmeshed_faces = &mut buffers.unmeshed_faces;
+        vertices.clear();
+        elements.clear();
+        translucent_vertices.clear();
+        translucent_centers.clear();
 
         let mut face_ranges = [(0, 0), ..6];
 
+        let empty = histogram.is_empty();
+        let hides_interior = histogram.hides_interior();
+
         for face in faces.iter() {
             let num_elements_start = elements.len();
+            if empty {
+                continue;
+            }
 
             let face_normal_int = Vector3 { x: face.normal.x as int, y: face.normal.y as int, z: face.normal.z as int };
+            let (x_start, x_end) = search_range(face_normal_int.x, hides_interior);
+            let (y_start, y_end) = search_range(face_normal_int.y, hides_interior);
+            let (z_start, z_end) = search_range(face_normal_int.z, hides_interior);
 
-            let mut unmeshed_faces = BlockBitmap::new();
-            for x in std::iter::range(0, CHUNK_SIZE as int) {
-                for y in std::iter::range(0, CHUNK_SIZE as int) {
-                    for z in std::iter::range(0, CHUNK_SIZE as int) {
-                        let block = &t.get(x, y, z);
+            for x in std::iter::range(x_start, x_end) {
+                for y in std::iter::range(y_start, y_end) {
+                    for z in std::iter::range(z_start, z_end) {
+                        let block = t.get(x, y, z);
 
                         if block.blocktype == BlockAir {
                             continue;
@@ -85,52 +162,74 @@ impl Mesh {
                             y + face_normal_int.y,
                             z + face_normal_int.z);
 
-                        if neighbor.is_opaque() {
+                        if face_hidden(block, neighbor, face) {
                             continue;
                         }
 
-                        unmeshed_faces.insert(x, y, z);
+                        let p = Vector3::new(x, y, z);
+                        unmeshed_faces.get_mut(block.blocktype.to_u8() as uint).insert(
+                            p.dot(&face.di), p.dot(&face.dj), p.dot(&face.dk));
                     }
                 }
             }
 
-            for i in std::iter::range(0, CHUNK_SIZE as int) {
-                for j in std::iter::range(0, CHUNK_SIZE as int) {
-                    for k in std::iter::range(0, CHUNK_SIZE as int) {
-                        let Vector3 { x: x, y: y, z: z } = face.di.mul_s(i).add_v(&face.dj.mul_s(j)).add_v(&face.dk.mul_s(k));
-                        let block = &t.get(x, y, z);
-
-                        if !unmeshed_faces.contains(x, y, z) {
-                            continue;
-                        }
-
-                        let block_position = Vector3 {
-                            x: x as f32,
-                            y: y as f32,
-                            z: z as f32,
-                        };
-
-                        let dim = expand_face(t, &unmeshed_faces, face, Vector3 { x: x, y: y, z: z });
-                        let dim_f = Vector3 { x: dim.x as f32, y: dim.y as f32, z: dim.z as f32 };
-
-                        for dx in range(0, dim.x) {
-                            for dy in range(0, dim.y) {
-                                for dz in range(0, dim.z) {
-                                    unmeshed_faces.remove(x + dx, y + dy, z + dz);
+            for &blocktype in blocks::registry().types().iter() {
+                if blocktype == BlockAir || histogram.count(blocktype) == 0 {
+                    continue;
+                }
+                let bitmap = unmeshed_faces.get_mut(blocktype.to_u8() as uint);
+
+                for i in std::iter::range(0, CHUNK_SIZE as int) {
+                    for j in std::iter::range(0, CHUNK_SIZE as int) {
+                        /* Lowest unmeshed face left in the column, if any */
+                        while bitmap.column(i, j) != 0 {
+                            let k = bitmap.column(i, j).trailing_zeros() as int;
+                            /* The sides of blocks shorter than a cell can't
+                               join the ones above them */
+                            let (max_j, max_k) = if blocktype.height() < 1.0 {
+                                (if face.dj.y != 0 { 1 } else { CHUNK_SIZE }, if face.dk.y != 0 { 1 } else { CHUNK_SIZE })
+                            } else {
+                                (CHUNK_SIZE, CHUNK_SIZE)
+                            };
+                            let (len_j, len_k) = expand_face(bitmap, i, j, k, max_j, max_k);
+
+                            let Vector3 { x: x, y: y, z: z } = face.di.mul_s(i).add_v(&face.dj.mul_s(j)).add_v(&face.dk.mul_s(k));
+                            let block_position = Vector3 {
+                                x: x as f32,
+                                y: y as f32,
+                                z: z as f32,
+                            };
+
+                            let dim = (Vector3 { x: 1, y: 1, z: 1 }).
+                                add_v(&face.dk.mul_s(len_k - 1)).
+                                add_v(&face.dj.mul_s(len_j - 1));
+                            /* Quads of short blocks are one cell high, cut down to the block */
+                            let dim_f = Vector3 { x: dim.x as f32, y: dim.y as f32 * blocktype.height(), z: dim.z as f32 };
+
+                            if blocktype.is_translucent() {
+                                for v in face.vertices.iter() {
+                                    translucent_vertices.push(TranslucentVertexData {
+                                        position: v.mul_v(&dim_f).add_v(&block_position),
+                                        blocktype: blocktype.to_u8() as f32,
+                                        face: face.index as f32,
+                                    });
                                 }
+                                let quad = translucent_vertices.slice_from(translucent_vertices.len() - 4);
+                                translucent_centers.push(quad[0].position.add_v(&quad[3].position).mul_s(0.5));
+                                continue;
                             }
-                        }
 
-                        let vertex_offset = vertices.len();
-                        for v in face.vertices.iter() {
-                            vertices.push(VertexData {
-                                position: v.mul_v(&dim_f).add_v(&block_position),
-                                blocktype: block.blocktype as u8 as f32,
-                            });
-                        }
+                            let vertex_offset = vertices.len();
+                            for v in face.vertices.iter() {
+                                vertices.push(VertexData {
+                                    position: v.mul_v(&dim_f).add_v(&block_position),
+                                    blocktype: blocktype.to_u8() as f32,
+                                });
+                            }
 
-                        for e in face_elements.iter() {
-                            elements.push(vertex_offset as GLuint + *e);
+                            for e in face_elements.iter() {
+                                elements.push(vertex_offset as GLuint + *e);
+                            }
                         }
                     }
                 }
@@ -142,90 +241,152 @@ impl Mesh {
         box Mesh {
             vertex_buffer: None,
             element_buffer: None,
-            vertices: vertices,
-            elements: elements,
+            vertices: vertices.clone(),
+            elements: elements.clone(),
             face_ranges: face_ranges,
+            translucent_buffer: None,
+            translucent_element_buffer: None,
+            translucent_vertices: translucent_vertices.clone(),
+            translucent_centers: translucent_centers.clone(),
+            sorted_for: None,
+            voxels: None,
         }
     }
 
-    pub fn finish(&mut self) {
+    pub fn has_translucent(&self) -> bool {
+        !self.translucent_centers.is_empty()
+    }
+
+    pub fn translucent_element_count(&self) -> uint {
+        self.translucent_centers.len() * face_elements.len()
+    }
+
+    /// Orders the translucent quads farthest first from eye, which is
+    /// relative to the chunk, and uploads the new order.
+    pub fn sort_translucent(&mut self, eye: Vector3<f32>) {
+        if !self.has_translucent() {
+            return;
+        }
+
+        let mut order: Vec<(f32, uint)> = self.translucent_centers.iter().enumerate().map(|(i, c)| {
+            let d = c.sub_v(&eye);
+            (d.dot(&d), i)
+        }).collect();
+        order.sort_by(|&(a, _), &(b, _)| b.partial_cmp(&a).unwrap_or(Equal));
+
+        let mut elements = Vec::with_capacity(order.len() * face_elements.len());
+        for &(_, i) in order.iter() {
+            for e in face_elements.iter() {
+                elements.push((i * 4) as GLuint + *e);
+            }
+        }
+
+        self.translucent_element_buffer = Some(hgl::Ebo::from_indices(elements.as_slice()));
+        self.sorted_for = Some(eye);
+    }
+
+    /// Uploads the mesh to GL. Returns the number of buffers uploaded.
+    pub fn finish(&mut self) -> uint {
+        let mut uploads = 0;
         if !self.elements.is_empty() {
             self.vertex_buffer = Some(hgl::Vbo::from_data(self.vertices.slice(0, self.vertices.len()), hgl::StaticDraw));
             self.element_buffer = Some(hgl::Ebo::from_indices(self.elements.slice(0, self.elements.len())));
+            uploads += 2;
         }
 
-        self.vertices.clear();
-        self.elements.clear();
+        if !self.translucent_vertices.is_empty() {
+            self.translucent_buffer = Some(hgl::Vbo::from_data(self.translucent_vertices.as_slice(), hgl::StaticDraw));
+            /* Until the first sort_translucent() */
+            let mut unsorted = Vec::with_capacity(self.translucent_centers.len() * face_elements.len());
+            for i in range(0, self.translucent_centers.len()) {
+                for e in face_elements.iter() {
+                    unsorted.push((i * 4) as GLuint + *e);
+                }
+            }
+            self.translucent_element_buffer = Some(hgl::Ebo::from_indices(unsorted.as_slice()));
+            uploads += 2;
+        }
+
+        /* Uploaded, so only the GPU's copy is needed */
+        self.vertices = Vec::new();
+        self.elements = Vec::new();
+        self.translucent_vertices = Vec::new();
+        uploads
     }
 }
 
-fn expand_face(t : &Terrain,
-               unmeshed_faces : &BlockBitmap,
-               face: &Face,
-               p: Vector3<int>) -> Vector3<int> {
-
-    let len_k = run_length(t, unmeshed_faces, p, face.dk);
-    let len_j = range(0, len_k).
-        map(|k| run_length(t, unmeshed_faces, p.add_v(&face.dk.mul_s(k)), face.dj)).
-        min().unwrap();
-
-    (Vector3 { x: 1, y: 1, z: 1 }).
-        add_v(&face.dk.mul_s(len_k - 1)).
-        add_v(&face.dj.mul_s(len_j - 1))
+/// Whether the face of block toward neighbor is hidden: behind an opaque
+/// block, between two blocks of water, or between two short blocks side by
+/// side.
+fn face_hidden(block: Block, neighbor: Block, face: &Face) -> bool {
+    if neighbor.is_opaque() {
+        return true;
+    }
+    if neighbor.blocktype != block.blocktype {
+        return false;
+    }
+    block.blocktype.is_translucent() || (block.blocktype.height() < 1.0 && face.normal.y == 0.0)
 }
 
-fn run_length(t : &Terrain,
-              unmeshed_faces : &BlockBitmap,
-              mut p: Vector3<int>,
-              dp: Vector3<int>) -> int {
-    let block = &t.get(p.x, p.y, p.z);
-    let max_len = Vector3::new(CHUNK_SIZE as int, CHUNK_SIZE as int, CHUNK_SIZE as int).sub_v(&p).dot(&dp);
-
-    let mut len = 1;
-
-    while len < max_len {
-        p.add_self_v(&dp);
+/// Grows a quad from the unmeshed face at (i, j, k), first along k as far
+/// as the column's run of faces goes, then along j for as many columns as
+/// have that whole run unmeshed too, up to max_j and max_k long. Removes
+/// the faces it covers and returns the quad's length along j and k.
+fn expand_face(unmeshed_faces : &mut BlockBitmap, i: int, j: int, k: int, max_j: int, max_k: int) -> (int, int) {
+    let len_k = unmeshed_faces.run(i, j, k).min(max_k);
+    let span = span_mask(k, len_k);
+
+    let mut len_j = 1;
+    while len_j < max_j && j + len_j < CHUNK_SIZE as int && unmeshed_faces.column(i, j + len_j) & span == span {
+        len_j += 1;
+    }
 
-        if unmeshed_faces.contains(p.x, p.y, p.z) {
-            let b = t.get(p.x, p.y, p.z);
-            if b.blocktype == block.blocktype {
-                len += 1;
-            } else {
-                break;
-            }
-        } else {
-            break;
-        }
+    for dj in range(0, len_j) {
+        unmeshed_faces.clear(i, j + dj, span);
     }
 
-    len
+    (len_j, len_k)
+}
+
+/// Mask of len bits starting at bit k.
+fn span_mask(k: int, len: int) -> u64 {
+    let bits = if len >= 64 { !0u64 } else { (1u64 << len as uint) - 1 };
+    bits << k as uint
 }
 
+/// One bit per block of a chunk, as a mask per column along k with bit k for
+/// the block at k. A u64 holds a column of any chunk size up to 64.
 struct BlockBitmap {
-    set : BitvSet
+    columns : [u64, ..CHUNK_SIZEu*CHUNK_SIZEu],
 }
 
 impl BlockBitmap {
     pub fn new() -> BlockBitmap {
         BlockBitmap {
-            set: BitvSet::new()
+            columns: [0, ..CHUNK_SIZEu*CHUNK_SIZEu],
         }
     }
 
-    pub fn contains(&self, x: int, y: int, z: int) -> bool {
-        self.set.contains(&BlockBitmap::index(x, y, z))
+    pub fn column(&self, i: int, j: int) -> u64 {
+        self.columns[BlockBitmap::index(i, j)]
     }
 
-    pub fn insert(&mut self, x: int, y: int, z: int) {
-        self.set.insert(BlockBitmap::index(x, y, z));
+    pub fn insert(&mut self, i: int, j: int, k: int) {
+        self.columns[BlockBitmap::index(i, j)] |= 1 << k as uint;
     }
 
-    pub fn remove(&mut self, x: int, y: int, z: int) {
-        self.set.remove(&BlockBitmap::index(x, y, z));
+    /// Removes the bits of mask from column (i, j).
+    pub fn clear(&mut self, i: int, j: int, mask: u64) {
+        self.columns[BlockBitmap::index(i, j)] &= !mask;
     }
 
-    fn index(x: int, y: int, z: int) -> uint {
-        (x*CHUNK_SIZE as int*CHUNK_SIZE as int + y*CHUNK_SIZE as int + z) as uint
+    /// Number of consecutive bits set in the column from k up.
+    pub fn run(&self, i: int, j: int, k: int) -> int {
+        (!(self.column(i, j) >> k as uint)).trailing_zeros() as int
+    }
+
+    fn index(i: int, j: int) -> uint {
+        (i*CHUNK_SIZE as int + j) as uint
     }
 }
 
@@ -324,3 +485,301 @@ pub static faces : [Face, ..NUM_FACES] = [
         ],
     },
 ];
+
+/// Builds a chunk with the given blocks, which may be in the border, and
+/// air everywhere else.
+#[cfg(test)]
+fn test_terrain(blocks: &[(Vector3<int>, BlockType)]) -> Box<Terrain> {
+    let mut t = Terrain::new();
+    for &(p, blocktype) in blocks.iter() {
+        t.set(p.x, p.y, p.z, blocktype);
+    }
+    t
+}
+
+/// Meshes t and returns the face index and opposite corners of each quad.
+#[cfg(test)]
+fn test_quads(t: &Terrain) -> Vec<(uint, Vector3<f32>, Vector3<f32>)> {
+    let mesh = Mesh::gen(t, &BlockHistogram::of(t), &mut MeshBuffers::new());
+    let corners = |vertices: &[Vector3<f32>]| {
+        let min = vertices.iter().fold(vertices[0], |a, v| Vector3::new(a.x.min(v.x), a.y.min(v.y), a.z.min(v.z)));
+        let max = vertices.iter().fold(vertices[0], |a, v| Vector3::new(a.x.max(v.x), a.y.max(v.y), a.z.max(v.z)));
+        (min, max)
+    };
+
+    let mut quads = Vec::new();
+    for face in faces.iter() {
+        let (start, count) = mesh.face_ranges[face.index];
+        for q in range(0, count / face_elements.len()) {
+            let base = mesh.elements[start + q * face_elements.len()] as uint;
+            let vertices: Vec<Vector3<f32>> = mesh.vertices.slice(base, base + 4).iter().map(|v| v.position).collect();
+            let (min, max) = corners(vertices.as_slice());
+            quads.push((face.index, min, max));
+        }
+    }
+    for quad in mesh.translucent_vertices.as_slice().chunks(4) {
+        let vertices: Vec<Vector3<f32>> = quad.iter().map(|v| v.position).collect();
+        let (min, max) = corners(vertices.as_slice());
+        quads.push((quad[0].face as uint, min, max));
+    }
+    quads
+}
+
+/// Block faces of t that should be drawn, as face index and block.
+#[cfg(test)]
+fn test_exposed_faces(t: &Terrain) -> Vec<(uint, Vector3<int>)> {
+    let mut exposed = Vec::new();
+    for face in faces.iter() {
+        for x in range(0, CHUNK_SIZE) {
+            for y in range(0, CHUNK_SIZE) {
+                for z in range(0, CHUNK_SIZE) {
+                    let block = t.get(x, y, z);
+                    let n = face.normal;
+                    let neighbor = t.get(x + n.x as int, y + n.y as int, z + n.z as int);
+                    if block.blocktype != BlockAir && !face_hidden(block, neighbor, face) {
+                        exposed.push((face.index, Vector3::new(x, y, z)));
+                    }
+                }
+            }
+        }
+    }
+    exposed
+}
+
+/// Block faces each quad covers, in the same form as test_exposed_faces.
+#[cfg(test)]
+fn test_covered_faces(quads: &[(uint, Vector3<f32>, Vector3<f32>)]) -> Vec<(uint, Vector3<int>)> {
+    let mut covered = Vec::new();
+    for &(index, min, max) in quads.iter() {
+        let n = faces[index].normal;
+        /* The quad lies on the far side of its blocks along the normal, or
+           inside the cell of a short block */
+        let axis = |min: f32, max: f32, n: f32| -> (int, int) {
+            if n > 0.0 {
+                (min.ceil() as int - 1, min.ceil() as int)
+            } else if n < 0.0 {
+                (min as int, min as int + 1)
+            } else {
+                (min as int, max.ceil() as int)
+            }
+        };
+        let (x0, x1) = axis(min.x, max.x, n.x);
+        let (y0, y1) = axis(min.y, max.y, n.y);
+        let (z0, z1) = axis(min.z, max.z, n.z);
+        for x in range(x0, x1) {
+            for y in range(y0, y1) {
+                for z in range(z0, z1) {
+                    covered.push((index, Vector3::new(x, y, z)));
+                }
+            }
+        }
+    }
+    covered
+}
+
+/// Checks that the quads cover every exposed face exactly once, so the mesh
+/// has no holes and no overlaps.
+#[cfg(test)]
+fn assert_watertight(t: &Terrain) {
+    let key = |&(index, p): &(uint, Vector3<int>)| (index, p.x, p.y, p.z);
+    let mut exposed: Vec<(uint, int, int, int)> = test_exposed_faces(t).iter().map(|f| key(f)).collect();
+    let mut covered: Vec<(uint, int, int, int)> = test_covered_faces(test_quads(t).as_slice()).iter().map(|f| key(f)).collect();
+    exposed.sort();
+    covered.sort();
+    assert_eq!(covered, exposed);
+}
+
+#[test]
+fn test_block_bitmap() {
+    let mut bitmap = BlockBitmap::new();
+    assert_eq!(bitmap.column(1, 2), 0);
+    for k in range(3, 7) {
+        bitmap.insert(1, 2, k);
+    }
+    bitmap.insert(1, 2, 8);
+    assert_eq!(bitmap.column(1, 2), 0b101111000);
+    assert_eq!(bitmap.column(2, 1), 0);
+    assert_eq!(bitmap.run(1, 2, 3), 4);
+    assert_eq!(bitmap.run(1, 2, 5), 2);
+    assert_eq!(bitmap.run(1, 2, 7), 0);
+    bitmap.clear(1, 2, span_mask(4, 2));
+    assert_eq!(bitmap.run(1, 2, 3), 1);
+
+    /* A full column runs to the edge of the chunk */
+    for k in range(0, CHUNK_SIZE) {
+        bitmap.insert(0, 0, k);
+    }
+    assert_eq!(bitmap.run(0, 0, 0), CHUNK_SIZE);
+    assert_eq!(bitmap.run(0, 0, CHUNK_SIZE - 1), 1);
+    assert_eq!(span_mask(0, 64), !0);
+}
+
+#[test]
+fn test_expand_face() {
+    /* Columns 0 to 2 have faces at k = 1..4, column 1 also at 5 */
+    let mut bitmap = BlockBitmap::new();
+    for j in range(0, 3) {
+        for k in range(1, 5) {
+            bitmap.insert(0, j, k);
+        }
+    }
+    bitmap.insert(0, 1, 5);
+    bitmap.insert(0, 3, 1);
+
+    assert_eq!(expand_face(&mut bitmap, 0, 0, 1, CHUNK_SIZE, CHUNK_SIZE), (3, 4));
+    assert_eq!(bitmap.column(0, 0), 0);
+    assert_eq!(bitmap.column(0, 1), 1 << 5);
+    assert_eq!(bitmap.column(0, 3), 1 << 1);
+
+    /* Limited, it leaves the rest for other quads */
+    bitmap.insert(0, 4, 1);
+    bitmap.insert(0, 4, 2);
+    assert_eq!(expand_face(&mut bitmap, 0, 3, 1, 1, CHUNK_SIZE), (1, 1));
+    assert_eq!(expand_face(&mut bitmap, 0, 4, 1, CHUNK_SIZE, 1), (1, 1));
+    assert_eq!(bitmap.column(0, 4), 1 << 2);
+}
+
+#[test]
+fn test_mesh_quad_counts() {
+    let v = |x: int, y: int, z: int| Vector3::new(x, y, z);
+    assert_eq!(test_quads(&*test_terrain([])).len(), 0);
+    assert_eq!(test_quads(&*test_terrain([(v(3, 4, 5), BlockStone)])).len(), 6);
+
+    /* A full chunk is one quad per side */
+    let mut full = Vec::new();
+    for x in range(0, CHUNK_SIZE) {
+        for y in range(0, CHUNK_SIZE) {
+            for z in range(0, CHUNK_SIZE) {
+                full.push((v(x, y, z), BlockStone));
+            }
+        }
+    }
+    let quads = test_quads(&*test_terrain(full.as_slice()));
+    assert_eq!(quads.len(), 6);
+    for &(_, min, max) in quads.iter() {
+        let d = max.sub_v(&min);
+        assert_eq!(d.x * d.y + d.y * d.z + d.z * d.x, (CHUNK_SIZE * CHUNK_SIZE) as f32);
+    }
+
+    /* Nothing in a checkerboard can merge */
+    let mut checkerboard = Vec::new();
+    for x in range(0, 4) {
+        for y in range(0, 4) {
+            for z in range(0, 4) {
+                if (x + y + z) % 2 == 0 {
+                    checkerboard.push((v(x, y, z), BlockStone));
+                }
+            }
+        }
+    }
+    assert_eq!(test_quads(&*test_terrain(checkerboard.as_slice())).len(), 6 * 32);
+
+    /* An L of five blocks, flat in z */
+    let l = [(v(0, 0, 0), BlockStone), (v(1, 0, 0), BlockStone), (v(2, 0, 0), BlockStone),
+             (v(0, 1, 0), BlockStone), (v(0, 2, 0), BlockStone)];
+    let quads = test_quads(&*test_terrain(l));
+    let per_face: Vec<uint> = range(0, NUM_FACES).map(|i| quads.iter().filter(|&&(index, _, _)| index == i).count()).collect();
+    assert_eq!(per_face, vec![2, 2, 2, 1, 2, 1]);
+
+    /* Different blocktypes don't merge, but hide the faces between them */
+    assert_eq!(test_quads(&*test_terrain([(v(0, 0, 0), BlockStone), (v(1, 0, 0), BlockDirt)])).len(), 10);
+}
+
+#[test]
+fn test_mesh_watertight() {
+    let v = |x: int, y: int, z: int| Vector3::new(x, y, z);
+    assert_watertight(&*test_terrain([(v(0, 0, 0), BlockStone)]));
+    assert_watertight(&*test_terrain([(v(0, 0, 0), BlockStone), (v(0, 1, 0), BlockStone), (v(1, 0, 0), BlockStone),
+                                      (v(0, 0, 1), BlockDirt), (v(-1, 0, 0), BlockStone)]));
+
+    /* A pool of water with a column sticking up out of it */
+    let mut pool = vec![(v(4, 4, 4), BlockWater), (v(4, 5, 4), BlockStone)];
+    for x in range(0, 8) {
+        for z in range(0, 8) {
+            pool.push((v(x, 3, z), BlockWater));
+        }
+    }
+    assert_watertight(&*test_terrain(pool.as_slice()));
+}
+
+/// Layers of snow are cut down to their height, and their sides only merge
+/// sideways.
+#[test]
+fn test_mesh_snow() {
+    let v = |x: int, y: int, z: int| Vector3::new(x, y, z);
+    let mut blocks = Vec::new();
+    for x in range(0, 4) {
+        blocks.push((v(x, 0, 0), BlockStone));
+        blocks.push((v(x, 1, 0), BlockSnow));
+    }
+    /* A second layer on a stone block stacked on the row */
+    blocks.push((v(0, 2, 0), BlockStone));
+    blocks.push((v(0, 3, 0), BlockSnow));
+    let t = test_terrain(blocks.as_slice());
+    assert_watertight(&*t);
+
+    let top = 1.0 + BlockSnow.height();
+    let quads = test_quads(&*t);
+    for &(index, min, max) in quads.iter() {
+        if index == 4 && min.y > 1.0 && min.y < 2.0 {
+            assert_eq!((min.y, max.y), (top, top));
+        }
+        if faces[index].normal.y == 0.0 && min.y >= 1.0 && min.y < 2.0 {
+            assert_eq!((min.y, max.y), (1.0, top));
+        }
+    }
+    /* The row's top merges into one quad, under the stone block in x = 0 */
+    assert!(quads.iter().any(|&(index, min, max)| index == 4 && min.y == top && max.x - min.x == 3.0));
+}
+
+/// A chunk in the middle of an ocean only has its surface meshed, as one
+/// quad, even though the search for faces is cut short.
+#[test]
+fn test_mesh_ocean() {
+    let n = CHUNK_SIZE as int;
+    let mut ocean = Vec::new();
+    for x in range(-1, n + 1) {
+        for z in range(-1, n + 1) {
+            for y in range(-1, n) {
+                ocean.push((Vector3::new(x, y, z), BlockWater));
+            }
+        }
+    }
+    let t = test_terrain(ocean.as_slice());
+    assert!(BlockHistogram::of(&*t).hides_interior());
+    let quads = test_quads(&*t);
+    assert_eq!(quads.len(), 1);
+    let (index, min, max) = quads[0];
+    assert!(faces[index].normal == Vector3::new(0.0, 1.0, 0.0));
+    assert_eq!((min.y, max.y), (n as f32, n as f32));
+    assert_watertight(&*t);
+}
+
+/// On random blocks, including in the border, the quads' total area is the
+/// number of exposed block faces, and they cover exactly those faces.
+#[test]
+fn test_mesh_random() {
+    use featurerng::FeatureRng;
+
+    let types = [BlockAir, BlockStone, BlockDirt, BlockWater];
+    for seed in range(0u32, 20) {
+        let mut rng = FeatureRng::new(seed, Vector3::new(0, 0, 0), "mesh test");
+        let mut blocks = Vec::new();
+        for x in range(-1, 9) {
+            for y in range(-1, 9) {
+                for z in range(-1, 9) {
+                    blocks.push((Vector3::new(x, y, z), types[rng.below(types.len() as u32) as uint]));
+                }
+            }
+        }
+        let t = test_terrain(blocks.as_slice());
+
+        let quads = test_quads(&*t);
+        let area = quads.iter().fold(0.0, |sum, &(_, min, max)| {
+            let d = max.sub_v(&min);
+            sum + d.x * d.y + d.y * d.z + d.z * d.x
+        });
+        assert_eq!(area, test_exposed_faces(&*t).len() as f32);
+        assert_watertight(&*t);
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Progress of mining the targeted block.
+//!
+//! Damage accumulates while the player keeps mining the same block and is
+//! lost when they stop or switch blocks. A block breaks once it has been
+//! mined for as long as its hardness.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector3;
+
+use terrain::BlockType;
+
+/// Number of crack overlay images, from barely scratched to nearly broken.
+pub static CRACK_STAGES : uint = 8;
+
+pub struct Mining {
+    target: Option<Vector3<i64>>,
+    /// Seconds spent mining target.
+    progress: f64,
+    hardness: f64,
+}
+
+impl Mining {
+    pub fn new() -> Mining {
+        Mining { target: None, progress: 0.0, hardness: 0.0 }
+    }
+
+    /// Mines target for tick_length seconds, or stops mining if target is
+    /// None. Returns the block's position when it breaks.
+    pub fn tick(&mut self, target: Option<(Vector3<i64>, BlockType)>, tick_length: f64) -> Option<Vector3<i64>> {
+        let (p, blocktype) = match target {
+            Some(x) => x,
+            None => {
+                self.stop();
+                return None;
+            },
+        };
+
+        let hardness = match blocktype.hardness() {
+            Some(hardness) => hardness,
+            None => {
+                self.stop();
+                return None;
+            },
+        };
+
+        if self.target != Some(p) {
+            self.target = Some(p);
+            self.progress = 0.0;
+            self.hardness = hardness;
+        }
+
+        self.progress += tick_length;
+        if self.progress >= self.hardness {
+            self.stop();
+            Some(p)
+        } else {
+            None
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.target = None;
+        self.progress = 0.0;
+    }
+
+    /// Returns the block being mined and its crack stage, from 0 to
+    /// CRACK_STAGES - 1.
+    pub fn crack(&self) -> Option<(Vector3<i64>, uint)> {
+        self.target.map(|p| {
+            let stage = (self.progress / self.hardness * CRACK_STAGES as f64) as uint;
+            (p, stage.min(CRACK_STAGES - 1))
+        })
+    }
+}
+
+#[test]
+fn test_mining() {
+    use terrain::{BlockDirt, BlockWater};
+
+    let p = Vector3::new(1, 2, 3);
+    let mut mining = Mining::new();
+    assert!(mining.tick(Some((p, BlockDirt)), 0.3).is_none());
+    assert_eq!(mining.crack(), Some((p, 4)));
+
+    /* Switching blocks starts over */
+    let q = Vector3::new(1, 2, 4);
+    assert!(mining.tick(Some((q, BlockDirt)), 0.3).is_none());
+    assert_eq!(mining.tick(Some((q, BlockDirt)), 0.3), Some(q));
+    assert!(mining.crack().is_none());
+
+    assert!(mining.tick(Some((p, BlockWater)), 10.0).is_none());
+}
@@ -0,0 +1,84 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vector3;
+
+/// The chunks within a radius of a center chunk, nearest first.
+///
+/// The sphere of offsets is built and sorted once. Moving the center only
+/// translates it, and asking again for the same center returns the last
+/// result without touching it.
+pub struct NearbyChunks {
+    /// Offsets within the radius, nearest first.
+    offsets: Vec<Vector3<i64>>,
+    /// Chunk coords was computed around.
+    center: Option<Vector3<i64>>,
+    coords: Vec<Vector3<i64>>,
+}
+
+impl NearbyChunks {
+    pub fn new(radius: i64) -> NearbyChunks {
+        NearbyChunks {
+            offsets: sphere_offsets(radius),
+            center: None,
+            coords: Vec::new(),
+        }
+    }
+
+    /// Returns the chunks within the radius of center, nearest first.
+    pub fn around<'a>(&'a mut self, center: Vector3<i64>) -> &'a [Vector3<i64>] {
+        if self.center != Some(center) {
+            self.coords = self.offsets.iter().map(|o| o.add_v(&center)).collect();
+            self.center = Some(center);
+        }
+        self.coords.as_slice()
+    }
+}
+
+/// Returns the offsets closer than radius to the origin, nearest first.
+fn sphere_offsets(radius: i64) -> Vec<Vector3<i64>> {
+    let r = radius;
+    let mut offsets = Vec::new();
+    for x in range(-r, r+1) {
+        for y in range(-r, r+1) {
+            for z in range(-r, r+1) {
+                let c = Vector3::new(x, y, z);
+                if c.dot(&c) < r*r {
+                    offsets.push(c);
+                }
+            }
+        }
+    }
+    offsets.sort_by(|b,a| b.dot(b).cmp(&a.dot(a)));
+    offsets
+}
+
+#[test]
+fn test_nearby_chunks() {
+    let mut nearby = NearbyChunks::new(2);
+    let expected = {
+        let coords = nearby.around(Vector3::new(10, 0, -5));
+        /* The center, its 6 face neighbors and 12 edge neighbors */
+        assert_eq!(coords.len(), 19);
+        assert_eq!(coords[0], Vector3::new(10, 0, -5));
+        let d = |c: &Vector3<i64>| c.sub_v(&Vector3::new(10, 0, -5)).dot(&c.sub_v(&Vector3::new(10, 0, -5)));
+        assert!(coords.windows(2).all(|w| d(&w[0]) <= d(&w[1])));
+        coords.to_vec()
+    };
+    assert_eq!(nearby.around(Vector3::new(10, 0, -5)).to_vec(), expected);
+    assert_eq!(nearby.around(Vector3::new(11, 0, -5))[0], Vector3::new(11, 0, -5));
+}
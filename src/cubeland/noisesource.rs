@@ -0,0 +1,110 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interface to the noise functions terrain generation is built from, so
+//! each stage of the generator can use whichever backend suits it.
+//!
+//! Every backend must be deterministic: the same seed and position give the
+//! same value on every platform.
+
+use gradient::{GradientNoise, SimplexNoise, ValueNoise};
+
+pub trait Noise {
+    /// Returns the noise at block (x, y, z), roughly within -1 to 1.
+    fn get(&self, x: i64, y: i64, z: i64) -> f64;
+
+    /// Fills out with the noise at a column of blocks, starting at (x, y, z)
+    /// and stepping step blocks up. Backends can override this with
+    /// something faster than sampling each block separately.
+    fn get_column(&self, x: i64, y: i64, z: i64, step: i64, out: &mut [f64]) {
+        for (i, v) in out.mut_iter().enumerate() {
+            *v = self.get(x, y + i as i64 * step, z);
+        }
+    }
+
+    /// Fills out with the noise at height y for every x in xs and z in zs,
+    /// x-major. Backends can override this to share work between nearby
+    /// samples.
+    fn get_grid(&self, xs: &[i64], y: i64, zs: &[i64], out: &mut [f64]) {
+        for (i, &x) in xs.iter().enumerate() {
+            for (j, &z) in zs.iter().enumerate() {
+                out[i * zs.len() + j] = self.get(x, y, z);
+            }
+        }
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum NoiseKind {
+    NoisePerlin,
+    NoiseSimplex,
+    NoiseValue,
+}
+
+impl NoiseKind {
+    pub fn from_name(name: &str) -> Option<NoiseKind> {
+        match name {
+            "perlin" => Some(NoisePerlin),
+            "simplex" => Some(NoiseSimplex),
+            "value" => Some(NoiseValue),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            NoisePerlin => "perlin",
+            NoiseSimplex => "simplex",
+            NoiseValue => "value",
+        }
+    }
+
+    /// Whether this kind of noise can repeat, for worlds that wrap around.
+    pub fn tiles(&self) -> bool {
+        *self != NoiseSimplex
+    }
+
+    /// Creates a fractal sum of octaves of this kind of noise, each twice the
+    /// frequency and persistence times the amplitude of the last. With a
+    /// period, which needs a kind that tiles(), it repeats every period
+    /// blocks along x and z.
+    pub fn create(&self, seed: u32, octaves: uint, frequency: f64, persistence: f64,
+                  period: Option<i64>) -> Box<Noise> {
+        match *self {
+            NoisePerlin => box GradientNoise::new(seed, octaves, frequency, persistence, period) as Box<Noise>,
+            NoiseSimplex => {
+                if period.is_some() {
+                    fail!("simplex noise can't repeat");
+                }
+                box SimplexNoise::new(seed, octaves, frequency, persistence) as Box<Noise>
+            },
+            NoiseValue => box ValueNoise::new(seed, octaves, frequency, persistence, period) as Box<Noise>,
+        }
+    }
+}
+
+#[test]
+fn test_noise_kind() {
+    for &kind in [NoisePerlin, NoiseSimplex, NoiseValue].iter() {
+        assert_eq!(NoiseKind::from_name(kind.name()), Some(kind));
+
+        let noise = kind.create(7, 4, 0.015, 0.5, None);
+        let mut column = [0.0, ..5];
+        noise.get_column(10, -3, 20, 4, column.as_mut_slice());
+        for i in range(0u, 5) {
+            assert_eq!(column[i], noise.get(10, -3 + i as i64 * 4, 20));
+        }
+    }
+    assert_eq!(NoiseKind::from_name("fractal"), None);
+}
@@ -0,0 +1,204 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-player permission flags enforced by the host of a world.
+//!
+//! Flags are stored in the world directory, one "name flag..." line per
+//! player listing the flags that are on. The player "*" holds the flags for
+//! anyone without a line of their own. The host checks edits with
+//! allow_edit() and client-reported movement with check_move() before
+//! applying them.
+
+extern crate cgmath;
+
+use std::collections::HashMap;
+use std::io::{File, IoError, IoResult, OtherIoError};
+
+use cgmath::vector::{EuclideanVector, Vector};
+use cgmath::vector::Vector3;
+
+/// Fastest a player without creative_fly may move, in blocks per second.
+static MAX_WALK_SPEED : f64 = 10.0;
+
+static DEFAULT_PLAYER : &'static str = "*";
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct PlayerFlags {
+    /// May place and break blocks.
+    pub can_edit: bool,
+    /// May fly and move at any speed.
+    pub creative_fly: bool,
+    /// Watches only: may fly anywhere but never edit.
+    pub spectator: bool,
+}
+
+pub static DEFAULT_FLAGS : PlayerFlags = PlayerFlags {
+    can_edit: true,
+    creative_fly: true,
+    spectator: false,
+};
+
+impl PlayerFlags {
+    /// Sets the flag with the given name, returning false if there's no
+    /// such flag.
+    pub fn set(&mut self, flag: &str, value: bool) -> bool {
+        match flag {
+            "can_edit" => self.can_edit = value,
+            "creative_fly" => self.creative_fly = value,
+            "spectator" => self.spectator = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Names of the flags that are on.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.can_edit { names.push("can_edit"); }
+        if self.creative_fly { names.push("creative_fly"); }
+        if self.spectator { names.push("spectator"); }
+        names
+    }
+}
+
+pub struct Permissions {
+    /// None if the file didn't load, so saving can't overwrite it.
+    path: Option<Path>,
+    players: HashMap<String, PlayerFlags>,
+}
+
+impl Permissions {
+    /// Loads the permissions file at path, or starts with just the defaults
+    /// if it doesn't exist.
+    pub fn load(path: Path) -> Result<Permissions, String> {
+        if !path.exists() {
+            return Ok(Permissions { path: Some(path), players: HashMap::new() });
+        }
+
+        let src = match File::open(&path).read_to_string() {
+            Ok(src) => src,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        let mut permissions = Permissions { path: None, players: HashMap::new() };
+
+        for line in src.as_slice().lines() {
+            let words: Vec<&str> = line.words().collect();
+            if words.is_empty() {
+                continue;
+            }
+
+            let mut flags = PlayerFlags { can_edit: false, creative_fly: false, spectator: false };
+            for flag in words.slice_from(1).iter() {
+                if !flags.set(*flag, true) {
+                    return Err(format!("{}: unknown flag '{}'", path.display(), flag));
+                }
+            }
+            permissions.players.insert(words[0].to_string(), flags);
+        }
+
+        permissions.path = Some(path);
+        Ok(permissions)
+    }
+
+    /// Just the defaults, for when the file didn't load. Changes can't be
+    /// saved, so the file is left for the admin to fix.
+    pub fn defaults() -> Permissions {
+        Permissions { path: None, players: HashMap::new() }
+    }
+
+    pub fn save(&self) -> IoResult<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Err(IoError {
+                kind: OtherIoError,
+                desc: "the permissions file didn't load, so it isn't overwritten",
+                detail: None,
+            }),
+        };
+
+        let mut names: Vec<&String> = self.players.keys().collect();
+        names.sort();
+
+        let mut file = try!(File::create(path));
+        for name in names.iter() {
+            let flags = self.players.get(*name);
+            try!(writeln!(file, "{} {}", name, flags.names().connect(" ")));
+        }
+        Ok(())
+    }
+
+    pub fn flags(&self, name: &str) -> PlayerFlags {
+        match self.players.find_equiv(&name) {
+            Some(flags) => *flags,
+            None => match self.players.find_equiv(&DEFAULT_PLAYER) {
+                Some(flags) => *flags,
+                None => DEFAULT_FLAGS,
+            },
+        }
+    }
+
+    /// Sets a flag for one player, or for everyone without their own flags
+    /// if name is "*". Returns false if there's no such flag.
+    pub fn set(&mut self, name: &str, flag: &str, value: bool) -> bool {
+        let mut flags = self.flags(name);
+        if !flags.set(flag, value) {
+            return false;
+        }
+        self.players.insert(name.to_string(), flags);
+        true
+    }
+
+    pub fn allow_edit(&self, name: &str) -> bool {
+        let flags = self.flags(name);
+        flags.can_edit && !flags.spectator
+    }
+
+    /// Returns where a player reporting a move from old to new over
+    /// tick_length seconds may actually end up.
+    pub fn check_move(&self, name: &str, old: Vector3<f64>, new: Vector3<f64>, tick_length: f64) -> Vector3<f64> {
+        let flags = self.flags(name);
+        if flags.creative_fly || flags.spectator {
+            return new;
+        }
+
+        let delta = new.sub_v(&old);
+        let max_distance = MAX_WALK_SPEED * tick_length;
+        if delta.length() <= max_distance {
+            new
+        } else {
+            old.add_v(&delta.normalize_to(max_distance))
+        }
+    }
+}
+
+#[test]
+fn test_permissions() {
+    let mut permissions = Permissions::defaults();
+    assert!(permissions.allow_edit("alice"));
+
+    assert!(permissions.set("*", "can_edit", false));
+    assert!(permissions.set("alice", "can_edit", true));
+    assert!(permissions.allow_edit("alice"));
+    assert!(!permissions.allow_edit("bob"));
+    assert!(!permissions.set("bob", "bogus", true));
+
+    assert!(permissions.set("bob", "creative_fly", false));
+    let old = Vector3::new(0.0, 0.0, 0.0);
+    let new = permissions.check_move("bob", old, Vector3::new(100.0, 0.0, 0.0), 1.0);
+    assert_eq!(new, Vector3::new(MAX_WALK_SPEED, 0.0, 0.0));
+
+    /* Defaults standing in for a file that didn't load never overwrite it */
+    assert!(permissions.save().is_err());
+}
@@ -0,0 +1,113 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Photo mode: screenshots at several times the window's resolution.
+//!
+//! A photo is drawn in scale by scale tiles the size of the window, into an
+//! offscreen framebuffer. Each tile narrows the projection to its part of
+//! the view, so the tiles join into one larger image with nothing
+//! stretched.
+
+extern crate gl;
+extern crate cgmath;
+
+use std::io;
+use std::io::File;
+use std::io::fs;
+
+use gl::types::*;
+
+use cgmath::vector::Vector2;
+
+use fbo::FBO;
+use png;
+use time;
+use renderer::Renderer;
+
+pub static MIN_SCALE : u32 = 2;
+pub static MAX_SCALE : u32 = 4;
+pub static DEFAULT_SCALE : u32 = 2;
+
+/// Draws the view at scale times the window's size by calling draw once per
+/// tile. Returns the size of the image and its RGB pixels, top row first.
+pub fn capture(renderer: &mut Renderer, scale: u32, draw: |&Renderer|)
+        -> Result<(Vector2<u32>, Vec<u8>), String> {
+    let tile_size = renderer.window_size();
+    let size = Vector2::new(tile_size.x * scale, tile_size.y * scale);
+    let fbo = try!(FBO::new(tile_size.x as GLint, tile_size.y as GLint));
+
+    let mut image = Vec::from_elem((size.x * size.y * 3) as uint, 0u8);
+    let mut tile = Vec::from_elem((tile_size.x * tile_size.y * 3) as uint, 0u8);
+
+    fbo.bind();
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    for y in range(0, scale) {
+        for x in range(0, scale) {
+            renderer.set_tile(Some((scale, x, y)));
+            draw(&*renderer);
+            unsafe {
+                gl::ReadPixels(0, 0, tile_size.x as GLint, tile_size.y as GLint,
+                               gl::RGB, gl::UNSIGNED_BYTE,
+                               tile.as_mut_slice().as_mut_ptr() as *mut GLvoid);
+            }
+            place_tile(image.as_mut_slice(), size, tile.as_slice(), tile_size, x, y);
+        }
+    }
+    renderer.set_tile(None);
+    fbo.unbind();
+
+    Ok((size, image))
+}
+
+/// Writes a captured image to a new file in dir and returns its path.
+pub fn save(dir: &Path, size: Vector2<u32>, rgb: &[u8]) -> Result<Path, String> {
+    try!(fs::mkdir_recursive(dir, io::UserDir).map_err(|e| format!("{}: {}", dir.display(), e)));
+
+    let stamp = time::now().strftime("%Y%m%d-%H%M%S");
+    let mut path = dir.join(format!("photo-{}.png", stamp));
+    let mut n = 1u;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("photo-{}-{}.png", stamp, n));
+    }
+
+    let data = png::encode(size.x, size.y, rgb);
+    try!(File::create(&path).write(data.as_slice()).map_err(|e| format!("{}: {}", path.display(), e)));
+    Ok(path)
+}
+
+/// Copies tile (x, y), counting from the bottom left, into image. Both are
+/// RGB; the tile's rows run bottom to top, as GL reads them, and the
+/// image's top to bottom.
+fn place_tile(image: &mut [u8], size: Vector2<u32>, tile: &[u8], tile_size: Vector2<u32>,
+              x: u32, y: u32) {
+    let row_bytes = (tile_size.x * 3) as uint;
+    for r in range(0, tile_size.y) {
+        let row = size.y - 1 - (y * tile_size.y + r);
+        let dst = ((row * size.x + x * tile_size.x) * 3) as uint;
+        let src = r as uint * row_bytes;
+        image.mut_slice(dst, dst + row_bytes).copy_from(tile.slice(src, src + row_bytes));
+    }
+}
+
+#[test]
+fn test_place_tile() {
+    let size = Vector2::new(4u32, 2);
+    let tile_size = Vector2::new(2u32, 1);
+    let mut image = Vec::from_elem(4 * 2 * 3, 0u8);
+    place_tile(image.as_mut_slice(), size, [1, 1, 1, 2, 2, 2], tile_size, 1, 0);
+    place_tile(image.as_mut_slice(), size, [3, 3, 3, 4, 4, 4], tile_size, 0, 1);
+    assert_eq!(image, vec![3, 3, 3, 4, 4, 4, 0, 0, 0, 0, 0, 0,
+                           0, 0, 0, 0, 0, 0, 1, 1, 1, 2, 2, 2]);
+}
@@ -0,0 +1,178 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Where the game keeps its files on each platform.
+//!
+//! Linux and other Unixes follow the XDG base directory spec, Windows uses
+//! %APPDATA% and macOS ~/Library/Application Support. Setting CUBELAND_HOME
+//! puts everything under that one directory instead, e.g. for a portable
+//! install or to keep test worlds apart.
+
+use std::os;
+
+/// Environment variable that overrides all of the directories.
+static HOME_VAR : &'static str = "CUBELAND_HOME";
+
+static APP_NAME : &'static str = "cubeland";
+
+/// Data the game writes: saved worlds and exported maps.
+pub fn data_dir() -> Path {
+    data_dir_with(|name| os::getenv(name), &home_dir())
+}
+
+/// Settings the player edits by hand, like script hooks.
+pub fn config_dir() -> Path {
+    config_dir_with(|name| os::getenv(name), &home_dir())
+}
+
+/// Photos, in a subdirectory per world.
+pub fn screenshot_dir() -> Path {
+    screenshot_dir_with(|name| os::getenv(name), &home_dir())
+}
+
+/// Saved worlds, one directory each.
+///
+/// Worlds used to be kept in ~/.cubeland/worlds. That's still used while
+/// it exists and the new directory doesn't, so old worlds aren't lost.
+pub fn save_dir() -> Path {
+    let dir = data_dir().join("worlds");
+    let legacy = home_dir().join_many([".cubeland", "worlds"]);
+    if os::getenv(HOME_VAR).is_none() && !dir.exists() && legacy.exists() {
+        legacy
+    } else {
+        dir
+    }
+}
+
+fn home_dir() -> Path {
+    os::homedir().unwrap_or(Path::new("."))
+}
+
+/// Returns the directory named by environment variable name, if it's set
+/// and not empty.
+fn env_dir(getenv: |&str| -> Option<String>, name: &str) -> Option<Path> {
+    match getenv(name) {
+        Some(ref dir) if !dir.is_empty() => Some(Path::new(dir.as_slice())),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "windows"), not(target_os = "macos"))]
+fn data_dir_with(getenv: |&str| -> Option<String>, home: &Path) -> Path {
+    match env_dir(|name| getenv(name), HOME_VAR) {
+        Some(dir) => dir,
+        None => env_dir(|name| getenv(name), "XDG_DATA_HOME").
+            unwrap_or(home.join_many([".local", "share"])).join(APP_NAME),
+    }
+}
+
+#[cfg(not(target_os = "windows"), not(target_os = "macos"))]
+fn config_dir_with(getenv: |&str| -> Option<String>, home: &Path) -> Path {
+    match env_dir(|name| getenv(name), HOME_VAR) {
+        Some(dir) => dir.join("config"),
+        None => env_dir(|name| getenv(name), "XDG_CONFIG_HOME").
+            unwrap_or(home.join(".config")).join(APP_NAME),
+    }
+}
+
+#[cfg(not(target_os = "windows"), not(target_os = "macos"))]
+fn screenshot_dir_with(getenv: |&str| -> Option<String>, home: &Path) -> Path {
+    match env_dir(|name| getenv(name), HOME_VAR) {
+        Some(dir) => dir.join("screenshots"),
+        /* Set by xdg-user-dirs in some desktop sessions */
+        None => env_dir(|name| getenv(name), "XDG_PICTURES_DIR").
+            unwrap_or(home.join("Pictures")).join(APP_NAME),
+    }
+}
+
+/// Roaming application data, so worlds follow the player between machines
+/// on a domain.
+#[cfg(target_os = "windows")]
+fn data_dir_with(getenv: |&str| -> Option<String>, home: &Path) -> Path {
+    match env_dir(|name| getenv(name), HOME_VAR) {
+        Some(dir) => dir,
+        None => env_dir(|name| getenv(name), "APPDATA").
+            unwrap_or(home.join_many(["AppData", "Roaming"])).join(APP_NAME),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn config_dir_with(getenv: |&str| -> Option<String>, home: &Path) -> Path {
+    match env_dir(|name| getenv(name), HOME_VAR) {
+        Some(dir) => dir.join("config"),
+        None => data_dir_with(|name| getenv(name), home).join("config"),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn screenshot_dir_with(getenv: |&str| -> Option<String>, home: &Path) -> Path {
+    match env_dir(|name| getenv(name), HOME_VAR) {
+        Some(dir) => dir.join("screenshots"),
+        None => home.join_many(["Pictures", APP_NAME]),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn data_dir_with(getenv: |&str| -> Option<String>, home: &Path) -> Path {
+    match env_dir(|name| getenv(name), HOME_VAR) {
+        Some(dir) => dir,
+        None => home.join_many(["Library", "Application Support", APP_NAME]),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn config_dir_with(getenv: |&str| -> Option<String>, home: &Path) -> Path {
+    match env_dir(|name| getenv(name), HOME_VAR) {
+        Some(dir) => dir.join("config"),
+        None => data_dir_with(|name| getenv(name), home).join("config"),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn screenshot_dir_with(getenv: |&str| -> Option<String>, home: &Path) -> Path {
+    match env_dir(|name| getenv(name), HOME_VAR) {
+        Some(dir) => dir.join("screenshots"),
+        None => home.join_many(["Pictures", APP_NAME]),
+    }
+}
+
+#[test]
+fn test_home_override() {
+    let home = Path::new("/home/player");
+    let getenv = |name: &str| match name {
+        "CUBELAND_HOME" => Some("/portable".to_string()),
+        _ => Some("/elsewhere".to_string()),
+    };
+    assert_eq!(data_dir_with(|name| getenv(name), &home), Path::new("/portable"));
+    assert_eq!(config_dir_with(|name| getenv(name), &home), Path::new("/portable/config"));
+    assert_eq!(screenshot_dir_with(|name| getenv(name), &home), Path::new("/portable/screenshots"));
+}
+
+#[cfg(not(target_os = "windows"), not(target_os = "macos"))]
+#[test]
+fn test_xdg_dirs() {
+    let home = Path::new("/home/player");
+    assert_eq!(data_dir_with(|_| None, &home), Path::new("/home/player/.local/share/cubeland"));
+    assert_eq!(config_dir_with(|_| None, &home), Path::new("/home/player/.config/cubeland"));
+    assert_eq!(screenshot_dir_with(|_| None, &home), Path::new("/home/player/Pictures/cubeland"));
+
+    /* Empty variables count as unset */
+    let getenv = |name: &str| match name {
+        "XDG_DATA_HOME" => Some("/data".to_string()),
+        "XDG_CONFIG_HOME" => Some("".to_string()),
+        _ => None,
+    };
+    assert_eq!(data_dir_with(|name| getenv(name), &home), Path::new("/data/cubeland"));
+    assert_eq!(config_dir_with(|name| getenv(name), &home), Path::new("/home/player/.config/cubeland"));
+}
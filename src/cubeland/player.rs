@@ -0,0 +1,139 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std::io::{File, IoResult};
+
+use cgmath::vector::Vector2;
+use cgmath::vector::Vector3;
+
+/// Player state persisted with the world, one "key value..." pair per line.
+pub struct PlayerState {
+    pub position: Vector3<f64>,
+    pub angle: Vector2<f64>,
+    /// Name of the camera's SpeedPreset.
+    pub speed: String,
+    pub speed_scale: f64,
+    pub wireframe: bool,
+    pub creative: bool,
+    /// Index of the selected hotbar slot.
+    pub hotbar: uint,
+    /// Block counts by blocktype name.
+    pub inventory: Vec<(String, uint)>,
+}
+
+impl PlayerState {
+    pub fn save(&self, path: &Path) -> IoResult<()> {
+        let mut file = try!(File::create(path));
+        try!(writeln!(file, "position {} {} {}", self.position.x, self.position.y, self.position.z));
+        try!(writeln!(file, "angle {} {}", self.angle.x, self.angle.y));
+        try!(writeln!(file, "speed {} {}", self.speed, self.speed_scale));
+        try!(writeln!(file, "wireframe {}", self.wireframe));
+        try!(writeln!(file, "creative {}", self.creative));
+        try!(writeln!(file, "hotbar {}", self.hotbar));
+        for &(ref name, count) in self.inventory.iter() {
+            try!(writeln!(file, "inventory {} {}", name, count));
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<PlayerState, String> {
+        let src = match File::open(path).read_to_string() {
+            Ok(src) => src,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        let mut state = PlayerState {
+            position: Vector3::new(0.0, 20.0, 0.0),
+            angle: Vector2::new(0.0, 0.0),
+            speed: "sprint".to_string(),
+            speed_scale: 1.0,
+            wireframe: false,
+            creative: false,
+            hotbar: 0,
+            inventory: Vec::new(),
+        };
+
+        for line in src.as_slice().lines() {
+            let words: Vec<&str> = line.words().collect();
+            if words.is_empty() {
+                continue;
+            }
+
+            let values = words.slice_from(1);
+            let ok = match (words[0], values.len()) {
+                ("position", 3) => match parse_f64s(values) {
+                    Some(v) => { state.position = Vector3::new(v[0], v[1], v[2]); true },
+                    None => false,
+                },
+                ("angle", 2) => match parse_f64s(values) {
+                    Some(v) => { state.angle = Vector2::new(v[0], v[1]); true },
+                    None => false,
+                },
+                ("speed", 2) => match from_str::<f64>(values[1]) {
+                    Some(scale) => {
+                        state.speed = values[0].to_string();
+                        state.speed_scale = scale;
+                        true
+                    },
+                    None => false,
+                },
+                /* Saved by older builds, where fast meant ten times normal speed */
+                ("fast", 1) => match from_str::<bool>(values[0]) {
+                    Some(b) => {
+                        if b {
+                            state.speed = "fast-fly".to_string();
+                        }
+                        true
+                    },
+                    None => false,
+                },
+                ("wireframe", 1) => match from_str::<bool>(values[0]) {
+                    Some(b) => { state.wireframe = b; true },
+                    None => false,
+                },
+                ("creative", 1) => match from_str::<bool>(values[0]) {
+                    Some(b) => { state.creative = b; true },
+                    None => false,
+                },
+                ("hotbar", 1) => match from_str::<uint>(values[0]) {
+                    Some(slot) => { state.hotbar = slot; true },
+                    None => false,
+                },
+                ("inventory", 2) => match from_str::<uint>(values[1]) {
+                    Some(count) => { state.inventory.push((values[0].to_string(), count)); true },
+                    None => false,
+                },
+                /* Ignore unknown keys so older builds can read newer saves */
+                _ => true,
+            };
+
+            if !ok {
+                return Err(format!("{}: invalid line '{}'", path.display(), line));
+            }
+        }
+
+        Ok(state)
+    }
+}
+
+fn parse_f64s(words: &[&str]) -> Option<Vec<f64>> {
+    let values: Vec<f64> = words.iter().filter_map(|w| from_str::<f64>(*w)).collect();
+    if values.len() == words.len() {
+        Some(values)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal PNG encoder for 8-bit RGB images, enough to write map tiles.
+//! Pixel data is compressed with flate and wrapped in a zlib stream by hand.
+
+extern crate flate;
+
+static SIGNATURE : [u8, ..8] = [137, 80, 78, 71, 13, 10, 26, 10];
+/// Bit depth 8, color type 2 (RGB).
+static BIT_DEPTH : u8 = 8;
+static COLOR_TYPE_RGB : u8 = 2;
+
+/// Encodes an image given as rows of RGB triples, top row first.
+pub fn encode(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), (width * height * 3) as uint);
+
+    let mut header = Vec::new();
+    push_u32(&mut header, width);
+    push_u32(&mut header, height);
+    header.push_all([BIT_DEPTH, COLOR_TYPE_RGB, 0, 0, 0]);
+
+    /* Each row starts with its filter type, 0 for none */
+    let row_len = (width * 3) as uint;
+    let mut raw = Vec::with_capacity(rgb.len() + height as uint);
+    for row in rgb.chunks(row_len) {
+        raw.push(0u8);
+        raw.push_all(row);
+    }
+
+    /* zlib header for deflate with a 32K window, then the adler32 trailer */
+    let mut data = vec![0x78u8, 0x01];
+    let compressed = flate::deflate_bytes(raw.as_slice()).expect("deflate failed");
+    data.push_all(compressed.as_slice());
+    push_u32(&mut data, adler32(raw.as_slice()));
+
+    let mut png = Vec::new();
+    png.push_all(SIGNATURE);
+    push_chunk(&mut png, b"IHDR", header.as_slice());
+    push_chunk(&mut png, b"IDAT", data.as_slice());
+    push_chunk(&mut png, b"IEND", []);
+    png
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    push_u32(png, data.len() as u32);
+    png.push_all(kind);
+    png.push_all(data);
+    let mut checked = Vec::from_slice(kind);
+    checked.push_all(data);
+    push_u32(png, crc32(checked.as_slice()));
+}
+
+fn push_u32(v: &mut Vec<u8>, x: u32) {
+    v.push_all([(x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8]);
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in bytes.iter() {
+        crc ^= byte as u32;
+        for _ in range(0u, 8) {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    crc ^ 0xFFFFFFFF
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn test_png() {
+    assert_eq!(crc32(b"IEND"), 0xAE426082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+    let png = encode(2, 1, [255, 0, 0, 0, 0, 255]);
+    assert_eq!(png.slice(0, 8), SIGNATURE.as_slice());
+    /* IHDR: length, type, width 2, height 1 */
+    assert_eq!(png.slice(8, 24), [0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 2, 0, 0, 0, 1].as_slice());
+    /* Ends with an empty IEND chunk */
+    assert_eq!(png.slice(png.len() - 12, png.len()), [0, 0, 0, 0, 73, 69, 78, 68, 0xAE, 0x42, 0x60, 0x82].as_slice());
+}
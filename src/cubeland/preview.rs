@@ -0,0 +1,348 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! --preview: a camera orbiting a few chunks generated with settings read
+//! from a file, regenerated whenever the file changes, for working on the
+//! terrain generator without a world streaming in around it.
+//!
+//! The file is TOML and every key is optional. A missing file is created
+//! with the defaults:
+//!
+//!     seed = 42
+//!     density_noise = "perlin"   # or "simplex" or "value"
+//!     height_noise = "perlin"
+//!     stride = 4                 # blocks between density samples
+//!     center = [0, 0, 0]         # chunk in the middle of the view
+//!     radius = 1                 # chunks on each side of it
+//!
+//! Space stops and starts the orbit, L toggles wireframe and Escape quits.
+//! Nothing is saved; chunks go to a temporary directory.
+
+extern crate gl;
+extern crate glfw;
+extern crate cgmath;
+extern crate toml;
+
+use std::f64::consts::PI;
+use std::io::{File, TempDir};
+
+use cgmath::vector::{EuclideanVector, Vector, Vector2, Vector3, Vector4};
+use glfw::Context;
+use time::precise_time_ns;
+
+use CHUNK_SIZE;
+use DEFAULT_WINDOW_SIZE;
+use WORLD_SEED;
+use chunk::ChunkLoader;
+use clouds::Clouds;
+use horizon::Horizon;
+use noisesource::NoiseKind;
+use ratelimiter::RateLimiter;
+use renderer::Renderer;
+use terrain::{TerrainNoise, TerrainQuality, valid_stride};
+use ui;
+use ui::Ui;
+use worldheight::WorldHeight;
+use wrap::Wrap;
+
+/// Seconds per turn around the chunks.
+static ORBIT_TIME : f64 = 30.0;
+/// Nanoseconds between checks of whether the file changed.
+static RELOAD_INTERVAL : u64 = 500 * 1000 * 1000;
+/// Largest radius accepted. Every chunk is generated before it's shown.
+static MAX_RADIUS : i64 = 3;
+
+static KEYS : [&'static str, ..6] = ["seed", "density_noise", "height_noise", "stride", "center", "radius"];
+
+static DEFAULT_SETTINGS : &'static str = "\
+seed = 42
+density_noise = \"perlin\"
+height_noise = \"perlin\"
+stride = 4
+center = [0, 0, 0]
+radius = 1
+";
+
+/// What to generate, read from the file.
+struct Settings {
+    seed: u32,
+    noise: TerrainNoise,
+    stride: uint,
+    center: Vector3<i64>,
+    radius: i64,
+}
+
+/// The chunks generated with one version of the settings.
+struct Preview {
+    settings: Settings,
+    chunk_loader: ChunkLoader,
+    horizon: Horizon,
+    /// Seconds generating and meshing took.
+    gen_time: f64,
+}
+
+impl Preview {
+    fn new(settings: Settings, dir: &Path) -> Preview {
+        let mut quality = TerrainQuality::new();
+        quality.stride = settings.stride;
+        quality.budget = None;
+        let height = WorldHeight::default();
+        let mut chunk_loader = ChunkLoader::new(settings.seed, Wrap::none(), height, dir.clone(), quality,
+                                                settings.noise.clone(), None);
+        let start = precise_time_ns();
+        let r = settings.radius;
+        for x in range(-r, r + 1) {
+            for y in range(-r, r + 1) {
+                for z in range(-r, r + 1) {
+                    let c = settings.center.add_v(&Vector3::new(x, y, z));
+                    if height.contains_chunk(c.y) {
+                        chunk_loader.load_sync(c);
+                    }
+                }
+            }
+        }
+        let gen_time = (precise_time_ns() - start) as f64 / 1e9;
+        let mut horizon = Horizon::new(settings.seed, None, height, &settings.noise);
+        horizon.enabled = false;
+        Preview { settings: settings, chunk_loader: chunk_loader, horizon: horizon, gen_time: gen_time }
+    }
+}
+
+/// Opens the preview window for the settings in path, until it's closed.
+pub fn run(path: &Path) {
+    if !path.exists() {
+        match File::create(path).write_str(DEFAULT_SETTINGS) {
+            Ok(()) => println!("Wrote default preview settings to {}", path.display()),
+            Err(e) => fail!("Error writing {}: {}", path.display(), e),
+        }
+    }
+    let settings = match load(path) {
+        Ok(settings) => settings,
+        Err(msg) => fail!("Error loading {}: {}", path.display(), msg),
+    };
+    let dir = match TempDir::new("cubeland-preview") {
+        Ok(dir) => dir,
+        Err(e) => fail!("Error creating a temporary directory: {}", e),
+    };
+
+    let c: Option<glfw::ErrorCallback<()>> = None;
+    let glfw = glfw::init(c).unwrap();
+    let (window, events) = glfw.create_window(DEFAULT_WINDOW_SIZE.x, DEFAULT_WINDOW_SIZE.y, "Cubeland preview", glfw::Windowed)
+        .expect("Failed to create GLFW window.");
+    window.set_all_polling(true);
+    window.make_current();
+    gl::load_with(|x| glfw.get_proc_address(x));
+    glfw.set_swap_interval(1);
+
+    let (framebuffer_width, framebuffer_height) = window.get_framebuffer_size();
+    let framebuffer_size = Vector2 { x: framebuffer_width as u32, y: framebuffer_height as u32 };
+    let mut renderer = Renderer::new(framebuffer_size);
+    let mut ui = Ui::new(framebuffer_size);
+    let mut clouds = Clouds::new();
+    clouds.enabled = false;
+
+    let mut preview = Preview::new(settings, dir.path());
+    println!("Generated preview in {:.2}s", preview.gen_time);
+    let mut error: Option<String> = None;
+    let mut modified = modified_time(path);
+    let mut reload_limiter = RateLimiter::new(RELOAD_INTERVAL);
+
+    let mut orbiting = true;
+    let mut orbit = 0.0f64;
+    let mut last_tick = precise_time_ns();
+
+    while !window.should_close() {
+        glfw.poll_events();
+        for (_, event) in glfw::flush_messages(&events) {
+            match event {
+                glfw::FramebufferSizeEvent(w, h) => {
+                    renderer.set_window_size(Vector2 { x: w as u32, y: h as u32 });
+                    ui.set_window_size(Vector2 { x: w as u32, y: h as u32 });
+                },
+                glfw::KeyEvent(glfw::KeyEscape, _, glfw::Press, _) => window.set_should_close(true),
+                glfw::KeyEvent(glfw::KeySpace, _, glfw::Press, _) => orbiting = !orbiting,
+                glfw::KeyEvent(glfw::KeyL, _, glfw::Press, _) => renderer.toggle_wireframe_mode(),
+                _ => {},
+            }
+        }
+
+        let now = precise_time_ns();
+        let tick_length = (now - last_tick) as f64 / 1e9;
+        last_tick = now;
+        if orbiting {
+            orbit = (orbit + tick_length * 2.0 * PI / ORBIT_TIME) % (2.0 * PI);
+        }
+
+        /* Editors often replace the file, so it can be briefly missing */
+        if reload_limiter.limit() {
+            let m = modified_time(path);
+            if m.is_some() && m != modified {
+                modified = m;
+                match load(path) {
+                    Ok(settings) => {
+                        /* Frees the old chunks first */
+                        drop(preview);
+                        preview = Preview::new(settings, dir.path());
+                        println!("Regenerated preview in {:.2}s", preview.gen_time);
+                        error = None;
+                    },
+                    Err(msg) => {
+                        println!("Error loading {}: {}", path.display(), msg);
+                        error = Some(msg);
+                    },
+                }
+            }
+        }
+
+        let settings = &preview.settings;
+        let chunk = CHUNK_SIZE as f64;
+        let target = Vector3::new(settings.center.x as f64 + 0.5, settings.center.y as f64 + 0.5,
+                                  settings.center.z as f64 + 0.5).mul_s(chunk);
+        let distance = (settings.radius as f64 + 1.0) * chunk * 2.0;
+        let eye = target.add_v(&Vector3::new(orbit.sin() * distance, distance * 0.5, orbit.cos() * distance));
+        let d = target.sub_v(&eye).normalize();
+        let angle = Vector2::new(-d.y.asin(), d.x.atan2(-d.z));
+
+        renderer.set_far_plane((distance * 3.0) as f32);
+        {
+            let regions = preview.chunk_loader.find_nearby_regions(settings.center, settings.radius);
+            renderer.render(regions.as_slice(), &clouds, &preview.horizon, eye, angle);
+        }
+
+        let white = Vector4::new(1.0, 1.0, 1.0, 1.0);
+        let lines = [
+            format!("{}", path.display()),
+            format!("seed {}, {} density, {} height, stride {}", settings.seed,
+                    settings.noise.density.name(), settings.noise.height.name(), settings.stride),
+            format!("center ({}, {}, {}), radius {}, {:.2}s", settings.center.x, settings.center.y,
+                    settings.center.z, settings.radius, preview.gen_time),
+        ];
+        let mut y = 8.0;
+        for line in lines.iter() {
+            ui.text(8.0, y, line.as_slice(), white);
+            y += ui::line_height();
+        }
+        match error {
+            Some(ref msg) => ui.text(8.0, y, format!("Error: {}", msg).as_slice(), Vector4::new(1.0, 0.3, 0.3, 1.0)),
+            None => {},
+        }
+        ui.draw();
+
+        window.swap_buffers();
+    }
+}
+
+fn modified_time(path: &Path) -> Option<u64> {
+    path.stat().ok().map(|stat| stat.modified)
+}
+
+fn load(path: &Path) -> Result<Settings, String> {
+    File::open(path).read_to_string().map_err(|e| e.to_string()).
+        and_then(|src| parse(src.as_slice()))
+}
+
+fn parse(src: &str) -> Result<Settings, String> {
+    let mut parser = toml::Parser::new(src);
+    let table = match parser.parse() {
+        Some(table) => table,
+        None => {
+            let e = &parser.errors[0];
+            let (line, col) = parser.to_linecol(e.lo);
+            return Err(format!("line {}, column {}: {}", line + 1, col + 1, e.desc));
+        },
+    };
+    for key in table.keys() {
+        if !KEYS.contains(&key.as_slice()) {
+            return Err(format!("unknown key {}", key));
+        }
+    }
+
+    let mut settings = Settings {
+        seed: WORLD_SEED,
+        noise: TerrainNoise::new(),
+        stride: TerrainQuality::new().stride,
+        center: Vector3::new(0, 0, 0),
+        radius: 1,
+    };
+    match get(&table, "seed") {
+        Some(value) => match value.as_integer() {
+            Some(seed) if seed >= 0 && seed <= 0xffffffff => settings.seed = seed as u32,
+            _ => return Err("seed isn't a 32 bit unsigned number".to_string()),
+        },
+        None => {},
+    }
+    match get(&table, "density_noise") {
+        Some(value) => match value.as_str().and_then(NoiseKind::from_name) {
+            Some(kind) => settings.noise.density = kind,
+            None => return Err("density_noise isn't perlin, simplex or value".to_string()),
+        },
+        None => {},
+    }
+    match get(&table, "height_noise") {
+        Some(value) => match value.as_str().and_then(NoiseKind::from_name) {
+            Some(kind) => settings.noise.height = kind,
+            None => return Err("height_noise isn't perlin, simplex or value".to_string()),
+        },
+        None => {},
+    }
+    match get(&table, "stride") {
+        Some(value) => match value.as_integer() {
+            Some(stride) if stride > 0 && valid_stride(stride as uint) => settings.stride = stride as uint,
+            _ => return Err(format!("stride isn't a number of blocks that divides {}", CHUNK_SIZE)),
+        },
+        None => {},
+    }
+    match get(&table, "center") {
+        Some(value) => {
+            let parts: Vec<Option<i64>> = match value.as_slice() {
+                Some(values) => values.iter().map(|v| v.as_integer()).collect(),
+                None => Vec::new(),
+            };
+            match parts.as_slice() {
+                [Some(x), Some(y), Some(z)] => settings.center = Vector3::new(x, y, z),
+                _ => return Err("center isn't 3 chunk coordinates".to_string()),
+            }
+        },
+        None => {},
+    }
+    match get(&table, "radius") {
+        Some(value) => match value.as_integer() {
+            Some(radius) if radius >= 0 && radius <= MAX_RADIUS => settings.radius = radius,
+            _ => return Err(format!("radius isn't a number of chunks from 0 to {}", MAX_RADIUS)),
+        },
+        None => {},
+    }
+    Ok(settings)
+}
+
+fn get<'a>(table: &'a toml::TomlTable, key: &str) -> Option<&'a toml::Value> {
+    table.find(&key.to_string())
+}
+
+#[test]
+fn test_parse() {
+    let settings = parse(DEFAULT_SETTINGS).unwrap();
+    assert_eq!(settings.seed, 42);
+    assert_eq!(settings.radius, 1);
+
+    let settings = parse("height_noise = \"simplex\"\ncenter = [2, -1, 3]\n").unwrap();
+    assert_eq!(settings.noise.height.name(), "simplex");
+    assert_eq!(settings.noise.density.name(), "perlin");
+    assert_eq!(settings.center, Vector3::new(2, -1, 3));
+
+    assert!(parse("radius = 10\n").is_err());
+    assert!(parse("stride = 5\n").is_err());
+    assert!(parse("center = [1, 2]\n").is_err());
+    assert!(parse("octaves = 4\n").is_err());
+}
@@ -0,0 +1,58 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Describing changes to chunks block by block, for the edit journal and
+//! for rewinding a chunk to an earlier entry. Blocks are named by their
+//! index in the chunk, so a list of edits doesn't repeat whole coordinates.
+
+use CHUNK_SIZE;
+use terrain::{BlockType, Terrain};
+
+/// Longest chat message, in bytes.
+pub static MAX_CHAT_LENGTH : uint = 256;
+
+/// Returns the blocks that differ between two versions of a chunk's terrain,
+/// ignoring the border.
+pub fn diff(old: &Terrain, new: &Terrain) -> Vec<(u32, BlockType)> {
+    let mut edits = Vec::new();
+    for x in range(0, CHUNK_SIZE) {
+        for y in range(0, CHUNK_SIZE) {
+            for z in range(0, CHUNK_SIZE) {
+                let blocktype = new.get(x, y, z).blocktype;
+                if old.get(x, y, z).blocktype != blocktype {
+                    edits.push((block_index(x, y, z), blocktype));
+                }
+            }
+        }
+    }
+    edits
+}
+
+/// 32 bits, since a chunk of 64^3 blocks has more than a u16 can index.
+pub fn block_index(x: int, y: int, z: int) -> u32 {
+    (x*CHUNK_SIZE*CHUNK_SIZE + y*CHUNK_SIZE + z) as u32
+}
+
+pub fn block_position(index: u32) -> (int, int, int) {
+    let i = index as int;
+    (i / (CHUNK_SIZE*CHUNK_SIZE), (i / CHUNK_SIZE) % CHUNK_SIZE, i % CHUNK_SIZE)
+}
+
+#[test]
+fn test_block_index() {
+    let index = block_index(1, 2, 3);
+    assert_eq!(block_position(index), (1, 2, 3));
+    let last = CHUNK_SIZE - 1;
+    assert_eq!(block_position(block_index(last, last, last)), (last, last, last));
+}
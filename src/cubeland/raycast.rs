@@ -0,0 +1,99 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Finding the block a ray hits, by stepping through the block grid one
+//! block boundary at a time (Amanatides & Woo).
+
+extern crate std;
+extern crate cgmath;
+
+use cgmath::vector::Vector3;
+
+pub struct RayHit {
+    /// The block that was hit.
+    pub block: Vector3<i64>,
+    /// Normal of the face the ray entered through, which points at the
+    /// neighbouring block a new block would be placed in.
+    pub normal: Vector3<i64>,
+    pub distance: f64,
+}
+
+/// Returns the first block within max_distance of origin along direction
+/// (which must be normalized) for which is_solid returns true.
+pub fn raycast(origin: Vector3<f64>, direction: Vector3<f64>, max_distance: f64,
+               is_solid: |Vector3<i64>| -> bool) -> Option<RayHit> {
+    let mut block = Vector3::new(origin.x.floor() as i64, origin.y.floor() as i64, origin.z.floor() as i64);
+    let mut normal = Vector3::new(0i64, 0, 0);
+
+    let (step_x, mut t_max_x, t_delta_x) = axis(origin.x, direction.x);
+    let (step_y, mut t_max_y, t_delta_y) = axis(origin.y, direction.y);
+    let (step_z, mut t_max_z, t_delta_z) = axis(origin.z, direction.z);
+
+    let mut distance = 0.0;
+    while distance <= max_distance {
+        if is_solid(block) {
+            return Some(RayHit { block: block, normal: normal, distance: distance });
+        }
+
+        /* Cross whichever block boundary the ray reaches first */
+        if t_max_x < t_max_y && t_max_x < t_max_z {
+            block.x += step_x;
+            distance = t_max_x;
+            t_max_x += t_delta_x;
+            normal = Vector3::new(-step_x, 0, 0);
+        } else if t_max_y < t_max_z {
+            block.y += step_y;
+            distance = t_max_y;
+            t_max_y += t_delta_y;
+            normal = Vector3::new(0, -step_y, 0);
+        } else {
+            block.z += step_z;
+            distance = t_max_z;
+            t_max_z += t_delta_z;
+            normal = Vector3::new(0, 0, -step_z);
+        }
+    }
+
+    None
+}
+
+/// Returns the step direction along one axis, the distance along the ray to
+/// the first block boundary on that axis, and the distance between
+/// boundaries.
+fn axis(origin: f64, direction: f64) -> (i64, f64, f64) {
+    if direction > 0.0 {
+        (1, (origin.floor() + 1.0 - origin) / direction, 1.0 / direction)
+    } else if direction < 0.0 {
+        (-1, (origin - origin.floor()) / -direction, 1.0 / -direction)
+    } else {
+        (0, std::f64::INFINITY, std::f64::INFINITY)
+    }
+}
+
+#[test]
+fn test_raycast() {
+    let hit = raycast(Vector3::new(0.5, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0), 10.0,
+                      |p| p == Vector3::new(3, 0, 0));
+    match hit {
+        Some(hit) => {
+            assert_eq!(hit.block, Vector3::new(3, 0, 0));
+            assert_eq!(hit.normal, Vector3::new(-1, 0, 0));
+            assert_eq!(hit.distance, 2.5);
+        },
+        None => fail!("expected a hit"),
+    }
+
+    assert!(raycast(Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.0, -1.0, 0.0), 10.0,
+                    |p| p.y < -20).is_none());
+}
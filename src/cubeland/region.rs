@@ -0,0 +1,61 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std::num::Integer;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vector3;
+
+use chunk::Chunk;
+
+/// Width of a region in chunks along each axis.
+pub static REGION_SIZE : i64 = 4;
+
+/// A group of loaded chunks sharing a REGION_SIZE^3 cell, so that culling and
+/// cache queries can reject whole groups at once.
+pub struct Region<'a> {
+    pub coord: Vector3<i64>,
+    pub chunks: Vec<&'a Box<Chunk>>,
+}
+
+impl<'a> Region<'a> {
+    /// Returns the coordinate of a chunk in this region relative to the
+    /// region's own coordinate, which differs from chunk.coord when the world
+    /// wraps around.
+    pub fn chunk_coord(&self, chunk: &Chunk) -> Vector3<i64> {
+        unwrap_chunk_coord(self.coord, chunk.coord)
+    }
+}
+
+/// Moves chunk coordinate c into region r, keeping its offset within its
+/// own region.
+pub fn unwrap_chunk_coord(r: Vector3<i64>, c: Vector3<i64>) -> Vector3<i64> {
+    let base = region_coord(c).mul_s(REGION_SIZE);
+    r.mul_s(REGION_SIZE).add_v(&c.sub_v(&base))
+}
+
+/// Returns the coordinate of the region containing chunk coordinate c.
+pub fn region_coord(c: Vector3<i64>) -> Vector3<i64> {
+    Vector3::new(c.x.div_floor(&REGION_SIZE),
+                 c.y.div_floor(&REGION_SIZE),
+                 c.z.div_floor(&REGION_SIZE))
+}
+
+#[test]
+fn test_region_coord() {
+    assert_eq!(region_coord(Vector3::new(0, 3, 4)), Vector3::new(0, 0, 1));
+    assert_eq!(region_coord(Vector3::new(-1, -4, -5)), Vector3::new(-1, -1, -2));
+}
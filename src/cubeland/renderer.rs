@@ -14,15 +14,20 @@
 
 extern crate native;
 extern crate gl;
+extern crate hgl;
 extern crate cgmath;
 extern crate noise;
 
 use std;
+use std::cell::Cell;
 use std::num;
+use std::num::Integer;
 use std::ptr;
 use std::str;
 use std::vec;
 
+use time::precise_time_ns;
+
 use gl::types::*;
 
 use cgmath::angle;
@@ -37,13 +42,218 @@ use cgmath::angle::{rad, deg};
 
 use check_gl;
 use chunk;
+use clouds::{Clouds, CLOUD_TILE_SIZE};
+use colorscheme::{ColorScheme, SchemeNormal};
+use entity::Entities;
+use hand::{Hand, HELD_BLOCK_SIZE};
+use horizon::{Horizon, HorizonVertex, Placeholder};
+use lang;
 use mesh;
 use mesh::Mesh;
-use chunk::Chunk;
+use mining::CRACK_STAGES;
+use region::{Region, REGION_SIZE};
+use terrain::BlockType;
 use CHUNK_SIZE;
+use CHUNK_SIZEu;
+use VISIBLE_RADIUS;
 use texture;
+use texture::{BLOCK_TABLE_SIZE, BLOCK_TABLE_ROWS};
+use weather::{WeatherState, WeatherSnow};
+use worldborder::WorldBorder;
+use voxeltexture::VOXEL_TEXTURE_SIZE;
+
+/// Direction the sunlight travels in until something moves the sun.
+static DEFAULT_SUN_DIRECTION : Vector3<f32> = Vector3 { x: 0.408248, y: -0.816497, z: 0.408248 };
+
+/// Sky and fog color on a clear day.
+pub static CLEAR_SKY_COLOR : Vector3<f32> = Vector3 { x: 0.0, y: 0.75, z: 1.0 };
+pub static CLEAR_FOG_DENSITY : f32 = 0.003;
+
+/// Vertical field of view, in degrees.
+static FIELD_OF_VIEW : f32 = 75.0;
+static NEAR_PLANE : f32 = 0.1;
+/// Far clipping plane distance until set_far_plane() is called.
+static DEFAULT_FAR_PLANE : f32 = 1000.0;
+
+/// Seconds a newly loaded chunk takes to emerge from the fog.
+static CHUNK_FADE_TIME : f32 = 0.5;
+
+/// Blocks after which chunk texture coordinates wrap around. The noise
+/// texture repeats every 128 blocks at scale 1, so this is a whole number of
+/// repeats at every power of two scale the block table can hold.
+static TEXTURE_PERIOD : i64 = 128 * 256;
+
+static RAIN_COLOR : Vector4<f32> = Vector4 { x: 0.6, y: 0.65, z: 0.8, w: 0.5 };
+static SNOW_COLOR : Vector4<f32> = Vector4 { x: 1.0, y: 1.0, z: 1.0, w: 0.9 };
+/// Length in blocks of a raindrop's streak.
+static RAIN_STREAK : f64 = 0.5;
+
+static BORDER_COLOR : Vector4<f32> = Vector4 { x: 1.0, y: 0.3, z: 0.2, w: 0.25 };
+/// The border wall is drawn this many blocks above and below the camera.
+static BORDER_WALL_HEIGHT : f64 = 256.0;
+
+/// Distance in blocks from the camera to the edge of the cloud sheet.
+static CLOUD_RADIUS : f32 = 800.0;
+
+/// Brightness of a face facing away from the sun.
+static AMBIENT_LIGHT : f32 = 0.2;
+/// Brightness added to a face facing the sun straight on.
+static DIFFUSE_LIGHT : f32 = 0.8;
+
+/// Returns the brightness of each face direction, indexed like mesh::faces,
+/// when lit by a sun shining along sun_direction.
+pub fn face_brightness(sun_direction: Vector3<f32>) -> [f32, ..6] {
+    let mut brightness = [0.0f32, ..6];
+    for face in mesh::faces.iter() {
+        brightness[face.index] = AMBIENT_LIGHT + DIFFUSE_LIGHT * (-face.normal.dot(&sun_direction)).max(0.0);
+    }
+    brightness
+}
+
+/// A colored line segment in world coordinates.
+struct Line {
+    from: Vector3<f64>,
+    to: Vector3<f64>,
+    color: Vector4<f32>,
+    /// Seconds left to draw it for.
+    remaining: f64,
+}
+
+/// Segments used to approximate each circle of a debug sphere.
+static SPHERE_SEGMENTS : uint = 16;
+
+/// Immediate-mode debug drawing, for any subsystem to show what it's doing.
+///
+/// Shapes are queued with a color and a lifetime in seconds and drawn as
+/// lines, all in one batch, by Renderer::render_debug(). A lifetime of zero
+/// draws the shape for a single frame.
+pub struct DebugDraw {
+    lines: Vec<Line>,
+}
+
+impl DebugDraw {
+    pub fn new() -> DebugDraw {
+        DebugDraw { lines: Vec::new() }
+    }
 
-static LIGHT_DIRECTION : Vector3<f32> = Vector3 { x: 0.408248, y: -0.816497, z: 0.408248 };
+    pub fn line(&mut self, from: Vector3<f64>, to: Vector3<f64>, color: Vector4<f32>, lifetime: f64) {
+        self.lines.push(Line { from: from, to: to, color: color, remaining: lifetime });
+    }
+
+    /// Draws the edges of the axis-aligned box from min to max.
+    pub fn aabb(&mut self, min: Vector3<f64>, max: Vector3<f64>, color: Vector4<f32>, lifetime: f64) {
+        let corner = |i: uint| {
+            Vector3::new(if i & 1 == 0 { min.x } else { max.x },
+                         if i & 2 == 0 { min.y } else { max.y },
+                         if i & 4 == 0 { min.z } else { max.z })
+        };
+        for i in range(0u, 8) {
+            for &bit in [1u, 2, 4].iter() {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color, lifetime);
+                }
+            }
+        }
+    }
+
+    /// Draws a sphere as three circles around its axes.
+    pub fn sphere(&mut self, center: Vector3<f64>, radius: f64, color: Vector4<f32>, lifetime: f64) {
+        let point = |axis: uint, i: uint| {
+            let a = i as f64 * 2.0 * std::f64::consts::PI / SPHERE_SEGMENTS as f64;
+            let (u, v) = (a.cos() * radius, a.sin() * radius);
+            match axis {
+                0 => Vector3::new(center.x, center.y + u, center.z + v),
+                1 => Vector3::new(center.x + u, center.y, center.z + v),
+                _ => Vector3::new(center.x + u, center.y + v, center.z),
+            }
+        };
+        for axis in range(0u, 3) {
+            for i in range(0, SPHERE_SEGMENTS) {
+                self.line(point(axis, i), point(axis, i + 1), color, lifetime);
+            }
+        }
+    }
+
+    /// Draws a ray of the given length from origin along direction (which
+    /// must be normalized), with a small box marking its end.
+    pub fn ray(&mut self, origin: Vector3<f64>, direction: Vector3<f64>, length: f64, color: Vector4<f32>, lifetime: f64) {
+        let end = origin.add_v(&direction.mul_s(length));
+        self.line(origin, end, color, lifetime);
+        self.aabb(end.sub_s(0.05), end.add_s(0.05), color, lifetime);
+    }
+
+    /// Ages the shapes by tick_length seconds, dropping those that have been
+    /// drawn for their whole lifetime. Call after rendering each frame.
+    pub fn expire(&mut self, tick_length: f64) {
+        for line in self.lines.mut_iter() {
+            line.remaining -= tick_length;
+        }
+        self.lines.retain(|line| line.remaining > 0.0);
+    }
+
+    pub fn len(&self) -> uint {
+        self.lines.len()
+    }
+}
+
+/// What the renderer has done since its counts were last reset, so
+/// performance work can go by more than the frame rate.
+#[deriving(Clone, PartialEq, Show)]
+pub struct RenderStats {
+    pub draw_calls: uint,
+    /// Chunks drawn, and chunks skipped as outside the view, alone or with
+    /// their whole region.
+    pub chunks_drawn: uint,
+    pub chunks_culled: uint,
+    pub triangles: uint,
+    /// Vertex and element buffers uploaded to GL.
+    pub buffer_uploads: uint,
+    /// Shader program, texture and buffer binds.
+    pub state_changes: uint,
+}
+
+impl RenderStats {
+    pub fn new() -> RenderStats {
+        RenderStats {
+            draw_calls: 0,
+            chunks_drawn: 0,
+            chunks_culled: 0,
+            triangles: 0,
+            buffer_uploads: 0,
+            state_changes: 0,
+        }
+    }
+
+    pub fn add(&mut self, other: &RenderStats) {
+        self.draw_calls += other.draw_calls;
+        self.chunks_drawn += other.chunks_drawn;
+        self.chunks_culled += other.chunks_culled;
+        self.triangles += other.triangles;
+        self.buffer_uploads += other.buffer_uploads;
+        self.state_changes += other.state_changes;
+    }
+
+    /// Returns the average of these counts over frames frames, rounded
+    /// down.
+    pub fn per_frame(&self, frames: uint) -> RenderStats {
+        let frames = std::cmp::max(frames, 1);
+        RenderStats {
+            draw_calls: self.draw_calls / frames,
+            chunks_drawn: self.chunks_drawn / frames,
+            chunks_culled: self.chunks_culled / frames,
+            triangles: self.triangles / frames,
+            buffer_uploads: self.buffer_uploads / frames,
+            state_changes: self.state_changes / frames,
+        }
+    }
+
+    /// The counts as lines of text, for the HUD and the log.
+    pub fn lines(&self) -> Vec<String> {
+        vec!(lang::format("hud.draw_calls", [self.draw_calls.to_string(), self.triangles.to_string()]),
+             lang::format("hud.chunks", [self.chunks_drawn.to_string(), self.chunks_culled.to_string()]),
+             lang::format("hud.uploads", [self.buffer_uploads.to_string(), self.state_changes.to_string()]))
+    }
+}
 
 enum RenderMode {
     RenderModeNormal,
@@ -82,11 +292,28 @@ pub struct Renderer {
     res : Resources,
     window_size : Vector2<u32>,
     mode : RenderMode,
+    /// Direction the sunlight travels in.
+    sun_direction : Vector3<f32>,
+    sky_color : Vector3<f32>,
+    fog_density : f32,
+    /// Scale and column and row of the part of the view to draw, when
+    /// drawing a larger image in tiles.
+    tile : Option<(u32, u32, u32)>,
+    /// Distance to the far clipping plane, in blocks.
+    far_plane : f32,
+    /// Seconds flowing blocks have been animated for.
+    time : f64,
+    /// Counted as it draws, which only needs a shared borrow.
+    stats : Cell<RenderStats>,
+    /// Anisotropic filtering level of the mipmapped textures. 1 is off.
+    anisotropy : f32,
+    /// Recolors blocks and entities.
+    color_scheme : ColorScheme,
 }
 
 impl Renderer {
     pub fn new(window_size : Vector2<u32>) -> Renderer {
-        let res = match Resources::load() {
+        let res = match Resources::load(SchemeNormal) {
             Ok(x) => x,
             Err(msg) => fail!("Error loading graphics resources: {}", msg),
         };
@@ -97,15 +324,28 @@ impl Renderer {
             res: res,
             window_size: window_size,
             mode: RenderModeNormal,
+            sun_direction: DEFAULT_SUN_DIRECTION,
+            sky_color: CLEAR_SKY_COLOR,
+            fog_density: CLEAR_FOG_DENSITY,
+            tile: None,
+            far_plane: DEFAULT_FAR_PLANE,
+            time: 0.0,
+            stats: Cell::new(RenderStats::new()),
+            anisotropy: 1.0,
+            color_scheme: SchemeNormal,
         }
     }
 
     pub fn render(
             &self,
-            chunks : &[&Box<chunk::Chunk>],
-            camera_position : Vector3<f32>,
+            regions : &[Region],
+            clouds : &Clouds,
+            horizon : &Horizon,
+            camera_position : Vector3<f64>,
             camera_angle : Vector2<f64>)
     {
+        let eye = camera_position;
+
         gl::Enable(gl::TEXTURE_2D);
         gl::Enable(gl::DEPTH_TEST);
 
@@ -121,40 +361,44 @@ impl Renderer {
         }
 
         gl::UseProgram(self.res.program);
+        self.count(|s| s.state_changes += 3);
         gl::ActiveTexture(gl::TEXTURE0);
         gl::Uniform1i(self.res.uniform_texture, 0);
         gl::BindTexture(gl::TEXTURE_2D, self.res.texture);
+        gl::ActiveTexture(gl::TEXTURE2);
+        gl::BindTexture(gl::TEXTURE_2D, self.res.block_table);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::Uniform1i(self.res.uniform_block_table, 2);
+        gl::Uniform1f(self.res.uniform_block_table_size, BLOCK_TABLE_SIZE as f32);
+        gl::Uniform1f(self.res.uniform_block_table_rows, BLOCK_TABLE_ROWS as f32);
+        gl::Uniform1f(self.res.uniform_chunk_size, CHUNK_SIZE as f32);
+        gl::Uniform1f(self.res.uniform_time, self.time as f32);
         gl::EnableVertexAttribArray(self.res.attr_position);
         gl::EnableVertexAttribArray(self.res.attr_blocktype);
 
         gl::Viewport(0, 0, self.window_size.x as GLint, self.window_size.y as GLint);
 
-        gl::ClearColor(0.0, 0.75, 1.0, 1.0);
+        gl::ClearColor(self.sky_color.x, self.sky_color.y, self.sky_color.z, 1.0);
         gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
-        let aspect_ratio = self.window_size.x as f32 / self.window_size.y as f32;
-
-        let projection = cgmath::projection::perspective(
-            deg(75.0f32),
-            aspect_ratio,
-            0.1f32, 1000.0f32);
+        let projection = self.projection();
 
         unsafe {
             gl::UniformMatrix4fv(self.res.uniform_projection, 1, gl::FALSE, projection.ptr());
+            gl::Uniform3fv(self.res.uniform_fog_color, 1, self.sky_color.ptr());
         }
+        gl::Uniform1f(self.res.uniform_fog_density, self.fog_density);
+        gl::Uniform1i(self.res.uniform_voxels, 1);
+        gl::Uniform1f(self.res.uniform_voxel_texture_size, VOXEL_TEXTURE_SIZE as f32);
 
+        /* Recomputed every frame so the sun can move */
+        let brightness = face_brightness(self.sun_direction);
         unsafe {
-            gl::Uniform3fv(self.res.uniform_light_direction, 1, LIGHT_DIRECTION.ptr());
+            gl::Uniform1fv(self.res.uniform_face_brightness, 6, &brightness[0]);
         }
 
-        let camera_translation = Matrix4::<f32>::from_cols(
-            Vector4::<f32>::unit_x(),
-            Vector4::<f32>::unit_y(),
-            Vector4::<f32>::unit_z(),
-            camera_position.mul_s(-1.0f32).extend(1.0f32));
-        let camera_rotation_x = from_axis_angle(Vector3::unit_x(), rad(camera_angle.x as f32));
-        let camera_rotation_y = from_axis_angle(Vector3::unit_y(), rad(camera_angle.y as f32));
-        let camera = camera_rotation_x.mul_m(&camera_rotation_y).mul_m(&camera_translation);
+        let camera_chunk_coord = chunk::containing_chunk(camera_position);
+        let (origin, camera_position, camera) = view(camera_position, camera_angle);
 
         unsafe {
             gl::Uniform3fv(self.res.uniform_camera_position, 1, camera_position.ptr());
@@ -162,60 +406,106 @@ impl Renderer {
         }
 
         let clip_transform = projection.mul_m(&camera);
+        let now = precise_time_ns();
+
+        /* Chunks with translucent quads, drawn after all opaque geometry */
+        let mut translucent: Vec<(f32, Vector3<f32>, &chunk::Chunk, f32)> = Vec::new();
 
-        let camera_chunk_coord = Vector3::new(camera_position.x as i64,
-                                           camera_position.y as i64,
-                                           camera_position.z as i64).
-                                       div_s(CHUNK_SIZE as i64);
-
-        for chunk in chunks.iter() {
-            let mut chunk_pos = Vector3::new(chunk.coord.x as f32,
-                                          chunk.coord.y as f32,
-                                          chunk.coord.z as f32).
-                                      mul_s(CHUNK_SIZE as f32);
-
-            /* Calculate drop due to surface curvature */
-            static planet_radius : f32 = 6371000.0f32 / 5000.0f32;
-            let horiz_dist = (Vector3 { x: camera_position.x, y: 0.0f32, z: camera_position.z }).
-                sub_v(&Vector3::new(chunk_pos.x, 0.0f32, chunk_pos.z)).length();
-            let adj_horiz_dist = (horiz_dist - 100f32).max(0.0f32);
-            let drop = planet_radius - (planet_radius.powf(2.0f32) - adj_horiz_dist.powf(2.0f32)).sqrt();
-            chunk_pos.y -= drop;
-
-            if view_frustum_cull(&clip_transform, &chunk_pos.extend(0.0f32)) {
+        for region in regions.iter() {
+            if region_cull(&clip_transform, region, origin, camera_position) {
+                self.count(|s| s.chunks_culled += region.chunks.len());
                 continue;
             }
 
-            let mesh : &Mesh = &*chunk.mesh;
-            self.bind_mesh(mesh);
+            for chunk in region.chunks.iter() {
+                let chunk_coord = region.chunk_coord(&***chunk);
+                let offset = chunk_coord.sub_v(&camera_chunk_coord).mul_s(CHUNK_SIZE as i64);
+                let mut chunk_pos = Vector3::new(offset.x as f32, offset.y as f32, offset.z as f32);
 
-            unsafe {
-                gl::Uniform3fv(self.res.uniform_chunk_position, 1, chunk_pos.ptr());
-            }
+                let horiz_dist = (Vector3 { x: camera_position.x, y: 0.0f32, z: camera_position.z }).
+                    sub_v(&Vector3::new(chunk_pos.x, 0.0f32, chunk_pos.z)).length();
+                chunk_pos.y -= curvature_drop(horiz_dist);
 
-            for face in mesh::faces.iter() {
-                if !face_visible(face, chunk.coord, camera_chunk_coord) {
+                let chunk_size = CHUNK_SIZE as f32;
+                if view_frustum_cull(&clip_transform, chunk_pos, chunk_pos.add_s(chunk_size)) {
+                    self.count(|s| s.chunks_culled += 1);
                     continue;
                 }
+                self.count(|s| s.chunks_drawn += 1);
 
-                unsafe {
-                    gl::Uniform3fv(self.res.uniform_normal, 1, face.normal.ptr());
+                let mesh : &Mesh = &*chunk.mesh;
+                let fade = chunk_fade(now, chunk.shown_time);
+                if mesh.has_translucent() {
+                    let d = chunk_pos.add_s(chunk_size / 2.0).sub_v(&camera_position);
+                    translucent.push((d.dot(&d), chunk_pos, &***chunk, fade));
                 }
 
-                let (offset, count) = mesh.face_ranges[face.index];
+                self.bind_mesh(mesh);
+
                 unsafe {
-                    gl::DrawElements(
-                        gl::TRIANGLES,
-                        count as i32,
-                        gl::UNSIGNED_INT,
-                        std::mem::transmute(
-                            offset *
-                            std::mem::size_of::<GLuint>()));
+                    gl::Uniform3fv(self.res.uniform_chunk_position, 1, chunk_pos.ptr());
+                    gl::Uniform3fv(self.res.uniform_texture_origin, 1, texture_origin(chunk.coord).ptr());
+                    gl::Uniform3fv(self.res.uniform_biome_tints, 8, chunk.biome_tints[0].ptr());
+                }
+                gl::Uniform1f(self.res.uniform_fade, fade);
+
+                match mesh.voxels {
+                    Some(ref voxels) => {
+                        gl::ActiveTexture(gl::TEXTURE1);
+                        voxels.bind();
+                        self.count(|s| s.state_changes += 1);
+                        gl::ActiveTexture(gl::TEXTURE0);
+                        gl::Uniform1i(self.res.uniform_use_voxels, 1);
+                    },
+                    None => gl::Uniform1i(self.res.uniform_use_voxels, 0),
+                }
+
+                for face in mesh::faces.iter() {
+                    if !face_visible(face, chunk_coord, camera_chunk_coord) {
+                        continue;
+                    }
+
+                    unsafe {
+                        gl::Uniform3fv(self.res.uniform_normal, 1, face.normal.ptr());
+                    }
+                    gl::Uniform1i(self.res.uniform_face, face.index as GLint);
+
+                    let (offset, count) = mesh.face_ranges[face.index];
+                    unsafe {
+                        gl::DrawElements(
+                            gl::TRIANGLES,
+                            count as i32,
+                            gl::UNSIGNED_INT,
+                            std::mem::transmute(
+                                offset *
+                                std::mem::size_of::<GLuint>()));
+                    }
+                    self.count_draw(gl::TRIANGLES, count);
                 }
             }
         }
 
+        gl::DisableVertexAttribArray(self.res.attr_position);
+        gl::DisableVertexAttribArray(self.res.attr_blocktype);
+
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_3D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+
+        self.render_horizon(horizon, origin, camera_position, &projection, &camera, brightness[4]);
+
+        if clouds.enabled {
+            self.render_clouds(clouds, eye, origin, &projection, &camera, brightness[4]);
+        }
+
+        /* Farthest chunk first, so blending composites back to front */
+        translucent.sort_by(|&(a, _, _, _), &(b, _, _, _)| b.partial_cmp(&a).unwrap_or(Equal));
+        self.render_translucent(translucent.as_slice(), &projection, &camera, &brightness);
+
         gl::UseProgram(0);
+        gl::ActiveTexture(gl::TEXTURE2);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
         gl::BindTexture(gl::TEXTURE_2D, 0);
         gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
         gl::Disable(gl::CULL_FACE);
@@ -223,15 +513,552 @@ impl Renderer {
         gl::Disable(gl::DEPTH_TEST);
     }
 
+    /// Draws the cloud sheet centered on the camera, blended over the sky and
+    /// any opaque geometry in front of it. The sheet is lit like the top of a
+    /// block.
+    fn render_clouds(&self, clouds: &Clouds, eye: Vector3<f64>, origin: Vector3<i64>,
+                     projection: &Matrix4<f32>, camera: &Matrix4<f32>, brightness: f32) {
+        let center = relative(Vector3::new(eye.x, clouds.height, eye.z), origin);
+        let texture_offset = clouds.texture_offset(eye.x, eye.z);
+
+        gl::Disable(gl::CULL_FACE);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::DepthMask(gl::FALSE);
+
+        gl::UseProgram(self.res.cloud_program);
+        gl::BindTexture(gl::TEXTURE_2D, self.res.cloud_texture);
+        self.count(|s| s.state_changes += 2);
+        gl::Uniform1i(self.res.cloud_uniform_texture, 0);
+        gl::Uniform1f(self.res.cloud_uniform_radius, CLOUD_RADIUS);
+        gl::Uniform1f(self.res.cloud_uniform_tile_size, CLOUD_TILE_SIZE as f32);
+        gl::Uniform1f(self.res.cloud_uniform_density, clouds.density());
+        gl::Uniform1f(self.res.cloud_uniform_brightness, brightness);
+        unsafe {
+            gl::UniformMatrix4fv(self.res.cloud_uniform_projection, 1, gl::FALSE, projection.ptr());
+            gl::UniformMatrix4fv(self.res.cloud_uniform_view, 1, gl::FALSE, camera.ptr());
+            gl::Uniform3fv(self.res.cloud_uniform_center, 1, center.ptr());
+            gl::Uniform2fv(self.res.cloud_uniform_texture_offset, 1, texture_offset.ptr());
+        }
+
+        gl::EnableVertexAttribArray(self.res.cloud_attr_position);
+        self.res.cloud_vbo.bind();
+        self.count(|s| s.state_changes += 1);
+        unsafe {
+            gl::VertexAttribPointer(self.res.cloud_attr_position, 2, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<Vector2<f32>>() as GLint,
+                                    ptr::null());
+        }
+
+        gl::DrawArrays(gl::TRIANGLES, 0, 6);
+        self.count_draw(gl::TRIANGLES, 6);
+
+        gl::DisableVertexAttribArray(self.res.cloud_attr_position);
+        gl::BindTexture(gl::TEXTURE_2D, self.res.texture);
+        self.count(|s| s.state_changes += 1);
+        gl::DepthMask(gl::TRUE);
+        gl::Disable(gl::BLEND);
+    }
+
+    /// Draws the horizon grid past the edge of the loaded chunks.
+    fn render_horizon(&self, horizon: &Horizon, origin: Vector3<i64>, camera_position: Vector3<f32>,
+                      projection: &Matrix4<f32>, camera: &Matrix4<f32>, brightness: f32) {
+        let (center, vbo, ebo, count) = match horizon.buffers() {
+            Some(x) => x,
+            None => return,
+        };
+        let center = relative(center, origin);
+        /* A chunk short of the visible radius, where chunks are still loaded
+           in every direction */
+        let inner_radius = ((VISIBLE_RADIUS - 1) * CHUNK_SIZEu) as f32;
+
+        gl::UseProgram(self.res.horizon_program);
+        self.count(|s| s.state_changes += 3);
+        gl::Uniform1f(self.res.horizon_uniform_fog_density, self.fog_density);
+        gl::Uniform1f(self.res.horizon_uniform_brightness, brightness);
+        gl::Uniform1f(self.res.horizon_uniform_inner_radius, inner_radius);
+        unsafe {
+            gl::UniformMatrix4fv(self.res.horizon_uniform_projection, 1, gl::FALSE, projection.ptr());
+            gl::UniformMatrix4fv(self.res.horizon_uniform_view, 1, gl::FALSE, camera.ptr());
+            gl::Uniform3fv(self.res.horizon_uniform_center, 1, center.ptr());
+            gl::Uniform3fv(self.res.horizon_uniform_camera_position, 1, camera_position.ptr());
+            gl::Uniform3fv(self.res.horizon_uniform_fog_color, 1, self.sky_color.ptr());
+        }
+
+        gl::EnableVertexAttribArray(self.res.horizon_attr_position);
+        gl::EnableVertexAttribArray(self.res.horizon_attr_color);
+        vbo.bind();
+        unsafe {
+            gl::VertexAttribPointer(self.res.horizon_attr_position, 3, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<HorizonVertex>() as GLint,
+                                    ptr::null());
+            gl::VertexAttribPointer(self.res.horizon_attr_color, 3, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<HorizonVertex>() as GLint,
+                                    std::mem::transmute(offset_of!(HorizonVertex, color)));
+        }
+        ebo.bind();
+
+        unsafe {
+            gl::DrawElements(gl::TRIANGLES, count as i32, gl::UNSIGNED_INT, ptr::null());
+        }
+        self.count_draw(gl::TRIANGLES, count);
+
+        gl::DisableVertexAttribArray(self.res.horizon_attr_position);
+        gl::DisableVertexAttribArray(self.res.horizon_attr_color);
+    }
+
+    /// Draws the translucent quads of the given chunks, in order, blended
+    /// over the opaque geometry without writing depth. Each chunk's quads are
+    /// already sorted back to front by ChunkLoader::sort_translucent().
+    fn render_translucent(&self, chunks: &[(f32, Vector3<f32>, &chunk::Chunk, f32)],
+                          projection: &Matrix4<f32>, camera: &Matrix4<f32>,
+                          brightness: &[f32, ..6]) {
+        if chunks.is_empty() {
+            return;
+        }
+
+        gl::Disable(gl::CULL_FACE);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::DepthMask(gl::FALSE);
+
+        gl::UseProgram(self.res.translucent_program);
+        self.count(|s| s.state_changes += 1);
+        gl::Uniform1i(self.res.translucent_uniform_texture, 0);
+        gl::Uniform1i(self.res.translucent_uniform_block_table, 2);
+        gl::Uniform1f(self.res.translucent_uniform_block_table_size, BLOCK_TABLE_SIZE as f32);
+        gl::Uniform1f(self.res.translucent_uniform_block_table_rows, BLOCK_TABLE_ROWS as f32);
+        gl::Uniform1f(self.res.translucent_uniform_chunk_size, CHUNK_SIZE as f32);
+        gl::Uniform1f(self.res.translucent_uniform_time, self.time as f32);
+        unsafe {
+            gl::UniformMatrix4fv(self.res.translucent_uniform_projection, 1, gl::FALSE, projection.ptr());
+            gl::UniformMatrix4fv(self.res.translucent_uniform_view, 1, gl::FALSE, camera.ptr());
+            gl::Uniform1fv(self.res.translucent_uniform_face_brightness, 6, &brightness[0]);
+            gl::Uniform3fv(self.res.translucent_uniform_fog_color, 1, self.sky_color.ptr());
+        }
+        gl::Uniform1f(self.res.translucent_uniform_fog_density, self.fog_density);
+
+        gl::EnableVertexAttribArray(self.res.translucent_attr_position);
+        gl::EnableVertexAttribArray(self.res.translucent_attr_blocktype);
+        gl::EnableVertexAttribArray(self.res.translucent_attr_face);
+
+        for &(_, chunk_pos, chunk, fade) in chunks.iter() {
+            let mesh = &*chunk.mesh;
+            match (&mesh.translucent_buffer, &mesh.translucent_element_buffer) {
+                (&Some(ref vbo), &Some(ref ebo)) => unsafe {
+                    vbo.bind();
+                    let stride = std::mem::size_of::<mesh::TranslucentVertexData>() as GLint;
+                    gl::VertexAttribPointer(self.res.translucent_attr_position, 3, gl::FLOAT,
+                                            gl::FALSE as GLboolean, stride, ptr::null());
+                    gl::VertexAttribPointer(self.res.translucent_attr_blocktype, 1, gl::FLOAT,
+                                            gl::FALSE as GLboolean, stride,
+                                            std::mem::transmute(offset_of!(mesh::TranslucentVertexData, blocktype)));
+                    gl::VertexAttribPointer(self.res.translucent_attr_face, 1, gl::FLOAT,
+                                            gl::FALSE as GLboolean, stride,
+                                            std::mem::transmute(offset_of!(mesh::TranslucentVertexData, face)));
+                    ebo.bind();
+
+                    gl::Uniform3fv(self.res.translucent_uniform_chunk_position, 1, chunk_pos.ptr());
+                    gl::Uniform3fv(self.res.translucent_uniform_texture_origin, 1, texture_origin(chunk.coord).ptr());
+                    gl::Uniform3fv(self.res.translucent_uniform_biome_tints, 8, chunk.biome_tints[0].ptr());
+                    gl::Uniform1f(self.res.translucent_uniform_fade, fade);
+                    gl::DrawElements(gl::TRIANGLES, mesh.translucent_element_count() as i32, gl::UNSIGNED_INT, ptr::null());
+                    self.count(|s| s.state_changes += 2);
+                    self.count_draw(gl::TRIANGLES, mesh.translucent_element_count());
+                },
+                _ => {},
+            }
+        }
+
+        gl::DisableVertexAttribArray(self.res.translucent_attr_position);
+        gl::DisableVertexAttribArray(self.res.translucent_attr_blocktype);
+        gl::DisableVertexAttribArray(self.res.translucent_attr_face);
+        gl::DepthMask(gl::TRUE);
+        gl::Disable(gl::BLEND);
+    }
+
+    /// Draws the crack overlay for the given mining stage over block p. Call
+    /// after render() with the same camera.
+    pub fn render_crack(
+            &self,
+            p: Vector3<i64>,
+            stage: uint,
+            camera_position: Vector3<f64>,
+            camera_angle: Vector2<f64>)
+    {
+        let (origin, _, camera) = view(camera_position, camera_angle);
+        let projection = self.projection();
+        let offset = p.sub_v(&origin);
+        let block_position = Vector3::new(offset.x as f32, offset.y as f32, offset.z as f32);
+
+        gl::Enable(gl::TEXTURE_2D);
+        gl::Enable(gl::DEPTH_TEST);
+        gl::DepthFunc(gl::LEQUAL);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        /* Pull the overlay toward the camera so it wins the depth test against the block */
+        gl::Enable(gl::POLYGON_OFFSET_FILL);
+        gl::PolygonOffset(-1.0, -1.0);
+
+        gl::UseProgram(self.res.overlay_program);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, self.res.crack_texture);
+        self.count(|s| s.state_changes += 3);
+        gl::Uniform1i(self.res.overlay_uniform_texture, 0);
+        gl::Uniform1f(self.res.overlay_uniform_stage, stage as f32);
+
+        unsafe {
+            gl::UniformMatrix4fv(self.res.overlay_uniform_projection, 1, gl::FALSE, projection.ptr());
+            gl::UniformMatrix4fv(self.res.overlay_uniform_view, 1, gl::FALSE, camera.ptr());
+            gl::Uniform3fv(self.res.overlay_uniform_block_position, 1, block_position.ptr());
+        }
+
+        gl::EnableVertexAttribArray(self.res.overlay_attr_position);
+        gl::EnableVertexAttribArray(self.res.overlay_attr_texcoord);
+        self.res.overlay_vbo.bind();
+        unsafe {
+            gl::VertexAttribPointer(self.res.overlay_attr_position, 3, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<OverlayVertex>() as GLint,
+                                    ptr::null());
+            gl::VertexAttribPointer(self.res.overlay_attr_texcoord, 2, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<OverlayVertex>() as GLint,
+                                    std::mem::transmute(offset_of!(OverlayVertex, texcoord)));
+        }
+
+        gl::DrawArrays(gl::TRIANGLES, 0, 36);
+        self.count_draw(gl::TRIANGLES, 36);
+
+        gl::DisableVertexAttribArray(self.res.overlay_attr_position);
+        gl::DisableVertexAttribArray(self.res.overlay_attr_texcoord);
+        gl::UseProgram(0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::Disable(gl::POLYGON_OFFSET_FILL);
+        gl::Disable(gl::BLEND);
+        gl::DepthFunc(gl::LESS);
+        gl::Disable(gl::TEXTURE_2D);
+        gl::Disable(gl::DEPTH_TEST);
+    }
+
+    /// Draws entities as colored boxes, from their transform and render
+    /// components. Call after render() with the same camera.
+    pub fn render_entities(
+            &self,
+            entities: &Entities,
+            camera_position: Vector3<f64>,
+            camera_angle: Vector2<f64>)
+    {
+        let (origin, _, camera) = view(camera_position, camera_angle);
+        let projection = self.projection();
+
+        gl::Enable(gl::DEPTH_TEST);
+        gl::Enable(gl::CULL_FACE);
+
+        gl::UseProgram(self.res.entity_program);
+        unsafe {
+            gl::UniformMatrix4fv(self.res.entity_uniform_projection, 1, gl::FALSE, projection.ptr());
+            gl::UniformMatrix4fv(self.res.entity_uniform_view, 1, gl::FALSE, camera.ptr());
+            gl::Uniform3fv(self.res.entity_uniform_light_direction, 1, self.sun_direction.ptr());
+        }
+
+        gl::EnableVertexAttribArray(self.res.entity_attr_position);
+        self.res.overlay_vbo.bind();
+        self.count(|s| s.state_changes += 2);
+        unsafe {
+            gl::VertexAttribPointer(self.res.entity_attr_position, 3, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<OverlayVertex>() as GLint,
+                                    ptr::null());
+        }
+
+        for (t, r) in entities.transforms().iter().zip(entities.renders().iter()) {
+            let color = self.color_scheme.apply_rgba(r.color);
+            let position = relative(t.position, origin);
+            let size = Vector3::new(t.half_extent.x as f32, t.half_extent.y as f32, t.half_extent.z as f32).mul_s(2.0);
+            let model = translation(position).
+                mul_m(&from_axis_angle(Vector3::unit_y(), rad(t.yaw as f32))).
+                mul_m(&Matrix4::new(size.x, 0.0, 0.0, 0.0,
+                                    0.0, size.y, 0.0, 0.0,
+                                    0.0, 0.0, size.z, 0.0,
+                                    0.0, 0.0, 0.0, 1.0)).
+                mul_m(&translation(Vector3::new(-0.5f32, -0.5, -0.5)));
+
+            unsafe {
+                gl::UniformMatrix4fv(self.res.entity_uniform_model, 1, gl::FALSE, model.ptr());
+                gl::Uniform4fv(self.res.entity_uniform_color, 1, color.ptr());
+            }
+            gl::DrawArrays(gl::TRIANGLES, 0, 36);
+            self.count_draw(gl::TRIANGLES, 36);
+        }
+
+        gl::DisableVertexAttribArray(self.res.entity_attr_position);
+        gl::UseProgram(0);
+        gl::Disable(gl::CULL_FACE);
+        gl::Disable(gl::DEPTH_TEST);
+    }
+
+    /// Draws blocktype held in the lower right corner of the view, posed by
+    /// hand. Call last among the world passes: it clears the depth buffer so
+    /// the block never sinks into nearby terrain.
+    pub fn render_held_block(&self, blocktype: BlockType, hand: &Hand, camera_angle: Vector2<f64>) {
+        let (position, pitch) = hand.pose();
+        let model = translation(position).
+            mul_m(&from_axis_angle(Vector3::unit_x(), rad(-pitch))).
+            mul_m(&from_axis_angle(Vector3::unit_y(), rad(0.6f32))).
+            mul_m(&scale(HELD_BLOCK_SIZE)).
+            mul_m(&translation(Vector3::new(-0.5f32, -0.5, -0.5)));
+
+        /* The block is drawn in view space, so the sun has to be too */
+        let rotation = from_axis_angle(Vector3::unit_x(), rad(camera_angle.x as f32)).
+            mul_m(&from_axis_angle(Vector3::unit_y(), rad(camera_angle.y as f32)));
+        let sun_direction = rotation.mul_v(&self.sun_direction.extend(0.0)).truncate();
+        let view = Matrix4::<f32>::identity();
+        let projection = self.projection();
+
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+        gl::Enable(gl::DEPTH_TEST);
+        gl::Enable(gl::CULL_FACE);
+
+        gl::UseProgram(self.res.entity_program);
+        unsafe {
+            gl::UniformMatrix4fv(self.res.entity_uniform_projection, 1, gl::FALSE, projection.ptr());
+            gl::UniformMatrix4fv(self.res.entity_uniform_view, 1, gl::FALSE, view.ptr());
+            gl::UniformMatrix4fv(self.res.entity_uniform_model, 1, gl::FALSE, model.ptr());
+            gl::Uniform3fv(self.res.entity_uniform_light_direction, 1, sun_direction.ptr());
+            gl::Uniform4fv(self.res.entity_uniform_color, 1, self.color_scheme.apply_rgba(blocktype.color()).ptr());
+        }
+
+        gl::EnableVertexAttribArray(self.res.entity_attr_position);
+        self.res.overlay_vbo.bind();
+        self.count(|s| s.state_changes += 2);
+        unsafe {
+            gl::VertexAttribPointer(self.res.entity_attr_position, 3, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<OverlayVertex>() as GLint,
+                                    ptr::null());
+        }
+
+        gl::DrawArrays(gl::TRIANGLES, 0, 36);
+        self.count_draw(gl::TRIANGLES, 36);
+
+        gl::DisableVertexAttribArray(self.res.entity_attr_position);
+        gl::UseProgram(0);
+        gl::Disable(gl::CULL_FACE);
+        gl::Disable(gl::DEPTH_TEST);
+    }
+
+    /// Draws the shapes queued in debug. Call after render() with the same
+    /// camera.
+    pub fn render_debug(
+            &self,
+            debug: &DebugDraw,
+            camera_position: Vector3<f64>,
+            camera_angle: Vector2<f64>)
+    {
+        let lines = debug.lines.as_slice();
+        if lines.is_empty() {
+            return;
+        }
+
+        let (origin, _, camera) = view(camera_position, camera_angle);
+
+        let mut vertices = Vec::with_capacity(lines.len() * 2);
+        for line in lines.iter() {
+            vertices.push(LineVertex { position: relative(line.from, origin), color: line.color });
+            vertices.push(LineVertex { position: relative(line.to, origin), color: line.color });
+        }
+
+        self.draw_lines(vertices.as_slice(), gl::LINES, &camera);
+    }
+
+    /// Draws falling rain as streaks or snow as points. Call after render()
+    /// with the same camera.
+    pub fn render_weather(
+            &self,
+            weather: &WeatherState,
+            camera_position: Vector3<f64>,
+            camera_angle: Vector2<f64>)
+    {
+        let particles = weather.particles();
+        if particles.is_empty() {
+            return;
+        }
+
+        let (origin, _, camera) = view(camera_position, camera_angle);
+
+        let mut vertices = Vec::with_capacity(particles.len() * 2);
+        if weather.weather() == WeatherSnow {
+            for &p in particles.iter() {
+                vertices.push(LineVertex { position: relative(p, origin), color: SNOW_COLOR });
+            }
+            gl::PointSize(2.0);
+            self.draw_lines(vertices.as_slice(), gl::POINTS, &camera);
+            gl::PointSize(1.0);
+        } else {
+            for &p in particles.iter() {
+                let top = p.add_v(&Vector3::new(0.0, RAIN_STREAK, 0.0));
+                vertices.push(LineVertex { position: relative(p, origin), color: RAIN_COLOR });
+                vertices.push(LineVertex { position: relative(top, origin), color: RAIN_COLOR });
+            }
+            self.draw_lines(vertices.as_slice(), gl::LINES, &camera);
+        }
+    }
+
+    /// Draws the world border as translucent walls, where they're within the
+    /// visible radius. Call after render() with the same camera.
+    pub fn render_border(
+            &self,
+            border: &WorldBorder,
+            camera_position: Vector3<f64>,
+            camera_angle: Vector2<f64>)
+    {
+        let (origin, _, camera) = view(camera_position, camera_angle);
+        let reach = (VISIBLE_RADIUS * CHUNK_SIZEu) as f64;
+        let r = border.radius as f64;
+        let bottom = camera_position.y - BORDER_WALL_HEIGHT;
+        let top = camera_position.y + BORDER_WALL_HEIGHT;
+
+        let mut vertices = Vec::new();
+        /* One wall along z at each x edge, then one along x at each z edge */
+        for &(along_z, edge, center) in [(true, -r, camera_position.z), (true, r, camera_position.z),
+                                         (false, -r, camera_position.x), (false, r, camera_position.x)].iter() {
+            let distance = if along_z { camera_position.x - edge } else { camera_position.z - edge };
+            if distance.abs() > reach {
+                continue;
+            }
+            let from = (center - reach).max(-r);
+            let to = (center + reach).min(r);
+            if from >= to {
+                continue;
+            }
+            let corner = |a: f64, y: f64| {
+                let p = if along_z { Vector3::new(edge, y, a) } else { Vector3::new(a, y, edge) };
+                LineVertex { position: relative(p, origin), color: BORDER_COLOR }
+            };
+            vertices.push(corner(from, bottom));
+            vertices.push(corner(to, bottom));
+            vertices.push(corner(from, top));
+            vertices.push(corner(from, top));
+            vertices.push(corner(to, bottom));
+            vertices.push(corner(to, top));
+        }
+
+        if !vertices.is_empty() {
+            gl::Disable(gl::CULL_FACE);
+            gl::DepthMask(gl::FALSE);
+            self.draw_lines(vertices.as_slice(), gl::TRIANGLES, &camera);
+            gl::DepthMask(gl::TRUE);
+        }
+    }
+
+    /// Draws a flat square over each chunk column that hasn't loaded yet,
+    /// fogged and curved like the chunks that will replace it.
+    pub fn render_placeholders(
+            &self,
+            placeholders: &[Placeholder],
+            camera_position: Vector3<f64>,
+            camera_angle: Vector2<f64>)
+    {
+        if placeholders.is_empty() {
+            return;
+        }
+        let (origin, camera_position, camera) = view(camera_position, camera_angle);
+        let size = CHUNK_SIZE as f32;
+        /* Lit like the top of a block, as the horizon is */
+        let brightness = face_brightness(self.sun_direction)[4];
+
+        let mut vertices = Vec::with_capacity(placeholders.len() * 6);
+        for placeholder in placeholders.iter() {
+            let corner = relative(placeholder.corner, origin);
+            let ground = self.color_scheme.apply(placeholder.color);
+            /* Dropped by the same amount as a chunk at that corner */
+            let horiz_dist = Vector3::new(camera_position.x - corner.x, 0.0, camera_position.z - corner.z).length();
+            let y = corner.y - curvature_drop(horiz_dist);
+            let vertex = |dx: f32, dz: f32| {
+                let p = Vector3::new(corner.x + dx, y, corner.z + dz);
+                let d = p.sub_v(&camera_position).length();
+                let fog = (-(d * d) * self.fog_density * self.fog_density * 1.44).exp2().max(0.0).min(1.0);
+                let color = self.sky_color.mul_s(1.0 - fog).add_v(&ground.mul_s(brightness * fog));
+                LineVertex { position: p, color: Vector4::new(color.x, color.y, color.z, 1.0) }
+            };
+            vertices.push(vertex(0.0, 0.0));
+            vertices.push(vertex(size, 0.0));
+            vertices.push(vertex(0.0, size));
+            vertices.push(vertex(0.0, size));
+            vertices.push(vertex(size, 0.0));
+            vertices.push(vertex(size, size));
+        }
+
+        gl::Disable(gl::CULL_FACE);
+        self.draw_lines(vertices.as_slice(), gl::TRIANGLES, &camera);
+    }
+
+    /// Draws vertices with the line program, depth tested and blended.
+    fn draw_lines(&self, vertices: &[LineVertex], mode: GLenum, camera: &Matrix4<f32>) {
+        let projection = self.projection();
+        let vbo = hgl::Vbo::from_data(vertices, hgl::StreamDraw);
+        self.count(|s| s.buffer_uploads += 1);
+
+        gl::Enable(gl::DEPTH_TEST);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+        gl::UseProgram(self.res.line_program);
+        self.count(|s| s.state_changes += 2);
+        unsafe {
+            gl::UniformMatrix4fv(self.res.line_uniform_projection, 1, gl::FALSE, projection.ptr());
+            gl::UniformMatrix4fv(self.res.line_uniform_view, 1, gl::FALSE, camera.ptr());
+        }
+
+        gl::EnableVertexAttribArray(self.res.line_attr_position);
+        gl::EnableVertexAttribArray(self.res.line_attr_color);
+        vbo.bind();
+        unsafe {
+            gl::VertexAttribPointer(self.res.line_attr_position, 3, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<LineVertex>() as GLint,
+                                    ptr::null());
+            gl::VertexAttribPointer(self.res.line_attr_color, 4, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<LineVertex>() as GLint,
+                                    std::mem::transmute(offset_of!(LineVertex, color)));
+        }
+
+        gl::DrawArrays(mode, 0, vertices.len() as GLint);
+        self.count_draw(mode, vertices.len());
+
+        gl::DisableVertexAttribArray(self.res.line_attr_position);
+        gl::DisableVertexAttribArray(self.res.line_attr_color);
+        gl::UseProgram(0);
+        gl::Disable(gl::BLEND);
+        gl::Disable(gl::DEPTH_TEST);
+    }
+
     pub fn reload_resources(&mut self) {
-        match Resources::load() {
+        match Resources::load(self.color_scheme) {
             Ok(res) => {
                 self.res = res;
+                self.apply_anisotropy();
             },
             Err(msg) => println!("Error reloading graphics resources: {}", msg),
         }
     }
 
+    pub fn wireframe_mode(&self) -> bool {
+        match self.mode {
+            RenderModeWireframe => true,
+            RenderModeNormal => false,
+        }
+    }
+
+    pub fn set_wireframe_mode(&mut self, wireframe: bool) {
+        self.mode = if wireframe { RenderModeWireframe } else { RenderModeNormal };
+    }
+
     pub fn toggle_wireframe_mode(&mut self) {
         self.mode = match self.mode {
             RenderModeWireframe => RenderModeNormal,
@@ -239,10 +1066,135 @@ impl Renderer {
         };
     }
 
+    /// Sets the clear color and the color and density of the fog blending
+    /// distant terrain into it.
+    pub fn set_sky(&mut self, color: Vector3<f32>, fog_density: f32) {
+        self.sky_color = color;
+        self.fog_density = fog_density;
+    }
+
+    /// Lighting is recomputed from the new direction on the next frame.
+    pub fn set_sun_direction(&mut self, direction: Vector3<f32>) {
+        self.sun_direction = direction.normalize();
+    }
+
+    /// Sets the anisotropic filtering level of the mipmapped textures, up to
+    /// what the driver supports. 1 turns it off.
+    pub fn set_anisotropy(&mut self, level: f32) {
+        self.anisotropy = level.max(1.0).min(texture::max_anisotropy());
+        self.apply_anisotropy();
+    }
+
+    /// The level in effect, which is 1 if the driver doesn't support
+    /// anisotropic filtering.
+    pub fn anisotropy(&self) -> f32 {
+        self.anisotropy
+    }
+
+    fn apply_anisotropy(&self) {
+        texture::set_anisotropy(self.res.texture, self.anisotropy);
+        texture::set_anisotropy(self.res.cloud_texture, self.anisotropy);
+    }
+
+    /// Recolors blocks and entities for scheme.
+    pub fn set_color_scheme(&mut self, scheme: ColorScheme) {
+        if scheme == self.color_scheme {
+            return;
+        }
+        self.color_scheme = scheme;
+        let block_table = texture::make_block_table(scheme);
+        unsafe { gl::DeleteTextures(1, &self.res.block_table); }
+        self.res.block_table = block_table;
+    }
+
+    /// Nothing farther than distance from the camera is drawn.
+    pub fn set_far_plane(&mut self, distance: f32) {
+        self.far_plane = distance;
+    }
+
+    /// Animates flowing blocks, like lava, by tick_length seconds.
+    pub fn tick(&mut self, tick_length: f64) {
+        self.time += tick_length;
+    }
+
+    /// Returns what has been drawn since the last reset_stats().
+    pub fn stats(&self) -> RenderStats {
+        self.stats.get()
+    }
+
+    /// Starts counting from zero, e.g. at the end of each frame.
+    pub fn reset_stats(&self) {
+        self.stats.set(RenderStats::new());
+    }
+
+    fn count(&self, f: |&mut RenderStats|) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    /// Counts a draw call of vertices vertices (or elements) in mode.
+    fn count_draw(&self, mode: GLenum, vertices: uint) {
+        self.count(|s| {
+            s.draw_calls += 1;
+            if mode == gl::TRIANGLES {
+                s.triangles += vertices / 3;
+            }
+        });
+    }
+
     pub fn set_window_size(&mut self, window_size: Vector2<u32>) {
         self.window_size = window_size;
     }
 
+    pub fn window_size(&self) -> Vector2<u32> {
+        self.window_size
+    }
+
+    /// Draws only tile (x, y) of the view, counting from the bottom left,
+    /// enlarged to fill the window as if the window were scale times
+    /// bigger. None draws the whole view.
+    pub fn set_tile(&mut self, tile: Option<(u32, u32, u32)>) {
+        self.tile = tile;
+    }
+
+    fn projection(&self) -> Matrix4<f32> {
+        let aspect_ratio = self.window_size.x as f32 / self.window_size.y as f32;
+
+        let projection = cgmath::projection::perspective(
+            deg(FIELD_OF_VIEW),
+            aspect_ratio,
+            NEAR_PLANE, self.far_plane);
+
+        match self.tile {
+            Some((scale, x, y)) => tile_projection(scale, x, y).mul_m(&projection),
+            None => projection,
+        }
+    }
+
+    /// Returns the corners of the view frustum in world space: the near
+    /// plane's, then the far plane's, each counterclockwise from the bottom
+    /// left. Ignores the tile set by set_tile().
+    pub fn frustum_corners(&self, camera_position: Vector3<f64>, camera_angle: Vector2<f64>) -> Vec<Vector3<f64>> {
+        let aspect_ratio = self.window_size.x as f32 / self.window_size.y as f32;
+        let tan = (FIELD_OF_VIEW.to_radians() / 2.0).tan();
+
+        /* Undoes the camera rotation in view() */
+        let rotation_y = from_axis_angle(Vector3::unit_y(), rad(-camera_angle.y as f32));
+        let rotation_x = from_axis_angle(Vector3::unit_x(), rad(-camera_angle.x as f32));
+        let to_world = rotation_y.mul_m(&rotation_x);
+
+        let mut corners = Vec::with_capacity(8);
+        for &d in [NEAR_PLANE, self.far_plane].iter() {
+            let (w, h) = (d * tan * aspect_ratio, d * tan);
+            for &(sx, sy) in [(-1.0f32, -1.0f32), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].iter() {
+                let v = to_world.mul_v(&Vector4::new(sx * w, sy * h, -d, 0.0));
+                corners.push(camera_position.add_v(&Vector3::new(v.x as f64, v.y as f64, v.z as f64)));
+            }
+        }
+        corners
+    }
+
     fn bind_mesh(&self, mesh: &Mesh) {
         match (&mesh.vertex_buffer, &mesh.element_buffer) {
             (&Some(ref vbo), &Some(ref ebo)) => unsafe {
@@ -257,6 +1209,7 @@ impl Renderer {
                                         std::mem::transmute(offset_of!(mesh::VertexData, blocktype)));
 
                 ebo.bind();
+                self.count(|s| s.state_changes += 2);
             },
         _ => {}
         }
@@ -268,65 +1221,344 @@ struct Resources {
     vertex_shader: GLuint,
     fragment_shader: GLuint,
     texture: GLuint,
+    block_table: GLuint,
     uniform_view: GLint,
     uniform_projection: GLint,
     uniform_camera_position: GLint,
     uniform_chunk_position: GLint,
+    uniform_texture_origin: GLint,
     uniform_texture: GLint,
-    uniform_light_direction: GLint,
+    uniform_block_table: GLint,
+    uniform_block_table_size: GLint,
+    uniform_block_table_rows: GLint,
+    uniform_biome_tints: GLint,
+    uniform_chunk_size: GLint,
+    uniform_time: GLint,
+    uniform_face_brightness: GLint,
+    uniform_face: GLint,
     uniform_normal: GLint,
+    uniform_fog_color: GLint,
+    uniform_fog_density: GLint,
+    uniform_voxels: GLint,
+    uniform_use_voxels: GLint,
+    uniform_fade: GLint,
+    uniform_voxel_texture_size: GLint,
     attr_position: GLuint,
     attr_blocktype: GLuint,
+    overlay_program: GLuint,
+    overlay_vertex_shader: GLuint,
+    overlay_fragment_shader: GLuint,
+    crack_texture: GLuint,
+    overlay_vbo: hgl::Vbo,
+    overlay_uniform_view: GLint,
+    overlay_uniform_projection: GLint,
+    overlay_uniform_block_position: GLint,
+    overlay_uniform_stage: GLint,
+    overlay_uniform_texture: GLint,
+    overlay_attr_position: GLuint,
+    overlay_attr_texcoord: GLuint,
+    entity_program: GLuint,
+    entity_vertex_shader: GLuint,
+    entity_fragment_shader: GLuint,
+    entity_uniform_view: GLint,
+    entity_uniform_projection: GLint,
+    entity_uniform_model: GLint,
+    entity_uniform_color: GLint,
+    entity_uniform_light_direction: GLint,
+    entity_attr_position: GLuint,
+    translucent_program: GLuint,
+    translucent_vertex_shader: GLuint,
+    translucent_fragment_shader: GLuint,
+    translucent_uniform_view: GLint,
+    translucent_uniform_projection: GLint,
+    translucent_uniform_chunk_position: GLint,
+    translucent_uniform_texture_origin: GLint,
+    translucent_uniform_face_brightness: GLint,
+    translucent_uniform_texture: GLint,
+    translucent_uniform_block_table: GLint,
+    translucent_uniform_block_table_size: GLint,
+    translucent_uniform_block_table_rows: GLint,
+    translucent_uniform_biome_tints: GLint,
+    translucent_uniform_chunk_size: GLint,
+    translucent_uniform_time: GLint,
+    translucent_uniform_fog_color: GLint,
+    translucent_uniform_fog_density: GLint,
+    translucent_uniform_fade: GLint,
+    translucent_attr_position: GLuint,
+    translucent_attr_blocktype: GLuint,
+    translucent_attr_face: GLuint,
+    cloud_program: GLuint,
+    cloud_vertex_shader: GLuint,
+    cloud_fragment_shader: GLuint,
+    cloud_texture: GLuint,
+    cloud_vbo: hgl::Vbo,
+    cloud_uniform_view: GLint,
+    cloud_uniform_projection: GLint,
+    cloud_uniform_center: GLint,
+    cloud_uniform_radius: GLint,
+    cloud_uniform_texture_offset: GLint,
+    cloud_uniform_tile_size: GLint,
+    cloud_uniform_texture: GLint,
+    cloud_uniform_density: GLint,
+    cloud_uniform_brightness: GLint,
+    cloud_attr_position: GLuint,
+    line_program: GLuint,
+    line_vertex_shader: GLuint,
+    line_fragment_shader: GLuint,
+    line_uniform_view: GLint,
+    line_uniform_projection: GLint,
+    line_attr_position: GLuint,
+    line_attr_color: GLuint,
+    horizon_program: GLuint,
+    horizon_vertex_shader: GLuint,
+    horizon_fragment_shader: GLuint,
+    horizon_uniform_view: GLint,
+    horizon_uniform_projection: GLint,
+    horizon_uniform_center: GLint,
+    horizon_uniform_camera_position: GLint,
+    horizon_uniform_fog_color: GLint,
+    horizon_uniform_fog_density: GLint,
+    horizon_uniform_brightness: GLint,
+    horizon_uniform_inner_radius: GLint,
+    horizon_attr_position: GLuint,
+    horizon_attr_color: GLuint,
 }
 
 impl Resources {
-    fn load() -> Result<Resources, String> {
-        let vs_src = std::io::fs::File::open_mode(&std::path::Path::new("shaders/main.vs.glsl"), std::io::Open, std::io::Read).unwrap().read_to_end().unwrap();
-        let vs = match compile_shader(vs_src.slice(0, vs_src.len()), gl::VERTEX_SHADER) {
-            Ok(vs) => vs,
-            Err(msg) => { return Err(String::from_str("vertex shader ") + msg) },
-        };
-
-        let fs_src = std::io::fs::File::open_mode(&std::path::Path::new("shaders/main.fs.glsl"), std::io::Open, std::io::Read).unwrap().read_to_end().unwrap();
-        let fs = match compile_shader(fs_src.slice(0, fs_src.len()), gl::FRAGMENT_SHADER) {
-            Ok(fs) => fs,
-            Err(msg) => { return Err(String::from_str("fragment shader ") + msg) },
-        };
-
-        let program = match link_program(vs, fs) {
-            Ok(program) => program,
-            Err(msg) => { return Err(String::from_str("linking ") + msg) },
-        };
+    fn load(scheme: ColorScheme) -> Result<Resources, String> {
+        let (program, vs, fs) = try!(load_program("main"));
+        let (overlay_program, overlay_vs, overlay_fs) = try!(load_program("overlay"));
+        let (entity_program, entity_vs, entity_fs) = try!(load_program("entity"));
+        let (line_program, line_vs, line_fs) = try!(load_program("line"));
+        let (translucent_program, translucent_vs, translucent_fs) = try!(load_program("translucent"));
+        let (cloud_program, cloud_vs, cloud_fs) = try!(load_program("clouds"));
+        let (horizon_program, horizon_vs, horizon_fs) = try!(load_program("horizon"));
 
         let texture = texture::make_noise_texture();
+        let block_table = texture::make_block_table(scheme);
 
         let uniform_view = unsafe { "view".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_projection = unsafe { "projection".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_camera_position = unsafe { "camera_position".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_chunk_position = unsafe { "chunk_position".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_texture_origin = unsafe { "texture_origin".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_texture = unsafe { "texture".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
-        let uniform_light_direction = unsafe { "light_direction".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_block_table = unsafe { "block_table".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_block_table_size = unsafe { "block_table_size".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_block_table_rows = unsafe { "block_table_rows".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_biome_tints = unsafe { "biome_tints".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_chunk_size = unsafe { "chunk_size".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_time = unsafe { "time".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_face_brightness = unsafe { "face_brightness".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_face = unsafe { "face".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_normal = unsafe { "normal".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_fog_color = unsafe { "fog_color".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_fog_density = unsafe { "fog_density".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_voxels = unsafe { "voxels".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_use_voxels = unsafe { "use_voxels".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_fade = unsafe { "fade".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_voxel_texture_size = unsafe { "voxel_texture_size".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
 
         let attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
         assert!(attr_position as u32 != gl::INVALID_VALUE);
         let attr_blocktype = unsafe { "blocktype".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
         assert!(attr_blocktype as u32 != gl::INVALID_VALUE);
 
+        let crack_texture = texture::make_crack_texture(CRACK_STAGES);
+        let overlay_vertices = make_overlay_vertices();
+        let overlay_vbo = hgl::Vbo::from_data(overlay_vertices.as_slice(), hgl::StaticDraw);
+
+        let overlay_uniform_view = unsafe { "view".with_c_str(|ptr| gl::GetUniformLocation(overlay_program, ptr)) };
+        let overlay_uniform_projection = unsafe { "projection".with_c_str(|ptr| gl::GetUniformLocation(overlay_program, ptr)) };
+        let overlay_uniform_block_position = unsafe { "block_position".with_c_str(|ptr| gl::GetUniformLocation(overlay_program, ptr)) };
+        let overlay_uniform_stage = unsafe { "stage".with_c_str(|ptr| gl::GetUniformLocation(overlay_program, ptr)) };
+        let overlay_uniform_texture = unsafe { "texture".with_c_str(|ptr| gl::GetUniformLocation(overlay_program, ptr)) };
+
+        let overlay_attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(overlay_program, ptr) as GLuint) };
+        assert!(overlay_attr_position as u32 != gl::INVALID_VALUE);
+        let overlay_attr_texcoord = unsafe { "texcoord".with_c_str(|ptr| gl::GetAttribLocation(overlay_program, ptr) as GLuint) };
+        assert!(overlay_attr_texcoord as u32 != gl::INVALID_VALUE);
+
+        let entity_uniform_view = unsafe { "view".with_c_str(|ptr| gl::GetUniformLocation(entity_program, ptr)) };
+        let entity_uniform_projection = unsafe { "projection".with_c_str(|ptr| gl::GetUniformLocation(entity_program, ptr)) };
+        let entity_uniform_model = unsafe { "model".with_c_str(|ptr| gl::GetUniformLocation(entity_program, ptr)) };
+        let entity_uniform_color = unsafe { "color".with_c_str(|ptr| gl::GetUniformLocation(entity_program, ptr)) };
+        let entity_uniform_light_direction = unsafe { "light_direction".with_c_str(|ptr| gl::GetUniformLocation(entity_program, ptr)) };
+
+        let entity_attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(entity_program, ptr) as GLuint) };
+        assert!(entity_attr_position as u32 != gl::INVALID_VALUE);
+
+        let translucent_uniform_view = unsafe { "view".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_projection = unsafe { "projection".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_chunk_position = unsafe { "chunk_position".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_texture_origin = unsafe { "texture_origin".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_face_brightness = unsafe { "face_brightness".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_texture = unsafe { "texture".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_block_table = unsafe { "block_table".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_block_table_size = unsafe { "block_table_size".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_block_table_rows = unsafe { "block_table_rows".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_biome_tints = unsafe { "biome_tints".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_chunk_size = unsafe { "chunk_size".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_time = unsafe { "time".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_fog_color = unsafe { "fog_color".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_fog_density = unsafe { "fog_density".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+        let translucent_uniform_fade = unsafe { "fade".with_c_str(|ptr| gl::GetUniformLocation(translucent_program, ptr)) };
+
+        let translucent_attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(translucent_program, ptr) as GLuint) };
+        assert!(translucent_attr_position as u32 != gl::INVALID_VALUE);
+        let translucent_attr_blocktype = unsafe { "blocktype".with_c_str(|ptr| gl::GetAttribLocation(translucent_program, ptr) as GLuint) };
+        assert!(translucent_attr_blocktype as u32 != gl::INVALID_VALUE);
+        let translucent_attr_face = unsafe { "face".with_c_str(|ptr| gl::GetAttribLocation(translucent_program, ptr) as GLuint) };
+        assert!(translucent_attr_face as u32 != gl::INVALID_VALUE);
+
+        let cloud_texture = texture::make_cloud_texture();
+        let cloud_vertices = [
+            Vector2::new(-1.0f32, -1.0), Vector2::new(1.0, -1.0), Vector2::new(1.0, 1.0),
+            Vector2::new(-1.0f32, -1.0), Vector2::new(1.0, 1.0), Vector2::new(-1.0, 1.0),
+        ];
+        let cloud_vbo = hgl::Vbo::from_data(cloud_vertices.as_slice(), hgl::StaticDraw);
+
+        let cloud_uniform_view = unsafe { "view".with_c_str(|ptr| gl::GetUniformLocation(cloud_program, ptr)) };
+        let cloud_uniform_projection = unsafe { "projection".with_c_str(|ptr| gl::GetUniformLocation(cloud_program, ptr)) };
+        let cloud_uniform_center = unsafe { "center".with_c_str(|ptr| gl::GetUniformLocation(cloud_program, ptr)) };
+        let cloud_uniform_radius = unsafe { "radius".with_c_str(|ptr| gl::GetUniformLocation(cloud_program, ptr)) };
+        let cloud_uniform_texture_offset = unsafe { "texture_offset".with_c_str(|ptr| gl::GetUniformLocation(cloud_program, ptr)) };
+        let cloud_uniform_tile_size = unsafe { "tile_size".with_c_str(|ptr| gl::GetUniformLocation(cloud_program, ptr)) };
+        let cloud_uniform_texture = unsafe { "texture".with_c_str(|ptr| gl::GetUniformLocation(cloud_program, ptr)) };
+        let cloud_uniform_density = unsafe { "density".with_c_str(|ptr| gl::GetUniformLocation(cloud_program, ptr)) };
+        let cloud_uniform_brightness = unsafe { "brightness".with_c_str(|ptr| gl::GetUniformLocation(cloud_program, ptr)) };
+
+        let cloud_attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(cloud_program, ptr) as GLuint) };
+        assert!(cloud_attr_position as u32 != gl::INVALID_VALUE);
+
+        let horizon_uniform_view = unsafe { "view".with_c_str(|ptr| gl::GetUniformLocation(horizon_program, ptr)) };
+        let horizon_uniform_projection = unsafe { "projection".with_c_str(|ptr| gl::GetUniformLocation(horizon_program, ptr)) };
+        let horizon_uniform_center = unsafe { "center".with_c_str(|ptr| gl::GetUniformLocation(horizon_program, ptr)) };
+        let horizon_uniform_camera_position = unsafe { "camera_position".with_c_str(|ptr| gl::GetUniformLocation(horizon_program, ptr)) };
+        let horizon_uniform_fog_color = unsafe { "fog_color".with_c_str(|ptr| gl::GetUniformLocation(horizon_program, ptr)) };
+        let horizon_uniform_fog_density = unsafe { "fog_density".with_c_str(|ptr| gl::GetUniformLocation(horizon_program, ptr)) };
+        let horizon_uniform_brightness = unsafe { "brightness".with_c_str(|ptr| gl::GetUniformLocation(horizon_program, ptr)) };
+        let horizon_uniform_inner_radius = unsafe { "inner_radius".with_c_str(|ptr| gl::GetUniformLocation(horizon_program, ptr)) };
+
+        let horizon_attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(horizon_program, ptr) as GLuint) };
+        assert!(horizon_attr_position as u32 != gl::INVALID_VALUE);
+        let horizon_attr_color = unsafe { "color".with_c_str(|ptr| gl::GetAttribLocation(horizon_program, ptr) as GLuint) };
+        assert!(horizon_attr_color as u32 != gl::INVALID_VALUE);
+
+        let line_uniform_view = unsafe { "view".with_c_str(|ptr| gl::GetUniformLocation(line_program, ptr)) };
+        let line_uniform_projection = unsafe { "projection".with_c_str(|ptr| gl::GetUniformLocation(line_program, ptr)) };
+
+        let line_attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(line_program, ptr) as GLuint) };
+        assert!(line_attr_position as u32 != gl::INVALID_VALUE);
+        let line_attr_color = unsafe { "color".with_c_str(|ptr| gl::GetAttribLocation(line_program, ptr) as GLuint) };
+        assert!(line_attr_color as u32 != gl::INVALID_VALUE);
+
         Ok(Resources {
             program: program,
             vertex_shader: vs,
             fragment_shader: fs,
             texture: texture,
+            block_table: block_table,
             uniform_view: uniform_view,
             uniform_projection: uniform_projection,
             uniform_camera_position: uniform_camera_position,
             uniform_chunk_position: uniform_chunk_position,
+            uniform_texture_origin: uniform_texture_origin,
             uniform_texture: uniform_texture,
-            uniform_light_direction: uniform_light_direction,
+            uniform_block_table: uniform_block_table,
+            uniform_block_table_size: uniform_block_table_size,
+            uniform_block_table_rows: uniform_block_table_rows,
+            uniform_biome_tints: uniform_biome_tints,
+            uniform_chunk_size: uniform_chunk_size,
+            uniform_time: uniform_time,
+            uniform_face_brightness: uniform_face_brightness,
+            uniform_face: uniform_face,
             uniform_normal: uniform_normal,
+            uniform_fog_color: uniform_fog_color,
+            uniform_fog_density: uniform_fog_density,
+            uniform_voxels: uniform_voxels,
+            uniform_use_voxels: uniform_use_voxels,
+            uniform_fade: uniform_fade,
+            uniform_voxel_texture_size: uniform_voxel_texture_size,
             attr_position: attr_position,
             attr_blocktype: attr_blocktype,
+            overlay_program: overlay_program,
+            overlay_vertex_shader: overlay_vs,
+            overlay_fragment_shader: overlay_fs,
+            crack_texture: crack_texture,
+            overlay_vbo: overlay_vbo,
+            overlay_uniform_view: overlay_uniform_view,
+            overlay_uniform_projection: overlay_uniform_projection,
+            overlay_uniform_block_position: overlay_uniform_block_position,
+            overlay_uniform_stage: overlay_uniform_stage,
+            overlay_uniform_texture: overlay_uniform_texture,
+            overlay_attr_position: overlay_attr_position,
+            overlay_attr_texcoord: overlay_attr_texcoord,
+            entity_program: entity_program,
+            entity_vertex_shader: entity_vs,
+            entity_fragment_shader: entity_fs,
+            entity_uniform_view: entity_uniform_view,
+            entity_uniform_projection: entity_uniform_projection,
+            entity_uniform_model: entity_uniform_model,
+            entity_uniform_color: entity_uniform_color,
+            entity_uniform_light_direction: entity_uniform_light_direction,
+            entity_attr_position: entity_attr_position,
+            translucent_program: translucent_program,
+            translucent_vertex_shader: translucent_vs,
+            translucent_fragment_shader: translucent_fs,
+            translucent_uniform_view: translucent_uniform_view,
+            translucent_uniform_projection: translucent_uniform_projection,
+            translucent_uniform_chunk_position: translucent_uniform_chunk_position,
+            translucent_uniform_texture_origin: translucent_uniform_texture_origin,
+            translucent_uniform_face_brightness: translucent_uniform_face_brightness,
+            translucent_uniform_texture: translucent_uniform_texture,
+            translucent_uniform_block_table: translucent_uniform_block_table,
+            translucent_uniform_block_table_size: translucent_uniform_block_table_size,
+            translucent_uniform_block_table_rows: translucent_uniform_block_table_rows,
+            translucent_uniform_biome_tints: translucent_uniform_biome_tints,
+            translucent_uniform_chunk_size: translucent_uniform_chunk_size,
+            translucent_uniform_time: translucent_uniform_time,
+            translucent_uniform_fog_color: translucent_uniform_fog_color,
+            translucent_uniform_fog_density: translucent_uniform_fog_density,
+            translucent_uniform_fade: translucent_uniform_fade,
+            translucent_attr_position: translucent_attr_position,
+            translucent_attr_blocktype: translucent_attr_blocktype,
+            translucent_attr_face: translucent_attr_face,
+            cloud_program: cloud_program,
+            cloud_vertex_shader: cloud_vs,
+            cloud_fragment_shader: cloud_fs,
+            cloud_texture: cloud_texture,
+            cloud_vbo: cloud_vbo,
+            cloud_uniform_view: cloud_uniform_view,
+            cloud_uniform_projection: cloud_uniform_projection,
+            cloud_uniform_center: cloud_uniform_center,
+            cloud_uniform_radius: cloud_uniform_radius,
+            cloud_uniform_texture_offset: cloud_uniform_texture_offset,
+            cloud_uniform_tile_size: cloud_uniform_tile_size,
+            cloud_uniform_texture: cloud_uniform_texture,
+            cloud_uniform_density: cloud_uniform_density,
+            cloud_uniform_brightness: cloud_uniform_brightness,
+            cloud_attr_position: cloud_attr_position,
+            line_program: line_program,
+            line_vertex_shader: line_vs,
+            line_fragment_shader: line_fs,
+            line_uniform_view: line_uniform_view,
+            line_uniform_projection: line_uniform_projection,
+            line_attr_position: line_attr_position,
+            line_attr_color: line_attr_color,
+            horizon_program: horizon_program,
+            horizon_vertex_shader: horizon_vs,
+            horizon_fragment_shader: horizon_fs,
+            horizon_uniform_view: horizon_uniform_view,
+            horizon_uniform_projection: horizon_uniform_projection,
+            horizon_uniform_center: horizon_uniform_center,
+            horizon_uniform_camera_position: horizon_uniform_camera_position,
+            horizon_uniform_fog_color: horizon_uniform_fog_color,
+            horizon_uniform_fog_density: horizon_uniform_fog_density,
+            horizon_uniform_brightness: horizon_uniform_brightness,
+            horizon_uniform_inner_radius: horizon_uniform_inner_radius,
+            horizon_attr_position: horizon_attr_position,
+            horizon_attr_color: horizon_attr_color,
         })
     }
 }
@@ -334,27 +1566,152 @@ impl Resources {
 impl Drop for Resources {
     fn drop(&mut self) {
         unsafe { gl::DeleteTextures(1, &self.texture); }
+        unsafe { gl::DeleteTextures(1, &self.block_table); }
         gl::DeleteProgram(self.program);
         gl::DeleteShader(self.vertex_shader);
         gl::DeleteShader(self.fragment_shader);
+        unsafe { gl::DeleteTextures(1, &self.crack_texture); }
+        gl::DeleteProgram(self.overlay_program);
+        gl::DeleteShader(self.overlay_vertex_shader);
+        gl::DeleteShader(self.overlay_fragment_shader);
+        gl::DeleteProgram(self.entity_program);
+        gl::DeleteShader(self.entity_vertex_shader);
+        gl::DeleteShader(self.entity_fragment_shader);
+        gl::DeleteProgram(self.translucent_program);
+        gl::DeleteShader(self.translucent_vertex_shader);
+        gl::DeleteShader(self.translucent_fragment_shader);
+        unsafe { gl::DeleteTextures(1, &self.cloud_texture); }
+        gl::DeleteProgram(self.cloud_program);
+        gl::DeleteShader(self.cloud_vertex_shader);
+        gl::DeleteShader(self.cloud_fragment_shader);
+        gl::DeleteProgram(self.line_program);
+        gl::DeleteShader(self.line_vertex_shader);
+        gl::DeleteShader(self.line_fragment_shader);
+        gl::DeleteProgram(self.horizon_program);
+        gl::DeleteShader(self.horizon_vertex_shader);
+        gl::DeleteShader(self.horizon_fragment_shader);
+    }
+}
+
+struct LineVertex {
+    position: Vector3<f32>,
+    color: Vector4<f32>,
+}
+
+struct OverlayVertex {
+    position: Vector3<f32>,
+    texcoord: Vector2<f32>,
+}
+
+/// Slightly more than one so the overlay covers the block's faces.
+static OVERLAY_SCALE : f32 = 1.002;
+
+/// Returns the triangles of a unit cube, each face textured with a whole
+/// crack image.
+fn make_overlay_vertices() -> Vec<OverlayVertex> {
+    let corners = [(0.0f32, 0.0f32), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+    let mut vertices = Vec::new();
+
+    for axis in range(0u, 3) {
+        for side in range(0u, 2) {
+            for &i in [0u, 1, 2, 0, 2, 3].iter() {
+                let (u, v) = corners[i];
+                let mut p = [0.0f32, ..3];
+                p[axis] = side as f32;
+                p[(axis + 1) % 3] = u;
+                p[(axis + 2) % 3] = v;
+                let position = Vector3::new(p[0], p[1], p[2]).sub_s(0.5).mul_s(OVERLAY_SCALE).add_s(0.5);
+                vertices.push(OverlayVertex { position: position, texcoord: Vector2::new(u, v) });
+            }
+        }
     }
+
+    vertices
 }
 
-fn view_frustum_cull(m : &Matrix4<f32>, p: &Vector4<f32>) -> bool {
-    static L : f32 = CHUNK_SIZE as f32;
+/// Returns the origin that positions are made relative to when rendering,
+/// the camera position relative to it, and the view matrix.
+///
+/// Rendering is relative to the origin of the camera's chunk. The offsets are
+/// computed in integer and f64 space here so the f32 positions reaching the
+/// shaders stay small no matter how far the camera is from spawn.
+fn view(camera_position: Vector3<f64>, camera_angle: Vector2<f64>) -> (Vector3<i64>, Vector3<f32>, Matrix4<f32>) {
+    let origin = chunk::containing_chunk(camera_position).mul_s(CHUNK_SIZE as i64);
+    let camera_position = Vector3::new((camera_position.x - origin.x as f64) as f32,
+                                       (camera_position.y - origin.y as f64) as f32,
+                                       (camera_position.z - origin.z as f64) as f32);
+
+    let camera_translation = Matrix4::<f32>::from_cols(
+        Vector4::<f32>::unit_x(),
+        Vector4::<f32>::unit_y(),
+        Vector4::<f32>::unit_z(),
+        camera_position.mul_s(-1.0f32).extend(1.0f32));
+    let camera_rotation_x = from_axis_angle(Vector3::unit_x(), rad(camera_angle.x as f32));
+    let camera_rotation_y = from_axis_angle(Vector3::unit_y(), rad(camera_angle.y as f32));
+    let camera = camera_rotation_x.mul_m(&camera_rotation_y).mul_m(&camera_translation);
+
+    (origin, camera_position, camera)
+}
 
-    static vertices : [Vector4<f32>, ..8] = [
-        Vector4 { x: 0.0, y: 0.0, z: L,   w: 1.0 }, /* front bottom left */
-        Vector4 { x: L,   y: 0.0, z: L,   w: 1.0 }, /* front bottom right */
-        Vector4 { x: 0.0, y: L,   z: L,   w: 1.0 }, /* front top left */
-        Vector4 { x: L,   y: L,   z: L,   w: 1.0 }, /* front top right */
-        Vector4 { x: L,   y: 0.0, z: 0.0, w: 1.0 }, /* back bottom right */
-        Vector4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 }, /* back bottom left */
-        Vector4 { x: L,   y: L,   z: 0.0, w: 1.0 }, /* back top right */
-        Vector4 { x: 0.0, y: L,   z: 0.0, w: 1.0 }, /* back top left */
+/// Returns where the texture coordinates of chunk c start: its origin in
+/// blocks, wrapped around in integer space. Like the chunk offsets from
+/// view(), it stays a small f32 however far the chunk is from spawn, but
+/// the pattern carries on across chunk borders instead of starting over.
+fn texture_origin(c: Vector3<i64>) -> Vector3<f32> {
+    let origin = c.mul_s(CHUNK_SIZE as i64);
+    Vector3::new(origin.x.mod_floor(&TEXTURE_PERIOD) as f32,
+                 origin.y.mod_floor(&TEXTURE_PERIOD) as f32,
+                 origin.z.mod_floor(&TEXTURE_PERIOD) as f32)
+}
+
+/// Converts a world position to one relative to the origin returned by
+/// view().
+fn relative(p: Vector3<f64>, origin: Vector3<i64>) -> Vector3<f32> {
+    Vector3::new((p.x - origin.x as f64) as f32,
+                 (p.y - origin.y as f64) as f32,
+                 (p.z - origin.z as f64) as f32)
+}
+
+fn translation(v: Vector3<f32>) -> Matrix4<f32> {
+    Matrix4::from_cols(
+        Vector4::unit_x(),
+        Vector4::unit_y(),
+        Vector4::unit_z(),
+        v.extend(1.0f32))
+}
+
+fn scale(s: f32) -> Matrix4<f32> {
+    Matrix4::new(s, 0.0, 0.0, 0.0,
+                 0.0, s, 0.0, 0.0,
+                 0.0, 0.0, s, 0.0,
+                 0.0, 0.0, 0.0, 1.0)
+}
+
+/// Maps tile (x, y) of a scale by scale grid over clip space to the whole of
+/// clip space.
+fn tile_projection(scale: u32, x: u32, y: u32) -> Matrix4<f32> {
+    let s = scale as f32;
+    Matrix4::new(s, 0.0, 0.0, 0.0,
+                 0.0, s, 0.0, 0.0,
+                 0.0, 0.0, 1.0, 0.0,
+                 s - 1.0 - 2.0 * x as f32, s - 1.0 - 2.0 * y as f32, 0.0, 1.0)
+}
+
+/// Returns true if the axis-aligned box from min to max is entirely outside
+/// the view frustum.
+fn view_frustum_cull(m : &Matrix4<f32>, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+    let vertices = [
+        Vector4 { x: min.x, y: min.y, z: max.z, w: 1.0 }, /* front bottom left */
+        Vector4 { x: max.x, y: min.y, z: max.z, w: 1.0 }, /* front bottom right */
+        Vector4 { x: min.x, y: max.y, z: max.z, w: 1.0 }, /* front top left */
+        Vector4 { x: max.x, y: max.y, z: max.z, w: 1.0 }, /* front top right */
+        Vector4 { x: max.x, y: min.y, z: min.z, w: 1.0 }, /* back bottom right */
+        Vector4 { x: min.x, y: min.y, z: min.z, w: 1.0 }, /* back bottom left */
+        Vector4 { x: max.x, y: max.y, z: min.z, w: 1.0 }, /* back top right */
+        Vector4 { x: min.x, y: max.y, z: min.z, w: 1.0 }, /* back top left */
     ];
 
-    let clip_vertices: Vec<Vector4<f32>> = vertices.iter().map(|v| m.mul_v(&p.add_v(v))).collect();
+    let clip_vertices: Vec<Vector4<f32>> = vertices.iter().map(|v| m.mul_v(v)).collect();
 
     if clip_vertices.iter().all(|v| v.x < -v.w) {
         return true;
@@ -383,6 +1740,45 @@ fn view_frustum_cull(m : &Matrix4<f32>, p: &Vector4<f32>) -> bool {
     return false;
 }
 
+/// Returns how far a chunk first shown at shown_time has faded in, from 0
+/// (hidden in the fog) to 1.
+fn chunk_fade(now: u64, shown_time: u64) -> f32 {
+    if now <= shown_time {
+        return 0.0;
+    }
+    let elapsed = (now - shown_time) as f32 / 1e9;
+    (elapsed / CHUNK_FADE_TIME).min(1.0)
+}
+
+/// Calculate drop due to surface curvature
+fn curvature_drop(horiz_dist: f32) -> f32 {
+    static planet_radius : f32 = 6371000.0f32 / 5000.0f32;
+    let adj_horiz_dist = (horiz_dist - 100f32).max(0.0f32);
+    planet_radius - (planet_radius.powf(2.0f32) - adj_horiz_dist.powf(2.0f32)).sqrt()
+}
+
+/// Returns true if the bounding box of the whole region, including the
+/// curvature drop applied to its chunks, is outside the view frustum.
+/// Positions are relative to origin, like everything else in render().
+fn region_cull(m : &Matrix4<f32>, region: &Region, origin: Vector3<i64>, camera_position: Vector3<f32>) -> bool {
+    let size = CHUNK_SIZE as i64 * REGION_SIZE;
+    let offset = region.coord.mul_s(size).sub_v(&origin);
+    let min = Vector3::new(offset.x as f32, offset.y as f32, offset.z as f32);
+    let max = min.add_s(size as f32);
+
+    /* Nearest and farthest horizontal distances from the camera to the region */
+    let near_x = camera_position.x.max(min.x).min(max.x) - camera_position.x;
+    let near_z = camera_position.z.max(min.z).min(max.z) - camera_position.z;
+    let far_x = (camera_position.x - min.x).abs().max((camera_position.x - max.x).abs());
+    let far_z = (camera_position.z - min.z).abs().max((camera_position.z - max.z).abs());
+    let near_drop = curvature_drop((near_x*near_x + near_z*near_z).sqrt());
+    let far_drop = curvature_drop((far_x*far_x + far_z*far_z).sqrt());
+
+    view_frustum_cull(m,
+                      Vector3::new(min.x, min.y - far_drop, min.z),
+                      Vector3::new(max.x, max.y - near_drop, max.z))
+}
+
 fn face_visible(face : &mesh::Face, a: Vector3<i64>, b: Vector3<i64>) -> bool {
     let dp = b.sub_v(&a);
 
@@ -397,6 +1793,37 @@ fn face_visible(face : &mesh::Face, a: Vector3<i64>, b: Vector3<i64>) -> bool {
     }
 }
 
+/// Compiles and links shaders/<name>.vs.glsl and shaders/<name>.fs.glsl,
+/// returning the program and the two shaders.
+pub fn load_program(name: &str) -> Result<(GLuint, GLuint, GLuint), String> {
+    let vs_path = std::path::Path::new(format!("shaders/{}.vs.glsl", name));
+    let vs_src = match std::io::fs::File::open(&vs_path).read_to_end() {
+        Ok(src) => src,
+        Err(e) => return Err(format!("{}: {}", vs_path.display(), e)),
+    };
+    let vs = match compile_shader(vs_src.as_slice(), gl::VERTEX_SHADER) {
+        Ok(vs) => vs,
+        Err(msg) => { return Err(format!("{} vertex shader {}", name, msg)) },
+    };
+
+    let fs_path = std::path::Path::new(format!("shaders/{}.fs.glsl", name));
+    let fs_src = match std::io::fs::File::open(&fs_path).read_to_end() {
+        Ok(src) => src,
+        Err(e) => return Err(format!("{}: {}", fs_path.display(), e)),
+    };
+    let fs = match compile_shader(fs_src.as_slice(), gl::FRAGMENT_SHADER) {
+        Ok(fs) => fs,
+        Err(msg) => { return Err(format!("{} fragment shader {}", name, msg)) },
+    };
+
+    let program = match link_program(vs, fs) {
+        Ok(program) => program,
+        Err(msg) => { return Err(format!("linking {} {}", name, msg)) },
+    };
+
+    Ok((program, vs, fs))
+}
+
 fn compile_shader(src: &[u8], ty: GLenum) -> Result<GLuint, String> {
     let shader = gl::CreateShader(ty);
     unsafe {
@@ -443,3 +1870,74 @@ fn link_program(vs: GLuint, fs: GLuint) -> Result<GLuint, String> {
     }
     Ok(program)
 }
+
+#[test]
+fn test_face_brightness() {
+    let brightness = face_brightness(Vector3::new(0.0, -1.0, 0.0));
+    assert_eq!(brightness[4], AMBIENT_LIGHT + DIFFUSE_LIGHT); /* top */
+    assert_eq!(brightness[5], AMBIENT_LIGHT); /* bottom */
+    assert_eq!(brightness[0], AMBIENT_LIGHT); /* front */
+}
+
+#[test]
+fn test_debug_draw() {
+    let mut debug = DebugDraw::new();
+    debug.aabb(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0), Vector4::new(1.0, 1.0, 1.0, 1.0), 0.0);
+    assert_eq!(debug.len(), 12);
+    debug.line(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector4::new(1.0, 0.0, 0.0, 1.0), 1.0);
+    debug.sphere(Vector3::new(0.0, 0.0, 0.0), 1.0, Vector4::new(0.0, 1.0, 0.0, 1.0), 0.0);
+    assert_eq!(debug.len(), 13 + 3 * SPHERE_SEGMENTS);
+
+    /* Single frame shapes go after one frame, the rest once their lifetime is up */
+    debug.expire(0.5);
+    assert_eq!(debug.len(), 1);
+    debug.expire(0.5);
+    assert_eq!(debug.len(), 0);
+}
+
+#[test]
+fn test_tile_projection() {
+    /* The bottom right quarter of clip space, at two depths */
+    let m = tile_projection(2, 1, 0);
+    assert_eq!(m.mul_v(&Vector4::new(0.0, -1.0, 0.5, 1.0)), Vector4::new(-1.0, -1.0, 0.5, 1.0));
+    assert_eq!(m.mul_v(&Vector4::new(2.0, 0.0, 1.0, 2.0)), Vector4::new(2.0, 2.0, 1.0, 2.0));
+    assert_eq!(tile_projection(1, 0, 0), Matrix4::identity());
+}
+
+#[test]
+fn test_chunk_fade() {
+    let second = 1000000000u64;
+    assert_eq!(chunk_fade(5 * second, 5 * second), 0.0);
+    assert_eq!(chunk_fade(4 * second, 5 * second), 0.0);
+    assert_eq!(chunk_fade(5 * second + second / 4, 5 * second), 0.25 / CHUNK_FADE_TIME);
+    assert_eq!(chunk_fade(10 * second, 5 * second), 1.0);
+}
+
+#[test]
+fn test_render_stats() {
+    let mut total = RenderStats::new();
+    let frame = RenderStats { draw_calls: 10, triangles: 7, ..RenderStats::new() };
+    total.add(&frame);
+    total.add(&frame);
+    total.add(&RenderStats { chunks_culled: 4, ..RenderStats::new() });
+    assert_eq!(total.per_frame(3), RenderStats { draw_calls: 6, triangles: 4, chunks_culled: 1, ..RenderStats::new() });
+    assert_eq!(total.per_frame(0), total);
+}
+
+#[test]
+fn test_far_from_spawn() {
+    /* Everything reaching the shaders is small and exact millions of blocks out */
+    let (origin, camera_position, _) = view(Vector3::new(1e7 + 0.5, 10.25, -1e7 - 0.5), Vector2::new(0.0, 0.0));
+    assert_eq!(origin, Vector3::new(10000000, 0, -10000032));
+    assert_eq!(camera_position, Vector3::new(0.5, 10.25, 31.5));
+    assert_eq!(relative(Vector3::new(1e7 + 100.0, 0.0, -1e7), origin), Vector3::new(100.0, 0.0, 32.0));
+
+    /* Neighboring chunks' textures line up, and wrap on a whole repeat */
+    let s = CHUNK_SIZE as i64;
+    let far = Vector3::new(100000 / s, -3, -100000 / s);
+    let a = texture_origin(far);
+    let b = texture_origin(far.add_v(&Vector3::new(1, 0, 0)));
+    assert_eq!(b.x - a.x, CHUNK_SIZE as f32);
+    assert!(a.y >= 0.0 && a.z >= 0.0 && a.z < TEXTURE_PERIOD as f32);
+    assert_eq!(texture_origin(Vector3::new(TEXTURE_PERIOD / s, 0, -TEXTURE_PERIOD / s)), Vector3::new(0.0, 0.0, 0.0));
+}
@@ -0,0 +1,102 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Spatial hash of axis-aligned boxes, for finding the ones near a point
+//! without scanning all of them.
+//!
+//! Space is divided into cubic cells and each box is listed in every cell it
+//! overlaps. Only cells with boxes in them take up memory.
+
+extern crate cgmath;
+
+use std::collections::HashMap;
+
+use cgmath::vector::Vector3;
+
+use coord::{ChunkCoord, FnvHasher};
+
+pub struct SpatialHash {
+    cell_size: f64,
+    cells: HashMap<ChunkCoord, Vec<uint>, FnvHasher>,
+}
+
+impl SpatialHash {
+    /// cell_size should be a few times the size of a typical box.
+    pub fn new(cell_size: f64) -> SpatialHash {
+        SpatialHash {
+            cell_size: cell_size,
+            cells: HashMap::with_hasher(FnvHasher),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    /// Adds the box from min to max under index.
+    pub fn insert(&mut self, index: uint, min: Vector3<f64>, max: Vector3<f64>) {
+        let (lo, hi) = (self.cell(min), self.cell(max));
+        for x in range(lo.x, hi.x + 1) {
+            for y in range(lo.y, hi.y + 1) {
+                for z in range(lo.z, hi.z + 1) {
+                    let key = ChunkCoord { x: x, y: y, z: z };
+                    self.cells.find_or_insert_with(key, |_| Vec::new()).push(index);
+                }
+            }
+        }
+    }
+
+    /// Returns the indices of boxes that may overlap the box from min to max,
+    /// in increasing order. Boxes in the same cells that don't overlap it are
+    /// included too.
+    pub fn query(&self, min: Vector3<f64>, max: Vector3<f64>) -> Vec<uint> {
+        let (lo, hi) = (self.cell(min), self.cell(max));
+        let mut indices = Vec::new();
+        for x in range(lo.x, hi.x + 1) {
+            for y in range(lo.y, hi.y + 1) {
+                for z in range(lo.z, hi.z + 1) {
+                    match self.cells.find(&ChunkCoord { x: x, y: y, z: z }) {
+                        Some(cell) => indices.push_all(cell.as_slice()),
+                        None => {},
+                    }
+                }
+            }
+        }
+        indices.sort();
+        indices.dedup();
+        indices
+    }
+
+    fn cell(&self, p: Vector3<f64>) -> Vector3<i64> {
+        Vector3::new((p.x / self.cell_size).floor() as i64,
+                     (p.y / self.cell_size).floor() as i64,
+                     (p.z / self.cell_size).floor() as i64)
+    }
+}
+
+#[test]
+fn test_spatial_hash() {
+    let mut hash = SpatialHash::new(4.0);
+    hash.insert(0, Vector3::new(0.5, 0.5, 0.5), Vector3::new(1.5, 1.5, 1.5));
+    hash.insert(1, Vector3::new(3.5, 0.0, 0.0), Vector3::new(4.5, 1.0, 1.0));
+    hash.insert(2, Vector3::new(-20.0, 0.0, 0.0), Vector3::new(-19.0, 1.0, 1.0));
+
+    assert_eq!(hash.query(Vector3::new(1.0, 1.0, 1.0), Vector3::new(2.0, 2.0, 2.0)), vec![0, 1]);
+    assert_eq!(hash.query(Vector3::new(5.0, 0.0, 0.0), Vector3::new(6.0, 1.0, 1.0)), vec![1]);
+    assert_eq!(hash.query(Vector3::new(-19.5, 0.0, 0.0), Vector3::new(-19.5, 0.0, 0.0)), vec![2]);
+    assert!(hash.query(Vector3::new(100.0, 0.0, 0.0), Vector3::new(101.0, 1.0, 1.0)).is_empty());
+
+    hash.clear();
+    assert!(hash.query(Vector3::new(1.0, 1.0, 1.0), Vector3::new(2.0, 2.0, 2.0)).is_empty());
+}
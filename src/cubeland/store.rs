@@ -0,0 +1,301 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! On-disk storage of edited chunks, one file per chunk.
+//!
+//! Each file is a small header (magic, data length, Adler-32 checksum of the
+//! data) followed by the data. Files are written to a temporary name, synced
+//! and then renamed over the old file, so a crash mid-save leaves either the
+//! old or the new version intact, never a mix. A file that fails its checksum
+//! is reported and ignored.
+//!
+//! The data is the palette of block types it was written with (see
+//! blocks.rs), the chunk's blocks, and then whether the chunk's mobs have
+//! been spawned and the entities that were in it. The blocks are usually its
+//! journal (see journal.rs), with the stride and generator fingerprint needed
+//! to regenerate the terrain it applies to, and otherwise the whole terrain
+//! (see Terrain::to_bytes). Older files have no palette, older still always
+//! have the whole terrain, and the oldest have no entities either; each has
+//! its own magic.
+
+extern crate cgmath;
+
+use std::io;
+use std::io::{File, IoResult, MemWriter, BufReader};
+use std::io::fs;
+
+use cgmath::vector::Vector3;
+
+use blocks::Palette;
+use entity::Entity;
+use journal::Journal;
+use terrain::{Terrain, TERRAIN_BYTES, valid_stride};
+
+static MAGIC : &'static [u8] = b"CUBECHK4";
+/// Magic of files without a palette.
+static MAGIC_V3 : &'static [u8] = b"CUBECHK3";
+/// Magic of files with the whole terrain and entities.
+static MAGIC_V2 : &'static [u8] = b"CUBECHK2";
+/// Magic of files with only terrain.
+static MAGIC_V1 : &'static [u8] = b"CUBECHK1";
+
+static TAG_TERRAIN : u8 = 0;
+static TAG_JOURNAL : u8 = 1;
+
+/// How a saved chunk's blocks are stored.
+pub enum SavedBlocks {
+    SavedTerrain(Box<Terrain>),
+    /// A journal to replay over the terrain generated with a stride by a
+    /// generator with a fingerprint.
+    SavedJournal(uint, u64, Journal),
+}
+
+pub struct SavedChunk {
+    pub blocks: SavedBlocks,
+    /// False if the chunk's mobs haven't been spawned yet.
+    pub populated: bool,
+    pub entities: Vec<Entity>,
+}
+
+/// Serializes a chunk with its whole terrain for save_chunk().
+pub fn encode_chunk(terrain: &Terrain, populated: bool, entities: &[Entity]) -> Vec<u8> {
+    let mut w = MemWriter::new();
+    /* Writing to a MemWriter can't fail */
+    Palette::write(&mut w).unwrap();
+    w.write_u8(TAG_TERRAIN).unwrap();
+    w.write(terrain.to_bytes().as_slice()).unwrap();
+    write_entities(&mut w, populated, entities).unwrap();
+    w.unwrap()
+}
+
+/// Serializes a chunk as its journal for save_chunk(). stride and
+/// fingerprint are what its terrain was generated with.
+pub fn encode_journal_chunk(stride: uint, fingerprint: u64, journal: &Journal,
+                            populated: bool, entities: &[Entity]) -> Vec<u8> {
+    let mut w = MemWriter::new();
+    Palette::write(&mut w).unwrap();
+    w.write_u8(TAG_JOURNAL).unwrap();
+    w.write_le_u32(stride as u32).unwrap();
+    w.write_le_u64(fingerprint).unwrap();
+    journal.write(&mut w).unwrap();
+    write_entities(&mut w, populated, entities).unwrap();
+    w.unwrap()
+}
+
+fn write_entities(w: &mut Writer, populated: bool, entities: &[Entity]) -> IoResult<()> {
+    try!(w.write_u8(populated as u8));
+    try!(w.write_le_u32(entities.len() as u32));
+    for entity in entities.iter() {
+        try!(entity.write(w));
+    }
+    Ok(())
+}
+
+/// Decodes the data of a file with the given magic. Returns None if the
+/// data is invalid.
+fn decode_chunk(data: &[u8], magic: &[u8]) -> Option<SavedChunk> {
+    if magic == MAGIC_V1 {
+        return Terrain::from_bytes(data).map(|terrain| {
+            SavedChunk { blocks: SavedTerrain(terrain), populated: false, entities: Vec::new() }
+        });
+    }
+
+    let mut r = BufReader::new(data);
+    let palette = if magic == MAGIC {
+        match Palette::read(&mut r) {
+            Ok(Some(palette)) => palette,
+            _ => return None,
+        }
+    } else {
+        Palette::builtin()
+    };
+    let blocks = if magic == MAGIC_V2 {
+        read_terrain(&mut r, &palette)
+    } else {
+        read_blocks(&mut r, &palette)
+    };
+    match (blocks, read_entities(&mut r, &palette)) {
+        (Ok(Some(blocks)), Ok(Some((populated, entities)))) if r.eof() => {
+            Some(SavedChunk { blocks: blocks, populated: populated, entities: entities })
+        },
+        _ => None,
+    }
+}
+
+fn read_blocks(r: &mut Reader, palette: &Palette) -> IoResult<Option<SavedBlocks>> {
+    let tag = try!(r.read_u8());
+    if tag == TAG_TERRAIN {
+        read_terrain(r, palette)
+    } else if tag == TAG_JOURNAL {
+        let stride = try!(r.read_le_u32()) as uint;
+        let fingerprint = try!(r.read_le_u64());
+        if !valid_stride(stride) {
+            return Ok(None);
+        }
+        Ok(try!(Journal::read(r, palette)).map(|journal| SavedJournal(stride, fingerprint, journal)))
+    } else {
+        Ok(None)
+    }
+}
+
+fn read_terrain(r: &mut Reader, palette: &Palette) -> IoResult<Option<SavedBlocks>> {
+    let mut bytes = try!(r.read_exact(TERRAIN_BYTES));
+    if !palette.translate(bytes.as_mut_slice()) {
+        return Ok(None);
+    }
+    Ok(Terrain::from_bytes(bytes.as_slice()).map(|terrain| SavedTerrain(terrain)))
+}
+
+fn read_entities(r: &mut Reader, palette: &Palette) -> IoResult<Option<(bool, Vec<Entity>)>> {
+    let populated = try!(r.read_u8()) != 0;
+    let count = try!(r.read_le_u32()) as uint;
+    let mut entities = Vec::new();
+    for _ in range(0, count) {
+        match try!(Entity::read(r, palette)) {
+            Some(entity) => entities.push(entity),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some((populated, entities)))
+}
+
+pub fn chunk_path(dir: &Path, c: Vector3<i64>) -> Path {
+    dir.join(format!("{}_{}_{}.chunk", c.x, c.y, c.z))
+}
+
+pub fn save_chunk(dir: &Path, c: Vector3<i64>, data: &[u8]) -> IoResult<()> {
+    try!(fs::mkdir_recursive(dir, io::UserDir));
+
+    let path = chunk_path(dir, c);
+    let tmp_path = path.with_extension("chunk.tmp");
+
+    {
+        let mut file = try!(File::create(&tmp_path));
+        try!(file.write(MAGIC));
+        try!(file.write_le_u32(data.len() as u32));
+        try!(file.write_le_u32(checksum(data)));
+        try!(file.write(data));
+        try!(file.fsync());
+    }
+
+    fs::rename(&tmp_path, &path)
+}
+
+/// Returns the saved chunk c, or None if it was never saved or can't be
+/// read.
+pub fn load_chunk(dir: &Path, c: Vector3<i64>) -> Option<SavedChunk> {
+    let path = chunk_path(dir, c);
+    if !path.exists() {
+        return None;
+    }
+
+    let (data, magic) = match read_chunk_file(&path) {
+        Ok(Some(x)) => x,
+        Ok(None) => {
+            println!("Error loading chunk {}: corrupt file", path.display());
+            return None;
+        },
+        Err(e) => {
+            println!("Error loading chunk {}: {}", path.display(), e);
+            return None;
+        },
+    };
+
+    match decode_chunk(data.as_slice(), magic.as_slice()) {
+        Some(saved) => Some(saved),
+        None => {
+            println!("Error loading chunk {}: corrupt data", path.display());
+            None
+        },
+    }
+}
+
+/// Returns the data in a chunk file and its magic, or None if the header or
+/// checksum doesn't match.
+fn read_chunk_file(path: &Path) -> IoResult<Option<(Vec<u8>, Vec<u8>)>> {
+    let mut file = try!(File::open(path));
+
+    let magic = try!(file.read_exact(MAGIC.len()));
+    if [MAGIC, MAGIC_V3, MAGIC_V2, MAGIC_V1].iter().all(|&m| magic.as_slice() != m) {
+        return Ok(None);
+    }
+
+    let len = try!(file.read_le_u32()) as uint;
+    let expected_checksum = try!(file.read_le_u32());
+    let data = try!(file.read_to_end());
+
+    if data.len() != len || checksum(data.as_slice()) != expected_checksum {
+        return Ok(None);
+    }
+
+    Ok(Some((data, magic)))
+}
+
+/// Adler-32
+pub fn checksum(data: &[u8]) -> u32 {
+    static MOD : u32 = 65521;
+    let mut a : u32 = 1;
+    let mut b : u32 = 0;
+    for &byte in data.iter() {
+        a = (a + byte as u32) % MOD;
+        b = (b + a) % MOD;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn test_checksum() {
+    assert_eq!(checksum(b"Wikipedia"), 0x11E60398);
+}
+
+#[test]
+fn test_encode_chunk() {
+    use entity::Entities;
+    use entity::Pig;
+
+    let mut entities = Entities::new();
+    entities.spawn(Pig, Vector3::new(1.5, 2.0, -3.25), Vector3::new(0.0, -1.0, 0.0));
+    let data = encode_chunk(&*Terrain::new(), true, entities.to_vec().as_slice());
+
+    let saved = decode_chunk(data.as_slice(), MAGIC).unwrap();
+    assert!(saved.populated);
+    assert_eq!(saved.entities.len(), 1);
+    assert_eq!(saved.entities[0].transform.position, Vector3::new(1.5, 2.0, -3.25));
+    assert_eq!(saved.entities[0].physics.velocity, Vector3::new(0.0, -1.0, 0.0));
+    assert!(decode_chunk(data.slice_to(data.len() - 1), MAGIC).is_none());
+
+    /* Files from before palettes have none, and from before journals no tag
+       either. An empty palette is two bytes. */
+    assert!(decode_chunk(data.slice_from(2), MAGIC_V3).is_some());
+    assert!(decode_chunk(data.slice_from(3), MAGIC_V2).is_some());
+    let old = decode_chunk(Terrain::new().to_bytes().as_slice(), MAGIC_V1).unwrap();
+    assert!(!old.populated);
+}
+
+#[test]
+fn test_encode_journal_chunk() {
+    use terrain::BlockStone;
+
+    let mut journal = Journal::new();
+    journal.record(1, 2, 3, BlockStone);
+    let data = encode_journal_chunk(4, 99, &journal, false, []);
+    assert!(data.len() < 64);
+
+    match decode_chunk(data.as_slice(), MAGIC) {
+        Some(SavedChunk { blocks: SavedJournal(stride, fingerprint, journal), populated: false, .. }) => {
+            assert_eq!((stride, fingerprint, journal.len()), (4, 99, 1));
+        },
+        _ => fail!("bad decode"),
+    }
+}
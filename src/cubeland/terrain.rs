@@ -13,109 +13,345 @@
 // limitations under the License.
 
 extern crate cgmath;
-extern crate noise;
 
 use std;
+use std::collections::HashMap;
+use std::num::Integer;
 
 use cgmath::vector::Vector;
 use cgmath::vector::Vector3;
 
-use noise::sources::Perlin;
-use noise::Source;
-
 use CHUNK_SIZE;
 use CHUNK_SIZEu;
+use blocks;
+use climate::Climate;
+use coord::FnvHasher;
+use noisesource::{Noise, NoiseKind, NoisePerlin};
+use worldheight::WorldHeight;
 
-#[repr(u8)]
-#[deriving(PartialEq, Eq)]
-pub enum BlockType {
-    BlockAir = 0,
-    BlockGrass = 1,
-    BlockStone = 2,
-    BlockDirt = 3,
-    BlockWater = 4,
-}
+pub use blocks::{BlockType, BlockAir, BlockGrass, BlockStone, BlockDirt, BlockWater, BlockSnow, BlockSand, BlockLava};
 
+#[deriving(Clone)]
 pub struct Block {
     pub blocktype: BlockType,
 }
 
 impl Block {
     pub fn is_opaque(&self) -> bool {
-        self.blocktype != BlockAir
+        self.blocktype.info().opaque
+    }
+}
+
+/// How many of each blocktype a chunk has, not counting its border. Cheap
+/// to keep up to date, so it's computed once when the chunk loads and
+/// adjusted on every edit.
+#[deriving(Clone, PartialEq, Show)]
+pub struct BlockHistogram {
+    /// By numeric ID.
+    counts: Vec<uint>,
+}
+
+impl BlockHistogram {
+    pub fn new() -> BlockHistogram {
+        BlockHistogram { counts: Vec::from_elem(blocks::registry().len(), 0) }
+    }
+
+    pub fn of(t: &Terrain) -> BlockHistogram {
+        let mut histogram = BlockHistogram::new();
+        for x in range(0, CHUNK_SIZE as int) {
+            for y in range(0, CHUNK_SIZE as int) {
+                for z in range(0, CHUNK_SIZE as int) {
+                    *histogram.counts.get_mut(t.get(x, y, z).blocktype.to_u8() as uint) += 1;
+                }
+            }
+        }
+        histogram
+    }
+
+    pub fn count(&self, blocktype: BlockType) -> uint {
+        self.counts[blocktype.to_u8() as uint]
+    }
+
+    pub fn total(&self) -> uint {
+        self.counts.iter().fold(0, |a, &b| a + b)
+    }
+
+    /// Records a block changing from old to new.
+    pub fn replace(&mut self, old: BlockType, new: BlockType) {
+        *self.counts.get_mut(old.to_u8() as uint) -= 1;
+        *self.counts.get_mut(new.to_u8() as uint) += 1;
+    }
+
+    pub fn add(&mut self, other: &BlockHistogram) {
+        for (a, &b) in self.counts.mut_iter().zip(other.counts.iter()) {
+            *a += b;
+        }
+    }
+
+    /// Whether every block is air.
+    pub fn is_empty(&self) -> bool {
+        self.count(BlockAir) == self.total()
+    }
+
+    /// Whether every block is opaque, so that only faces on the outside of
+    /// the chunk can be visible.
+    pub fn is_opaque(&self) -> bool {
+        blocks::registry().types().iter().all(|&b| Block { blocktype: b }.is_opaque() || self.count(b) == 0)
     }
+
+    /// Whether no block inside the chunk can have a visible face toward
+    /// another block inside it: every block is opaque, or every block is
+    /// the same see-through type, like the middle of an ocean.
+    pub fn hides_interior(&self) -> bool {
+        self.is_opaque() || blocks::registry().types().iter().any(|&b| {
+            b.is_translucent() && self.count(b) == self.total()
+        })
+    }
+}
+
+/// Blocks between density samples unless configured otherwise.
+pub static DEFAULT_TERRAIN_STRIDE : uint = 4;
+
+/// Seconds of queued generation work allowed by default.
+pub static DEFAULT_TERRAIN_BUDGET : f64 = 0.5;
+
+/// Returns whether density can be sampled every stride blocks.
+pub fn valid_stride(stride: uint) -> bool {
+    stride > 0 && CHUNK_SIZEu % stride == 0
 }
 
+/// How finely chunks are generated, by distance from the camera.
+///
+/// Coarser sampling is faster but smooths away small features, so chunks
+/// within detail_radius of the camera can be sampled at every block. When
+/// generation falls behind, e.g. during fast travel, chunks are generated
+/// without caves first so there's something to show.
+#[deriving(Clone, PartialEq, Show)]
+pub struct TerrainQuality {
+    /// Blocks between density samples.
+    pub stride: uint,
+    /// Chunks closer than this many chunks to the camera's chunk on every
+    /// axis are sampled at every block. 0 turns full resolution off.
+    pub detail_radius: i64,
+    /// Seconds of queued generation work beyond which new chunks get a
+    /// cheap pass first, refined later. None always generates in full.
+    pub budget: Option<f64>,
+}
+
+impl TerrainQuality {
+    pub fn new() -> TerrainQuality {
+        TerrainQuality { stride: DEFAULT_TERRAIN_STRIDE, detail_radius: 0, budget: Some(DEFAULT_TERRAIN_BUDGET) }
+    }
+
+    /// Returns the stride for a chunk offset by d chunks from the camera's.
+    pub fn stride(&self, d: Vector3<i64>) -> uint {
+        if d.x.abs().max(d.y.abs()).max(d.z.abs()) < self.detail_radius {
+            1
+        } else {
+            self.stride
+        }
+    }
+}
+
+/// Noise backend for each stage of terrain generation.
+#[deriving(Clone, PartialEq, Show)]
+pub struct TerrainNoise {
+    /// Carves caves and overhangs out of the ground.
+    pub density: NoiseKind,
+    /// Shapes the ground's surface.
+    pub height: NoiseKind,
+}
+
+impl TerrainNoise {
+    pub fn new() -> TerrainNoise {
+        TerrainNoise { density: NoisePerlin, height: NoisePerlin }
+    }
+}
+
+/// Generates the terrain for a seed. Noise is sampled at integer block
+/// positions with deterministic noise backends and combined using only basic
+/// arithmetic, so every platform generates exactly the same world from the
+/// same seed and TerrainNoise.
 pub struct TerrainGenerator {
-    density : Perlin,
-    height : Perlin,
+    seed : u32,
+    density : Box<Noise>,
+    height : Box<Noise>,
+    /// Moves the inland edge of beaches, so the shoreline isn't a contour.
+    beach : Box<Noise>,
+    /// Rivers run along where this crosses zero.
+    river : Box<Noise>,
+    /// World width in blocks along x and z, if the world wraps around. The
+    /// noise repeats over it too, so there's no seam where it wraps.
+    period : Option<i64>,
+    world_height : WorldHeight,
+    fingerprint : u64,
+    climate : Climate,
 }
 
+/// Terrains with at most this many blocks that aren't air, counting the
+/// border, keep just those in a map. A map entry takes several times the
+/// space of an array element, so this is well below where they break even.
+static SPARSE_LIMIT : uint = TERRAIN_BYTES / 32;
+
+/// Blocks by index().
+enum Storage {
+    /// Only the blocks that aren't air. Most chunks above the surface are
+    /// empty sky except for a few tree tops or a floating island.
+    Sparse(HashMap<u32, BlockType, FnvHasher>),
+    Dense(Vec<Block>),
+}
+
+/// A chunk's blocks, including a one block border of its neighbors'.
+///
+/// Starts out sparse and switches to a dense array once enough blocks are
+/// set. Chunks only go back to sparse when they're read from bytes, so
+/// mining doesn't flip a chunk back and forth.
 pub struct Terrain {
-    blocks: [[[Block, ..CHUNK_SIZE+2], ..CHUNK_SIZE+2], ..CHUNK_SIZE+2],
+    storage: Storage,
 }
 
 impl TerrainGenerator {
-    pub fn new(seed: u32) -> TerrainGenerator {
+    /// A period needs noise kinds that tile().
+    pub fn new(seed: u32, period: Option<i64>, world_height: WorldHeight, noise: &TerrainNoise) -> TerrainGenerator {
         TerrainGenerator {
-            density: Perlin {
-                seed: seed as int,
-                octaves: 4,
-                frequency: 0.015,
-                lacunarity: 2.0,
-                persistence: 0.5,
-                quality: noise::Standard,
-            },
-            height: Perlin {
-                seed: seed as int * 71,
-                octaves: 8,
-                frequency: 0.001,
-                lacunarity: 2.0,
-                persistence: 0.5,
-                quality: noise::Best,
-            },
+            seed: seed,
+            density: noise.density.create(seed, 4, 0.015, 0.5, period),
+            height: noise.height.create(seed * 71, 8, 0.001, 0.5, period),
+            beach: NoisePerlin.create(seed * 113 + 5, 2, 1.0 / 48.0, 0.5, period),
+            river: NoisePerlin.create(seed * 131 + 17, 2, 1.0 / 800.0, 0.3, period),
+            period: period,
+            world_height: world_height,
+            fingerprint: fingerprint(seed, period, world_height, noise),
+            climate: Climate::new(seed, period),
         }
     }
 
-    pub fn gen(&self, p: Vector3<f64>) -> Box<Terrain> {
-        let def_block = Block { blocktype: BlockAir };
-        let mut t = box Terrain {
-            blocks: [[[def_block, ..CHUNK_SIZEu+2], ..CHUNK_SIZEu+2], ..CHUNK_SIZEu+2],
-        };
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
 
-        static Su : uint = 4;
-        static S : int = Su as int;
-
-        let mut density = [[[0.0, ..(CHUNK_SIZEu/Su)+3], ..(CHUNK_SIZEu/Su)+3], ..(CHUNK_SIZEu/Su)+3];
-        for density_x in std::iter::range(-1, CHUNK_SIZE/S+1) {
-            for density_y in std::iter::range(-1, CHUNK_SIZE/S+1) {
-                for density_z in std::iter::range(-1, CHUNK_SIZE/S+1) {
-                    let v = Vector3::new(p.x + (density_x * S) as f64,
-                                      p.y + (density_y * S) as f64,
-                                      p.z + (density_z * S) as f64);
-                    density[(density_x+1) as uint][(density_y+1) as uint][(density_z+1) as uint] =
-                        self.density.get(v.x, v.y, v.z);
+    pub fn climate<'a>(&'a self) -> &'a Climate {
+        &self.climate
+    }
+
+    /// Identifies everything besides the chunk and stride that generated
+    /// terrain depends on, so edits saved over it can tell whether it would
+    /// come out the same.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    fn wrap(&self, v: i64) -> i64 {
+        match self.period {
+            Some(period) => v.mod_floor(&period),
+            None => v,
+        }
+    }
+
+    /// Returns the height in blocks of the terrain surface, before caves are
+    /// carved out, at every column (x, z) for x in xs and z in zs, x-major.
+    /// Heights are within the world's vertical extent.
+    pub fn surface_heights(&self, xs: &[i64], zs: &[i64]) -> Vec<f64> {
+        let xs: Vec<i64> = xs.iter().map(|&x| self.wrap(x)).collect();
+        let zs: Vec<i64> = zs.iter().map(|&z| self.wrap(z)).collect();
+        let mut heights = Vec::from_elem(xs.len() * zs.len(), 0.0f64);
+        self.height.get_grid(xs.as_slice(), 0, zs.as_slice(), heights.as_mut_slice());
+        let mut rivers = Vec::from_elem(xs.len() * zs.len(), 0.0f64);
+        self.river.get_grid(xs.as_slice(), 0, zs.as_slice(), rivers.as_mut_slice());
+        let bottom = self.world_height.bottom as f64;
+        let top = self.world_height.top as f64;
+        for (h, &river) in heights.mut_iter().zip(rivers.iter()) {
+            *h = carve_river(*h * 100.0, river).max(bottom).min(top);
+        }
+        heights
+    }
+
+    /// Returns whether the surface of every column (x, z) for x in xs and z
+    /// in zs, x-major, is sand, given its height from surface_heights().
+    pub fn sandy(&self, xs: &[i64], zs: &[i64], heights: &[f64]) -> Vec<bool> {
+        let xs: Vec<i64> = xs.iter().map(|&x| self.wrap(x)).collect();
+        let zs: Vec<i64> = zs.iter().map(|&z| self.wrap(z)).collect();
+        let mut noise = Vec::from_elem(xs.len() * zs.len(), 0.0f64);
+        self.beach.get_grid(xs.as_slice(), 0, zs.as_slice(), noise.as_mut_slice());
+        heights.iter().zip(noise.iter()).map(|(&h, &n)| is_sand(h, n)).collect()
+    }
+
+    /// Returns the density below which the density noise carves a cave at
+    /// height y. Caves thin out over the CAVE_FADE blocks above the bottom
+    /// of the world, so it ends in solid stone.
+    fn cave_threshold(&self, y: f64) -> f64 {
+        let above_bottom = (y - self.world_height.bottom as f64) / CAVE_FADE;
+        CAVE_THRESHOLD - (1.0 - above_bottom.max(0.0).min(1.0)) * (1.0 + CAVE_THRESHOLD)
+    }
+
+    /// Generates the chunk at p, sampling density every stride blocks and
+    /// interpolating in between. stride must divide CHUNK_SIZE.
+    pub fn gen(&self, p: Vector3<f64>, stride: uint) -> Box<Terrain> {
+        self.generate(p, stride, true)
+    }
+
+    /// Generates the chunk at p from the height noise alone, without the
+    /// caves the density noise carves out. Much faster than gen(), for
+    /// showing something until the full terrain is ready.
+    pub fn gen_coarse(&self, p: Vector3<f64>) -> Box<Terrain> {
+        self.generate(p, CHUNK_SIZEu, false)
+    }
+
+    fn generate(&self, p: Vector3<f64>, stride: uint, caves: bool) -> Box<Terrain> {
+        assert!(valid_stride(stride));
+        let mut t = Terrain::new();
+        let origin = Vector3::new(p.x as i64, p.y as i64, p.z as i64);
+
+        let s = stride as int;
+        let n = CHUNK_SIZEu/stride + 3;
+        /* Columns along y are contiguous, so they can be filled at once */
+        let index = |x: int, y: int, z: int| -> uint { (x as uint * n + z as uint) * n + y as uint };
+
+        /* No caves in chunks entirely outside the world */
+        let caves = caves &&
+            origin.y + CHUNK_SIZE as i64 + 1 > self.world_height.bottom &&
+            origin.y - 1 < self.world_height.top;
+
+        let mut density = Vec::from_elem(n*n*n, 0.0f64);
+        if caves {
+            let column_len = (CHUNK_SIZE/s + 2) as uint;
+            for density_x in std::iter::range(-1, CHUNK_SIZE/s+1) {
+                for density_z in std::iter::range(-1, CHUNK_SIZE/s+1) {
+                    let start = index(density_x+1, 0, density_z+1);
+                    self.density.get_column(self.wrap(origin.x + (density_x * s) as i64),
+                                            origin.y - s as i64,
+                                            self.wrap(origin.z + (density_z * s) as i64),
+                                            s as i64,
+                                            density.mut_slice(start, start + column_len));
                 }
             }
         }
 
-        let water_height = -12.0;
+        /* Height of every column, including the border */
+        let xs: Vec<i64> = range(-1, CHUNK_SIZE as i64 + 1).map(|d| origin.x + d).collect();
+        let zs: Vec<i64> = range(-1, CHUNK_SIZE as i64 + 1).map(|d| origin.z + d).collect();
+        let heights = self.surface_heights(xs.as_slice(), zs.as_slice());
+        let sandy = self.sandy(xs.as_slice(), zs.as_slice(), heights.as_slice());
+
         let dirt_height = 4.0;
 
         for block_x in std::iter::range(-1, CHUNK_SIZE as int + 1) {
             for block_z in std::iter::range(-1, CHUNK_SIZE as int + 1) {
-                let x = p.x + block_x as f64;
-                let z = p.z + block_z as f64;
-
-                let height = self.height.get(x, 0.0, z) * 100.0;
+                let column = (block_x + 1) as uint * zs.len() + (block_z + 1) as uint;
+                let height = heights[column];
 
                 for block_y in range(-1, CHUNK_SIZE+1) {
                     let mut blocktype = BlockAir;
                     let v = p.add_v(&Vector3::new(block_x as f64, block_y as f64, block_z as f64));
 
-                    if v.y < height {
+                    if v.y < self.world_height.bottom as f64 {
+                        /* The floor of the world */
+                        blocktype = BlockStone;
+                    } else if v.y < height {
                         if v.y > height - dirt_height {
-                            if v.y > height - 2.0 {
+                            if sandy[column] {
+                                blocktype = BlockSand;
+                            } else if v.y > height - 2.0 {
                                 blocktype = BlockGrass;
                             } else {
                                 blocktype = BlockDirt;
@@ -125,26 +361,27 @@ impl TerrainGenerator {
                         }
                     }
 
-                    if blocktype == BlockAir && v.y < water_height {
+                    if blocktype == BlockAir && v.y < WATER_HEIGHT && v.y < self.world_height.top as f64 {
                         blocktype = BlockWater;
                     }
 
-                    if blocktype != BlockAir && blocktype != BlockWater {
+                    if caves && blocktype != BlockAir && blocktype != BlockWater &&
+                       v.y >= self.world_height.bottom as f64 {
                         /* Trilinear interpolation of lower-resolution density */
-                        let fx = (block_x as f64 / S as f64).fract();
-                        let fy = (block_y as f64 / S as f64).fract();
-                        let fz = (block_z as f64 / S as f64).fract();
-                        let x = (block_x+S)/S;
-                        let y = (block_y+S)/S;
-                        let z = (block_z+S)/S;
-                        let dxyz = density[x as uint][y as uint][z as uint];
-                        let dxyZ = density[x as uint][y as uint][(z+1) as uint];
-                        let dxYz = density[x as uint][(y+1) as uint][z as uint];
-                        let dxYZ = density[x as uint][(y+1) as uint][(z+1) as uint];
-                        let dXyz = density[(x+1) as uint][y as uint][z as uint];
-                        let dXyZ = density[(x+1) as uint][y as uint][(z+1) as uint];
-                        let dXYz = density[(x+1) as uint][(y+1) as uint][z as uint];
-                        let dXYZ = density[(x+1) as uint][(y+1) as uint][(z+1) as uint];
+                        let fx = (block_x as f64 / s as f64).fract();
+                        let fy = (block_y as f64 / s as f64).fract();
+                        let fz = (block_z as f64 / s as f64).fract();
+                        let x = (block_x+s)/s;
+                        let y = (block_y+s)/s;
+                        let z = (block_z+s)/s;
+                        let dxyz = density[index(x, y, z)];
+                        let dxyZ = density[index(x, y, z+1)];
+                        let dxYz = density[index(x, y+1, z)];
+                        let dxYZ = density[index(x, y+1, z+1)];
+                        let dXyz = density[index(x+1, y, z)];
+                        let dXyZ = density[index(x+1, y, z+1)];
+                        let dXYz = density[index(x+1, y+1, z)];
+                        let dXYZ = density[index(x+1, y+1, z+1)];
 
                         let d = dxyz * (1.0-fx) * (1.0-fy) * (1.0-fz) +
                                 dxyZ * (1.0-fx) * (1.0-fy) * fz +
@@ -155,14 +392,14 @@ impl TerrainGenerator {
                                 dXYz * fx * fy * (1.0-fz) +
                                 dXYZ * fx * fy * fz;
 
-                        if d < -0.2 {
-                            blocktype = BlockAir;
+                        if d < self.cave_threshold(v.y) {
+                            /* The deepest caves are flooded with lava */
+                            blocktype = if self.world_height.is_lava(v.y as i64) { BlockLava } else { BlockAir };
                         }
                     }
 
                     if blocktype != BlockAir {
-                        let block = t.get_mut(block_x, block_y, block_z);
-                        block.blocktype = blocktype;
+                        t.set(block_x, block_y, block_z, blocktype);
                     }
                 }
             }
@@ -172,12 +409,424 @@ impl TerrainGenerator {
     }
 }
 
+/// Density below which caves are carved out, away from the bottom of the
+/// world.
+static CAVE_THRESHOLD : f64 = -0.2;
+/// Blocks above the bottom of the world over which caves thin out.
+static CAVE_FADE : f64 = 32.0;
+
+/// Air below this height is filled with water.
+pub static WATER_HEIGHT : f64 = -12.0;
+
+/// Blocks above the water that still count as beach.
+static BEACH_HEIGHT : i64 = 3;
+/// Sand covers the bottom this far below the water near the shore.
+static SAND_DEPTH : f64 = 4.0;
+/// Blocks the inland edge of a beach moves up or down with the beach noise.
+static BEACH_NOISE : f64 = 3.0;
+
+/// Blocks of water in a river.
+static RIVER_DEPTH : f64 = 3.0;
+/// River noise within this of zero is river bed.
+static RIVER_BED : f64 = 0.01;
+/// River noise within this of zero is in a river valley.
+static RIVER_BANK : f64 = 0.05;
+/// River valleys are carved fully up to this height, and fade out above it
+/// up to RIVER_MAX_HEIGHT.
+static RIVER_FADE_HEIGHT : f64 = 10.0;
+static RIVER_MAX_HEIGHT : f64 = 50.0;
+
+/// Surfaces above this are mountains. Same as where it starts snowing.
+pub static MOUNTAIN_HEIGHT : i64 = 40;
+
+/// Kind of terrain at a column. There's no biome noise, so this is only
+/// what the height of the surface already decides.
+#[deriving(PartialEq, Eq, Show)]
+pub enum Biome {
+    BiomeOcean,
+    BiomeBeach,
+    BiomePlains,
+    BiomeMountains,
+}
+
+impl Biome {
+    /// Returns the biome of a column whose highest solid block is at y.
+    pub fn of_surface(y: i64) -> Biome {
+        let water = WATER_HEIGHT as i64;
+        if y < water - 1 {
+            BiomeOcean
+        } else if y < water + BEACH_HEIGHT {
+            BiomeBeach
+        } else if y <= MOUNTAIN_HEIGHT {
+            BiomePlains
+        } else {
+            BiomeMountains
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BiomeOcean => "ocean",
+            BiomeBeach => "beach",
+            BiomePlains => "plains",
+            BiomeMountains => "mountains",
+        }
+    }
+
+    /// Whether a player can stand on the surface without swimming.
+    pub fn is_land(&self) -> bool {
+        *self != BiomeOcean
+    }
+}
+
+/// Returns height with a river valley carved into it, for a column with
+/// river noise n. The noise is continuous, so rivers line up across chunks
+/// without any planning. Its zero crossings are the river beds, RIVER_DEPTH
+/// under the water, which fills them and joins the sea wherever they reach
+/// it. The valley fades out between RIVER_FADE_HEIGHT and RIVER_MAX_HEIGHT,
+/// so rivers rise out of the hills instead of cutting canyons through
+/// mountains.
+fn carve_river(height: f64, n: f64) -> f64 {
+    let bed = WATER_HEIGHT - RIVER_DEPTH;
+    if height <= bed || height >= RIVER_MAX_HEIGHT {
+        return height;
+    }
+    let d = n.abs();
+    if d >= RIVER_BANK {
+        return height;
+    }
+    /* 0 on the bed, rising smoothly to 1 at the top of the banks */
+    let t = ((d - RIVER_BED) / (RIVER_BANK - RIVER_BED)).max(0.0);
+    let t = t * t * (3.0 - 2.0 * t);
+    let strength = ((RIVER_MAX_HEIGHT - height) / (RIVER_MAX_HEIGHT - RIVER_FADE_HEIGHT)).min(1.0);
+    height - (height - bed) * (1.0 - t) * strength
+}
+
+/// Whether a column whose surface is at height, with beach noise n, is
+/// sand: from SAND_DEPTH below the water to about BEACH_HEIGHT above it.
+fn is_sand(height: f64, n: f64) -> bool {
+    height >= WATER_HEIGHT - SAND_DEPTH && height < WATER_HEIGHT + BEACH_HEIGHT as f64 + BEACH_NOISE * n
+}
+
+/// FNV-1a of the generator's settings, which unlike SipHash with fixed keys
+/// is sure to stay the same between releases. Worlds without lava hash the
+/// same as they did before there was any. Wrapped worlds hash differently
+/// than before their noise repeated, since their terrain changed.
+fn fingerprint(seed: u32, period: Option<i64>, world_height: WorldHeight, noise: &TerrainNoise) -> u64 {
+    let mut values = vec![seed as i64, period.unwrap_or(0), world_height.bottom, world_height.top,
+                          noise.density as i64, noise.height as i64];
+    match world_height.lava {
+        Some(lava) => values.push(lava),
+        None => {},
+    }
+    if period.is_some() {
+        values.push(1);
+    }
+    let mut h = 0xcbf29ce484222325u64;
+    for &v in values.iter() {
+        for i in range(0u, 8) {
+            h = (h ^ ((v >> (i * 8)) as u64 & 0xff)) * 0x100000001b3;
+        }
+    }
+    h
+}
+
+/// Size of a serialized Terrain, which includes the one block border.
+pub static TERRAIN_BYTES : uint = (CHUNK_SIZEu+2)*(CHUNK_SIZEu+2)*(CHUNK_SIZEu+2);
+
+/// Position of block (x, y, z) in to_bytes(): x major, then y, then z,
+/// starting from the border.
+fn index(x: int, y: int, z: int) -> uint {
+    let n = CHUNK_SIZEu + 2;
+    ((x+1) as uint * n + (y+1) as uint) * n + (z+1) as uint
+}
+
 impl Terrain {
-    pub fn get<'a>(&'a self, x: int, y: int, z: int) -> &'a Block {
-        &self.blocks[(x+1) as uint][(y+1) as uint][(z+1) as uint]
+    /// All air.
+    pub fn new() -> Box<Terrain> {
+        box Terrain {
+            storage: Sparse(HashMap::with_hasher(FnvHasher)),
+        }
+    }
+
+    /// Serializes the blocktypes, one byte per block.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self.storage {
+            Sparse(ref blocks) => {
+                let mut bytes = Vec::from_elem(TERRAIN_BYTES, BlockAir.to_u8());
+                for (&i, blocktype) in blocks.iter() {
+                    *bytes.get_mut(i as uint) = blocktype.to_u8();
+                }
+                bytes
+            },
+            Dense(ref blocks) => blocks.iter().map(|block| block.blocktype.to_u8()).collect(),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Box<Terrain>> {
+        if bytes.len() != TERRAIN_BYTES {
+            return None;
+        }
+
+        let mut blocks = Vec::with_capacity(TERRAIN_BYTES);
+        for &v in bytes.iter() {
+            match BlockType::from_u8(v) {
+                Some(blocktype) => blocks.push(Block { blocktype: blocktype }),
+                None => return None,
+            }
+        }
+
+        let mut t = box Terrain { storage: Dense(blocks) };
+        t.compact();
+        Some(t)
+    }
+
+    pub fn get(&self, x: int, y: int, z: int) -> Block {
+        let i = index(x, y, z);
+        match self.storage {
+            Sparse(ref blocks) => Block { blocktype: blocks.find(&(i as u32)).map(|&b| b).unwrap_or(BlockAir) },
+            Dense(ref blocks) => blocks[i],
+        }
+    }
+
+    pub fn set(&mut self, x: int, y: int, z: int, blocktype: BlockType) {
+        let i = index(x, y, z);
+        let full = match self.storage {
+            Sparse(ref mut blocks) => {
+                if blocktype == BlockAir {
+                    blocks.remove(&(i as u32));
+                    return;
+                }
+                blocks.insert(i as u32, blocktype);
+                blocks.len() > SPARSE_LIMIT
+            },
+            Dense(ref mut blocks) => {
+                blocks.get_mut(i).blocktype = blocktype;
+                return;
+            },
+        };
+        if full {
+            self.expand();
+        }
+    }
+
+    /// Whether only the blocks that aren't air are stored.
+    pub fn is_sparse(&self) -> bool {
+        match self.storage {
+            Sparse(_) => true,
+            Dense(_) => false,
+        }
+    }
+
+    /// Switches to dense storage.
+    fn expand(&mut self) {
+        let dense = match self.storage {
+            Sparse(ref blocks) => {
+                let mut dense = Vec::from_elem(TERRAIN_BYTES, Block { blocktype: BlockAir });
+                for (&i, &blocktype) in blocks.iter() {
+                    dense.get_mut(i as uint).blocktype = blocktype;
+                }
+                dense
+            },
+            Dense(_) => return,
+        };
+        self.storage = Dense(dense);
+    }
+
+    /// Switches to sparse storage if there are few enough blocks.
+    fn compact(&mut self) {
+        let sparse = match self.storage {
+            Dense(ref blocks) => {
+                if blocks.iter().filter(|block| block.blocktype != BlockAir).count() > SPARSE_LIMIT {
+                    return;
+                }
+                let mut sparse = HashMap::with_hasher(FnvHasher);
+                for (i, block) in blocks.iter().enumerate() {
+                    if block.blocktype != BlockAir {
+                        sparse.insert(i as u32, block.blocktype);
+                    }
+                }
+                sparse
+            },
+            Sparse(_) => return,
+        };
+        self.storage = Sparse(sparse);
     }
+}
+
+#[test]
+fn test_block_histogram() {
+    let mut t = Terrain::new();
+    let mut histogram = BlockHistogram::of(&*t);
+    assert_eq!(histogram.total(), CHUNK_SIZEu * CHUNK_SIZEu * CHUNK_SIZEu);
+    assert!(histogram.is_empty());
+
+    /* The border doesn't count */
+    t.set(-1, 0, 0, BlockStone);
+    assert_eq!(BlockHistogram::of(&*t), histogram);
+
+    t.set(0, 0, 0, BlockWater);
+    histogram.replace(BlockAir, BlockWater);
+    assert_eq!(BlockHistogram::of(&*t), histogram);
+    assert!(!histogram.is_empty());
+    assert!(!histogram.is_opaque());
+
+    let mut sum = BlockHistogram::new();
+    sum.add(&histogram);
+    sum.add(&histogram);
+    assert_eq!(sum.count(BlockWater), 2);
+}
+
+#[test]
+fn test_terrain_storage() {
+    let mut t = Terrain::new();
+    assert!(t.is_sparse());
+    t.set(-1, 0, CHUNK_SIZE, BlockStone);
+    t.set(3, 4, 5, BlockWater);
+    t.set(3, 4, 5, BlockAir);
+    assert!(t.is_sparse());
+    assert!(t.get(-1, 0, CHUNK_SIZE).blocktype == BlockStone);
+    assert!(t.get(3, 4, 5).blocktype == BlockAir);
+    let sparse_bytes = t.to_bytes();
+    assert_eq!(sparse_bytes.iter().filter(|&&v| v != BlockAir.to_u8()).count(), 1);
+
+    /* Filling the bottom layers goes past the limit */
+    for x in range(0, CHUNK_SIZE) {
+        for y in range(0, 4) {
+            for z in range(0, CHUNK_SIZE) {
+                t.set(x, y, z, BlockDirt);
+            }
+        }
+    }
+    assert!(!t.is_sparse());
+    assert!(t.get(-1, 0, CHUNK_SIZE).blocktype == BlockStone);
+    assert!(t.get(7, 3, 7).blocktype == BlockDirt);
+    let dense_bytes = t.to_bytes();
+    let read = Terrain::from_bytes(dense_bytes.as_slice()).unwrap();
+    assert!(!read.is_sparse());
+    assert_eq!(read.to_bytes(), dense_bytes);
+
+    /* Reading few enough blocks goes back to sparse */
+    let read = Terrain::from_bytes(sparse_bytes.as_slice()).unwrap();
+    assert!(read.is_sparse());
+    assert_eq!(read.to_bytes(), sparse_bytes);
+}
+
+#[test]
+fn test_terrain_quality() {
+    assert!(valid_stride(1) && valid_stride(4) && valid_stride(CHUNK_SIZEu));
+    assert!(!valid_stride(0) && !valid_stride(3));
+
+    let mut quality = TerrainQuality::new();
+    assert_eq!(quality.stride(Vector3::new(0, 0, 0)), DEFAULT_TERRAIN_STRIDE);
 
-    pub fn get_mut<'a>(&'a mut self, x: int, y: int, z: int) -> &'a mut Block {
-        &mut self.blocks[(x+1) as uint][(y+1) as uint][(z+1) as uint]
+    quality.detail_radius = 2;
+    assert_eq!(quality.stride(Vector3::new(0, 0, 0)), 1);
+    assert_eq!(quality.stride(Vector3::new(1, -1, 1)), 1);
+    assert_eq!(quality.stride(Vector3::new(0, 0, -2)), DEFAULT_TERRAIN_STRIDE);
+}
+
+#[test]
+fn test_world_height_generation() {
+    let s = CHUNK_SIZE as f64;
+    let world_height = WorldHeight::new(-CHUNK_SIZE as i64, CHUNK_SIZE as i64).unwrap();
+    let generator = TerrainGenerator::new(42, None, world_height, &TerrainNoise::new());
+
+    let below = generator.gen(Vector3::new(0.0, -2.0 * s, 0.0), 4);
+    let histogram = BlockHistogram::of(&*below);
+    assert_eq!(histogram.count(BlockStone), histogram.total());
+
+    let above = generator.gen(Vector3::new(0.0, s, 0.0), 4);
+    assert!(BlockHistogram::of(&*above).is_empty());
+
+    let heights = generator.surface_heights([-100000, 0, 100000], [-100000, 0, 100000]);
+    assert!(heights.iter().all(|&h| h >= -s && h <= s));
+}
+
+#[test]
+fn test_biome_of_surface() {
+    let water = WATER_HEIGHT as i64;
+    /* The top block of a column is at y = water - 1 when it's level with the water */
+    assert_eq!(Biome::of_surface(water - 2), BiomeOcean);
+    assert_eq!(Biome::of_surface(water - 1), BiomeBeach);
+    assert_eq!(Biome::of_surface(water + BEACH_HEIGHT), BiomePlains);
+    assert_eq!(Biome::of_surface(MOUNTAIN_HEIGHT + 1), BiomeMountains);
+    assert!(!BiomeOcean.is_land() && BiomeBeach.is_land());
+}
+
+#[test]
+fn test_carve_river() {
+    let bed = WATER_HEIGHT - RIVER_DEPTH;
+    assert_eq!(carve_river(0.0, 0.0), bed);
+    assert_eq!(carve_river(0.0, -RIVER_BED), bed);
+    assert_eq!(carve_river(0.0, RIVER_BANK), 0.0);
+    /* The banks slope up */
+    let mid = carve_river(0.0, (RIVER_BED + RIVER_BANK) / 2.0);
+    assert!(mid > bed && mid < 0.0);
+    assert!(carve_river(0.0, RIVER_BANK * 0.9) > mid);
+    /* Deep water and mountains are left alone, and the hills between are
+       only cut partway down */
+    assert_eq!(carve_river(bed - 10.0, 0.0), bed - 10.0);
+    assert_eq!(carve_river(RIVER_MAX_HEIGHT + 1.0, 0.0), RIVER_MAX_HEIGHT + 1.0);
+    let hill = (RIVER_FADE_HEIGHT + RIVER_MAX_HEIGHT) / 2.0;
+    assert!(carve_river(hill, 0.0) > bed && carve_river(hill, 0.0) < hill);
+}
+
+#[test]
+fn test_beach() {
+    let water = WATER_HEIGHT;
+    assert!(is_sand(water, 0.0) && is_sand(water - SAND_DEPTH, 0.0));
+    assert!(!is_sand(water - SAND_DEPTH - 1.0, 1.0));
+    assert!(!is_sand(water + BEACH_HEIGHT as f64, 0.0));
+    assert!(is_sand(water + BEACH_HEIGHT as f64, 0.5));
+    assert!(!is_sand(water + 1.0, -1.0));
+
+    /* Generated sand is only ever where the surface is in the band */
+    let generator = TerrainGenerator::new(42, None, WorldHeight::default(), &TerrainNoise::new());
+    /* The chunk with the water level in it */
+    let p = Vector3::new(0.0, -(CHUNK_SIZE as f64), 0.0);
+    let t = generator.gen(p, DEFAULT_TERRAIN_STRIDE);
+    let xs: Vec<i64> = range(0, CHUNK_SIZE as i64).collect();
+    let heights = generator.surface_heights(xs.as_slice(), xs.as_slice());
+    let sandy = generator.sandy(xs.as_slice(), xs.as_slice(), heights.as_slice());
+    for x in range(0, CHUNK_SIZE) {
+        for z in range(0, CHUNK_SIZE) {
+            let column = x as uint * xs.len() + z as uint;
+            for y in range(0, CHUNK_SIZE) {
+                if t.get(x, y, z).blocktype == BlockSand {
+                    assert!(sandy[column]);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_lava() {
+    let s = CHUNK_SIZE as i64;
+    let world_height = WorldHeight::new(-2 * s, s).unwrap();
+    let dry_height = WorldHeight { lava: None, ..world_height };
+    let generator = TerrainGenerator::new(42, None, world_height, &TerrainNoise::new());
+    let dry = TerrainGenerator::new(42, None, dry_height, &TerrainNoise::new());
+    assert!(generator.fingerprint() != dry.fingerprint());
+
+    /* Lava is only ever in caves below the lava height, where the world
+       without lava has cave air */
+    let p = Vector3::new(0.0, (-2 * s) as f64, 0.0);
+    let t = generator.gen(p, DEFAULT_TERRAIN_STRIDE);
+    let d = dry.gen(p, DEFAULT_TERRAIN_STRIDE);
+    for x in range(0, CHUNK_SIZE) {
+        for y in range(0, CHUNK_SIZE) {
+            for z in range(0, CHUNK_SIZE) {
+                let lava = t.get(x, y, z).blocktype == BlockLava;
+                assert!(d.get(x, y, z).blocktype != BlockLava);
+                if world_height.is_lava(p.y as i64 + y as i64) {
+                    assert_eq!(lava, d.get(x, y, z).blocktype == BlockAir);
+                } else {
+                    assert!(!lava);
+                    assert!(t.get(x, y, z).blocktype == d.get(x, y, z).blocktype);
+                }
+            }
+        }
     }
 }
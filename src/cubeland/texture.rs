@@ -20,10 +20,16 @@ use time::precise_time_ns;
 
 use gl::types::*;
 use std::mem;
+use std::u32;
 
 use noise::sources::Perlin;
 use noise::Source;
 
+use blocks;
+use colorscheme::ColorScheme;
+use gradient;
+use lcg;
+
 pub fn make_noise_texture() -> GLuint {
     let start_time = precise_time_ns();
 
@@ -74,3 +80,262 @@ pub fn make_noise_texture() -> GLuint {
 
     tex
 }
+
+/// From GL_EXT_texture_filter_anisotropic, which the bindings leave out.
+static TEXTURE_MAX_ANISOTROPY : GLenum = 0x84FE;
+static MAX_TEXTURE_MAX_ANISOTROPY : GLenum = 0x84FF;
+
+/// Returns the highest anisotropic filtering level the driver supports, or
+/// 1 if it doesn't support anisotropic filtering.
+pub fn max_anisotropy() -> f32 {
+    let mut max : GLfloat = 0.0;
+    unsafe {
+        gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max);
+    }
+    /* Without the extension the query fails and max stays 0 */
+    gl::GetError();
+    max.max(1.0)
+}
+
+/// Sets the anisotropic filtering level of mipmapped 2D texture tex, so
+/// surfaces seen at a glancing angle, like distant terrain, stay sharp
+/// instead of blurring or shimmering. Level 1 turns it off. Does nothing if
+/// the driver doesn't support it.
+pub fn set_anisotropy(tex: GLuint, level: f32) {
+    let max = max_anisotropy();
+    if max <= 1.0 {
+        return;
+    }
+    gl::BindTexture(gl::TEXTURE_2D, tex);
+    gl::TexParameterf(gl::TEXTURE_2D, TEXTURE_MAX_ANISOTROPY, level.max(1.0).min(max));
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+}
+
+/// Width of the block table: one column per possible numeric block ID.
+pub static BLOCK_TABLE_SIZE : uint = 256;
+/// Height of the block table. The shaders look up row i at
+/// v = (i + 0.5) / BLOCK_TABLE_ROWS.
+pub static BLOCK_TABLE_ROWS : uint = 3;
+
+/// Returns how the chunk shaders draw each registered block type, as an
+/// RGBA texture with a column per numeric ID. The first row is the tint.
+/// The second is the two noise scales, encoded by encode_noise_scale(), the
+/// noise mix and the light. The third has 1 in red if the tint is shaded by
+/// the climate and 1 in green if the texture flows. Tints are recolored for
+/// scheme.
+pub fn make_block_table(scheme: ColorScheme) -> GLuint {
+    let width = BLOCK_TABLE_SIZE;
+    let mut pixels = Vec::from_elem(width * BLOCK_TABLE_ROWS * 4, 0u8);
+    let to_byte = |v: f32| (v.max(0.0).min(1.0) * 255.0).round() as u8;
+
+    for blocktype in blocks::registry().types().iter() {
+        let look = blocktype.look();
+        let x = blocktype.to_u8() as uint;
+        let tint = scheme.apply_rgba(look.tint);
+        let tint = [tint.x, tint.y, tint.z, tint.w];
+        for (i, &v) in tint.iter().enumerate() {
+            *pixels.get_mut(x * 4 + i) = to_byte(v);
+        }
+        let params = [encode_noise_scale(look.noise_scales[0]),
+                      encode_noise_scale(look.noise_scales[1]),
+                      to_byte(look.noise_mix),
+                      to_byte(look.light)];
+        for (i, &v) in params.iter().enumerate() {
+            *pixels.get_mut((width + x) * 4 + i) = v;
+        }
+        if look.biome_tint {
+            *pixels.get_mut((2 * width + x) * 4) = 255;
+        }
+        if look.flow {
+            *pixels.get_mut((2 * width + x) * 4 + 1) = 255;
+        }
+    }
+
+    let mut tex : GLuint = 0;
+
+    unsafe {
+        gl::GenTextures(1, &mut tex);
+    }
+
+    gl::BindTexture(gl::TEXTURE_2D, tex);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+
+    unsafe {
+        gl::TexImage2D(
+            gl::TEXTURE_2D, 0,
+            gl::RGBA8 as GLint,
+            width as GLint, BLOCK_TABLE_ROWS as GLint, 0,
+            gl::RGBA, gl::UNSIGNED_BYTE,
+            mem::transmute(&pixels[0]));
+    }
+
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+
+    tex
+}
+
+/// Encodes a texture coordinate scale from 1/256 to almost 256 in a byte,
+/// logarithmically in steps of 1/16 of a power of two. The shaders decode
+/// it as exp2(v/16 - 8).
+fn encode_noise_scale(scale: f32) -> u8 {
+    let v = ((scale.max(1e-6).log2() + 8.0) * 16.0).round();
+    v.max(0.0).min(255.0) as u8
+}
+
+/// Size in pixels of one crack image.
+static CRACK_SIZE : uint = 16;
+
+/// Returns a single channel texture holding stages crack images side by
+/// side, each a copy of the last with more cracks added.
+pub fn make_crack_texture(stages: uint) -> GLuint {
+    let width = CRACK_SIZE * stages;
+    let mut pixels = Vec::from_elem(width * CRACK_SIZE, 0u8);
+
+    /* Fixed LCG so the cracks look the same every run */
+    let mut rng_state = 12345u32;
+    let rand = |n: uint| -> uint {
+        (lcg::next(&mut rng_state) >> 16) as uint % n
+    };
+
+    /* Random walks from the middle; each stage adds a few */
+    let mut walks: Vec<Vec<(uint, uint)>> = Vec::new();
+    for stage in range(0, stages) {
+        for _ in range(0, 2) {
+            let mut x = CRACK_SIZE / 2;
+            let mut y = CRACK_SIZE / 2;
+            let mut walk = Vec::new();
+            for _ in range(0, 4 + stage * 2) {
+                x = (x + rand(3) + CRACK_SIZE - 1) % CRACK_SIZE;
+                y = (y + rand(3) + CRACK_SIZE - 1) % CRACK_SIZE;
+                walk.push((x, y));
+            }
+            walks.push(walk);
+        }
+
+        for walk in walks.iter() {
+            for &(x, y) in walk.iter() {
+                *pixels.get_mut(y * width + stage * CRACK_SIZE + x) = 255;
+            }
+        }
+    }
+
+    let mut tex : GLuint = 0;
+
+    unsafe {
+        gl::GenTextures(1, &mut tex);
+    }
+
+    gl::BindTexture(gl::TEXTURE_2D, tex);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+
+    unsafe {
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexImage2D(
+            gl::TEXTURE_2D, 0,
+            1 as GLint,
+            width as GLint, CRACK_SIZE as GLint, 0,
+            gl::RED, gl::UNSIGNED_BYTE,
+            mem::transmute(&pixels[0]));
+    }
+
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+
+    tex
+}
+
+/// Size in pixels of the cloud texture.
+static CLOUD_TEXTURE_SIZE : uint = 256;
+
+/// Returns a single channel texture of value noise that tiles seamlessly, for
+/// the cloud layer.
+pub fn make_cloud_texture() -> GLuint {
+    let size = CLOUD_TEXTURE_SIZE;
+    let mut pixels = Vec::with_capacity(size * size);
+
+    for y in range(0, size) {
+        for x in range(0, size) {
+            let mut total = 0.0;
+            let mut amplitude = 0.5;
+            let mut cells = 4;
+            for octave in range(0u32, 5) {
+                total += amplitude * tiling_value_noise(octave, x, y, size, cells);
+                amplitude *= 0.5;
+                cells *= 2;
+            }
+            /* The amplitudes sum to just under one */
+            pixels.push((total * 255.0) as u8);
+        }
+    }
+
+    let mut tex : GLuint = 0;
+
+    unsafe {
+        gl::GenTextures(1, &mut tex);
+    }
+
+    gl::BindTexture(gl::TEXTURE_2D, tex);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+
+    unsafe {
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexImage2D(
+            gl::TEXTURE_2D, 0,
+            1 as GLint,
+            size as GLint, size as GLint, 0,
+            gl::RED, gl::UNSIGNED_BYTE,
+            mem::transmute(&pixels[0]));
+    }
+
+    gl::GenerateMipmap(gl::TEXTURE_2D);
+
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+
+    tex
+}
+
+/// Returns random values on a cells by cells grid covering a size pixel
+/// image, smoothly interpolated at pixel (x, y). The grid wraps around at
+/// the edges so the image tiles. Values are from 0 to 1.
+fn tiling_value_noise(seed: u32, x: uint, y: uint, size: uint, cells: uint) -> f64 {
+    let cell_size = size / cells;
+    let (cx, cy) = (x / cell_size, y / cell_size);
+    let fade = |t: f64| t * t * (3.0 - 2.0 * t);
+    let u = fade((x % cell_size) as f64 / cell_size as f64);
+    let v = fade((y % cell_size) as f64 / cell_size as f64);
+
+    let corner = |dx: uint, dy: uint| {
+        let h = gradient::hash(seed, ((cx + dx) % cells) as i64, ((cy + dy) % cells) as i64, 0);
+        h as f64 / u32::MAX as f64
+    };
+
+    let top = corner(0, 0) * (1.0 - u) + corner(1, 0) * u;
+    let bottom = corner(0, 1) * (1.0 - u) + corner(1, 1) * u;
+    top * (1.0 - v) + bottom * v
+}
+
+#[test]
+fn test_tiling_value_noise() {
+    for i in range(0u, 64) {
+        /* The last pixel of a row blends into the first */
+        let a = tiling_value_noise(3, 255, i, 256, 8);
+        let b = tiling_value_noise(3, 0, i, 256, 8);
+        assert!((a - b).abs() < 0.05);
+        assert!(a >= 0.0 && a <= 1.0);
+    }
+}
+
+#[test]
+fn test_encode_noise_scale() {
+    let decode = |v: u8| (v as f32 / 16.0 - 8.0).exp2();
+    for &scale in [0.1f32, 0.5, 1.0, 2.0, 8.0, 16.0].iter() {
+        /* Within half a step */
+        assert!((decode(encode_noise_scale(scale)) / scale).log2().abs() <= 1.0 / 32.0);
+    }
+    assert_eq!(encode_noise_scale(0.0), 0);
+    assert_eq!(encode_noise_scale(1000.0), 255);
+}
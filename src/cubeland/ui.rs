@@ -0,0 +1,379 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 2D overlay drawn over the world: text and solid rectangles.
+//!
+//! Callers queue quads with text() and rect() while building a frame, and
+//! draw() uploads them all as one vertex buffer and renders them with a
+//! single draw call. Positions are in pixels from the top left of the window.
+//! Text uses a built-in 5x7 bitmap font, so there is no font file to load.
+
+extern crate gl;
+extern crate hgl;
+extern crate cgmath;
+
+use std::mem;
+use std::ptr;
+
+use gl::types::*;
+
+use cgmath::vector::Vector2;
+use cgmath::vector::Vector4;
+
+use check_gl;
+use colorscheme::{ColorScheme, SchemeNormal};
+use renderer::load_program;
+
+/// Each glyph is drawn at this multiple of its pixel size.
+static TEXT_SCALE : f32 = 2.0;
+
+/// Size of a glyph's cell in the font texture, including spacing.
+static GLYPH_WIDTH : uint = 6;
+static GLYPH_HEIGHT : uint = 8;
+
+static FONT_COLUMNS : uint = 16;
+static FONT_ROWS : uint = 6;
+static FONT_TEXTURE_WIDTH : uint = GLYPH_WIDTH * FONT_COLUMNS;
+static FONT_TEXTURE_HEIGHT : uint = GLYPH_HEIGHT * FONT_ROWS;
+
+static FIRST_CHAR : uint = 32;
+
+/// Index of the solid glyph used to draw rectangles, right after '~'.
+static SOLID_GLYPH : uint = 95;
+
+/// Glyphs for ' ' through '~', one byte per column, least significant bit at
+/// the top.
+static FONT : [[u8, ..5], ..95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], // ' ' !
+    [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7F, 0x14, 0x7F, 0x14], // " #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], // $ %
+    [0x36, 0x49, 0x56, 0x20, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], // & '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1C, 0x00], // ( )
+    [0x14, 0x08, 0x3E, 0x08, 0x14], [0x08, 0x08, 0x3E, 0x08, 0x08], // * +
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], // , -
+    [0x00, 0x60, 0x60, 0x00, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02], // . /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00], // 0 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4B, 0x31], // 2 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], // 4 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03], // 6 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1E], // 8 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], [0x00, 0x56, 0x36, 0x00, 0x00], // : ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14], // < =
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], // > ?
+    [0x32, 0x49, 0x79, 0x41, 0x3E], [0x7E, 0x11, 0x11, 0x11, 0x7E], // @ A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22], // B C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], [0x7F, 0x49, 0x49, 0x49, 0x41], // D E
+    [0x7F, 0x09, 0x09, 0x09, 0x01], [0x3E, 0x41, 0x49, 0x49, 0x7A], // F G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00], // H I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], // J K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], [0x7F, 0x02, 0x0C, 0x02, 0x7F], // L M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E], // N O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], // P Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], [0x46, 0x49, 0x49, 0x49, 0x31], // R S
+    [0x01, 0x01, 0x7F, 0x01, 0x01], [0x3F, 0x40, 0x40, 0x40, 0x3F], // T U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F], // V W
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x07, 0x08, 0x70, 0x08, 0x07], // X Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x00], // Z [
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7F, 0x00], // \ ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40], // ^ _
+    [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78], // ` a
+    [0x7F, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], // b c
+    [0x38, 0x44, 0x44, 0x48, 0x7F], [0x38, 0x54, 0x54, 0x54, 0x18], // d e
+    [0x08, 0x7E, 0x09, 0x01, 0x02], [0x0C, 0x52, 0x52, 0x52, 0x3E], // f g
+    [0x7F, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7D, 0x40, 0x00], // h i
+    [0x20, 0x40, 0x44, 0x3D, 0x00], [0x7F, 0x10, 0x28, 0x44, 0x00], // j k
+    [0x00, 0x41, 0x7F, 0x40, 0x00], [0x7C, 0x04, 0x18, 0x04, 0x78], // l m
+    [0x7C, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], // n o
+    [0x7C, 0x14, 0x14, 0x14, 0x08], [0x08, 0x14, 0x14, 0x18, 0x7C], // p q
+    [0x7C, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20], // r s
+    [0x04, 0x3F, 0x44, 0x40, 0x20], [0x3C, 0x40, 0x40, 0x20, 0x7C], // t u
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], [0x3C, 0x40, 0x30, 0x40, 0x3C], // v w
+    [0x44, 0x28, 0x10, 0x28, 0x44], [0x0C, 0x50, 0x50, 0x50, 0x3C], // x y
+    [0x44, 0x64, 0x54, 0x4C, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], // z {
+    [0x00, 0x00, 0x7F, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00], // | }
+    [0x10, 0x08, 0x08, 0x10, 0x08],                                 // ~
+];
+
+pub struct UiVertex {
+    pub position: Vector2<f32>,
+    pub texcoord: Vector2<f32>,
+    pub color: Vector4<f32>,
+}
+
+pub struct Ui {
+    res: Resources,
+    window_size: Vector2<u32>,
+    vertices: Vec<UiVertex>,
+    /// Recolors everything queued.
+    color_scheme: ColorScheme,
+}
+
+impl Ui {
+    pub fn new(window_size: Vector2<u32>) -> Ui {
+        let res = match Resources::load() {
+            Ok(x) => x,
+            Err(msg) => fail!("Error loading UI resources: {}", msg),
+        };
+
+        check_gl("after loading UI resources");
+
+        Ui {
+            res: res,
+            window_size: window_size,
+            vertices: Vec::new(),
+            color_scheme: SchemeNormal,
+        }
+    }
+
+    pub fn reload_resources(&mut self) {
+        match Resources::load() {
+            Ok(res) => {
+                self.res = res;
+            },
+            Err(msg) => println!("Error reloading UI resources: {}", msg),
+        }
+    }
+
+    pub fn set_window_size(&mut self, window_size: Vector2<u32>) {
+        self.window_size = window_size;
+    }
+
+    pub fn window_size(&self) -> Vector2<u32> {
+        self.window_size
+    }
+
+    /// Recolors everything queued from now on for scheme.
+    pub fn set_color_scheme(&mut self, scheme: ColorScheme) {
+        self.color_scheme = scheme;
+    }
+
+    /// Queues a line of text with its top left corner at (x, y). Characters
+    /// outside printable ASCII are drawn as '?'.
+    pub fn text(&mut self, x: f32, y: f32, text: &str, color: Vector4<f32>) {
+        let mut x = x;
+        for c in text.chars() {
+            let c = c as uint;
+            let glyph = if c >= FIRST_CHAR && c < FIRST_CHAR + FONT.len() {
+                c - FIRST_CHAR
+            } else {
+                '?' as uint - FIRST_CHAR
+            };
+            self.glyph(x, y, glyph, color);
+            x += char_width();
+        }
+    }
+
+    /// Queues a solid rectangle.
+    pub fn rect(&mut self, x: f32, y: f32, w: f32, h: f32, color: Vector4<f32>) {
+        /* Sample the middle of the solid glyph so filtering can't reach its edges */
+        let (u, v) = glyph_texcoord(SOLID_GLYPH);
+        let u = u + GLYPH_WIDTH as f32 / 2.0 / FONT_TEXTURE_WIDTH as f32;
+        let v = v + GLYPH_HEIGHT as f32 / 2.0 / FONT_TEXTURE_HEIGHT as f32;
+        self.quad(x, y, w, h, (u, v), (u, v), color);
+    }
+
+    /// Draws everything queued since the last call and clears the queue.
+    pub fn draw(&mut self) {
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let vbo = hgl::Vbo::from_data(self.vertices.as_slice(), hgl::StreamDraw);
+
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::Viewport(0, 0, self.window_size.x as GLint, self.window_size.y as GLint);
+
+        gl::UseProgram(self.res.program);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, self.res.texture);
+        gl::Uniform1i(self.res.uniform_texture, 0);
+        gl::Uniform2f(self.res.uniform_window_size, self.window_size.x as f32, self.window_size.y as f32);
+
+        gl::EnableVertexAttribArray(self.res.attr_position);
+        gl::EnableVertexAttribArray(self.res.attr_texcoord);
+        gl::EnableVertexAttribArray(self.res.attr_color);
+
+        let stride = mem::size_of::<UiVertex>() as GLint;
+        vbo.bind();
+        unsafe {
+            gl::VertexAttribPointer(self.res.attr_position, 2, gl::FLOAT,
+                                    gl::FALSE as GLboolean, stride,
+                                    ptr::null());
+            gl::VertexAttribPointer(self.res.attr_texcoord, 2, gl::FLOAT,
+                                    gl::FALSE as GLboolean, stride,
+                                    mem::transmute(offset_of!(UiVertex, texcoord)));
+            gl::VertexAttribPointer(self.res.attr_color, 4, gl::FLOAT,
+                                    gl::FALSE as GLboolean, stride,
+                                    mem::transmute(offset_of!(UiVertex, color)));
+        }
+
+        gl::DrawArrays(gl::TRIANGLES, 0, self.vertices.len() as GLint);
+
+        gl::DisableVertexAttribArray(self.res.attr_position);
+        gl::DisableVertexAttribArray(self.res.attr_texcoord);
+        gl::DisableVertexAttribArray(self.res.attr_color);
+        gl::UseProgram(0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::Disable(gl::BLEND);
+
+        self.vertices.clear();
+    }
+
+    fn glyph(&mut self, x: f32, y: f32, glyph: uint, color: Vector4<f32>) {
+        let (u, v) = glyph_texcoord(glyph);
+        let du = GLYPH_WIDTH as f32 / FONT_TEXTURE_WIDTH as f32;
+        let dv = GLYPH_HEIGHT as f32 / FONT_TEXTURE_HEIGHT as f32;
+        self.quad(x, y, char_width(), line_height(), (u, v), (u + du, v + dv), color);
+    }
+
+    fn quad(&mut self, x: f32, y: f32, w: f32, h: f32,
+            (u0, v0): (f32, f32), (u1, v1): (f32, f32),
+            color: Vector4<f32>) {
+        let color = self.color_scheme.apply_rgba(color);
+        let corners = [
+            (x, y, u0, v0), (x, y + h, u0, v1), (x + w, y + h, u1, v1),
+            (x, y, u0, v0), (x + w, y + h, u1, v1), (x + w, y, u1, v0),
+        ];
+        for &(x, y, u, v) in corners.iter() {
+            self.vertices.push(UiVertex {
+                position: Vector2::new(x, y),
+                texcoord: Vector2::new(u, v),
+                color: color,
+            });
+        }
+    }
+}
+
+/// Width in pixels of one character of text.
+pub fn char_width() -> f32 {
+    GLYPH_WIDTH as f32 * TEXT_SCALE
+}
+
+/// Height in pixels of a line of text.
+pub fn line_height() -> f32 {
+    GLYPH_HEIGHT as f32 * TEXT_SCALE
+}
+
+pub fn text_width(text: &str) -> f32 {
+    text.char_len() as f32 * char_width()
+}
+
+fn glyph_texcoord(glyph: uint) -> (f32, f32) {
+    let u = (glyph % FONT_COLUMNS * GLYPH_WIDTH) as f32 / FONT_TEXTURE_WIDTH as f32;
+    let v = (glyph / FONT_COLUMNS * GLYPH_HEIGHT) as f32 / FONT_TEXTURE_HEIGHT as f32;
+    (u, v)
+}
+
+/// Rasterizes FONT into a single channel texture, plus the solid glyph.
+fn make_font_texture() -> GLuint {
+    let mut pixels = Vec::from_elem(FONT_TEXTURE_WIDTH * FONT_TEXTURE_HEIGHT, 0u8);
+
+    for (i, columns) in FONT.iter().enumerate() {
+        let x0 = i % FONT_COLUMNS * GLYPH_WIDTH;
+        let y0 = i / FONT_COLUMNS * GLYPH_HEIGHT;
+        for (dx, &bits) in columns.iter().enumerate() {
+            for dy in range(0, GLYPH_HEIGHT) {
+                if bits & (1 << dy) != 0 {
+                    *pixels.get_mut((y0 + dy) * FONT_TEXTURE_WIDTH + x0 + dx) = 255;
+                }
+            }
+        }
+    }
+
+    {
+        let x0 = SOLID_GLYPH % FONT_COLUMNS * GLYPH_WIDTH;
+        let y0 = SOLID_GLYPH / FONT_COLUMNS * GLYPH_HEIGHT;
+        for dy in range(0, GLYPH_HEIGHT) {
+            for dx in range(0, GLYPH_WIDTH) {
+                *pixels.get_mut((y0 + dy) * FONT_TEXTURE_WIDTH + x0 + dx) = 255;
+            }
+        }
+    }
+
+    let mut tex : GLuint = 0;
+
+    unsafe {
+        gl::GenTextures(1, &mut tex);
+    }
+
+    gl::BindTexture(gl::TEXTURE_2D, tex);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+
+    unsafe {
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexImage2D(
+            gl::TEXTURE_2D, 0,
+            1 as GLint,
+            FONT_TEXTURE_WIDTH as GLint, FONT_TEXTURE_HEIGHT as GLint, 0,
+            gl::RED, gl::UNSIGNED_BYTE,
+            mem::transmute(&pixels[0]));
+    }
+
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+
+    tex
+}
+
+struct Resources {
+    program: GLuint,
+    vertex_shader: GLuint,
+    fragment_shader: GLuint,
+    texture: GLuint,
+    uniform_window_size: GLint,
+    uniform_texture: GLint,
+    attr_position: GLuint,
+    attr_texcoord: GLuint,
+    attr_color: GLuint,
+}
+
+impl Resources {
+    fn load() -> Result<Resources, String> {
+        let (program, vs, fs) = try!(load_program("ui"));
+
+        let texture = make_font_texture();
+
+        let uniform_window_size = unsafe { "window_size".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_texture = unsafe { "texture".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+
+        let attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
+        assert!(attr_position as u32 != gl::INVALID_VALUE);
+        let attr_texcoord = unsafe { "texcoord".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
+        assert!(attr_texcoord as u32 != gl::INVALID_VALUE);
+        let attr_color = unsafe { "color".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
+        assert!(attr_color as u32 != gl::INVALID_VALUE);
+
+        Ok(Resources {
+            program: program,
+            vertex_shader: vs,
+            fragment_shader: fs,
+            texture: texture,
+            uniform_window_size: uniform_window_size,
+            uniform_texture: uniform_texture,
+            attr_position: attr_position,
+            attr_texcoord: attr_texcoord,
+            attr_color: attr_color,
+        })
+    }
+}
+
+impl Drop for Resources {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.texture); }
+        gl::DeleteProgram(self.program);
+        gl::DeleteShader(self.vertex_shader);
+        gl::DeleteShader(self.fragment_shader);
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate gl;
+
+use std::mem;
+
+use gl::types::*;
+
+use CHUNK_SIZE;
+use CHUNK_SIZEu;
+use terrain::Terrain;
+
+/// Width of the texture along each axis: the chunk plus its one block
+/// border, so that samples at the edges see the neighboring chunks.
+pub static VOXEL_TEXTURE_SIZE : uint = CHUNK_SIZEu + 2;
+
+/// A chunk's terrain as a 3D texture the fragment shader can sample.
+///
+/// Each texel's red is 255 for an opaque block and 0 otherwise, and its
+/// green is how brightly the block glows, so light from lava and the like
+/// can spill onto the blocks next to it. The texture's s, t
+/// and r axes are the terrain's z, y and x axes, which is the order
+/// Terrain::to_bytes() lays blocks out in. Texel (0, 0, 0) is the border
+/// block at (-1, -1, -1).
+pub struct VoxelTexture {
+    texture: GLuint,
+}
+
+impl VoxelTexture {
+    pub fn new(t: &Terrain) -> VoxelTexture {
+        let texels = texels(t);
+        let size = VOXEL_TEXTURE_SIZE as GLint;
+
+        let mut texture : GLuint = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+        }
+
+        gl::BindTexture(gl::TEXTURE_3D, texture);
+        /* Linear filtering smooths the occupancy across block boundaries */
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as GLint);
+
+        unsafe {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage3D(
+                gl::TEXTURE_3D, 0,
+                2 as GLint,
+                size, size, size, 0,
+                gl::RG, gl::UNSIGNED_BYTE,
+                mem::transmute(&texels[0]));
+        }
+
+        gl::BindTexture(gl::TEXTURE_3D, 0);
+
+        VoxelTexture { texture: texture }
+    }
+
+    pub fn bind(&self) {
+        gl::BindTexture(gl::TEXTURE_3D, self.texture);
+    }
+}
+
+impl Drop for VoxelTexture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
+
+/// Returns the texels for t, two bytes per block including the border.
+pub fn texels(t: &Terrain) -> Vec<u8> {
+    let mut texels = Vec::with_capacity(VOXEL_TEXTURE_SIZE * VOXEL_TEXTURE_SIZE * VOXEL_TEXTURE_SIZE * 2);
+    for x in range(-1, CHUNK_SIZE + 1) {
+        for y in range(-1, CHUNK_SIZE + 1) {
+            for z in range(-1, CHUNK_SIZE + 1) {
+                let block = t.get(x, y, z);
+                texels.push(if block.is_opaque() { 255 } else { 0 });
+                texels.push((block.blocktype.look().light.max(0.0).min(1.0) * 255.0).round() as u8);
+            }
+        }
+    }
+    texels
+}
+
+#[test]
+fn test_texels() {
+    use terrain::{BlockStone, BlockWater, BlockLava};
+
+    let mut t = Terrain::new();
+    t.set(-1, -1, -1, BlockStone);
+    t.set(0, 1, 2, BlockStone);
+    t.set(2, 1, 0, BlockWater);
+    t.set(3, 0, 0, BlockLava);
+    let texels = texels(&*t);
+    let n = VOXEL_TEXTURE_SIZE;
+    let i = |x: uint, y: uint, z: uint| ((x+1)*n*n + (y+1)*n + (z+1)) * 2;
+    assert_eq!(texels.len(), n * n * n * 2);
+    assert_eq!(texels[0], 255);
+    /* z varies fastest, then y, then x, each offset by the border */
+    assert_eq!(texels[i(0, 1, 2)], 255);
+    /* Water doesn't occlude */
+    assert_eq!(texels[i(2, 1, 0)], 0);
+    /* Lava occludes and glows */
+    assert_eq!(texels[i(3, 0, 0)], 255);
+    assert_eq!(texels[i(3, 0, 0) + 1], 255);
+    assert_eq!(texels[i(0, 1, 2) + 1], 0);
+    assert_eq!(texels.iter().filter(|&&x| x != 0).count(), 4);
+}
@@ -0,0 +1,254 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Weather: clear skies, rain and snow, and the precipitation falling around
+//! the camera.
+//!
+//! Time is divided into spells of SPELL_LENGTH seconds and a hash of the
+//! world seed and each spell's index decides whether it precipitates, so the
+//! weather changes on an irregular but repeatable schedule. Precipitation
+//! fades in and out over TRANSITION_TIME, dimming the sky and thickening the
+//! fog as it does. There are no biomes yet, so it snows above SNOW_LINE and
+//! rains below it.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vector3;
+
+use chunk::ChunkLoader;
+use entity;
+use gradient;
+use lcg;
+use renderer::{CLEAR_SKY_COLOR, CLEAR_FOG_DENSITY};
+use terrain::BlockAir;
+
+/// Seconds each spell of weather lasts.
+static SPELL_LENGTH : f64 = 300.0;
+/// Out of 256, the chance that a spell precipitates.
+static PRECIPITATION_CHANCE : u32 = 80;
+/// Seconds to fade precipitation fully in or out.
+static TRANSITION_TIME : f64 = 20.0;
+/// Altitude above which precipitation falls as snow.
+static SNOW_LINE : f64 = 40.0;
+
+static OVERCAST_SKY_COLOR : Vector3<f32> = Vector3 { x: 0.45, y: 0.5, z: 0.55 };
+static SNOW_SKY_COLOR : Vector3<f32> = Vector3 { x: 0.75, y: 0.78, z: 0.8 };
+static OVERCAST_FOG_DENSITY : f32 = 0.008;
+
+/// Particles around the camera at full intensity.
+static MAX_PARTICLES : uint = 2000;
+/// Particles are kept within this many blocks of the camera on each axis.
+static PARTICLE_RANGE : f64 = 16.0;
+static RAIN_SPEED : f64 = 12.0;
+static SNOW_SPEED : f64 = 1.5;
+/// Blocks snowflakes wander sideways.
+static SNOW_SWAY : f64 = 0.3;
+
+#[deriving(PartialEq, Show)]
+pub enum Weather {
+    WeatherClear,
+    WeatherRain,
+    WeatherSnow,
+}
+
+impl Weather {
+    pub fn from_name(name: &str) -> Option<Weather> {
+        match name {
+            "clear" => Some(WeatherClear),
+            "rain" => Some(WeatherRain),
+            "snow" => Some(WeatherSnow),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            WeatherClear => "clear",
+            WeatherRain => "rain",
+            WeatherSnow => "snow",
+        }
+    }
+}
+
+pub struct WeatherState {
+    seed: u32,
+    time: f64,
+    /// Set by the weather command, overriding the schedule.
+    forced: Option<Weather>,
+    /// How far precipitation has faded in, from 0 to 1.
+    intensity: f64,
+    snow: bool,
+    particles: Vec<Vector3<f64>>,
+    rng: u32,
+}
+
+impl WeatherState {
+    pub fn new(seed: u32) -> WeatherState {
+        WeatherState {
+            seed: seed,
+            time: 0.0,
+            forced: None,
+            intensity: 0.0,
+            snow: false,
+            particles: Vec::new(),
+            rng: seed,
+        }
+    }
+
+    /// Returns the weather being faded toward, or currently showing.
+    pub fn weather(&self) -> Weather {
+        let precipitating = match self.forced {
+            Some(weather) => weather != WeatherClear,
+            None => self.scheduled(),
+        };
+        if !precipitating && self.intensity == 0.0 {
+            WeatherClear
+        } else if self.snow {
+            WeatherSnow
+        } else {
+            WeatherRain
+        }
+    }
+
+    /// None returns to the schedule.
+    pub fn force(&mut self, weather: Option<Weather>) {
+        self.forced = weather;
+    }
+
+    pub fn particles(&self) -> &[Vector3<f64>] {
+        self.particles.as_slice()
+    }
+
+    /// The sky and fog color.
+    pub fn sky_color(&self) -> Vector3<f32> {
+        let overcast = if self.snow { SNOW_SKY_COLOR } else { OVERCAST_SKY_COLOR };
+        let t = self.intensity as f32;
+        CLEAR_SKY_COLOR.mul_s(1.0 - t).add_v(&overcast.mul_s(t))
+    }
+
+    pub fn fog_density(&self) -> f32 {
+        let t = self.intensity as f32;
+        CLEAR_FOG_DENSITY * (1.0 - t) + OVERCAST_FOG_DENSITY * t
+    }
+
+    pub fn tick(&mut self, dt: f64, camera_position: Vector3<f64>, chunk_loader: &ChunkLoader) {
+        self.update(dt, camera_position.y);
+        self.move_particles(dt, camera_position, chunk_loader);
+    }
+
+    /// Advances the schedule and fades intensity toward it.
+    fn update(&mut self, dt: f64, altitude: f64) {
+        self.time += dt;
+
+        let precipitating = match self.forced {
+            Some(weather) => weather != WeatherClear,
+            None => self.scheduled(),
+        };
+        let step = dt / TRANSITION_TIME;
+        self.intensity = if precipitating {
+            (self.intensity + step).min(1.0)
+        } else {
+            (self.intensity - step).max(0.0)
+        };
+
+        self.snow = match self.forced {
+            Some(WeatherSnow) => true,
+            Some(WeatherRain) => false,
+            _ => altitude > SNOW_LINE,
+        };
+    }
+
+    fn scheduled(&self) -> bool {
+        let spell = (self.time / SPELL_LENGTH).floor() as i64;
+        gradient::hash(self.seed ^ 0x776561, spell, 0, 0) % 256 < PRECIPITATION_CHANCE
+    }
+
+    /// Lets particles fall, replacing any that land on a block or leave the
+    /// box around the camera with new ones at its top, and adds or removes
+    /// particles to match the intensity.
+    fn move_particles(&mut self, dt: f64, camera_position: Vector3<f64>, chunk_loader: &ChunkLoader) {
+        let wanted = (MAX_PARTICLES as f64 * self.intensity) as uint;
+        self.particles.truncate(wanted);
+        while self.particles.len() < wanted {
+            /* Anywhere in the box, so precipitation doesn't start as one sheet */
+            let y = camera_position.y + (lcg::random(&mut self.rng) * 2.0 - 1.0) * PARTICLE_RANGE;
+            let p = self.spawn_position(camera_position, y);
+            self.particles.push(p);
+        }
+
+        let speed = if self.snow { SNOW_SPEED } else { RAIN_SPEED };
+        let time = self.time;
+        let top = camera_position.y + PARTICLE_RANGE;
+        for i in range(0, self.particles.len()) {
+            let mut p = self.particles[i];
+            p.y -= speed * dt;
+            if self.snow {
+                /* Each flake sways on its own phase */
+                let phase = time + i as f64;
+                p.x += (phase * 1.3).sin() * SNOW_SWAY * dt;
+                p.z += (phase * 0.7).cos() * SNOW_SWAY * dt;
+            }
+
+            let landed = chunk_loader.get_block(entity::block_containing(p)) != Some(BlockAir);
+            let d = p.sub_v(&camera_position);
+            if landed || d.x.abs() > PARTICLE_RANGE || d.y < -PARTICLE_RANGE || d.z.abs() > PARTICLE_RANGE {
+                p = self.spawn_position(camera_position, top);
+            }
+            *self.particles.get_mut(i) = p;
+        }
+    }
+
+    fn spawn_position(&mut self, camera_position: Vector3<f64>, y: f64) -> Vector3<f64> {
+        let x = camera_position.x + (lcg::random(&mut self.rng) * 2.0 - 1.0) * PARTICLE_RANGE;
+        let z = camera_position.z + (lcg::random(&mut self.rng) * 2.0 - 1.0) * PARTICLE_RANGE;
+        Vector3::new(x, y, z)
+    }
+}
+
+#[test]
+fn test_weather() {
+    let mut weather = WeatherState::new(1);
+    weather.force(Some(WeatherRain));
+    weather.update(TRANSITION_TIME / 2.0, SNOW_LINE + 10.0);
+    assert_eq!(weather.weather(), WeatherRain);
+    assert!(weather.fog_density() > CLEAR_FOG_DENSITY);
+
+    weather.force(Some(WeatherSnow));
+    weather.update(TRANSITION_TIME, 0.0);
+    assert_eq!(weather.weather(), WeatherSnow);
+    assert_eq!(weather.sky_color(), SNOW_SKY_COLOR);
+
+    /* Precipitation fades out before the weather reads as clear */
+    weather.force(Some(WeatherClear));
+    weather.update(TRANSITION_TIME / 2.0, 0.0);
+    assert_eq!(weather.weather(), WeatherRain);
+    weather.update(TRANSITION_TIME, 0.0);
+    assert_eq!(weather.weather(), WeatherClear);
+    assert_eq!(weather.sky_color(), CLEAR_SKY_COLOR);
+}
+
+#[test]
+fn test_weather_schedule() {
+    let mut weather = WeatherState::new(42);
+    let mut precipitating = 0u;
+    for _ in range(0u, 100) {
+        weather.update(SPELL_LENGTH, 0.0);
+        if weather.scheduled() {
+            precipitating += 1;
+        }
+    }
+    /* Roughly PRECIPITATION_CHANCE / 256 of spells */
+    assert!(precipitating > 10 && precipitating < 60);
+}
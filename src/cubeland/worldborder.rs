@@ -0,0 +1,65 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector3;
+
+use CHUNK_SIZE;
+
+/// Square horizontal limit of a world, centered on the origin.
+///
+/// Columns with x and z within radius blocks of the origin are inside.
+/// Chunks entirely outside are never loaded, and players are kept inside,
+/// which bounds the size of the world on disk and in memory.
+#[deriving(Clone, PartialEq, Show)]
+pub struct WorldBorder {
+    pub radius: i64,
+}
+
+impl WorldBorder {
+    /// Returns None unless radius is positive.
+    pub fn new(radius: i64) -> Option<WorldBorder> {
+        if radius > 0 {
+            Some(WorldBorder { radius: radius })
+        } else {
+            None
+        }
+    }
+
+    /// Whether any column of the chunk at chunk coordinate c is inside.
+    pub fn contains_chunk(&self, c: Vector3<i64>) -> bool {
+        let s = CHUNK_SIZE as i64;
+        let overlaps = |v: i64| v * s < self.radius && (v + 1) * s > -self.radius;
+        overlaps(c.x) && overlaps(c.z)
+    }
+
+    /// Returns p moved horizontally to the nearest point inside.
+    pub fn clamp(&self, p: Vector3<f64>) -> Vector3<f64> {
+        let r = self.radius as f64;
+        Vector3::new(p.x.max(-r).min(r), p.y, p.z.max(-r).min(r))
+    }
+}
+
+#[test]
+fn test_world_border() {
+    let s = CHUNK_SIZE as i64;
+    assert_eq!(WorldBorder::new(0), None);
+    let border = WorldBorder::new(s + 1).unwrap();
+    assert!(border.contains_chunk(Vector3::new(0, 100, 0)));
+    assert!(border.contains_chunk(Vector3::new(1, 0, -2)));
+    assert!(!border.contains_chunk(Vector3::new(2, 0, 0)));
+    assert!(!border.contains_chunk(Vector3::new(0, 0, -3)));
+    assert_eq!(border.clamp(Vector3::new(1000.0, 5.0, -1.5)), Vector3::new((s + 1) as f64, 5.0, -1.5));
+}
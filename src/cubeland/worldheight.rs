@@ -0,0 +1,101 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::num::Integer;
+
+use CHUNK_SIZE;
+
+pub static DEFAULT_BOTTOM : i64 = -128;
+pub static DEFAULT_TOP : i64 = 256;
+/// Blocks above the bottom that deep caves fill with lava up to, unless a
+/// world says otherwise.
+pub static DEFAULT_LAVA_DEPTH : i64 = 24;
+
+/// Vertical extent of a world, in blocks.
+///
+/// Blocks below bottom are solid stone and blocks at or above top are air.
+/// Only chunks with blocks in between are loaded, so the world is a slab of
+/// chunk layers rather than a sphere around the camera.
+#[deriving(Clone, PartialEq, Show)]
+pub struct WorldHeight {
+    pub bottom: i64,
+    pub top: i64,
+    /// Caves below this height are filled with lava instead of air. None
+    /// for worlds without lava, like those made before there was any.
+    pub lava: Option<i64>,
+}
+
+impl WorldHeight {
+    /// Returns None unless bottom is below top. Lava fills caves up to
+    /// DEFAULT_LAVA_DEPTH above the bottom.
+    pub fn new(bottom: i64, top: i64) -> Option<WorldHeight> {
+        if bottom < top {
+            Some(WorldHeight { bottom: bottom, top: top, lava: Some(bottom + DEFAULT_LAVA_DEPTH) })
+        } else {
+            None
+        }
+    }
+
+    pub fn default() -> WorldHeight {
+        WorldHeight::new(DEFAULT_BOTTOM, DEFAULT_TOP).unwrap()
+    }
+
+    /// Whether block height y is in a lava lake when it's in a cave.
+    pub fn is_lava(&self, y: i64) -> bool {
+        self.lava.map_or(false, |lava| y < lava)
+    }
+
+    pub fn contains(&self, y: i64) -> bool {
+        y >= self.bottom && y < self.top
+    }
+
+    /// Whether any block of the chunk layer at chunk coordinate y is inside
+    /// the world.
+    pub fn contains_chunk(&self, y: i64) -> bool {
+        let s = CHUNK_SIZE as i64;
+        y * s < self.top && (y + 1) * s > self.bottom
+    }
+
+    /// Returns the lowest and highest chunk layers inside the world.
+    pub fn chunk_range(&self) -> (i64, i64) {
+        let s = CHUNK_SIZE as i64;
+        (self.bottom.div_floor(&s), (self.top - 1).div_floor(&s))
+    }
+
+    /// Returns the distance from a point at height y to the farthest block of
+    /// the world within horizontal blocks of it.
+    pub fn view_distance(&self, y: f64, horizontal: f64) -> f64 {
+        let vertical = (y - self.bottom as f64).max(self.top as f64 - y).max(0.0);
+        (horizontal * horizontal + vertical * vertical).sqrt()
+    }
+}
+
+#[test]
+fn test_world_height() {
+    let s = CHUNK_SIZE as i64;
+    assert_eq!(WorldHeight::new(10, 10), None);
+    let height = WorldHeight::new(-s - 1, s).unwrap();
+    assert!(height.contains(-s - 1));
+    assert!(!height.contains(s));
+    assert!(!height.contains_chunk(-3));
+    assert!(height.contains_chunk(-2));
+    assert!(height.contains_chunk(0));
+    assert!(!height.contains_chunk(1));
+    assert_eq!(height.chunk_range(), (-2, 0));
+    assert_eq!(WorldHeight::new(0, 40).unwrap().view_distance(10.0, 40.0), 50.0);
+
+    assert!(height.is_lava(-s - 1) && !height.is_lava(-s - 1 + DEFAULT_LAVA_DEPTH));
+    let dry = WorldHeight { lava: None, ..height };
+    assert!(!dry.is_lava(-s - 1));
+}
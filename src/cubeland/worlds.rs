@@ -0,0 +1,206 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Registry of named worlds, each stored in its own directory under
+//! platformpaths::save_dir() along with its seed, height and saved state.
+
+use std::io;
+use std::io::File;
+use std::io::fs;
+
+use CHUNK_SIZEu;
+use platformpaths;
+use worldborder::WorldBorder;
+use worldheight::WorldHeight;
+
+/// Chunk size of builds without a chunk size feature.
+static DEFAULT_CHUNK_SIZE : uint = 32;
+
+pub struct World {
+    pub name: String,
+    pub dir: Path,
+    pub seed: u32,
+    pub height: WorldHeight,
+    pub border: Option<WorldBorder>,
+}
+
+impl World {
+    pub fn player_path(&self) -> Path {
+        self.dir.join("player.txt")
+    }
+
+    pub fn permissions_path(&self) -> Path {
+        self.dir.join("permissions.txt")
+    }
+
+    /// Saved chunks only load into builds with the same chunk size, so
+    /// other sizes than the default keep theirs apart.
+    pub fn chunk_dir(&self) -> Path {
+        if CHUNK_SIZEu == DEFAULT_CHUNK_SIZE {
+            self.dir.join("chunks")
+        } else {
+            self.dir.join(format!("chunks-{}", CHUNK_SIZEu))
+        }
+    }
+
+    /// Generated terrain of evicted chunks, kept apart by chunk size like
+    /// chunk_dir(). Safe to delete.
+    pub fn cache_dir(&self) -> Path {
+        if CHUNK_SIZEu == DEFAULT_CHUNK_SIZE {
+            self.dir.join("cache")
+        } else {
+            self.dir.join(format!("cache-{}", CHUNK_SIZEu))
+        }
+    }
+
+    pub fn photo_dir(&self) -> Path {
+        platformpaths::screenshot_dir().join(self.name.as_slice())
+    }
+}
+
+pub fn worlds_dir() -> Path {
+    platformpaths::save_dir()
+}
+
+pub fn list() -> Result<Vec<String>, String> {
+    let dir = worlds_dir();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let paths = match fs::readdir(&dir) {
+        Ok(paths) => paths,
+        Err(e) => return Err(format!("{}: {}", dir.display(), e)),
+    };
+
+    let mut names: Vec<String> = paths.iter().
+        filter(|p| p.join("world.txt").exists()).
+        filter_map(|p| p.filename_str().map(|s| s.to_string())).
+        collect();
+    names.sort();
+    Ok(names)
+}
+
+pub fn create(name: &str, seed: u32, height: WorldHeight, border: Option<WorldBorder>) -> Result<World, String> {
+    try!(check_name(name));
+
+    let dir = worlds_dir().join(name);
+    if dir.join("world.txt").exists() {
+        return Err(format!("world '{}' already exists", name));
+    }
+
+    let mut contents = format!("seed {}\nheight {} {}", seed, height.bottom, height.top);
+    match height.lava {
+        Some(lava) => contents.push_str(format!("\nlava {}", lava).as_slice()),
+        None => {},
+    }
+    match border {
+        Some(border) => contents.push_str(format!("\nborder {}", border.radius).as_slice()),
+        None => {},
+    }
+    let result = fs::mkdir_recursive(&dir, io::UserDir).
+        and_then(|_| File::create(&dir.join("world.txt")).write_line(contents.as_slice()));
+
+    match result {
+        Ok(()) => Ok(World { name: name.to_string(), dir: dir, seed: seed, height: height, border: border }),
+        Err(e) => Err(format!("{}: {}", dir.display(), e)),
+    }
+}
+
+pub fn open(name: &str) -> Result<World, String> {
+    try!(check_name(name));
+
+    let dir = worlds_dir().join(name);
+    let path = dir.join("world.txt");
+    let src = match File::open(&path).read_to_string() {
+        Ok(src) => src,
+        Err(_) => return Err(format!("world '{}' does not exist", name)),
+    };
+
+    match parse_world_file(src.as_slice()) {
+        Some((seed, height, border)) => Ok(World { name: name.to_string(), dir: dir, seed: seed, height: height, border: border }),
+        None => Err(format!("{}: invalid world file", path.display())),
+    }
+}
+
+pub fn delete(name: &str) -> Result<(), String> {
+    try!(check_name(name));
+
+    let dir = worlds_dir().join(name);
+    if !dir.join("world.txt").exists() {
+        return Err(format!("world '{}' does not exist", name));
+    }
+
+    match fs::rmdir_recursive(&dir) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("{}: {}", dir.display(), e)),
+    }
+}
+
+/// Returns the seed, height and border from the contents of world.txt.
+/// Worlds created before the height was configurable have the default one,
+/// worlds without a lava line have no lava and worlds without a border line
+/// have no border.
+fn parse_world_file(src: &str) -> Option<(u32, WorldHeight, Option<WorldBorder>)> {
+    let mut seed = None;
+    let mut height = Some(WorldHeight::default());
+    let mut lava = Some(None);
+    let mut border = Some(None);
+    for line in src.lines() {
+        let words: Vec<&str> = line.words().collect();
+        match words.as_slice() {
+            ["seed", s] => seed = from_str::<u32>(s),
+            ["height", bottom, top] => height = match (from_str::<i64>(bottom), from_str::<i64>(top)) {
+                (Some(bottom), Some(top)) => WorldHeight::new(bottom, top),
+                _ => None,
+            },
+            ["lava", y] => lava = from_str::<i64>(y).map(|y| Some(y)),
+            ["border", r] => border = from_str::<i64>(r).and_then(WorldBorder::new).map(|b| Some(b)),
+            [] => {},
+            _ => return None,
+        }
+    }
+
+    match (seed, height, lava, border) {
+        (Some(seed), Some(height), Some(lava), Some(border)) => Some((seed, WorldHeight { lava: lava, ..height }, border)),
+        _ => None,
+    }
+}
+
+/// World names become directory names, so keep them simple.
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty() &&
+        name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid world name '{}'", name))
+    }
+}
+
+#[test]
+fn test_parse_world_file() {
+    let dry = |height: WorldHeight| WorldHeight { lava: None, ..height };
+    assert_eq!(parse_world_file("seed 42\n"), Some((42, dry(WorldHeight::default()), None)));
+    assert_eq!(parse_world_file("seed 7\nheight -64 128\n"), Some((7, dry(WorldHeight::new(-64, 128).unwrap()), None)));
+    assert_eq!(parse_world_file("seed 7\nborder 1000\n"), Some((7, dry(WorldHeight::default()), WorldBorder::new(1000))));
+    /* The lava line can come before the height it's in */
+    assert_eq!(parse_world_file("seed 7\nlava -50\nheight -64 128\n"),
+               Some((7, WorldHeight { lava: Some(-50), ..WorldHeight::new(-64, 128).unwrap() }, None)));
+    assert_eq!(parse_world_file("seed 7\nlava deep\n"), None);
+    assert_eq!(parse_world_file("seed 7\nborder 0\n"), None);
+    assert_eq!(parse_world_file("seed 7\nheight 128 -64\n"), None);
+    assert_eq!(parse_world_file("height -64 128\n"), None);
+    assert_eq!(parse_world_file("seed x\n"), None);
+}
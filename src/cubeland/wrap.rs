@@ -0,0 +1,125 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std::num::Integer;
+
+use cgmath::vector::Vector3;
+
+use CHUNK_SIZE;
+use region::REGION_SIZE;
+
+/// Optional horizontal wrap-around of the world.
+///
+/// When enabled the world is `size` chunks wide along x and z, and any
+/// coordinate outside [0, size) refers to the chunk on the opposite side.
+/// Chunks are cached and generated under their wrapped ("canonical")
+/// coordinate; only the renderer deals in unwrapped coordinates.
+pub struct Wrap {
+    size: Option<i64>,
+}
+
+impl Wrap {
+    pub fn none() -> Wrap {
+        Wrap { size: None }
+    }
+
+    /// The size is rounded up to a multiple of REGION_SIZE so that regions
+    /// tile the world exactly.
+    pub fn new(size: i64) -> Wrap {
+        let size = (size + REGION_SIZE - 1) / REGION_SIZE * REGION_SIZE;
+        Wrap { size: Some(size.max(REGION_SIZE)) }
+    }
+
+    /// World width in blocks, if wrapping.
+    pub fn period(&self) -> Option<i64> {
+        self.size.map(|size| size * CHUNK_SIZE as i64)
+    }
+
+    pub fn chunk(&self, c: Vector3<i64>) -> Vector3<i64> {
+        match self.size {
+            Some(size) => Vector3::new(c.x.mod_floor(&size), c.y, c.z.mod_floor(&size)),
+            None => c,
+        }
+    }
+
+    /// Returns the shortest chunk offset from b to a, going around the
+    /// world if that's shorter.
+    pub fn chunk_offset(&self, a: Vector3<i64>, b: Vector3<i64>) -> Vector3<i64> {
+        let d = Vector3::new(a.x - b.x, a.y - b.y, a.z - b.z);
+        match self.size {
+            Some(size) => {
+                let shortest = |v: i64| (v + size / 2).mod_floor(&size) - size / 2;
+                Vector3::new(shortest(d.x), d.y, shortest(d.z))
+            },
+            None => d,
+        }
+    }
+
+    /// Returns the shortest offset from position b to position a, going
+    /// around the world if that's shorter.
+    pub fn position_offset(&self, a: Vector3<f64>, b: Vector3<f64>) -> Vector3<f64> {
+        let d = Vector3::new(a.x - b.x, a.y - b.y, a.z - b.z);
+        match self.period() {
+            Some(period) => {
+                let period = period as f64;
+                let shortest = |v: f64| v - (v / period).round() * period;
+                Vector3::new(shortest(d.x), d.y, shortest(d.z))
+            },
+            None => d,
+        }
+    }
+
+    pub fn region(&self, r: Vector3<i64>) -> Vector3<i64> {
+        match self.size {
+            Some(size) => {
+                let n = size / REGION_SIZE;
+                Vector3::new(r.x.mod_floor(&n), r.y, r.z.mod_floor(&n))
+            },
+            None => r,
+        }
+    }
+
+    pub fn block(&self, p: Vector3<i64>) -> Vector3<i64> {
+        match self.period() {
+            Some(period) => Vector3::new(p.x.mod_floor(&period), p.y, p.z.mod_floor(&period)),
+            None => p,
+        }
+    }
+
+    pub fn position(&self, p: Vector3<f64>) -> Vector3<f64> {
+        match self.period() {
+            Some(period) => {
+                let period = period as f64;
+                let wrap = |v: f64| v - (v / period).floor() * period;
+                Vector3::new(wrap(p.x), p.y, wrap(p.z))
+            },
+            None => p,
+        }
+    }
+}
+
+#[test]
+fn test_wrap() {
+    let wrap = Wrap::new(6);
+    assert_eq!(wrap.chunk(Vector3::new(-1, -1, 8)), Vector3::new(7, -1, 0));
+    assert_eq!(wrap.region(Vector3::new(-1, 5, 2)), Vector3::new(1, 5, 0));
+    assert_eq!(Wrap::none().chunk(Vector3::new(-1, 0, 9)), Vector3::new(-1, 0, 9));
+    assert_eq!(wrap.chunk_offset(Vector3::new(7, 3, 1), Vector3::new(0, 1, 6)), Vector3::new(-1, 2, 3));
+    assert_eq!(Wrap::none().chunk_offset(Vector3::new(7, 0, 0), Vector3::new(0, 0, 0)), Vector3::new(7, 0, 0));
+    let period = wrap.period().unwrap() as f64;
+    assert_eq!(wrap.position_offset(Vector3::new(2.0, 1.0, 5.0), Vector3::new(period - 6.0, 0.0, 5.0)),
+               Vector3::new(8.0, 1.0, 0.0));
+}